@@ -261,6 +261,19 @@ impl RunActionDepFiles {
             labels: OrderedMap::new(),
         }
     }
+
+    /// The label of the dep file that tracks inputs tagged with `tag`. That is the dep file for
+    /// `tag` itself if there is one, and otherwise the first dep file whose tag was merged from
+    /// `tag`.
+    pub(crate) fn label_for_input(&self, tag: &ArtifactTag) -> Option<&Arc<str>> {
+        if let Some(label) = self.labels.get(tag) {
+            return Some(label);
+        }
+        self.labels
+            .iter()
+            .find(|(dep_file_tag, _label)| dep_file_tag.includes(tag))
+            .map(|(_tag, label)| label)
+    }
 }
 
 fn get_output_path_digest(
@@ -590,11 +603,7 @@ pub(crate) fn make_dep_file_bundle<'a>(
         .dupe();
     let dep_files_key = DepFilesKey::from_action_execution_target(ctx.target());
 
-    let DepFilesCommandLineVisitor {
-        inputs: declared_inputs,
-        tagged_outputs,
-        ..
-    } = visitor;
+    let (declared_inputs, tagged_outputs) = visitor.finish();
 
     // Filter out tags with no dep file associated with it
     let tagged_outputs: OrderedMap<ArtifactTag, DeclaredDepFile> = tagged_outputs
@@ -991,29 +1000,6 @@ impl Default for PartitionedInputs<Vec<ArtifactGroup>> {
 }
 
 impl PartitionedInputs<Vec<ArtifactGroup>> {
-    /// Add an input to this set of PartitionedInputs.
-    fn visit_input(
-        &mut self,
-        input: ArtifactGroup,
-        tag: Option<&ArtifactTag>,
-        dep_files: &RunActionDepFiles,
-    ) {
-        let input_group = match tag {
-            None => &mut self.untagged,
-            Some(tag) => {
-                // NOTE: If an input has a tag that doesn't match a dep file, we don't care about
-                // it.
-                match dep_files.labels.get(tag) {
-                    None => &mut self.untagged,
-                    // The tagged inputs have prepopulated keys on creation to ensure sorted keys, so the label must exist.
-                    Some(label) => self.tagged.get_mut(label).unwrap(),
-                }
-            }
-        };
-
-        input_group.push(input);
-    }
-
     /// Produce Directories from this set of PartitionedInputs. One directory will be produced for
     /// each tag (and one for untagged). This will actually allocate directories (whereas until now
     /// we only held references to artifacts).
@@ -1288,7 +1274,10 @@ pub struct ConcreteDepFiles {
 /// A command line visitor to collect inputs and outputs in a form relevant for dep files
 /// computations.
 pub(crate) struct DepFilesCommandLineVisitor<'a> {
-    pub inputs: PartitionedInputs<Vec<ArtifactGroup>>,
+    untagged_inputs: Vec<ArtifactGroup>,
+    /// The inputs of each dep file's label, with their order hints, in the order we visited them.
+    /// See `finish`.
+    tagged_inputs: OrderedMap<Arc<str>, Vec<(Option<i32>, ArtifactGroup)>>,
     pub tagged_outputs: OrderedMap<ArtifactTag, (Arc<str>, Option<Artifact>)>,
     dep_files: &'a RunActionDepFiles,
 }
@@ -1297,7 +1286,7 @@ impl<'a> DepFilesCommandLineVisitor<'a> {
     pub(crate) fn new(dep_files: &'a RunActionDepFiles) -> Self {
         // Prepopulate inputs & outputs to maintain the ordering of the labels declaration.
 
-        let tagged_inputs: OrderedMap<Arc<str>, Vec<ArtifactGroup>> = dep_files
+        let tagged_inputs: OrderedMap<Arc<str>, Vec<(Option<i32>, ArtifactGroup)>> = dep_files
             .labels
             .iter()
             .map(|(_tag, label)| (label.dupe(), Default::default()))
@@ -1308,20 +1297,79 @@ impl<'a> DepFilesCommandLineVisitor<'a> {
             .iter()
             .map(|(tag, label)| (tag.dupe(), (label.dupe(), None)))
             .collect();
+
         Self {
-            inputs: PartitionedInputs {
-                untagged: Default::default(),
-                tagged: tagged_inputs,
-            },
+            untagged_inputs: Default::default(),
+            tagged_inputs,
             tagged_outputs,
             dep_files,
         }
     }
+
+    /// All the inputs we visited so far, in no particular order.
+    pub(crate) fn visited_inputs(&self) -> impl Iterator<Item = &ArtifactGroup> {
+        self.untagged_inputs.iter().chain(
+            self.tagged_inputs
+                .values()
+                .flat_map(|inputs| inputs.iter().map(|(_order, input)| input)),
+        )
+    }
+
+    /// Once we are done visiting, the inputs partitioned by tag, and the dep file each tag
+    /// declares. The inputs of each tag are sorted by order hint, with those that have none last
+    /// (in the order we visited them).
+    pub(crate) fn finish(
+        self,
+    ) -> (
+        PartitionedInputs<Vec<ArtifactGroup>>,
+        OrderedMap<ArtifactTag, (Arc<str>, Option<Artifact>)>,
+    ) {
+        let tagged = self
+            .tagged_inputs
+            .into_iter()
+            .map(|(label, mut inputs)| {
+                // This is a stable sort, so inputs with the same hint keep the order we visited
+                // them in.
+                inputs.sort_by_key(|(order, _input)| (order.is_none(), *order));
+                (
+                    label,
+                    inputs.into_iter().map(|(_order, input)| input).collect(),
+                )
+            })
+            .collect();
+
+        (
+            PartitionedInputs {
+                untagged: self.untagged_inputs,
+                tagged,
+            },
+            self.tagged_outputs,
+        )
+    }
 }
 
 impl CommandLineArtifactVisitor for DepFilesCommandLineVisitor<'_> {
     fn visit_input(&mut self, input: ArtifactGroup, tag: Option<&ArtifactTag>) {
-        self.inputs.visit_input(input, tag, self.dep_files);
+        self.visit_input_with_order(input, tag, None)
+    }
+
+    fn visit_input_with_order(
+        &mut self,
+        input: ArtifactGroup,
+        tag: Option<&ArtifactTag>,
+        order: Option<i32>,
+    ) {
+        // NOTE: If an input has a tag that doesn't match a dep file, we don't care about it.
+        match tag.and_then(|tag| self.dep_files.label_for_input(tag)) {
+            // The tagged inputs have prepopulated keys on creation to ensure sorted keys, so the
+            // label must exist.
+            Some(label) => self
+                .tagged_inputs
+                .get_mut(label)
+                .unwrap()
+                .push((order, input)),
+            None => self.untagged_inputs.push(input),
+        }
     }
 
     fn visit_output(&mut self, artifact: OutputArtifact, tag: Option<&ArtifactTag>) {
@@ -1345,9 +1393,18 @@ mod test {
 
     use buck2_artifact::artifact::artifact_type::testing::BuildArtifactTestingExt;
     use buck2_artifact::deferred::id::DeferredId;
+    use buck2_build_api::interpreter::rule_defs::artifact::StarlarkArtifact;
+    use buck2_build_api::interpreter::rule_defs::artifact_tagging::TaggedCommandLine;
+    use buck2_build_api::interpreter::rule_defs::artifact_tagging::TaggedValue;
+    use buck2_build_api::interpreter::rule_defs::artifact_tagging::TaggedVisitor;
+    use buck2_build_api::interpreter::rule_defs::cmd_args::CommandLineArgLike;
+    use buck2_common::file_ops::FileMetadata;
     use buck2_core::configuration::data::ConfigurationData;
+    use buck2_core::directory::find;
     use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
     use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
+    use buck2_execute::directory::insert_file;
+    use starlark::values::Heap;
 
     use super::*;
 
@@ -1409,6 +1466,175 @@ mod test {
         assert_eq!(x, y);
     }
 
+    #[test]
+    fn test_dep_files_visitor_merged_inputs() {
+        let tag1 = ArtifactTag::new();
+        let tag2 = ArtifactTag::new();
+        let tag3 = ArtifactTag::new();
+        let merged = ArtifactTag::merge(&tag1, &tag2);
+
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let artifacts: Vec<_> = (0..4)
+            .map(|i| {
+                ArtifactGroup::Artifact(Artifact::from(BuildArtifact::testing_new(
+                    target.dupe(),
+                    ForwardRelativePathBuf::unchecked_new(format!("foo/bar{}.h", i)),
+                    DeferredId::testing_new(0),
+                )))
+            })
+            .collect();
+
+        let dep_files = RunActionDepFiles {
+            labels: OrderedMap::from_iter([
+                (merged.dupe(), Arc::from("merged")),
+                (tag2.dupe(), Arc::from("l2")),
+            ]),
+        };
+
+        let mut visitor = DepFilesCommandLineVisitor::new(&dep_files);
+        visitor.visit_input(artifacts[0].dupe(), Some(&tag1));
+        // tag2 has its own dep file, which takes precedence.
+        visitor.visit_input(artifacts[1].dupe(), Some(&tag2));
+        visitor.visit_input(artifacts[2].dupe(), Some(&merged));
+        // This should be untagged as tag3 was not merged.
+        visitor.visit_input(artifacts[3].dupe(), Some(&tag3));
+
+        let (inputs, _) = visitor.finish();
+        assert_eq!(
+            inputs.tagged.get("merged").unwrap(),
+            &vec![artifacts[0].dupe(), artifacts[2].dupe()]
+        );
+        assert_eq!(inputs.tagged.get("l2").unwrap(), &vec![artifacts[1].dupe()]);
+        assert_eq!(inputs.untagged, vec![artifacts[3].dupe()]);
+    }
+
+    #[test]
+    fn test_dep_files_visitor_inputs_by_extension() {
+        let tag = ArtifactTag::new();
+
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let artifacts: Vec<_> = [
+            "foo/bar.h",
+            "foo/bar.cpp",
+            "foo/bar",
+            "foo/BAR.H",
+            "foo/bar.tar.gz",
+        ]
+        .into_iter()
+        .map(|path| {
+            ArtifactGroup::Artifact(Artifact::from(BuildArtifact::testing_new(
+                target.dupe(),
+                ForwardRelativePathBuf::unchecked_new(path.to_owned()),
+                DeferredId::testing_new(0),
+            )))
+        })
+        .collect();
+
+        let dep_files = RunActionDepFiles {
+            labels: OrderedMap::from_iter([(tag.dupe(), Arc::from("headers"))]),
+        };
+
+        let visit = |extensions: &[String]| {
+            let mut visitor = DepFilesCommandLineVisitor::new(&dep_files);
+            let mut tagged =
+                TaggedVisitor::wrap(&tag, true, &mut visitor).with_extensions(extensions);
+            for artifact in &artifacts {
+                tagged.visit_input(artifact.dupe(), None);
+            }
+            visitor.finish().0
+        };
+
+        // Extensions are case-sensitive.
+        let inputs = visit(&[".h".to_owned()]);
+        assert_eq!(
+            inputs.tagged.get("headers").unwrap(),
+            &vec![artifacts[0].dupe()]
+        );
+        assert_eq!(
+            inputs.untagged,
+            vec![
+                artifacts[1].dupe(),
+                artifacts[2].dupe(),
+                artifacts[3].dupe(),
+                artifacts[4].dupe(),
+            ]
+        );
+
+        // An empty extension matches inputs without one.
+        let inputs = visit(&[".cpp".to_owned(), "".to_owned()]);
+        assert_eq!(
+            inputs.tagged.get("headers").unwrap(),
+            &vec![artifacts[1].dupe(), artifacts[2].dupe()]
+        );
+        assert_eq!(
+            inputs.untagged,
+            vec![
+                artifacts[0].dupe(),
+                artifacts[3].dupe(),
+                artifacts[4].dupe(),
+            ]
+        );
+
+        // Extensions can have several dots, and match the end of the file name.
+        let inputs = visit(&[".tar.gz".to_owned()]);
+        assert_eq!(
+            inputs.tagged.get("headers").unwrap(),
+            &vec![artifacts[4].dupe()]
+        );
+        let inputs = visit(&[".gz".to_owned()]);
+        assert_eq!(
+            inputs.tagged.get("headers").unwrap(),
+            &vec![artifacts[4].dupe()]
+        );
+    }
+
+    #[test]
+    fn test_dep_files_visitor_input_order() {
+        let tag = ArtifactTag::new();
+
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let artifacts: Vec<_> = ["foo/a.h", "foo/b.h", "foo/c.h", "foo/d.h"]
+            .into_iter()
+            .map(|path| {
+                ArtifactGroup::Artifact(Artifact::from(BuildArtifact::testing_new(
+                    target.dupe(),
+                    ForwardRelativePathBuf::unchecked_new(path.to_owned()),
+                    DeferredId::testing_new(0),
+                )))
+            })
+            .collect();
+
+        let dep_files = RunActionDepFiles {
+            labels: OrderedMap::from_iter([(tag.dupe(), Arc::from("headers"))]),
+        };
+
+        let mut visitor = DepFilesCommandLineVisitor::new(&dep_files);
+        // Inputs without an order go last, in the order we visit them, whereas those with one
+        // are sorted by it regardless of when we visit them.
+        TaggedVisitor::wrap(&tag, true, &mut visitor).visit_input(artifacts[0].dupe(), None);
+        TaggedVisitor::wrap(&tag, true, &mut visitor)
+            .with_order(Some(2))
+            .visit_input(artifacts[1].dupe(), None);
+        TaggedVisitor::wrap(&tag, true, &mut visitor).visit_input(artifacts[2].dupe(), None);
+        TaggedVisitor::wrap(&tag, true, &mut visitor)
+            .with_order(Some(1))
+            .visit_input(artifacts[3].dupe(), None);
+
+        let (inputs, _) = visitor.finish();
+        assert_eq!(
+            inputs.tagged.get("headers").unwrap(),
+            &vec![
+                artifacts[3].dupe(),
+                artifacts[1].dupe(),
+                artifacts[0].dupe(),
+                artifacts[2].dupe(),
+            ]
+        );
+    }
+
     #[test]
     fn test_declares_same_dep_files() {
         let target =
@@ -1466,4 +1692,67 @@ mod test {
         assert!(!decl2.declares_same_dep_files(&decl3));
         assert!(!decl3.declares_same_dep_files(&decl4));
     }
+
+    #[test]
+    fn test_filter_tagged_directory() -> anyhow::Result<()> {
+        // Tagging a directory tags what it contains: its contents are only known (and
+        // materialized) once we execute, but that is when we resolve dep files, which can select
+        // individual files within it.
+        let tag = ArtifactTag::new();
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let headers = Artifact::from(BuildArtifact::testing_new(
+            target,
+            ForwardRelativePathBuf::unchecked_new("headers".to_owned()),
+            DeferredId::testing_new(0),
+        ));
+
+        let dep_files = RunActionDepFiles {
+            labels: OrderedMap::from_iter([(tag.dupe(), Arc::from("headers"))]),
+        };
+
+        let heap = Heap::new();
+        let tagged = TaggedCommandLine::new(TaggedValue::new(
+            heap.alloc(StarlarkArtifact::new(headers.dupe())),
+            tag,
+        ));
+        let mut visitor = DepFilesCommandLineVisitor::new(&dep_files);
+        tagged.visit_artifacts(&mut visitor)?;
+        let (visited, _) = visitor.finish();
+        assert!(visited.untagged.is_empty());
+        assert_eq!(
+            visited.tagged.get("headers").unwrap(),
+            &vec![ArtifactGroup::Artifact(headers)]
+        );
+
+        // What the directory holds once the action executes.
+        let file = FileMetadata::empty(DigestConfig::testing_default().cas_digest_config());
+        let used = ProjectRelativePath::unchecked_new("out/headers/used.h");
+        let unused = ProjectRelativePath::unchecked_new("out/headers/unused.h");
+
+        let mut dir = ActionDirectoryBuilder::empty();
+        insert_file(&mut dir, used, file.dupe())?;
+        insert_file(&mut dir, unused, file.dupe())?;
+
+        let mut selector = DirectorySelector::empty();
+        selector.select(used);
+
+        let inputs = PartitionedInputs {
+            untagged: ActionDirectoryBuilder::empty(),
+            tagged: visited
+                .tagged
+                .keys()
+                .map(|label| (label.dupe(), dir.clone()))
+                .collect(),
+        }
+        .filter(ConcreteDepFiles {
+            contents: HashMap::from_iter([(Arc::from("headers"), selector)]),
+        });
+
+        let dir = inputs.tagged.get("headers").unwrap();
+        assert!(find(dir, used.iter())?.is_some());
+        assert!(find(dir, unused.iter())?.is_none());
+
+        Ok(())
+    }
 }
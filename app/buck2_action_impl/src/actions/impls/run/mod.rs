@@ -532,7 +532,7 @@ impl RunActionVisitor for DepFilesCommandLineVisitor<'_> {
     type Iter<'a> = impl Iterator<Item = &'a ArtifactGroup> where Self: 'a;
 
     fn inputs<'a>(&'a self) -> Self::Iter<'a> {
-        self.inputs.iter().flat_map(|g| g.iter())
+        self.visited_inputs()
     }
 }
 
@@ -8,6 +8,7 @@
  */
 
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use anyhow::Context;
@@ -128,6 +129,12 @@ enum RunActionError {
     InvalidDepFileOutputs { key: String, count: usize },
     #[error("`dep_files` with keys `{}` and {} are using the same tag", .first, .second)]
     ConflictingDepFiles { first: String, second: String },
+    #[error(
+        "output `{}` is not tagged with {}, which `require_outputs_tagged` requires of every output",
+        .output,
+        .tag
+    )]
+    UntaggedOutput { output: String, tag: String },
     #[error(
         "missing `metadata_path` parameter which is required when `metadata_env_var` parameter is present"
     )]
@@ -140,6 +147,8 @@ enum RunActionError {
         "Recursion limit exceeded when visiting artifacts: do you have a cycle in your inputs or outputs?"
     )]
     ArtifactVisitRecursionLimitExceeded,
+    #[error("{0} already has a primary value")]
+    DuplicatePrimary(ArtifactTag),
 }
 
 #[derive(Debug, buck2_error::Error)]
@@ -621,6 +630,9 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
     ///     and `--local-only` CLI flags. The CLI flags take precedence.
     ///     * The `force_full_hybrid_if_capable` option overrides the `use_limited_hybrid` hybrid.
     ///     The options listed above take precedence if set.
+    /// * `require_outputs_tagged`: if set to an `ArtifactTag`, every output of the action must be
+    ///   tagged with it, or this fails naming the first output that isn't. This guards against
+    ///   forgetting to tag one of several outputs that a dep file is meant to cover.
     ///
     /// When actions execute, they'll do so from the root of the repository. As they execute,
     /// actions have exclusive access to their output directory.
@@ -656,11 +668,16 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
             Either<ValueOf<'v, &'v WorkerRunInfo<'v>>, ValueOf<'v, &'v RunInfo<'v>>>,
         >,
         #[starlark(require = named, default = false)] unique_input_inodes: bool,
+        #[starlark(require = named)] require_outputs_tagged: Option<&'v ArtifactTag>,
         eval: &mut Evaluator<'v, '_>,
     ) -> anyhow::Result<NoneType> {
         struct RunCommandArtifactVisitor {
             inner: SimpleCommandLineArtifactVisitor,
             tagged_outputs: HashMap<ArtifactTag, Vec<OutputArtifact>>,
+            /// Every tag we saw, on inputs or outputs.
+            tags: HashSet<ArtifactTag>,
+            /// Tags we saw a primary value for.
+            primaries: HashSet<ArtifactTag>,
             depth: u64,
         }
 
@@ -669,6 +686,8 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
                 Self {
                     inner: SimpleCommandLineArtifactVisitor::new(),
                     tagged_outputs: HashMap::new(),
+                    tags: HashSet::new(),
+                    primaries: HashSet::new(),
                     depth: 0,
                 }
             }
@@ -676,6 +695,9 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
 
         impl CommandLineArtifactVisitor for RunCommandArtifactVisitor {
             fn visit_input(&mut self, input: ArtifactGroup, tag: Option<&ArtifactTag>) {
+                if let Some(tag) = tag {
+                    self.tags.insert(tag.dupe());
+                }
                 self.inner.visit_input(input, tag);
             }
 
@@ -683,6 +705,7 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
                 match tag {
                     None => {}
                     Some(tag) => {
+                        self.tags.insert(tag.dupe());
                         self.tagged_outputs
                             .entry(tag.dupe())
                             .or_default()
@@ -693,6 +716,13 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
                 self.inner.visit_output(artifact, tag);
             }
 
+            fn visit_primary(&mut self, tag: &ArtifactTag) -> anyhow::Result<()> {
+                if !self.primaries.insert(tag.dupe()) {
+                    return Err(RunActionError::DuplicatePrimary(tag.dupe()).into());
+                }
+                Ok(())
+            }
+
             fn push_frame(&mut self) -> anyhow::Result<()> {
                 self.depth += 1;
                 if self.depth > 1000 {
@@ -762,6 +792,8 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
         let RunCommandArtifactVisitor {
             inner: artifacts,
             tagged_outputs,
+            tags,
+            primaries: _,
             depth: _,
         } = artifact_visitor;
 
@@ -812,6 +844,17 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
         if artifacts.outputs.is_empty() {
             return Err(RunActionError::NoOutputsSpecified.into());
         }
+
+        if let Some(tag) = require_outputs_tagged {
+            let tagged = tagged_outputs.get(tag).map_or(&[][..], |t| t.as_slice());
+            if let Some(output) = artifacts.outputs.iter().find(|o| !tagged.contains(o)) {
+                return Err(RunActionError::UntaggedOutput {
+                    output: output.to_string(),
+                    tag: tag.to_string(),
+                }
+                .into());
+            }
+        }
         let heap = eval.heap();
 
         let starlark_values = heap.alloc(StarlarkRunActionValues {
@@ -836,12 +879,15 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
             force_full_hybrid_if_capable,
             unique_input_inodes,
         };
-        this.state().register_action(
+        let mut state = this.state();
+        let key = state.register_action(
             artifacts.inputs,
             artifacts.outputs,
             action,
             Some(starlark_values),
         )?;
+        state.record_tag_consumers(&key, tags);
+
         Ok(NoneType)
     }
 
@@ -1037,9 +1083,12 @@ fn analysis_actions_methods_actions(builder: &mut MethodsBuilder) {
     }
 
     /// Allocate a new input tag. Used with the `dep_files` argument to `run`.
-    fn artifact_tag<'v>(this: &AnalysisActions<'v>) -> anyhow::Result<ArtifactTag> {
-        let _ = this;
-        Ok(ArtifactTag::new())
+    fn artifact_tag<'v>(
+        this: &AnalysisActions<'v>,
+        eval: &mut Evaluator<'v, '_>,
+    ) -> anyhow::Result<ArtifactTag> {
+        let sequence_number = this.state().record_artifact_tag(eval)?;
+        Ok(ArtifactTag::new_in_analysis(sequence_number))
     }
 
     /// Obtain this daemon's digest configuration. This allows rules to discover what digests the
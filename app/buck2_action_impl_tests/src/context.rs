@@ -8,6 +8,7 @@
  */
 
 use buck2_build_api::analysis::registry::AnalysisRegistry;
+use buck2_build_api::interpreter::rule_defs::artifact_tagging::ArtifactTag;
 use buck2_build_api::interpreter::rule_defs::context::AnalysisContext;
 use buck2_build_api::interpreter::rule_defs::plugins::AnalysisPlugins;
 use buck2_build_api::interpreter::rule_defs::register_rule_defs;
@@ -36,6 +37,14 @@ use starlark::values::Value;
 fn run_ctx_test(
     content: &str,
     result_handler: impl FnOnce(anyhow::Result<Value>) -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    run_ctx_test_with_registry(content, |ret, _| result_handler(ret))
+}
+
+/// Like `run_ctx_test`, but also passes the analysis registry to `result_handler`.
+fn run_ctx_test_with_registry(
+    content: &str,
+    result_handler: impl FnOnce(anyhow::Result<Value>, &AnalysisRegistry) -> anyhow::Result<()>,
 ) -> anyhow::Result<()> {
     let func_mod = Module::new();
     let globals = GlobalsBuilder::standard().with(register_rule_defs).build();
@@ -90,7 +99,8 @@ fn run_ctx_test(
     ));
 
     let returned = eval.eval_function(test_function, &[ctx], &[]);
-    result_handler(returned)
+    let registry = ctx.downcast_ref::<AnalysisContext>().unwrap().take_state();
+    result_handler(returned, &registry)
 }
 
 #[test]
@@ -223,3 +233,112 @@ fn declare_output_require_bound() -> anyhow::Result<()> {
         ),
     })
 }
+
+#[test]
+fn run_records_tag_consumers() -> anyhow::Result<()> {
+    let content = indoc!(
+        r#"
+         def test(c):
+             t1 = c.actions.artifact_tag()
+             t2 = c.actions.artifact_tag()
+             unused = c.actions.artifact_tag()
+             a = c.actions.declare_output("a")
+             b = c.actions.declare_output("b")
+             c.actions.run(
+                 [
+                     t1.tag_artifacts(cmd_args(a.as_output())),
+                     t2.tag_artifacts(cmd_args(b.as_output())),
+                 ],
+                 category = "test_category",
+             )
+             return (t1, t2, unused)
+         "#
+    );
+
+    run_ctx_test_with_registry(content, |ret, registry| {
+        let (t1, t2, unused) = <(Value, Value, Value)>::unpack_value(ret?).unwrap();
+        let t1 = t1.downcast_ref::<ArtifactTag>().unwrap();
+        let t2 = t2.downcast_ref::<ArtifactTag>().unwrap();
+        let unused = unused.downcast_ref::<ArtifactTag>().unwrap();
+
+        let consumers = registry.tag_consumers(t1).collect::<Vec<_>>();
+        assert_eq!(consumers.len(), 1);
+        assert_eq!(registry.tag_consumers(t2).collect::<Vec<_>>(), consumers);
+        assert_eq!(registry.tag_consumers(unused).count(), 0);
+        Ok(())
+    })
+}
+
+#[test]
+fn artifact_tags_display_analysis_sequence_numbers() -> anyhow::Result<()> {
+    let content = indoc!(
+        r#"
+         def test(c):
+             t0 = c.actions.artifact_tag()
+             t1 = c.actions.artifact_tag()
+             assert_eq("ArtifactTag(#0)", str(t0))
+             assert_eq("ArtifactTag(#1)", str(t1))
+             assert_eq("ArtifactTag(#0+#1)", str(t0.merge(t1)))
+         "#
+    );
+
+    run_ctx_test(content, |ret| ret.map(|_| ()))
+}
+
+#[test]
+fn run_rejects_duplicate_primary() -> anyhow::Result<()> {
+    let content = indoc!(
+        r#"
+         def test(c):
+             t = c.actions.artifact_tag()
+             a = c.actions.declare_output("a")
+             b = c.actions.declare_output("b")
+             c.actions.run(
+                 [
+                     t.tag_artifacts(cmd_args(a.as_output()), primary = True),
+                     t.tag_artifacts(cmd_args(b.as_output()), primary = True),
+                 ],
+                 category = "test_category",
+             )
+         "#
+    );
+
+    let expect = "already has a primary value";
+    run_ctx_test(content, |ret| match ret {
+        Err(e) if e.to_string().contains(expect) => Ok(()),
+        _ => panic!(
+            "Expected a specific failure containing `{}`, got {:?}",
+            expect, ret
+        ),
+    })
+}
+
+#[test]
+fn run_require_outputs_tagged() -> anyhow::Result<()> {
+    let content = indoc!(
+        r#"
+         def test(c):
+             t = c.actions.artifact_tag()
+             a = c.actions.declare_output("a")
+             b = c.actions.declare_output("b")
+             forgotten = c.actions.declare_output("forgotten")
+             c.actions.run(
+                 [
+                     t.tag_artifacts(cmd_args(a.as_output(), b.as_output())),
+                     forgotten.as_output(),
+                 ],
+                 category = "test_category",
+                 require_outputs_tagged = t,
+             )
+         "#
+    );
+
+    let expect = "output `forgotten` is not tagged";
+    run_ctx_test(content, |ret| match ret {
+        Err(e) if e.to_string().contains(expect) => Ok(()),
+        _ => panic!(
+            "Expected a specific failure containing `{}`, got {:?}",
+            expect, ret
+        ),
+    })
+}
@@ -14,13 +14,17 @@ use buck2_artifact::artifact::artifact_type::Artifact;
 use buck2_artifact::artifact::build_artifact::BuildArtifact;
 use buck2_build_signals::NodeDuration;
 use buck2_core::fs::project_rel_path::ProjectRelativePathBuf;
+use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
 use buck2_data::ToProtoMessage;
 use buck2_events::dispatch::current_span;
 use buck2_events::dispatch::span_async;
 use buck2_execute::artifact::artifact_dyn::ArtifactDyn;
+use buck2_execute::artifact_value::ArtifactValue;
 use buck2_execute::materialize::materializer::HasMaterializer;
+use buck2_execute::output_size::OutputSize;
 use dice::DiceComputations;
 use dupe::Dupe;
+use dupe::OptionDupedExt;
 
 use crate::actions::artifact::get_artifact_fs::GetArtifactFs;
 use crate::build_signals::HasBuildSignals;
@@ -34,7 +38,9 @@ pub trait ArtifactMaterializer {
     async fn try_materialize_requested_artifact(
         &self,
         artifact: &BuildArtifact,
+        value: &ArtifactValue,
         required: bool,
+        requester: Option<&ConfiguredTargetLabel>,
     ) -> anyhow::Result<()>;
 }
 
@@ -51,7 +57,9 @@ impl ArtifactMaterializer for DiceComputations {
     async fn try_materialize_requested_artifact(
         &self,
         artifact: &BuildArtifact,
+        value: &ArtifactValue,
         required: bool,
+        requester: Option<&ConfiguredTargetLabel>,
     ) -> anyhow::Result<()> {
         let materializer = self.per_transaction_data().get_materializer();
         let artifact_fs = self.get_artifact_fs().await?;
@@ -74,14 +82,20 @@ impl ArtifactMaterializer for DiceComputations {
 
             if let Some(signals) = self.per_transaction_data().get_build_signals() {
                 let duration = now.elapsed();
+                // If we failed, we can't tell how much (if anything) we materialized.
+                let bytes = result
+                    .is_ok()
+                    .then(|| value.calc_output_count_and_bytes().bytes);
 
                 signals.final_materialization(
                     artifact.dupe(),
+                    requester.duped(),
                     NodeDuration {
                         user: duration,
                         total: duration,
                     },
                     current_span(),
+                    bytes,
                 );
             }
 
@@ -9,12 +9,14 @@
 
 use std::iter::zip;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
 
 use allocative::Allocative;
 use async_trait::async_trait;
 use buck2_artifact::actions::key::ActionKey;
 use buck2_artifact::artifact::build_artifact::BuildArtifact;
+use buck2_build_signals::CacheMissReason;
 use buck2_build_signals::NodeDuration;
 use buck2_common::events::HasEvents;
 use buck2_data::ToProtoMessage;
@@ -22,6 +24,8 @@ use buck2_error::Context;
 use buck2_events::dispatch::async_record_root_spans;
 use buck2_events::dispatch::span_async;
 use buck2_events::span::SpanId;
+use buck2_execute::execute::action_digest::ActionDigest;
+use buck2_execute::execute::kind::CommandExecutionKind;
 use buck2_execute::execute::result::CommandExecutionReport;
 use buck2_execute::execute::result::CommandExecutionStatus;
 use buck2_execute::output_size::OutputSize;
@@ -44,6 +48,7 @@ use crate::actions::execute::action_executor::HasActionExecutor;
 use crate::actions::key::ActionKeyExt;
 use crate::actions::RegisteredAction;
 use crate::artifact_groups::calculation::ensure_artifact_group_staged;
+use crate::build_signals::HasBuildSignals;
 use crate::deferred::calculation::DeferredCalculation;
 use crate::keep_going;
 
@@ -124,6 +129,20 @@ async fn build_action_no_redirect(
 
         let allow_omit_details = execute_result.is_ok();
 
+        let bytes_uploaded = command_reports
+            .iter()
+            .filter_map(|r| r.timing.re_bytes_uploaded)
+            .sum::<u64>();
+
+        // Only commands that ran remotely queued in RE.
+        let re_queue_duration = command_reports
+            .iter()
+            .filter_map(|r| match r.status.execution_kind() {
+                Some(CommandExecutionKind::Remote { queue_time, .. }) => Some(*queue_time),
+                _ => None,
+            })
+            .reduce(|a, b| a + b);
+
         let commands = future::join_all(
             command_reports
                 .iter()
@@ -143,6 +162,9 @@ async fn build_action_no_redirect(
         let wall_time;
         let error;
         let output_size;
+        let mut bytes_downloaded = 0;
+        let mut action_digest = None;
+        let mut cache_miss_reason = None;
 
         let mut prefers_local = None;
         let mut requires_local = None;
@@ -161,6 +183,18 @@ async fn build_action_no_redirect(
             Ok((outputs, meta)) => {
                 output_size = outputs.calc_output_count_and_bytes().bytes;
                 action_result = Ok(outputs);
+
+                // Outputs of commands that ran (or were cached) remotely live in RE until we
+                // download them.
+                if let Some(command) = meta.execution_kind.command() {
+                    if let CommandExecutionKind::Remote { .. }
+                    | CommandExecutionKind::ActionCache { .. }
+                    | CommandExecutionKind::RemoteDepFileCache { .. } = command.kind
+                    {
+                        bytes_downloaded = output_size;
+                    }
+                }
+
                 execution_kind = Some(meta.execution_kind.as_enum());
                 wall_time = Some(meta.timing.wall_time);
                 error = None;
@@ -174,6 +208,13 @@ async fn build_action_no_redirect(
                     did_dep_file_cache_upload = Some(command.did_dep_file_cache_upload);
                     dep_file_key = command.dep_file_key.clone();
                     eligible_for_full_hybrid = Some(command.eligible_for_full_hybrid);
+                    action_digest = command.kind.action_digest().cloned();
+                    cache_miss_reason = match command.kind {
+                        CommandExecutionKind::ActionCache { .. }
+                        | CommandExecutionKind::RemoteDepFileCache { .. } => None,
+                        _ if command.requires_local => Some(CacheMissReason::NotCacheable),
+                        _ => Some(CacheMissReason::NotInCache),
+                    };
                 }
             }
             Err(e) => {
@@ -203,6 +244,10 @@ async fn build_action_no_redirect(
                     .get_dispatcher()
                     .instant_event(e.as_proto_event());
 
+                if let Some(signals) = ctx.per_transaction_data().get_build_signals() {
+                    signals.action_failed(action.key().dupe());
+                }
+
                 action_result = Err(buck2_error::Error::from(e)
                     // Make sure to mark the error as emitted so that it is not printed out to console
                     // again in this command. We still need to keep it around for the build report (and
@@ -230,7 +275,16 @@ async fn build_action_no_redirect(
             .unwrap_or_default();
 
         (
-            (action_result, wall_time),
+            (
+                action_result,
+                wall_time,
+                bytes_downloaded,
+                bytes_uploaded,
+                output_size,
+                re_queue_duration,
+                action_digest,
+                cache_miss_reason,
+            ),
             Box::new(buck2_data::ActionExecutionEnd {
                 key: Some(action_key),
                 kind: action.kind().into(),
@@ -260,8 +314,19 @@ async fn build_action_no_redirect(
     };
 
     // boxed() the future so that we don't need to allocate space for it while waiting on input dependencies.
-    let ((res, wall_time), spans) =
-        async_record_root_spans(span_async(start_event, fut.boxed())).await;
+    let (
+        (
+            res,
+            wall_time,
+            bytes_downloaded,
+            bytes_uploaded,
+            output_bytes,
+            re_queue_duration,
+            action_digest,
+            cache_miss_reason,
+        ),
+        spans,
+    ) = async_record_root_spans(span_async(start_event, fut.boxed())).await;
 
     // TODO: This wall time is rather wrong. We should report a wall time on failures too.
     ctx.store_evaluation_data(BuildKeyActivationData {
@@ -271,6 +336,12 @@ async fn build_action_no_redirect(
             total: now.elapsed(),
         },
         spans,
+        bytes_downloaded,
+        bytes_uploaded,
+        output_bytes,
+        re_queue_duration,
+        action_digest,
+        cache_miss_reason,
     })?;
 
     res
@@ -280,6 +351,19 @@ pub struct BuildKeyActivationData {
     pub action: Arc<RegisteredAction>,
     pub duration: NodeDuration,
     pub spans: SmallVec<[SpanId; 1]>,
+    /// How many bytes of outputs this action left in RE for us to download.
+    pub bytes_downloaded: u64,
+    /// How many bytes of inputs we uploaded to RE to run this action.
+    pub bytes_uploaded: u64,
+    /// How many bytes of outputs this action produced, wherever they are.
+    pub output_bytes: u64,
+    /// How long the commands this action ran remotely spent queued in RE, if it ran any.
+    pub re_queue_duration: Option<Duration>,
+    /// The digest of the command this action ran, if it ran one successfully. Identical actions
+    /// (e.g. the same action analyzed under different configurations) have the same digest.
+    pub action_digest: Option<ActionDigest>,
+    /// Why this action wasn't served from a cache, if it ran a command that wasn't.
+    pub cache_miss_reason: Option<CacheMissReason>,
 }
 
 /// The cost of these calls are particularly critical. To control the cost (particularly size) of these calls
@@ -331,7 +331,7 @@ pub fn visit_json_artifacts(
             }
         }
         JsonUnpack::TaggedValue(v) => {
-            let mut visitor = v.wrap_visitor(visitor);
+            let mut visitor = v.wrap_visitor(visitor)?;
             visit_json_artifacts(*v.value(), &mut visitor)?;
         }
     }
@@ -15,7 +15,6 @@ use allocative::Allocative;
 use buck2_artifact::actions::key::ActionKey;
 use buck2_artifact::artifact::artifact_type::DeclaredArtifact;
 use buck2_artifact::artifact::artifact_type::OutputArtifact;
-use buck2_artifact::deferred::id::DeferredId;
 use buck2_core::base_deferred_key::BaseDeferredKey;
 use buck2_core::category::Category;
 use buck2_core::directory;
@@ -43,6 +42,7 @@ use crate::analysis::registry::AnalysisValueFetcher;
 use crate::artifact_groups::ArtifactGroup;
 use crate::deferred::types::DeferredRegistry;
 use crate::deferred::types::ReservedTrivialDeferredData;
+use crate::interpreter::rule_defs::artifact_tagging::ArtifactTag;
 
 /// The actions registry for a particular analysis of a rule implementation
 #[derive(Allocative)]
@@ -56,6 +56,8 @@ pub struct ActionsRegistry {
     )>,
     execution_platform: ExecutionPlatformResolution,
     claimed_output_paths: DirectoryBuilder<Option<FileSpan>, NoDigest>,
+    /// The actions that consumed values tagged with each tag, in the order they were registered.
+    tag_consumers: HashMap<ArtifactTag, IndexSet<ActionKey>>,
 }
 
 impl ActionsRegistry {
@@ -67,6 +69,7 @@ impl ActionsRegistry {
             pending: Default::default(),
             execution_platform,
             claimed_output_paths: DirectoryBuilder::empty(),
+            tag_consumers: HashMap::new(),
         }
     }
 
@@ -170,31 +173,41 @@ impl ActionsRegistry {
         Ok(declared)
     }
 
-    /// Registers the supplied action
+    /// Registers the supplied action, returning the key it will be registered under.
     pub fn register<A: UnregisteredAction + 'static>(
         &mut self,
         registry: &mut DeferredRegistry,
         inputs: IndexSet<ArtifactGroup>,
         outputs: IndexSet<OutputArtifact>,
         action: A,
-    ) -> anyhow::Result<DeferredId> {
+    ) -> anyhow::Result<ActionKey> {
         let reserved = registry.reserve_trivial::<Arc<RegisteredAction>>();
+        let key = ActionKey::new(reserved.data().dupe());
 
         let mut bound_outputs = IndexSet::with_capacity(outputs.len());
         for output in outputs {
-            let bound = output
-                .bind(ActionKey::new(reserved.data().dupe()))?
-                .as_base_artifact()
-                .dupe();
+            let bound = output.bind(key.dupe())?.as_base_artifact().dupe();
             bound_outputs.insert(bound);
         }
-        let id = reserved.data().deferred_key().id();
         self.pending.push((
             reserved,
             ActionToBeRegistered::new(inputs, bound_outputs, action),
         ));
 
-        Ok(id)
+        Ok(key)
+    }
+
+    /// Record that the action registered under `key` consumed values tagged with `tag`.
+    pub fn record_tag_consumer(&mut self, tag: ArtifactTag, key: &ActionKey) {
+        self.tag_consumers
+            .entry(tag)
+            .or_default()
+            .insert(key.dupe());
+    }
+
+    /// The actions that consumed values tagged with `tag`, in the order they were registered.
+    pub fn tag_consumers(&self, tag: &ArtifactTag) -> impl Iterator<Item = &ActionKey> {
+        self.tag_consumers.get(tag).into_iter().flatten()
     }
 
     /// Consumes the registry so no more 'Action's can be registered. This returns
@@ -13,15 +13,18 @@ use std::marker::PhantomData;
 use std::sync::Arc;
 
 use allocative::Allocative;
+use buck2_artifact::actions::key::ActionKey;
 use buck2_artifact::artifact::artifact_type::Artifact;
 use buck2_artifact::artifact::artifact_type::DeclaredArtifact;
 use buck2_artifact::artifact::artifact_type::OutputArtifact;
 use buck2_artifact::deferred::id::DeferredId;
 use buck2_core::base_deferred_key::BaseDeferredKey;
+use buck2_core::env_helper::EnvHelper;
 use buck2_core::execution_types::execution::ExecutionPlatformResolution;
 use buck2_core::fs::buck_out_path::BuckOutPath;
 use buck2_core::fs::paths::forward_rel_path::ForwardRelativePath;
 use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
+use buck2_core::soft_error;
 use buck2_execute::execute::request::OutputType;
 use derivative::Derivative;
 use dupe::Dupe;
@@ -51,6 +54,7 @@ use crate::dynamic::registry::DynamicRegistry;
 use crate::interpreter::rule_defs::artifact::associated::AssociatedArtifacts;
 use crate::interpreter::rule_defs::artifact::output_artifact_like::OutputArtifactArg;
 use crate::interpreter::rule_defs::artifact::StarlarkDeclaredArtifact;
+use crate::interpreter::rule_defs::artifact_tagging::ArtifactTag;
 use crate::interpreter::rule_defs::transitive_set::TransitiveSet;
 
 #[derive(Derivative, Trace, Allocative)]
@@ -67,6 +71,8 @@ pub struct AnalysisRegistry<'v> {
     pub anon_targets: Box<dyn AnonTargetsRegistryDyn<'v>>,
     analysis_value_storage: AnalysisValueStorage<'v>,
     pub short_path_assertions: HashMap<PromiseArtifactId, ForwardRelativePathBuf>,
+    /// How many artifact tags this analysis allocated.
+    artifact_tags_allocated: u64,
 }
 
 #[derive(buck2_error::Error, Debug)]
@@ -75,6 +81,17 @@ enum DeclaredArtifactError {
     DeclaredEmptyFileName,
 }
 
+#[derive(buck2_error::Error, Debug)]
+#[error(
+    "Analysis allocated more than {max} artifact tags{location}. Tags are meant to be allocated \
+    once per dep file, not once per artifact"
+)]
+struct ArtifactTagExplosion {
+    max: u64,
+    /// Where the tag that crossed the limit was allocated, if we know.
+    location: String,
+}
+
 impl<'v> AnalysisRegistry<'v> {
     pub fn new_from_owner(
         owner: BaseDeferredKey,
@@ -100,6 +117,7 @@ impl<'v> AnalysisRegistry<'v> {
             anon_targets: (ANON_TARGET_REGISTRY_NEW.get()?)(PhantomData, execution_platform),
             analysis_value_storage: AnalysisValueStorage::new(),
             short_path_assertions: HashMap::new(),
+            artifact_tags_allocated: 0,
         })
     }
 
@@ -119,6 +137,33 @@ impl<'v> AnalysisRegistry<'v> {
         self.actions.claim_output_path(path, declaration_location)
     }
 
+    /// Count an artifact tag allocated by this analysis, returning its sequence number among the
+    /// tags this analysis allocated. If `BUCK2_MAX_ARTIFACT_TAGS_PER_ANALYSIS` is set, report a
+    /// soft error (once) when the count exceeds it: that usually means a rule is allocating a tag
+    /// per artifact in a loop, which bloats dep file tracking.
+    pub fn record_artifact_tag(&mut self, eval: &Evaluator<'_, '_>) -> anyhow::Result<u64> {
+        static MAX_ARTIFACT_TAGS: EnvHelper<u64> =
+            EnvHelper::new("BUCK2_MAX_ARTIFACT_TAGS_PER_ANALYSIS");
+
+        let sequence_number = self.artifact_tags_allocated;
+        self.artifact_tags_allocated += 1;
+        if let Some(max) = MAX_ARTIFACT_TAGS.get_copied()? {
+            if self.artifact_tags_allocated == max.saturating_add(1) {
+                soft_error!(
+                    "artifact_tag_explosion",
+                    ArtifactTagExplosion {
+                        max,
+                        location: eval
+                            .call_stack_top_location()
+                            .map_or_else(String::new, |l| format!(" (the last one at `{}`)", l)),
+                    }
+                    .into()
+                )?;
+            }
+        }
+        Ok(sequence_number)
+    }
+
     pub(crate) fn declare_dynamic_output(
         &mut self,
         path: BuckOutPath,
@@ -205,14 +250,33 @@ impl<'v> AnalysisRegistry<'v> {
         outputs: IndexSet<OutputArtifact>,
         action: A,
         associated_value: Option<Value<'v>>,
-    ) -> anyhow::Result<()> {
-        let id = self
+    ) -> anyhow::Result<ActionKey> {
+        let key = self
             .actions
             .register(&mut self.deferred, inputs, outputs, action)?;
         if let Some(value) = associated_value {
-            self.analysis_value_storage.set_value(id, value);
+            self.analysis_value_storage
+                .set_value(key.deferred_key().id(), value);
         }
-        Ok(())
+        Ok(key)
+    }
+
+    /// Record that the action registered under `key` consumed values tagged with `tags`, so that
+    /// e.g. a dep file's tag can be traced back to the action that produces it.
+    pub fn record_tag_consumers(
+        &mut self,
+        key: &ActionKey,
+        tags: impl IntoIterator<Item = ArtifactTag>,
+    ) {
+        for tag in tags {
+            self.actions.record_tag_consumer(tag, key);
+        }
+    }
+
+    /// The actions that consumed values tagged with `tag`, in the order they were registered. For
+    /// dep files, this is the action whose dep file this tag governs.
+    pub fn tag_consumers(&self, tag: &ArtifactTag) -> impl Iterator<Item = &ActionKey> {
+        self.actions.tag_consumers(tag)
     }
 
     pub fn create_transitive_set(
@@ -294,6 +358,7 @@ impl<'v> AnalysisRegistry<'v> {
             anon_targets: _,
             analysis_value_storage,
             short_path_assertions: _,
+            artifact_tags_allocated: _,
         } = self;
 
         analysis_value_storage.write_to_module(env);
@@ -22,6 +22,7 @@ use buck2_core::configuration::compatibility::MaybeCompatible;
 use buck2_core::execution_types::executor_config::PathSeparatorKind;
 use buck2_core::provider::label::ConfiguredProvidersLabel;
 use buck2_core::provider::label::ProvidersLabel;
+use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
 use buck2_events::dispatch::console_message;
 use buck2_execute::artifact::fs::ExecutorFs;
 use buck2_node::nodes::configured_frontend::ConfiguredTargetNodeCalculation;
@@ -399,8 +400,9 @@ async fn build_configured_label_inner<'a>(
         .map({
             |(index, (output, provider_type))| {
                 let materialization_context = materialization_context.dupe();
-                materialize_artifact_group_owned(ctx, output, materialization_context).map(
-                    move |res| {
+                let requester = providers_label.target().dupe();
+                materialize_artifact_group_owned(ctx, output, materialization_context, requester)
+                    .map(move |res| {
                         let res =
                             res.map_err(buck2_error::Error::from)
                                 .map(|values| ProviderArtifacts {
@@ -409,8 +411,7 @@ async fn build_configured_label_inner<'a>(
                                 });
 
                         (index, res)
-                    },
-                )
+                    })
             }
         })
         .collect::<FuturesUnordered<_>>()
@@ -451,12 +452,20 @@ async fn build_configured_label_inner<'a>(
         Ok(stream.boxed())
     }
 }
+
 pub async fn materialize_artifact_group_owned(
     ctx: &DiceComputations,
     artifact_group: ArtifactGroup,
     materialization_context: MaterializationContext,
+    requester: ConfiguredTargetLabel,
 ) -> anyhow::Result<ArtifactGroupValues> {
-    materialize_artifact_group(ctx, &artifact_group, &materialization_context).await
+    materialize_artifact_group_for(
+        ctx,
+        &artifact_group,
+        &materialization_context,
+        Some(&requester),
+    )
+    .await
 }
 
 #[derive(Clone, Allocative)]
@@ -487,11 +496,22 @@ pub async fn materialize_artifact_group(
     ctx: &DiceComputations,
     artifact_group: &ArtifactGroup,
     materialization_context: &MaterializationContext,
+) -> anyhow::Result<ArtifactGroupValues> {
+    materialize_artifact_group_for(ctx, artifact_group, materialization_context, None).await
+}
+
+/// Like `materialize_artifact_group`, but on behalf of the top level target `requester`, so that
+/// build signals can attribute materializations to the targets that requested them.
+async fn materialize_artifact_group_for(
+    ctx: &DiceComputations,
+    artifact_group: &ArtifactGroup,
+    materialization_context: &MaterializationContext,
+    requester: Option<&ConfiguredTargetLabel>,
 ) -> anyhow::Result<ArtifactGroupValues> {
     let values = ctx.ensure_artifact_group(artifact_group).await?;
 
     if let MaterializationContext::Materialize { map, force } = materialization_context {
-        future::try_join_all(values.iter().filter_map(|(artifact, _value)| {
+        future::try_join_all(values.iter().filter_map(|(artifact, value)| {
             match artifact.as_parts().0 {
                 BaseArtifactKind::Build(artifact) => {
                     match map.entry(artifact.dupe()) {
@@ -502,11 +522,20 @@ pub async fn materialize_artifact_group(
                         }
                         Entry::Occupied(..) => {
                             // We've already requested this artifact, no use requesting it again.
+                            // But do record that this target wanted it too.
+                            if let (Some(requester), Some(signals)) =
+                                (requester, ctx.per_transaction_data().get_build_signals())
+                            {
+                                signals.final_materialization_requested(
+                                    artifact.dupe(),
+                                    requester.dupe(),
+                                );
+                            }
                             return None;
                         }
                     }
 
-                    Some(ctx.try_materialize_requested_artifact(artifact, *force))
+                    Some(ctx.try_materialize_requested_artifact(artifact, value, *force, requester))
                 }
                 BaseArtifactKind::Source(..) => None,
             }
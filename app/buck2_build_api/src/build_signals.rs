@@ -9,7 +9,9 @@
 
 use std::sync::Arc;
 
+use buck2_artifact::actions::key::ActionKey;
 use buck2_artifact::artifact::build_artifact::BuildArtifact;
+use buck2_build_signals::CriticalPathHistory;
 use buck2_build_signals::DeferredBuildSignals;
 use buck2_build_signals::NodeDuration;
 use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
@@ -29,6 +31,14 @@ pub fn create_build_signals() -> (BuildSignalsInstaller, Box<dyn DeferredBuildSi
     (CREATE_BUILD_SIGNALS.get().unwrap())()
 }
 
+pub static CREATE_CRITICAL_PATH_HISTORY: LateBinding<fn() -> Arc<dyn CriticalPathHistory>> =
+    LateBinding::new("CREATE_CRITICAL_PATH_HISTORY");
+
+/// Create the history of critical path durations that the daemon keeps across builds.
+pub fn create_critical_path_history() -> Arc<dyn CriticalPathHistory> {
+    (CREATE_CRITICAL_PATH_HISTORY.get().unwrap())()
+}
+
 /// Everything we need to setup build signals when starting a command.
 #[derive(Clone, Dupe)]
 pub struct BuildSignalsInstaller {
@@ -40,12 +50,56 @@ pub struct BuildSignalsInstaller {
 pub trait BuildSignals: Send + Sync + 'static {
     fn top_level_target(&self, label: ConfiguredTargetLabel, artifacts: Vec<ArtifactGroup>);
 
+    /// We materialized an artifact that was requested by the build. `requester` is the top level
+    /// target that requested it, if any. `bytes` is the size of the artifact, if we know it.
     fn final_materialization(
         &self,
         artifact: BuildArtifact,
+        requester: Option<ConfiguredTargetLabel>,
         duration: NodeDuration,
         span_id: Option<SpanId>,
+        bytes: Option<u64>,
+    );
+
+    /// Another top level target requested an artifact that was already requested (and which we
+    /// only materialize once).
+    fn final_materialization_requested(
+        &self,
+        artifact: BuildArtifact,
+        requester: ConfiguredTargetLabel,
     );
+
+    /// The action for `key` failed, which lets us report what was slow on the way to the failure.
+    fn action_failed(&self, key: ActionKey);
+
+    /// Work that we didn't evaluate ourselves (e.g. a custom remote step) finished. It is part of
+    /// the critical path graph like any other node.
+    fn external_node(&self, node: ExternalNode);
+}
+
+/// Identifies a node reported by an integration through `BuildSignals::external_node`.
+#[derive(Clone, Dupe, Debug, PartialEq, Eq, Hash)]
+pub struct ExternalNodeKey {
+    /// Identifies the node. This must be unique within a build, and should be the same across
+    /// builds for the same work.
+    pub id: Arc<str>,
+    /// How we display the node.
+    pub display: Arc<str>,
+}
+
+/// What an external node depends on.
+#[derive(Clone, Dupe, Debug)]
+pub enum ExternalNodeDep {
+    Action(ActionKey),
+    External(ExternalNodeKey),
+}
+
+/// Work that an integration wants on the critical path.
+pub struct ExternalNode {
+    pub key: ExternalNodeKey,
+    pub duration: NodeDuration,
+    pub deps: Vec<ExternalNodeDep>,
+    pub span_id: Option<SpanId>,
 }
 
 pub trait SetBuildSignals {
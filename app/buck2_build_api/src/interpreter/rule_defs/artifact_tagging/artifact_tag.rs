@@ -22,6 +22,7 @@ use starlark::environment::Methods;
 use starlark::environment::MethodsBuilder;
 use starlark::environment::MethodsStatic;
 use starlark::typing::Ty;
+use starlark::values::none::NoneOr;
 use starlark::values::starlark_value;
 use starlark::values::starlark_value_as_type::StarlarkValueAsType;
 use starlark::values::Freeze;
@@ -53,20 +54,137 @@ use crate::interpreter::rule_defs::cmd_args::value_as::ValueAsCommandLineLike;
 )]
 pub struct ArtifactTag {
     #[freeze(identity)]
-    identity: Arc<()>,
+    identity: Arc<ArtifactTagIdentity>,
+}
+
+/// The state shared by all copies of a given ArtifactTag. Tags are compared by the address of this.
+#[derive(Debug, Allocative)]
+struct ArtifactTagIdentity {
+    /// When this tag was created relative to the other tags its analysis allocated, if it was
+    /// allocated by an analysis. Unlike the address of this, it is the same on every run.
+    sequence_number: Option<u64>,
+    /// The tags this tag was produced from via `merge`. These are never merged tags themselves:
+    /// merging flattens membership. Empty for tags that weren't produced by `merge`.
+    members: Vec<ArtifactTag>,
+}
+
+impl ArtifactTagIdentity {
+    fn new() -> Self {
+        Self {
+            sequence_number: None,
+            members: Vec::new(),
+        }
+    }
 }
 
 impl ArtifactTag {
     pub fn new() -> Self {
         Self {
-            identity: Arc::new(()),
+            identity: Arc::new(ArtifactTagIdentity::new()),
+        }
+    }
+
+    /// A new tag that was the `sequence_number`th (from 0) tag allocated by its analysis. It
+    /// displays as `ArtifactTag(#N)` rather than by address, so output that includes it is stable
+    /// across runs and can be compared to snapshots.
+    pub fn new_in_analysis(sequence_number: u64) -> Self {
+        Self {
+            identity: Arc::new(ArtifactTagIdentity {
+                sequence_number: Some(sequence_number),
+                ..ArtifactTagIdentity::new()
+            }),
+        }
+    }
+
+    /// A tag that shares `other`'s identity: it compares equal to `other`, and values tagged with
+    /// either are grouped together. This is the intended way to reuse a tag that was passed in
+    /// (e.g. by a macro's caller), rather than relying on it being captured implicitly.
+    pub fn alias_of(other: &ArtifactTag) -> ArtifactTag {
+        other.dupe()
+    }
+
+    /// A new tag whose inputs are the union of those of `a` and `b`: when gathering inputs for it,
+    /// values tagged with either (or with any tag they were merged from) are included. The merged
+    /// tag is still its own identity, so it doesn't compare equal to `a` or `b`. Merging a tag
+    /// with itself returns that tag.
+    pub fn merge(a: &ArtifactTag, b: &ArtifactTag) -> ArtifactTag {
+        if a == b {
+            return a.dupe();
+        }
+
+        let mut members = Vec::new();
+        for tag in [a, b] {
+            for member in tag.flattened_members() {
+                if !members.contains(member) {
+                    members.push(member.dupe());
+                }
+            }
+        }
+
+        Self {
+            identity: Arc::new(ArtifactTagIdentity {
+                members,
+                ..ArtifactTagIdentity::new()
+            }),
+        }
+    }
+
+    /// The tags this tag was merged from, or just this tag if it wasn't produced by `merge`.
+    fn flattened_members(&self) -> &[ArtifactTag] {
+        if self.identity.members.is_empty() {
+            std::slice::from_ref(self)
+        } else {
+            &self.identity.members
+        }
+    }
+
+    /// The tags this tag was produced from via `merge`, in the order they were merged. Empty if
+    /// this tag wasn't produced by `merge`.
+    pub fn members(&self) -> &[ArtifactTag] {
+        &self.identity.members
+    }
+
+    /// Whether inputs tagged with `other` should be gathered for this tag: that is the case if
+    /// `other` is this tag, or if everything `other` was merged from (or `other` itself, if it
+    /// wasn't merged) is among the tags this tag was merged from.
+    pub fn includes(&self, other: &ArtifactTag) -> bool {
+        self == other
+            || (!self.identity.members.is_empty()
+                && other
+                    .flattened_members()
+                    .iter()
+                    .all(|m| self.identity.members.contains(m)))
+    }
+}
+
+impl ArtifactTag {
+    /// Write what identifies this tag in its `Display`: its sequence number if it has one, the
+    /// tags it was merged from if they all do, and its address otherwise.
+    fn fmt_id(&self, w: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let members = &self.identity.members;
+        if let Some(sequence_number) = self.identity.sequence_number {
+            write!(w, "#{}", sequence_number)
+        } else if !members.is_empty()
+            && members.iter().all(|m| m.identity.sequence_number.is_some())
+        {
+            for (i, member) in members.iter().enumerate() {
+                if i > 0 {
+                    write!(w, "+")?;
+                }
+                member.fmt_id(w)?;
+            }
+            Ok(())
+        } else {
+            write!(w, "{:x}", Arc::as_ptr(&self.identity) as usize)
         }
     }
 }
 
 impl fmt::Display for ArtifactTag {
     fn fmt(&self, w: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(w, "ArtifactTag({:x})", Arc::as_ptr(&self.identity) as usize)
+        write!(w, "ArtifactTag(")?;
+        self.fmt_id(w)?;
+        write!(w, ")")
     }
 }
 
@@ -110,36 +228,177 @@ impl<'v> StarlarkValue<'v> for ArtifactTag {
     }
 }
 
+fn tag_of_value<'v>(value: Value<'v>) -> Option<&'v ArtifactTag> {
+    if let Some(tagged) = TaggedValue::from_value(value) {
+        Some(tagged.tag())
+    } else if let Some(tagged) = TaggedCommandLine::from_value(value) {
+        Some(tagged.inner().tag())
+    } else {
+        None
+    }
+}
+
+fn wrap_tagged_value<'v>(value: TaggedValue<'v>) -> Either<TaggedValue<'v>, TaggedCommandLine<'v>> {
+    if ValueAsCommandLineLike::unpack_value(*value.value()).is_some() {
+        Either::Right(TaggedCommandLine::new(value))
+    } else {
+        Either::Left(value)
+    }
+}
+
 #[starlark_module]
 fn artifact_tag_methods(_: &mut MethodsBuilder) {
+    /// Tag the artifacts in `inner`. If `primary` is set, the value is also marked as the primary
+    /// member of this tag's group (e.g. the dep file itself, as opposed to the outputs it
+    /// describes). An action can only consume one primary value per tag.
+    ///
+    /// If `order` is set, inputs gathered by tag (e.g. for a dep file) are sorted by it: lower
+    /// orders come first, and inputs without one come last, in the order they were visited.
     fn tag_artifacts<'v>(
         this: &ArtifactTag,
         inner: Value<'v>,
+        #[starlark(require = named, default = false)] primary: bool,
+        #[starlark(require = named, default = NoneOr::None)] order: NoneOr<i32>,
     ) -> anyhow::Result<Either<TaggedValue<'v>, TaggedCommandLine<'v>>> {
-        let value = TaggedValue::new(inner, this.dupe());
-
-        Ok(if ValueAsCommandLineLike::unpack_value(inner).is_some() {
-            Either::Right(TaggedCommandLine::new(value))
-        } else {
-            Either::Left(value)
-        })
+        let mut value = TaggedValue::new(inner, this.dupe()).with_order(order.into_option());
+        if primary {
+            value = value.into_primary();
+        }
+        Ok(wrap_tagged_value(value))
     }
 
+    /// Tag only the inputs in `inner`. `order` works as for `tag_artifacts`.
     fn tag_inputs<'v>(
         this: &ArtifactTag,
         inner: Value<'v>,
+        #[starlark(require = named, default = NoneOr::None)] order: NoneOr<i32>,
     ) -> anyhow::Result<Either<TaggedValue<'v>, TaggedCommandLine<'v>>> {
-        let value = TaggedValue::inputs_only(inner, this.dupe());
+        Ok(wrap_tagged_value(
+            TaggedValue::inputs_only(inner, this.dupe()).with_order(order.into_option()),
+        ))
+    }
 
-        Ok(if ValueAsCommandLineLike::unpack_value(inner).is_some() {
-            Either::Right(TaggedCommandLine::new(value))
+    /// Tag only the inputs in `inner`, and keep `inner` out of the command line it is added to,
+    /// like `cmd_args().hidden()`. This is for inputs that the tool consumes some other way (e.g.
+    /// via an environment variable or a manifest), but that still need tracking under this tag.
+    fn tag_and_hide<'v>(
+        this: &ArtifactTag,
+        inner: Value<'v>,
+    ) -> anyhow::Result<TaggedCommandLine<'v>> {
+        if ValueAsCommandLineLike::unpack_value(inner).is_none() {
+            return Err(anyhow::anyhow!(
+                "Only command line arguments can be hidden, got `{}`",
+                inner.get_type()
+            ));
+        }
+        Ok(TaggedCommandLine::new(
+            TaggedValue::inputs_only(inner, this.dupe()).into_hidden(),
+        ))
+    }
+
+    /// Tag only the inputs in `inner` whose extension is one of `extensions`, e.g.
+    /// `[".h", ".hpp"]` to track headers in a dep file. Extensions include the leading dot, like
+    /// `artifact.extension`, may have several dots (e.g. `".tar.gz"`), and are case-sensitive.
+    /// Use `""` to match inputs without an extension.
+    fn tag_inputs_by_extension<'v>(
+        this: &ArtifactTag,
+        inner: Value<'v>,
+        extensions: Vec<String>,
+    ) -> anyhow::Result<Either<TaggedValue<'v>, TaggedCommandLine<'v>>> {
+        Ok(wrap_tagged_value(TaggedValue::inputs_with_extensions(
+            inner,
+            this.dupe(),
+            extensions,
+        )))
+    }
+
+    /// Move a value that was already tagged under this tag instead, and record the tags it had
+    /// before on the value we return (see `TaggedValue::derives_from`). Values that aren't tagged
+    /// are tagged as if by `tag_artifacts`.
+    fn retag<'v>(
+        this: &ArtifactTag,
+        inner: Value<'v>,
+    ) -> anyhow::Result<Either<TaggedValue<'v>, TaggedCommandLine<'v>>> {
+        let (inner, previous, inputs_only, primary, extensions, order, hidden, earlier) =
+            if let Some(tagged) = TaggedValue::from_value(inner) {
+                (
+                    tagged.value().to_value(),
+                    tagged.tag().dupe(),
+                    tagged.is_inputs_only(),
+                    tagged.is_primary(),
+                    tagged.extensions().map(|e| e.to_vec()),
+                    tagged.order(),
+                    tagged.is_hidden(),
+                    tagged.predecessors().to_vec(),
+                )
+            } else if let Some(tagged) = TaggedCommandLine::from_value(inner) {
+                let tagged = tagged.inner();
+                (
+                    tagged.value().to_value(),
+                    tagged.tag().dupe(),
+                    tagged.is_inputs_only(),
+                    tagged.is_primary(),
+                    tagged.extensions().map(|e| e.to_vec()),
+                    tagged.order(),
+                    tagged.is_hidden(),
+                    tagged.predecessors().to_vec(),
+                )
+            } else {
+                return Ok(wrap_tagged_value(TaggedValue::new(inner, this.dupe())));
+            };
+
+        // Most recent first. A tag is never its own predecessor.
+        let mut predecessors = Vec::new();
+        for tag in std::iter::once(previous).chain(earlier) {
+            if tag != *this && !predecessors.contains(&tag) {
+                predecessors.push(tag);
+            }
+        }
+
+        let value = if let Some(extensions) = extensions {
+            TaggedValue::inputs_with_extensions(inner, this.dupe(), extensions)
+        } else if inputs_only {
+            TaggedValue::inputs_only(inner, this.dupe())
         } else {
-            Either::Left(value)
-        })
+            TaggedValue::new(inner, this.dupe())
+        };
+        let mut value = value.with_order(order).with_predecessors(predecessors);
+        if primary {
+            value = value.into_primary();
+        }
+        if hidden {
+            value = value.into_hidden();
+        }
+
+        Ok(wrap_tagged_value(value))
+    }
+
+    /// A tag equal to this one, which groups values together with it. See `ArtifactTag::alias_of`.
+    fn alias(this: &ArtifactTag) -> anyhow::Result<ArtifactTag> {
+        Ok(ArtifactTag::alias_of(this))
+    }
+
+    /// A new tag whose inputs are the union of those of this tag and `other`. See
+    /// `ArtifactTag::merge`.
+    fn merge<'v>(this: &ArtifactTag, other: &ArtifactTag) -> anyhow::Result<ArtifactTag> {
+        Ok(ArtifactTag::merge(this, other))
     }
 }
 
 #[starlark_module]
 pub(crate) fn register_artifact_tag(globals: &mut GlobalsBuilder) {
     const ArtifactTag: StarlarkValueAsType<ArtifactTag> = StarlarkValueAsType::new();
+
+    /// Whether `value` was tagged with an `ArtifactTag`.
+    fn is_tagged<'v>(value: Value<'v>) -> anyhow::Result<bool> {
+        Ok(tag_of_value(value).is_some())
+    }
+
+    /// The `ArtifactTag` that `value` was tagged with, or `None` if it isn't tagged.
+    fn tag_of<'v>(value: Value<'v>) -> anyhow::Result<NoneOr<ArtifactTag>> {
+        Ok(match tag_of_value(value) {
+            Some(tag) => NoneOr::Other(tag.dupe()),
+            None => NoneOr::None,
+        })
+    }
 }
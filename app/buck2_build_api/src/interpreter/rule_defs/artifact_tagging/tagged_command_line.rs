@@ -16,9 +16,11 @@ use starlark::values::starlark_value;
 use starlark::values::Demand;
 use starlark::values::Freeze;
 use starlark::values::NoSerialize;
+use starlark::values::StarlarkHasher;
 use starlark::values::StarlarkValue;
 use starlark::values::Trace;
 use starlark::values::UnpackValue;
+use starlark::values::Value;
 use starlark::values::ValueLike;
 
 use super::TaggedValueGen;
@@ -52,6 +54,10 @@ impl<V> TaggedCommandLineGen<V> {
     pub fn new(inner: TaggedValueGen<V>) -> Self {
         Self { inner }
     }
+
+    pub fn inner(&self) -> &TaggedValueGen<V> {
+        &self.inner
+    }
 }
 
 starlark_complex_value!(pub TaggedCommandLine);
@@ -65,6 +71,17 @@ where
         demand.provide_value::<&dyn CommandLineArgLike>(self);
     }
 
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match TaggedCommandLine::from_value(other) {
+            Some(other) => self.inner.equals_tagged(other.inner()),
+            None => Ok(false),
+        }
+    }
+
+    fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        self.inner.write_tagged_hash(hasher)
+    }
+
     fn get_type_starlark_repr() -> Ty {
         Ty::starlark_value::<Self>()
     }
@@ -76,13 +93,16 @@ impl<'v, V: ValueLike<'v>> CommandLineArgLike for TaggedCommandLineGen<V> {
         cli: &mut dyn CommandLineBuilder,
         context: &mut dyn CommandLineContext,
     ) -> anyhow::Result<()> {
+        if self.inner.is_hidden() {
+            return Ok(());
+        }
         ValueAsCommandLineLike::unpack_value_err(self.inner.value().to_value())?
             .0
             .add_to_command_line(cli, context)
     }
 
     fn visit_artifacts(&self, visitor: &mut dyn CommandLineArtifactVisitor) -> anyhow::Result<()> {
-        let mut visitor = self.inner.wrap_visitor(visitor);
+        let mut visitor = self.inner.wrap_visitor(visitor)?;
 
         ValueAsCommandLineLike::unpack_value_err(self.inner.value().to_value())?
             .0
@@ -7,6 +7,8 @@
  * of this source tree.
  */
 
+use std::hash::Hash;
+
 use allocative::Allocative;
 use derive_more::Display;
 use starlark::any::ProvidesStaticType;
@@ -15,6 +17,7 @@ use starlark::typing::Ty;
 use starlark::values::starlark_value;
 use starlark::values::Freeze;
 use starlark::values::NoSerialize;
+use starlark::values::StarlarkHasher;
 use starlark::values::StarlarkValue;
 use starlark::values::Trace;
 use starlark::values::Value;
@@ -44,6 +47,18 @@ pub struct TaggedValueGen<V> {
     inner: V,
     tag: ArtifactTag,
     inputs_only: bool,
+    /// Whether this is the primary member of its tag's group.
+    primary: bool,
+    /// If set, only inputs with one of these extensions get tagged. See `TaggedVisitor`.
+    extensions: Option<Vec<String>>,
+    /// Where the inputs we tag go among the other inputs with this tag, when gathering them in
+    /// order (e.g. for a dep file). Lower comes first, and inputs without one come last.
+    order: Option<i32>,
+    /// Whether the value contributes nothing to the command line it is in. Its inputs are still
+    /// visited (and tagged), like those of `cmd_args().hidden()`.
+    hidden: bool,
+    /// The tags this value had before `retag` moved it under `tag`, most recent first.
+    predecessors: Vec<ArtifactTag>,
 }
 
 impl<'v> TaggedValue<'v> {
@@ -52,6 +67,11 @@ impl<'v> TaggedValue<'v> {
             inner,
             tag,
             inputs_only: false,
+            primary: false,
+            extensions: None,
+            order: None,
+            hidden: false,
+            predecessors: Vec::new(),
         }
     }
 
@@ -60,8 +80,56 @@ impl<'v> TaggedValue<'v> {
             inner,
             tag,
             inputs_only: true,
+            primary: false,
+            extensions: None,
+            order: None,
+            hidden: false,
+            predecessors: Vec::new(),
         }
     }
+
+    /// Like `inputs_only`, but only tag inputs whose extension is one of `extensions`.
+    pub fn inputs_with_extensions(
+        inner: Value<'v>,
+        tag: ArtifactTag,
+        extensions: Vec<String>,
+    ) -> Self {
+        Self {
+            inner,
+            tag,
+            inputs_only: true,
+            primary: false,
+            extensions: Some(extensions),
+            order: None,
+            hidden: false,
+            predecessors: Vec::new(),
+        }
+    }
+
+    /// Give the inputs this value tags an order hint. See `TaggedVisitor::with_order`.
+    pub fn with_order(mut self, order: Option<i32>) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Keep this value out of the command line it is in, while still reporting its inputs.
+    pub fn into_hidden(mut self) -> Self {
+        self.hidden = true;
+        self
+    }
+
+    /// Record the tags this value had before it was re-tagged, most recent first.
+    pub fn with_predecessors(mut self, predecessors: Vec<ArtifactTag>) -> Self {
+        self.predecessors = predecessors;
+        self
+    }
+
+    /// Mark this value as the primary member of its tag's group. Visitors are told about it via
+    /// `CommandLineArtifactVisitor::visit_primary` when the value is visited.
+    pub fn into_primary(mut self) -> Self {
+        self.primary = true;
+        self
+    }
 }
 
 starlark_complex_value!(pub TaggedValue);
@@ -71,20 +139,100 @@ impl<'v, V: ValueLike<'v> + 'v> StarlarkValue<'v> for TaggedValueGen<V>
 where
     Self: ProvidesStaticType<'v>,
 {
+    fn equals(&self, other: Value<'v>) -> anyhow::Result<bool> {
+        match TaggedValue::from_value(other) {
+            Some(other) => self.equals_tagged(other),
+            None => Ok(false),
+        }
+    }
+
+    fn write_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        self.write_tagged_hash(hasher)
+    }
+
     fn get_type_starlark_repr() -> Ty {
         Ty::starlark_value::<Self>()
     }
 }
 
+impl<'v, V: ValueLike<'v>> TaggedValueGen<V> {
+    /// Tagged values are equal if they tag equal values the same way: with the same tag, both
+    /// inputs only or not, both primary or not, restricted to the same extensions, with the same
+    /// order hint, and both hidden or not.
+    pub(crate) fn equals_tagged(&self, other: &TaggedValue<'v>) -> anyhow::Result<bool> {
+        Ok(self.tag == other.tag
+            && self.inputs_only == other.inputs_only
+            && self.primary == other.primary
+            && self.extensions == other.extensions
+            && self.order == other.order
+            && self.hidden == other.hidden
+            && self.inner.to_value().equals(other.inner)?)
+    }
+
+    /// Combine the tag's identity with the inner value's hash, so that a tagged value doesn't hash
+    /// like the value it wraps.
+    pub(crate) fn write_tagged_hash(&self, hasher: &mut StarlarkHasher) -> anyhow::Result<()> {
+        self.tag.hash(hasher);
+        self.inputs_only.hash(hasher);
+        self.primary.hash(hasher);
+        self.extensions.hash(hasher);
+        self.order.hash(hasher);
+        self.hidden.hash(hasher);
+        self.inner.to_value().write_hash(hasher)
+    }
+}
+
 impl<V> TaggedValueGen<V> {
     pub fn value(&self) -> &V {
         &self.inner
     }
 
+    pub fn tag(&self) -> &ArtifactTag {
+        &self.tag
+    }
+
+    pub fn is_inputs_only(&self) -> bool {
+        self.inputs_only
+    }
+
+    pub fn is_primary(&self) -> bool {
+        self.primary
+    }
+
+    pub fn extensions(&self) -> Option<&[String]> {
+        self.extensions.as_deref()
+    }
+
+    pub fn order(&self) -> Option<i32> {
+        self.order
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+
+    /// The tags this value had before `retag` moved it under its tag, most recent first.
+    pub fn predecessors(&self) -> &[ArtifactTag] {
+        &self.predecessors
+    }
+
+    /// Whether this value is tagged with `tag`, or was before being (transitively) re-tagged.
+    pub fn derives_from(&self, tag: &ArtifactTag) -> bool {
+        self.tag == *tag || self.predecessors.contains(tag)
+    }
+
     pub fn wrap_visitor<'a, 'b>(
         &'a self,
         visitor: &'b mut dyn CommandLineArtifactVisitor,
-    ) -> TaggedVisitor<'a, 'b> {
-        TaggedVisitor::wrap(&self.tag, self.inputs_only, visitor)
+    ) -> anyhow::Result<TaggedVisitor<'a, 'b>> {
+        if self.primary {
+            visitor.visit_primary(&self.tag)?;
+        }
+        let visitor =
+            TaggedVisitor::wrap(&self.tag, self.inputs_only, visitor).with_order(self.order);
+        Ok(match &self.extensions {
+            Some(extensions) => visitor.with_extensions(extensions),
+            None => visitor,
+        })
     }
 }
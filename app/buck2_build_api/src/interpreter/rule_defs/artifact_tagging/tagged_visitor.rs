@@ -8,6 +8,7 @@
  */
 
 use buck2_artifact::artifact::artifact_type::OutputArtifact;
+use buck2_core::fs::paths::file_name::FileName;
 
 use super::ArtifactTag;
 use crate::artifact_groups::ArtifactGroup;
@@ -18,6 +19,8 @@ pub struct TaggedVisitor<'a, 'b> {
     inner: &'b mut dyn CommandLineArtifactVisitor,
     tag: &'a ArtifactTag,
     inputs_only: bool,
+    extensions: Option<&'a [String]>,
+    order: Option<i32>,
 }
 
 impl<'a, 'b> TaggedVisitor<'a, 'b> {
@@ -30,15 +33,78 @@ impl<'a, 'b> TaggedVisitor<'a, 'b> {
             inner,
             tag,
             inputs_only,
+            extensions: None,
+            order: None,
         }
     }
+
+    /// Pass `order` along with the inputs we tag, so that visitors gathering inputs by tag can
+    /// sort them: lower orders come first, and inputs without one come last. Inputs we don't tag
+    /// keep whatever order they already had.
+    pub fn with_order(mut self, order: Option<i32>) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Only tag inputs whose file name has one of `extensions`, which include the leading dot
+    /// (like `artifact.extension`) and may have several (e.g. `.tar.gz`). Extensions are
+    /// case-sensitive, and `""` matches inputs with no extension. Inputs we can't see the path of (e.g. transitive set projections) never match.
+    /// Other inputs are passed through with whatever tag they already had.
+    pub fn with_extensions(mut self, extensions: &'a [String]) -> Self {
+        self.extensions = Some(extensions);
+        self
+    }
+
+    fn should_tag(&self, input: &ArtifactGroup) -> bool {
+        let extensions = match self.extensions {
+            Some(extensions) => extensions,
+            None => return true,
+        };
+
+        let artifact = match input {
+            ArtifactGroup::Artifact(artifact) => artifact,
+            ArtifactGroup::TransitiveSetProjection(..) | ArtifactGroup::Promise(..) => {
+                return false;
+            }
+        };
+
+        artifact
+            .get_path()
+            .with_filename(|file_name| has_extension(file_name.ok(), extensions))
+    }
+}
+
+fn has_extension(file_name: Option<&FileName>, extensions: &[String]) -> bool {
+    extensions.iter().any(|e| {
+        if e.is_empty() {
+            file_name.and_then(|f| f.extension()).is_none()
+        } else {
+            // Compare the whole suffix rather than the last extension, so that extensions with
+            // several dots (e.g. `.tar.gz`) match too.
+            file_name.map_or(false, |f| f.as_str().ends_with(e.as_str()))
+        }
+    })
 }
 
 impl<'a, 'b> CommandLineArtifactVisitor for TaggedVisitor<'a, 'b> {
     /// Ignore the inner tag, set our own. Nesting input groups generally isn't a great idea, but
     /// we can't statically prevent it.
-    fn visit_input(&mut self, input: ArtifactGroup, _tag: Option<&ArtifactTag>) {
-        self.inner.visit_input(input, Some(self.tag))
+    fn visit_input(&mut self, input: ArtifactGroup, tag: Option<&ArtifactTag>) {
+        self.visit_input_with_order(input, tag, None)
+    }
+
+    fn visit_input_with_order(
+        &mut self,
+        input: ArtifactGroup,
+        tag: Option<&ArtifactTag>,
+        order: Option<i32>,
+    ) {
+        if self.should_tag(&input) {
+            self.inner
+                .visit_input_with_order(input, Some(self.tag), self.order)
+        } else {
+            self.inner.visit_input_with_order(input, tag, order)
+        }
     }
 
     /// Same as above, no nesting here.
@@ -50,4 +116,9 @@ impl<'a, 'b> CommandLineArtifactVisitor for TaggedVisitor<'a, 'b> {
         };
         self.inner.visit_output(artifact, tag)
     }
+
+    /// Same as above: a primary nested in our value is a primary of our tag's group.
+    fn visit_primary(&mut self, _tag: &ArtifactTag) -> anyhow::Result<()> {
+        self.inner.visit_primary(self.tag)
+    }
 }
@@ -36,8 +36,25 @@ use crate::interpreter::rule_defs::resolved_macro::ResolvedMacro;
 pub trait CommandLineArtifactVisitor {
     fn visit_input(&mut self, input: ArtifactGroup, tag: Option<&ArtifactTag>);
 
+    /// Like `visit_input`, with a hint of where the input goes among the other inputs with its
+    /// tag (see `TaggedVisitor::with_order`). Only visitors that gather inputs in order care.
+    fn visit_input_with_order(
+        &mut self,
+        input: ArtifactGroup,
+        tag: Option<&ArtifactTag>,
+        _order: Option<i32>,
+    ) {
+        self.visit_input(input, tag)
+    }
+
     fn visit_output(&mut self, artifact: OutputArtifact, tag: Option<&ArtifactTag>);
 
+    /// Called when a value marked as the primary member of `tag`'s group is visited, before its
+    /// artifacts are. Visitors that care can use this to reject several primaries for one tag.
+    fn visit_primary(&mut self, _tag: &ArtifactTag) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// Those two functions can be used to keep track of recursion when visiting artifacts.
     fn push_frame(&mut self) -> anyhow::Result<()> {
         Ok(())
@@ -15,6 +15,8 @@ use buck2_build_api::actions::impls::json::SerializeValue;
 use buck2_build_api::artifact_groups::ArtifactGroup;
 use buck2_build_api::interpreter::rule_defs::artifact::starlark_artifact_like::ValueAsArtifactLike;
 use buck2_build_api::interpreter::rule_defs::artifact_tagging::ArtifactTag;
+use buck2_build_api::interpreter::rule_defs::artifact_tagging::TaggedCommandLine;
+use buck2_build_api::interpreter::rule_defs::artifact_tagging::TaggedValue;
 use buck2_build_api::interpreter::rule_defs::cmd_args::CommandLineArtifactVisitor;
 use buck2_interpreter_for_build::interpreter::testing::Tester;
 use dupe::Dupe;
@@ -26,55 +28,67 @@ use starlark::values::Value;
 use crate::interpreter::rule_defs::artifact::testing::artifactory;
 use crate::interpreter::rule_defs::artifact_tagging::testing::artifact_tag_factory;
 
-#[test]
-fn test_tagging() -> anyhow::Result<()> {
-    struct AssertVisitor {
-        tag: ArtifactTag,
-        artifact: Artifact,
+struct AssertVisitor {
+    tag: ArtifactTag,
+    artifact: Artifact,
+}
+
+impl CommandLineArtifactVisitor for AssertVisitor {
+    fn visit_input(&mut self, input: ArtifactGroup, tag: Option<&ArtifactTag>) {
+        assert_eq!(tag, Some(&self.tag));
+        assert_eq!(input, ArtifactGroup::Artifact(self.artifact.dupe()));
+    }
+
+    fn visit_output(&mut self, _artifact: OutputArtifact, _tag: Option<&ArtifactTag>) {}
+}
+
+#[starlark_module]
+fn assertions(builder: &mut GlobalsBuilder) {
+    fn check_artifact_is_tagged<'v>(
+        tagged: Value<'v>,
+        tag: Value<'v>,
+        artifact: ValueAsArtifactLike<'v>,
+    ) -> anyhow::Result<Value<'v>> {
+        let tag = ArtifactTag::from_value(tag).context("Invalid tag")?.dupe();
+
+        let artifact = artifact
+            .0
+            .get_bound_artifact()
+            .context("Not a bound artifact")?
+            .dupe();
+
+        visit_json_artifacts(tagged, &mut AssertVisitor { tag, artifact })?;
+        Ok(Value::new_none())
     }
 
-    impl CommandLineArtifactVisitor for AssertVisitor {
-        fn visit_input(&mut self, input: ArtifactGroup, tag: Option<&ArtifactTag>) {
-            assert_eq!(tag, Some(&self.tag));
-            assert_eq!(input, ArtifactGroup::Artifact(self.artifact.dupe()));
-        }
+    fn check_passthrough<'v>(tagged: Value<'v>, value: Value<'v>) -> anyhow::Result<Value<'v>> {
+        let json1 = serde_json::to_string(&SerializeValue {
+            value: tagged,
+            fs: None,
+        })?;
+
+        let json2 = serde_json::to_string(&SerializeValue { value, fs: None })?;
 
-        fn visit_output(&mut self, _artifact: OutputArtifact, _tag: Option<&ArtifactTag>) {}
+        assert_eq!(json1, json2);
+
+        Ok(Value::new_none())
     }
 
-    #[starlark_module]
-    fn assertions(builder: &mut GlobalsBuilder) {
-        fn check_artifact_is_tagged<'v>(
-            tagged: Value<'v>,
-            tag: Value<'v>,
-            artifact: ValueAsArtifactLike<'v>,
-        ) -> anyhow::Result<Value<'v>> {
-            let tag = ArtifactTag::from_value(tag).context("Invalid tag")?.dupe();
-
-            let artifact = artifact
-                .0
-                .get_bound_artifact()
-                .context("Not a bound artifact")?
-                .dupe();
-
-            visit_json_artifacts(tagged, &mut AssertVisitor { tag, artifact })?;
-            Ok(Value::new_none())
-        }
-
-        fn check_passthrough<'v>(tagged: Value<'v>, value: Value<'v>) -> anyhow::Result<Value<'v>> {
-            let json1 = serde_json::to_string(&SerializeValue {
-                value: tagged,
-                fs: None,
-            })?;
-
-            let json2 = serde_json::to_string(&SerializeValue { value, fs: None })?;
-
-            assert_eq!(json1, json2);
-
-            Ok(Value::new_none())
-        }
+    fn derives_from<'v>(tagged: Value<'v>, tag: Value<'v>) -> anyhow::Result<bool> {
+        let tag = ArtifactTag::from_value(tag).context("Invalid tag")?;
+        let tagged = match TaggedValue::from_value(tagged) {
+            Some(tagged) => tagged,
+            None => TaggedCommandLine::from_value(tagged)
+                .context("Not a tagged value")?
+                .inner(),
+        };
+
+        Ok(tagged.derives_from(tag))
     }
+}
 
+#[test]
+fn test_tagging() -> anyhow::Result<()> {
     let mut tester = Tester::new()?;
     tester.additional_globals(artifact_tag_factory);
     tester.additional_globals(artifactory);
@@ -94,3 +108,70 @@ fn test_tagging() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_retagging() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+    tester.additional_globals(artifactory);
+    tester.additional_globals(assertions);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+            t2 = make_tag()
+            t3 = make_tag()
+            a1 = source_artifact("foo", "bar")
+            v1 = {"foo": "bar"}
+
+            check_artifact_is_tagged(t2.retag(t1.tag_artifacts(a1)), t2, a1)
+            check_artifact_is_tagged(t3.retag(t2.retag(t1.tag_artifacts(a1))), t3, a1)
+            check_passthrough(t2.retag(t1.tag_artifacts(v1)), v1)
+
+            # Provenance is recorded on the re-tagged value, not on the tags.
+            retagged = t3.retag(t2.retag(t1.tag_artifacts(a1)))
+            assert_true(derives_from(retagged, t1))
+            assert_true(derives_from(retagged, t2))
+            assert_true(not derives_from(t1.tag_artifacts(a1), t2))
+            assert_true(not derives_from(t3.tag_artifacts(a1), t1))
+
+            # Untagged values are tagged as they would be by tag_artifacts.
+            check_artifact_is_tagged(t1.retag(a1), t1, a1)
+        "#
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_tag_introspection() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+    tester.additional_globals(artifactory);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+            a1 = source_artifact("foo", "bar")
+            v1 = {"foo": "bar"}
+
+            # A tagged command line.
+            assert_true(is_tagged(t1.tag_artifacts(a1)))
+            assert_eq(t1, tag_of(t1.tag_artifacts(a1)))
+
+            # A tagged value.
+            assert_true(is_tagged(t1.tag_inputs(v1)))
+            assert_eq(t1, tag_of(t1.tag_inputs(v1)))
+
+            # Plain values.
+            assert_false(is_tagged(a1))
+            assert_false(is_tagged(v1))
+            assert_eq(None, tag_of(a1))
+            assert_eq(None, tag_of(v1))
+        "#
+    ))?;
+
+    Ok(())
+}
@@ -23,6 +23,25 @@ fn test_artifact_tag_eq() {
     assert_ne!(t1, t2);
 }
 
+#[test]
+fn test_artifact_tag_display_sequence_numbers() {
+    let t0 = ArtifactTag::new_in_analysis(0);
+    let t1 = ArtifactTag::new_in_analysis(1);
+    assert_eq!(t0.to_string(), "ArtifactTag(#0)");
+    assert_eq!(t0.dupe().to_string(), "ArtifactTag(#0)");
+    assert_eq!(
+        ArtifactTag::merge(&t0, &t1).to_string(),
+        "ArtifactTag(#0+#1)"
+    );
+
+    // Tags that weren't allocated by an analysis display by address.
+    let unnumbered = ArtifactTag::new();
+    assert!(!unnumbered.to_string().contains('#'));
+    assert!(!ArtifactTag::merge(&t0, &unnumbered)
+        .to_string()
+        .contains('#'));
+}
+
 #[test]
 fn test_artifact_tag_starlark_eq() -> anyhow::Result<()> {
     let mut tester = Tester::new()?;
@@ -41,3 +60,227 @@ fn test_artifact_tag_starlark_eq() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_artifact_tag_alias() -> anyhow::Result<()> {
+    let t1 = ArtifactTag::new();
+    let alias = ArtifactTag::alias_of(&t1);
+    assert_eq!(alias, t1);
+    assert_ne!(alias, ArtifactTag::new());
+
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+            t2 = make_tag()
+
+            alias = t1.alias()
+            assert_eq(alias, t1)
+            assert_ne(alias, t2)
+            assert_eq(alias.tag_artifacts("foo"), t1.tag_artifacts("foo"))
+            assert_eq(tag_of(alias.tag_inputs("foo")), t1)
+        "#
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_artifact_tag_primary() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+            t2 = make_tag()
+
+            t1.tag_artifacts("foo", primary = True)
+            t1.tag_artifacts("bar")
+            t2.tag_artifacts("baz", primary = True)
+        "#
+    ))?;
+
+    // Duplicate primaries are only rejected when an action consumes them together.
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+
+            t1.tag_artifacts("foo", primary = True)
+            t1.tag_artifacts("bar", primary = True)
+        "#
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_tagged_value_hash() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    // There are no sets in Starlark, so rules deduplicate values using dict keys.
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+            t2 = make_tag()
+
+            # A tagged command line.
+            assert_eq(t1.tag_artifacts("foo"), t1.tag_artifacts("foo"))
+            assert_ne(t1.tag_artifacts("foo"), t1.tag_artifacts("bar"))
+            assert_ne(t1.tag_artifacts("foo"), t2.tag_artifacts("foo"))
+            assert_ne(t1.tag_artifacts("foo"), "foo")
+
+            # A tagged value that isn't a command line.
+            assert_eq(t1.tag_artifacts(1), t1.tag_artifacts(1))
+            assert_ne(t1.tag_artifacts(1), t2.tag_artifacts(1))
+            assert_ne(t1.tag_artifacts(1), 1)
+
+            unique = {}
+            for v in [
+                "foo",
+                t1.tag_artifacts("foo"),
+                t1.tag_artifacts("foo"),
+                t2.tag_artifacts("foo"),
+                1,
+                t1.tag_artifacts(1),
+                t1.tag_artifacts(1),
+            ]:
+                unique[v] = None
+            assert_eq(len(unique), 5)
+            assert_eq(unique[t1.tag_artifacts("foo")], None)
+        "#
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_tagged_value_eq_expansion() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    // Values that only differ in how they expand are distinct, including as dict keys.
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+
+            assert_ne(t1.tag_artifacts("foo"), t1.tag_inputs("foo"))
+            assert_ne(t1.tag_artifacts("foo"), t1.tag_artifacts("foo", primary = True))
+
+            unique = {}
+            for v in [t1.tag_artifacts("foo"), t1.tag_inputs("foo"), t1.tag_inputs("foo")]:
+                unique[v] = None
+            assert_eq(len(unique), 2)
+        "#
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_tag_inputs_by_extension() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+
+            tagged = t1.tag_inputs_by_extension("foo", [".h", ".hpp"])
+            assert_true(is_tagged(tagged))
+            assert_eq(tag_of(tagged), t1)
+            assert_eq(tagged, t1.tag_inputs_by_extension("foo", [".h", ".hpp"]))
+            assert_ne(tagged, t1.tag_inputs_by_extension("foo", [".cpp"]))
+            assert_ne(tagged, t1.tag_inputs("foo"))
+        "#
+    ))?;
+
+    Ok(())
+}
+
+#[test]
+fn test_artifact_tag_merge() {
+    let t1 = ArtifactTag::new();
+    let t2 = ArtifactTag::new();
+    let t3 = ArtifactTag::new();
+
+    let merged = ArtifactTag::merge(&t1, &t2);
+    assert_ne!(merged, t1);
+    assert_ne!(merged, t2);
+    assert_eq!(merged.members(), &[t1.dupe(), t2.dupe()]);
+    assert!(merged.includes(&merged));
+    assert!(merged.includes(&t1));
+    assert!(merged.includes(&t2));
+    assert!(!merged.includes(&t3));
+    assert!(!t1.includes(&merged));
+
+    // Each merge produces a new identity.
+    assert_ne!(merged, ArtifactTag::merge(&t1, &t2));
+}
+
+#[test]
+fn test_artifact_tag_merge_self() {
+    let t1 = ArtifactTag::new();
+    assert_eq!(ArtifactTag::merge(&t1, &t1), t1);
+    assert!(t1.members().is_empty());
+
+    let merged = ArtifactTag::merge(&t1, &ArtifactTag::new());
+    assert_eq!(ArtifactTag::merge(&merged, &merged), merged);
+}
+
+#[test]
+fn test_artifact_tag_merge_transitive() {
+    let t1 = ArtifactTag::new();
+    let t2 = ArtifactTag::new();
+    let t3 = ArtifactTag::new();
+
+    let m12 = ArtifactTag::merge(&t1, &t2);
+    let m123 = ArtifactTag::merge(&m12, &t3);
+    assert_eq!(m123.members(), &[t1.dupe(), t2.dupe(), t3.dupe()]);
+    assert!(m123.includes(&t1));
+    assert!(m123.includes(&t3));
+    // The intermediate merged tag is flattened away, but inputs tagged with it are still
+    // included.
+    assert!(m123.includes(&m12));
+    assert!(!m12.includes(&m123));
+
+    // Overlapping members are only recorded once.
+    let m23 = ArtifactTag::merge(&t2, &t3);
+    let all = ArtifactTag::merge(&m12, &m23);
+    assert_eq!(all.members(), &[t1.dupe(), t2.dupe(), t3.dupe()]);
+}
+
+#[test]
+fn test_artifact_tag_starlark_merge() -> anyhow::Result<()> {
+    let mut tester = Tester::new()?;
+    tester.additional_globals(artifact_tag_factory);
+
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t1 = make_tag()
+            t2 = make_tag()
+
+            merged = t1.merge(t2)
+            assert_ne(merged, t1)
+            assert_ne(merged, t2)
+            assert_eq(merged, merged)
+            assert_eq(t1.merge(t1), t1)
+            assert_eq(tag_of(merged.tag_artifacts("foo")), merged)
+        "#
+    ))?;
+
+    Ok(())
+}
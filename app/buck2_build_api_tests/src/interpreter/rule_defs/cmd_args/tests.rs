@@ -24,6 +24,7 @@ use starlark::values::UnpackValue;
 use starlark::values::Value;
 
 use crate::interpreter::rule_defs::artifact::testing::artifactory;
+use crate::interpreter::rule_defs::artifact_tagging::testing::artifact_tag_factory;
 use crate::interpreter::rule_defs::cmd_args::testing;
 
 #[starlark_module]
@@ -465,6 +466,40 @@ fn test_inputs_outputs() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_tag_and_hide() -> anyhow::Result<()> {
+    let mut tester = tester()?;
+    tester.additional_globals(artifact_tag_factory);
+    tester.run_starlark_bzl_test(indoc!(
+        r#"
+        def test():
+            t = make_tag()
+            artifact = source_artifact("foo", "bar/baz.h")
+
+            hidden = t.tag_and_hide(artifact)
+            assert_eq(t, tag_of(hidden))
+            assert_ne(hidden, t.tag_inputs(artifact))
+
+            cli = cmd_args("x", hidden)
+            assert_eq(["x"], get_args(cli))
+            assert_eq(make_inputs([artifact]), cli.inputs)
+        "#
+    ))?;
+
+    let mut tester = tester()?;
+    tester.additional_globals(artifact_tag_factory);
+    tester.run_starlark_bzl_test_expecting_error(
+        indoc!(
+            r#"
+            def test():
+                make_tag().tag_and_hide(1)
+            "#
+        ),
+        "Only command line arguments can be hidden",
+    );
+    Ok(())
+}
+
 #[test]
 fn test_ignore_artifacts() -> anyhow::Result<()> {
     let mut tester = tester()?;
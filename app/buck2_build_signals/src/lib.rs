@@ -10,20 +10,26 @@
 #![feature(error_generic_member_access)]
 
 use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
 use std::time::Duration;
 
 use allocative::Allocative;
 use anyhow::Context as _;
 use async_trait::async_trait;
 use buck2_core::fs::paths::file_name::FileNameBuf;
+use buck2_core::pattern::pattern_type::TargetPatternExtra;
+use buck2_core::pattern::ParsedPattern;
 use buck2_core::soft_error;
 use buck2_events::dispatch::EventDispatcher;
 use dice::UserComputationData;
 use dupe::Dupe;
 use futures::future::Future;
 
-#[derive(Copy, Clone, Dupe)]
+#[derive(Copy, Clone, Dupe, Allocative)]
 pub struct NodeDuration {
     /// The amount of time for this node that corresponds to something the user might be able to
     /// improve. We should better break this down.
@@ -33,11 +39,19 @@ pub struct NodeDuration {
 }
 
 impl NodeDuration {
-    /// Returns the duration we are using in our critical path calculation. This doesn't really
-    /// *need* to be a function but right now we use user and want to switch to total so it's
-    /// easier to do that if this is in a single function.
+    /// Returns the duration we are using in our critical path calculation by default. Use
+    /// `duration_for` when the policy is configurable.
     pub fn critical_path_duration(&self) -> Duration {
-        self.total
+        self.duration_for(DurationPolicy::default())
+    }
+
+    /// Returns the duration `policy` says to use in our critical path calculation.
+    pub fn duration_for(&self, policy: DurationPolicy) -> Duration {
+        match policy {
+            DurationPolicy::User => self.user,
+            DurationPolicy::Total => self.total,
+            DurationPolicy::Max => std::cmp::max(self.user, self.total),
+        }
     }
 
     pub fn zero() -> Self {
@@ -48,12 +62,79 @@ impl NodeDuration {
     }
 }
 
+/// Why an action that ran wasn't served from a cache, as far as we can tell.
+#[derive(Copy, Clone, Dupe, Debug, PartialEq, Eq, Allocative)]
+pub enum CacheMissReason {
+    /// The action can't be served from the action cache, e.g. because it has to run locally.
+    NotCacheable,
+    /// The action could have been served from the action cache, but it wasn't in there. We can't
+    /// tell whether that is because its command or inputs changed or because it was evicted.
+    NotInCache,
+}
+
+impl CacheMissReason {
+    pub fn to_proto(self) -> buck2_data::CacheMissReason {
+        match self {
+            Self::NotCacheable => buck2_data::CacheMissReason::NotCacheable,
+            Self::NotInCache => buck2_data::CacheMissReason::NotInCache,
+        }
+    }
+}
+
+/// Which of a node's durations (see `NodeDuration`) we use to compute the critical path.
+#[derive(Copy, Clone, Dupe, Debug, Default, PartialEq, Eq, Allocative)]
+pub enum DurationPolicy {
+    /// Only the time the user might be able to improve.
+    User,
+    #[default]
+    Total,
+    /// Whichever is larger. The total should never be less than the user duration, but this
+    /// guards against nodes that misreport their durations.
+    Max,
+}
+
+impl FromStr for DurationPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Self::User),
+            "total" => Ok(Self::Total),
+            "max" => Ok(Self::Max),
+            _ => Err(anyhow::anyhow!("Invalid duration policy: `{}`", s)),
+        }
+    }
+}
+
+impl DurationPolicy {
+    /// Whether this policy accounts for the total duration of nodes (as opposed to only the part
+    /// the user might be able to improve).
+    pub fn uses_total_duration(self) -> bool {
+        match self {
+            Self::User => false,
+            Self::Total | Self::Max => true,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Dupe, derive_more::Display, Allocative)]
 pub enum CriticalPathBackendName {
     #[display(fmt = "longest-path-graph")]
     LongestPathGraph,
     #[display(fmt = "default")]
     Default,
+    /// Runs both of the above and cross-checks their results. This is meant for debugging the
+    /// backends themselves.
+    #[display(fmt = "composite")]
+    Composite,
+    /// Like `LongestPathGraph`, but finds the chain of nodes that moved the most data to and from
+    /// RE instead of the one that took the longest.
+    #[display(fmt = "transferred-bytes")]
+    TransferredBytes,
+    /// Like `LongestPathGraph`, but finds the chain of actions that produced the most output
+    /// instead of the one that took the longest.
+    #[display(fmt = "output-bytes")]
+    OutputBytes,
 }
 
 impl FromStr for CriticalPathBackendName {
@@ -68,6 +149,18 @@ impl FromStr for CriticalPathBackendName {
             return Ok(Self::Default);
         }
 
+        if s == "composite" {
+            return Ok(Self::Composite);
+        }
+
+        if s == "transferred-bytes" {
+            return Ok(Self::TransferredBytes);
+        }
+
+        if s == "output-bytes" {
+            return Ok(Self::OutputBytes);
+        }
+
         Err(anyhow::anyhow!("Invalid backend name: `{}`", s))
     }
 }
@@ -76,6 +169,368 @@ pub struct BuildSignalsContext {
     pub command_name: String,
     pub metadata: HashMap<String, String>,
     pub isolation_prefix: FileNameBuf,
+    pub options: CriticalPathOptions,
+    /// Durations of critical path nodes in previous builds, used to flag nodes that were
+    /// unusually slow in this one.
+    pub critical_path_history: Option<Arc<dyn CriticalPathHistory>>,
+    /// If set, we write a line of JSON to this for each evaluation we receive, as we receive it,
+    /// for consumers that want to follow the build live. Writes happen off the build's path, so a
+    /// slow consumer only delays how soon we compute the critical path.
+    pub evaluation_stream: Option<Box<dyn Write + Send>>,
+    /// Given a short summary of the critical path once we computed it.
+    pub summary_observer: Option<Arc<dyn CriticalPathSummaryObserver>>,
+}
+
+/// Lets callers show users why their build was slow right where they'll look, e.g. alongside a
+/// command's error. The summary is a few lines of text listing the nodes on the critical path
+/// that are most worth improving.
+pub trait CriticalPathSummaryObserver: Send + Sync {
+    fn critical_path_summary(&self, summary: String);
+}
+
+/// Remembers how long critical path nodes took across builds (typically for as long as the daemon
+/// lives), so that a slow node can be reported as an anomaly rather than as a chronically slow
+/// step.
+pub trait CriticalPathHistory: Send + Sync {
+    /// Record that `node` (the stable id of a node on the critical path, which is the same across
+    /// builds) took `duration` in this build. Returns how that compares to previous builds if it is an outlier.
+    fn record(&self, node: &str, duration: Duration) -> Option<DurationAnomaly>;
+
+    /// Record the stable ids of the nodes on this build's critical path, for a build of `targets`
+    /// (which identifies the top level targets that were requested). Returns how similar it is to
+    /// the critical path of the previous build of the same targets, from 0 (no nodes in common) to
+    /// 1 (the same nodes), if there was one.
+    fn record_critical_path(&self, _targets: &str, _nodes: &[String]) -> Option<f32> {
+        None
+    }
+
+    /// Record this build's critical path, as the stable ids of its nodes and their durations, so
+    /// that we can later tell which nodes are consistently on it across builds.
+    fn record_critical_path_durations(&self, _path: &[(String, Duration)]) {}
+}
+
+/// A node that took much longer than it usually does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DurationAnomaly {
+    pub duration: Duration,
+    /// The mean duration of this node in previous builds.
+    pub mean: Duration,
+    /// The standard deviation of this node's duration in previous builds.
+    pub stddev: Duration,
+    /// How many standard deviations above the mean `duration` is.
+    pub sigma: f64,
+}
+
+/// Knobs that control how the critical path gets computed. The defaults match what we do when
+/// nothing is configured.
+#[derive(Clone)]
+pub struct CriticalPathOptions {
+    /// How long we are willing to spend computing critical path potentials once the build has
+    /// finished. If we exceed this, we fall back to reporting the critical path without
+    /// potentials.
+    pub potentials_timeout: Option<Duration>,
+    /// If set, write the whole graph we computed the critical path over to this path, as JSON.
+    /// This is only supported by the longest-path-graph backend.
+    pub graph_export_path: Option<PathBuf>,
+    /// When exporting the graph, also report the slack on each edge (i.e. how long the dependent
+    /// started after its dependency finished).
+    pub graph_export_edge_timing: bool,
+    /// If set, only export the part of the graph around the node with this key (as it is
+    /// displayed in the exported graph, or its stable id), which keeps the export readable when
+    /// debugging a specific node.
+    pub graph_export_focus: Option<String>,
+    /// How many dependencies or dependents away from `graph_export_focus` we export nodes.
+    pub graph_export_radius: usize,
+    /// If set, the longest-path-graph backend only considers what was required by top level
+    /// targets matching one of these patterns. This makes it affordable to use on large builds
+    /// when only some targets need a detailed critical path.
+    pub longest_path_graph_targets: Option<Vec<ParsedPattern<TargetPatternExtra>>>,
+    /// If set, write the critical path to this path in the "folded stacks" format, which can be
+    /// used to render it as a flamegraph.
+    pub folded_stacks_path: Option<PathBuf>,
+    /// If set, write the critical path to this path as a Mermaid diagram, which can be pasted
+    /// into Markdown (e.g. a PR description) to share it.
+    pub mermaid_path: Option<PathBuf>,
+    /// If set, write the critical path to this path as a Perfetto protobuf trace. This is far more
+    /// compact than a Chrome JSON trace.
+    pub perfetto_trace_path: Option<PathBuf>,
+    /// What to do when the same node gets evaluated more than once in a build.
+    pub duplicate_evaluation_policy: DuplicateEvaluationPolicy,
+    /// Also report the critical path through just the analysis phase (loading, configuring and
+    /// analyzing targets) and through just the execution phase (building and materializing). This
+    /// is only supported by the longest-path-graph backend.
+    pub phase_critical_paths: bool,
+    /// Also report the critical path through just loading packages and configuring targets. This
+    /// surfaces slowness due to e.g. selects and transitions, which is otherwise hidden since we
+    /// don't report configured target nodes on the critical path. This is only supported by the
+    /// longest-path-graph backend.
+    pub configuration_critical_path: bool,
+    /// How many span ids we keep for each node. Nodes normally have one span, but a misbehaving
+    /// action could emit thousands, so we drop the excess (and count how many we dropped) to bound
+    /// memory usage and the size of the critical path we log.
+    pub max_span_ids_per_node: usize,
+    /// If set, also report the critical path we would have had if the node with this key (as it
+    /// is displayed in the exported graph or folded stacks) had taken no time, i.e. what the next
+    /// bottleneck is. This is only supported by the longest-path-graph backend.
+    pub what_if_zero_cost: Option<String>,
+    /// If set, also report the critical path we would have had if only these top level targets
+    /// (by label, with or without configuration) had been requested. This is only supported by
+    /// the longest-path-graph backend.
+    pub focus_targets: Option<Vec<String>>,
+    /// Which of each node's durations we use to compute the critical path.
+    pub duration_policy: DurationPolicy,
+    /// Measure how much memory the backend is holding on to once the build finishes, and report
+    /// it. This is useful to keep an eye on how much the critical path costs us, but measuring
+    /// requires traversing everything the backend holds, which takes a while on large builds.
+    pub measure_backend_memory: bool,
+    /// How many distinct packages the targets in a single package may depend on before we report
+    /// that package. Such mega-packages are a build health problem, and make the work we do for
+    /// each load expensive.
+    pub max_load_fan_out: usize,
+    /// How many nodes a build must have before we report a critical path that is a single node.
+    /// That is fine for small builds, but on large ones it almost always means we missed the
+    /// edges between nodes, and the critical path is meaningless.
+    pub degenerate_critical_path_min_nodes: u64,
+    /// For each load on the critical path, report the chain of packages whose targets depended
+    /// on it, which tells why we loaded it. This requires `enrich_load_graph`.
+    pub load_chains: bool,
+    /// Whether to give each load a dependency on the load that discovered its package. This is a
+    /// heuristic for why we loaded packages when we did, which can be misleading in some repos.
+    /// Without it, loads only depend on what DICE reported.
+    pub enrich_load_graph: bool,
+    /// If set, collapse actions that ran identical commands (i.e. whose action digests match)
+    /// into one node before computing the critical path, combining their durations according to
+    /// this policy. The same action analyzed under several configurations otherwise shows up as
+    /// several nodes with near-identical costs, whereas this reports the logical work.
+    pub collapse_identical_actions: Option<CollapsedDurationPolicy>,
+    /// If set, link each entry on the critical path to its build event span using this template,
+    /// in which `{build_id}` and `{span_id}` are replaced by the build's trace id and the entry's
+    /// first span id. This lets UIs deep-link from the critical path into the span.
+    pub span_link_template: Option<String>,
+    /// Remove nodes that took no time (e.g. analyses without activation data, ensure and deferred
+    /// keys) from the graph before computing the critical path, connecting what depended on them
+    /// directly to what they depended on. This keeps the critical path and exported graph focused
+    /// on nodes that cost time, but changes their structure. This is only supported by the
+    /// longest-path-graph backend.
+    pub contract_zero_duration_nodes: bool,
+    /// For each analysis on the critical path, report how many top level targets depend on it,
+    /// and its duration split evenly between them. Analyses shared by many targets are otherwise
+    /// charged in full to each of them. This is only supported by the longest-path-graph backend.
+    pub attribute_shared_analysis: bool,
+    /// Whether we report materializations per artifact, or combine those that share an output
+    /// directory or producing action into a single node whose duration is their sum.
+    pub materialization_grouping: MaterializationGrouping,
+    /// If set, also simulate scheduling the build graph on this many cores, and report how long
+    /// that would take and which nodes bottlenecked it. This is more expensive than computing the
+    /// critical path, and is only supported by the longest-path-graph backend.
+    pub simulated_core_count: Option<usize>,
+    /// If set, log where the critical path stands at most this often while the build runs, as
+    /// `PartialCriticalPath` events. These are only a live view: the critical path we log at the
+    /// end of the build supersedes them.
+    pub partial_critical_path_interval: Option<Duration>,
+    /// For each entry on the critical path, report how many dependency hops away it is from the
+    /// nearest top level target. This is only supported by the longest-path-graph backend.
+    pub hops_from_top_level: bool,
+    /// Report how many nodes have how much slack, i.e. how much slower each could have been
+    /// before it would have made the critical path longer. This is only supported by the
+    /// longest-path-graph backend.
+    pub slack_histogram: bool,
+    /// Flag the entries on the critical path that are on every longest path through the build
+    /// graph, rather than only on the one we reported, and report all such nodes separately.
+    /// This is only supported by the longest-path-graph backend.
+    pub always_critical: bool,
+    /// If set, report up to this many of the nodes with the least slack that aren't on the
+    /// critical path (i.e. those that would become critical first if it got faster). This is
+    /// only supported by the longest-path-graph backend.
+    pub near_critical_count: Option<usize>,
+    /// Fixed costs to add to the duration of every node of a kind (by the name we display the kind
+    /// as, e.g. `BuildKey` for actions), to model per-node overhead such as scheduling. The
+    /// critical path is computed with those costs, and we report how much of it they make up.
+    /// Nodes removed by `contract_zero_duration_nodes` don't get them. This is only supported by
+    /// the longest-path-graph backend.
+    pub cost_floors: HashMap<String, Duration>,
+    /// Whether to hide the names of what is on the critical path when we log it.
+    pub redaction: CriticalPathRedaction,
+    /// If set, record the signals we process to this file, which `replay` can later run against
+    /// any backend.
+    pub signal_recording_path: Option<PathBuf>,
+    /// If set, stream a line of JSON to this path (which can be a named pipe) for each evaluation
+    /// we receive, as we receive it, for consumers that want to follow the build live.
+    pub evaluation_stream_path: Option<PathBuf>,
+    /// Print a short summary of what is worth improving on the critical path to the console once
+    /// the build finishes, so that users see why it was slow without digging through logs.
+    pub console_summary: bool,
+    /// Log a line at debug level for each signal we process, so that maintainers can see what a
+    /// critical path was computed from (with `RUST_LOG`) without recording it.
+    pub log_signals: bool,
+    /// Have the default backend approximate potentials for improvement with a second pass over
+    /// the critical path. These are less accurate than those of the longest-path-graph backend
+    /// (they can understate the savings), but much cheaper to compute.
+    pub default_backend_potentials: bool,
+    /// The labels of the platforms whose configurations are for the host, when grouping the
+    /// critical path by platform. We can't tell those apart from target platforms otherwise.
+    pub host_platforms: Vec<String>,
+}
+
+impl Default for CriticalPathOptions {
+    fn default() -> Self {
+        Self {
+            potentials_timeout: None,
+            graph_export_path: None,
+            graph_export_edge_timing: false,
+            graph_export_focus: None,
+            graph_export_radius: 2,
+            longest_path_graph_targets: None,
+            folded_stacks_path: None,
+            mermaid_path: None,
+            perfetto_trace_path: None,
+            duplicate_evaluation_policy: DuplicateEvaluationPolicy::default(),
+            phase_critical_paths: false,
+            configuration_critical_path: false,
+            max_span_ids_per_node: 64,
+            what_if_zero_cost: None,
+            focus_targets: None,
+            duration_policy: DurationPolicy::default(),
+            measure_backend_memory: false,
+            max_load_fan_out: 1000,
+            degenerate_critical_path_min_nodes: 1000,
+            load_chains: false,
+            enrich_load_graph: true,
+            collapse_identical_actions: None,
+            span_link_template: None,
+            contract_zero_duration_nodes: false,
+            attribute_shared_analysis: false,
+            materialization_grouping: MaterializationGrouping::default(),
+            simulated_core_count: None,
+            partial_critical_path_interval: None,
+            hops_from_top_level: false,
+            slack_histogram: false,
+            always_critical: false,
+            near_critical_count: None,
+            cost_floors: HashMap::new(),
+            redaction: CriticalPathRedaction::default(),
+            signal_recording_path: None,
+            evaluation_stream_path: None,
+            console_summary: false,
+            log_signals: false,
+            default_backend_potentials: false,
+            host_platforms: Vec::new(),
+        }
+    }
+}
+
+impl CriticalPathOptions {
+    /// Open `evaluation_stream_path`, if set, for `BuildSignalsContext::evaluation_stream`.
+    pub fn open_evaluation_stream(&self) -> anyhow::Result<Option<Box<dyn Write + Send>>> {
+        self.evaluation_stream_path
+            .as_ref()
+            .map(|path| {
+                let file = File::create(path).with_context(|| {
+                    format!("Error opening evaluation stream `{}`", path.display())
+                })?;
+                Ok(Box::new(file) as _)
+            })
+            .transpose()
+    }
+}
+
+/// Decides which evaluation of a node we keep when it gets evaluated more than once in a build
+/// (e.g. because DICE recomputed it).
+#[derive(Copy, Clone, Dupe, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateEvaluationPolicy {
+    FirstWins,
+    LastWins,
+    /// Keep the slowest evaluation. This is the conservative choice for a critical path.
+    #[default]
+    MaxDurationWins,
+}
+
+impl FromStr for DuplicateEvaluationPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "first-wins" => Ok(Self::FirstWins),
+            "last-wins" => Ok(Self::LastWins),
+            "max-duration-wins" => Ok(Self::MaxDurationWins),
+            _ => Err(anyhow::anyhow!(
+                "Invalid duplicate evaluation policy: `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// Whether we hide the names (of targets, packages, paths, and so on) of what is on the critical
+/// path when we log it, for builds whose logs end up somewhere those names shouldn't.
+#[derive(Copy, Clone, Dupe, Debug, Default, PartialEq, Eq)]
+pub enum CriticalPathRedaction {
+    #[default]
+    None,
+    /// Replace each name with a hash of it. Within a build the same name always hashes the same,
+    /// so the critical path's structure is preserved.
+    Hash,
+}
+
+impl FromStr for CriticalPathRedaction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::None),
+            "hash" => Ok(Self::Hash),
+            _ => Err(anyhow::anyhow!("Invalid critical path redaction: `{}`", s)),
+        }
+    }
+}
+
+/// How we combine the durations of identical actions when we collapse them into one node.
+#[derive(Copy, Clone, Dupe, Debug, PartialEq, Eq)]
+pub enum CollapsedDurationPolicy {
+    /// Take the longest duration, as if the actions had all run in parallel.
+    Max,
+    /// Add the durations up, which reflects the total work they represent.
+    Sum,
+}
+
+impl FromStr for CollapsedDurationPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "max" => Ok(Self::Max),
+            "sum" => Ok(Self::Sum),
+            _ => Err(anyhow::anyhow!(
+                "Invalid collapsed duration policy: `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// How we turn materializations into nodes of the critical path graph.
+#[derive(Copy, Clone, Dupe, Debug, Default, PartialEq, Eq)]
+pub enum MaterializationGrouping {
+    /// One node per materialized artifact.
+    #[default]
+    PerArtifact,
+    /// One node per output directory, for builds that materialize many small files side by side.
+    ByDirectory,
+    /// One node per action, covering all the outputs it produced.
+    ByAction,
+}
+
+impl FromStr for MaterializationGrouping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "per-artifact" => Ok(Self::PerArtifact),
+            "by-directory" => Ok(Self::ByDirectory),
+            "by-action" => Ok(Self::ByAction),
+            _ => Err(anyhow::anyhow!("Invalid materialization grouping: `{}`", s)),
+        }
+    }
 }
 
 /// Created along with the BuildSignalsInstaller (ideally, BuildSignalsInstaller's definition would
@@ -108,12 +563,15 @@ pub trait FinishBuildSignals: Send {
 /// This function arranges for a background task to be spawned that drives the receiver, while
 /// invoking the called function with a live BuildSignalSender that can be used to send events to
 /// the listening receiver. Upon return of `scope`, the sender terminates the receiver by sending a
-/// `BuildFinished` signal and joins the receiver task.
+/// `BuildFinished` signal and joins the receiver task, which computes the critical path. If that
+/// fails, it is a soft error, unless `strict` is set, in which case it fails the command (tests of
+/// the critical path itself want that).
 pub async fn scope<F, R, Fut>(
     deferred: Box<dyn DeferredBuildSignals>,
     events: EventDispatcher,
     backend: CriticalPathBackendName,
     ctx: BuildSignalsContext,
+    strict: bool,
     func: F,
 ) -> anyhow::Result<R>
 where
@@ -128,6 +586,11 @@ where
         .await
         .context("Error computing critical path");
     if let Err(e) = res {
+        if strict {
+            // If the command failed too, its own error is the one to report.
+            result?;
+            return Err(e);
+        }
         soft_error!("critical_path_computation_failed", e)?;
     }
     result
@@ -137,8 +600,26 @@ pub trait HasCriticalPathBackend {
     fn set_critical_path_backend(&mut self, backend: CriticalPathBackendName);
 
     fn get_critical_path_backend(&self) -> CriticalPathBackendName;
+
+    fn set_critical_path_options(&mut self, options: CriticalPathOptions);
+
+    fn get_critical_path_options(&self) -> CriticalPathOptions;
+
+    fn set_critical_path_strict(&mut self, strict: bool);
+
+    /// Whether failing to compute the critical path should fail the command.
+    fn get_critical_path_strict(&self) -> bool;
+
+    fn set_critical_path_history(&mut self, history: Arc<dyn CriticalPathHistory>);
+
+    /// The history to compare this command's critical path against, if it should be.
+    fn get_critical_path_history(&self) -> Option<Arc<dyn CriticalPathHistory>>;
 }
 
+struct CriticalPathStrict(bool);
+
+struct CriticalPathHistoryHolder(Arc<dyn CriticalPathHistory>);
+
 impl HasCriticalPathBackend for UserComputationData {
     fn set_critical_path_backend(&mut self, backend: CriticalPathBackendName) {
         self.data.set(backend);
@@ -150,4 +631,37 @@ impl HasCriticalPathBackend for UserComputationData {
             .get::<CriticalPathBackendName>()
             .expect("CriticalPathBackendName should be set")
     }
+
+    fn set_critical_path_options(&mut self, options: CriticalPathOptions) {
+        self.data.set(options);
+    }
+
+    fn get_critical_path_options(&self) -> CriticalPathOptions {
+        self.data
+            .get::<CriticalPathOptions>()
+            .expect("CriticalPathOptions should be set")
+            .clone()
+    }
+
+    fn set_critical_path_strict(&mut self, strict: bool) {
+        self.data.set(CriticalPathStrict(strict));
+    }
+
+    fn get_critical_path_strict(&self) -> bool {
+        self.data
+            .get::<CriticalPathStrict>()
+            .expect("CriticalPathStrict should be set")
+            .0
+    }
+
+    fn set_critical_path_history(&mut self, history: Arc<dyn CriticalPathHistory>) {
+        self.data.set(CriticalPathHistoryHolder(history));
+    }
+
+    fn get_critical_path_history(&self) -> Option<Arc<dyn CriticalPathHistory>> {
+        self.data
+            .get::<CriticalPathHistoryHolder>()
+            .ok()
+            .map(|holder| holder.0.dupe())
+    }
 }
@@ -7,25 +7,23 @@
  * of this source tree.
  */
 
-use std::sync::Arc;
+use std::time::Instant;
 
-use buck2_build_api::actions::RegisteredAction;
+use allocative::Allocative;
 use buck2_build_signals::CriticalPathBackendName;
-use buck2_build_signals::NodeDuration;
-use buck2_events::span::SpanId;
-use smallvec::SmallVec;
+use buck2_core::soft_error;
 
 use crate::BuildInfo;
+use crate::NodeData;
 use crate::NodeKey;
 
-pub(crate) trait BuildListenerBackend {
+/// Backends are `Allocative` so that we can report how much memory they hold on to.
+pub(crate) trait BuildListenerBackend: Allocative {
     fn process_node(
         &mut self,
         key: NodeKey,
-        value: Option<Arc<RegisteredAction>>,
-        duration: NodeDuration,
+        data: NodeData,
         dep_keys: impl IntoIterator<Item = NodeKey>,
-        span_ids: SmallVec<[SpanId; 1]>,
     );
 
     fn process_top_level_target(
@@ -34,7 +32,29 @@ pub(crate) trait BuildListenerBackend {
         artifacts: impl IntoIterator<Item = NodeKey>,
     );
 
-    fn finish(self) -> anyhow::Result<BuildInfo>;
+    /// The action for `key` (a `NodeKey::BuildKey`) failed. Backends that can should also report
+    /// the critical path leading to the first action that failed.
+    fn process_failed_action(&mut self, _key: NodeKey) {}
+
+    /// Produce the critical path. Backends that do expensive work here should try to respect
+    /// `deadline` (if any) by degrading their output rather than running past it.
+    fn finish(self, deadline: Option<Instant>) -> anyhow::Result<BuildInfo>;
 
     fn name() -> CriticalPathBackendName;
 }
+
+/// Whether `dep` is `key` itself. A node depending on itself indicates a bug in whatever produced
+/// it, so we report it, and backends skip such dependencies.
+pub(crate) fn is_self_dependency(key: &NodeKey, dep: &NodeKey) -> bool {
+    if key != dep {
+        return false;
+    }
+
+    let _ignored = soft_error!(
+        "critical_path_self_dependency",
+        anyhow::anyhow!("Node depends on itself: `{}`", key),
+        quiet: true
+    );
+
+    true
+}
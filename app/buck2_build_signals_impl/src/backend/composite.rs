@@ -0,0 +1,188 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::time::Duration;
+use std::time::Instant;
+
+use allocative::Allocative;
+use buck2_build_signals::CriticalPathBackendName;
+use buck2_core::soft_error;
+use dupe::Dupe;
+
+use crate::backend::backend::BuildListenerBackend;
+use crate::backend::default::DefaultBackend;
+use crate::backend::longest_path_graph::LongestPathGraphBackend;
+use crate::BuildInfo;
+use crate::NodeData;
+use crate::NodeKey;
+
+/// Runs both the default and the longest-path-graph backends on the same stream of signals. We
+/// report what the longest-path-graph backend produced, and use the default backend as a
+/// cross-check: if the two disagree on where the critical path ends, one of them is probably
+/// handling edges incorrectly.
+#[derive(Allocative)]
+pub(crate) struct CompositeBackend {
+    default: DefaultBackend,
+    longest_path: LongestPathGraphBackend,
+}
+
+impl CompositeBackend {
+    pub(crate) fn new(default: DefaultBackend, longest_path: LongestPathGraphBackend) -> Self {
+        Self {
+            default,
+            longest_path,
+        }
+    }
+}
+
+impl BuildListenerBackend for CompositeBackend {
+    fn process_node(
+        &mut self,
+        key: NodeKey,
+        data: NodeData,
+        dep_keys: impl IntoIterator<Item = NodeKey>,
+    ) {
+        let dep_keys = dep_keys.into_iter().collect::<Vec<_>>();
+        self.default
+            .process_node(key.dupe(), data.clone(), dep_keys.iter().cloned());
+        self.longest_path.process_node(key, data, dep_keys);
+    }
+
+    fn process_top_level_target(
+        &mut self,
+        analysis: NodeKey,
+        artifacts: impl IntoIterator<Item = NodeKey>,
+    ) {
+        let artifacts = artifacts.into_iter().collect::<Vec<_>>();
+        self.default
+            .process_top_level_target(analysis.dupe(), artifacts.iter().cloned());
+        self.longest_path
+            .process_top_level_target(analysis, artifacts);
+    }
+
+    fn process_failed_action(&mut self, key: NodeKey) {
+        self.default.process_failed_action(key.dupe());
+        self.longest_path.process_failed_action(key);
+    }
+
+    fn finish(self, deadline: Option<Instant>) -> anyhow::Result<BuildInfo> {
+        let default = self.default.finish(deadline)?;
+        let longest_path = self.longest_path.finish(deadline)?;
+
+        if let Some(disagreement) = TailDisagreement::new(&default, &longest_path) {
+            soft_error!(
+                "critical_path_backend_disagreement",
+                anyhow::anyhow!("{}", disagreement),
+                quiet: true
+            )?;
+        }
+
+        Ok(longest_path)
+    }
+
+    fn name() -> CriticalPathBackendName {
+        CriticalPathBackendName::Composite
+    }
+}
+
+#[derive(Debug, derive_more::Display, PartialEq)]
+#[display(
+    fmt = "Critical path backends disagree on the tail: default ended at `{}` ({:?}), longest-path-graph ended at `{}` ({:?})",
+    default_tail,
+    default_duration,
+    longest_path_tail,
+    longest_path_duration
+)]
+struct TailDisagreement {
+    default_tail: String,
+    default_duration: Duration,
+    longest_path_tail: String,
+    longest_path_duration: Duration,
+}
+
+impl TailDisagreement {
+    /// Returns the disagreement between the two backends' results, if their critical paths end on
+    /// different nodes.
+    fn new(default: &BuildInfo, longest_path: &BuildInfo) -> Option<Self> {
+        let (default_tail, default_data, _) = default.critical_path.last()?;
+        let (longest_path_tail, longest_path_data, _) = longest_path.critical_path.last()?;
+
+        if default_tail == longest_path_tail {
+            return None;
+        }
+
+        Some(Self {
+            default_tail: default_tail.to_string(),
+            default_duration: default_data.duration.critical_path_duration(),
+            longest_path_tail: longest_path_tail.to_string(),
+            longest_path_duration: longest_path_data.duration.critical_path_duration(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::key;
+
+    fn data(secs: u64) -> NodeData {
+        NodeData::testing_new(Duration::from_secs(secs))
+    }
+
+    #[test]
+    fn test_backends_agree() -> anyhow::Result<()> {
+        let mut backend =
+            CompositeBackend::new(DefaultBackend::new(), LongestPathGraphBackend::new());
+        backend.process_node(key("a"), data(1), std::iter::empty());
+        backend.process_node(key("b"), data(2), std::iter::once(key("a")));
+        backend.process_node(key("c"), data(1), std::iter::empty());
+
+        let default = {
+            let mut default = DefaultBackend::new();
+            default.process_node(key("a"), data(1), std::iter::empty());
+            default.process_node(key("b"), data(2), std::iter::once(key("a")));
+            default.process_node(key("c"), data(1), std::iter::empty());
+            default.finish(None)?
+        };
+
+        let info = backend.finish(None)?;
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(k, _, _)| k.to_string())
+                .collect::<Vec<_>>(),
+            default
+                .critical_path
+                .iter()
+                .map(|(k, _, _)| k.to_string())
+                .collect::<Vec<_>>(),
+        );
+        assert_eq!(TailDisagreement::new(&default, &info), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tail_disagreement() -> anyhow::Result<()> {
+        let mut a = DefaultBackend::new();
+        a.process_node(key("a"), data(1), std::iter::empty());
+        let a = a.finish(None)?;
+
+        let mut b = DefaultBackend::new();
+        b.process_node(key("b"), data(2), std::iter::empty());
+        let b = b.finish(None)?;
+
+        let disagreement = TailDisagreement::new(&a, &b).unwrap();
+        assert_eq!(disagreement.default_duration, Duration::from_secs(1));
+        assert_eq!(disagreement.longest_path_duration, Duration::from_secs(2));
+        assert_ne!(disagreement.default_tail, disagreement.longest_path_tail);
+
+        Ok(())
+    }
+}
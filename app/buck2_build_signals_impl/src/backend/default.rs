@@ -11,33 +11,38 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Display;
 use std::hash::Hash;
-use std::sync::Arc;
 use std::time::Duration;
+use std::time::Instant;
 
+use allocative::Allocative;
 use anyhow::Context as _;
-use buck2_build_api::actions::RegisteredAction;
 use buck2_build_signals::CriticalPathBackendName;
-use buck2_build_signals::NodeDuration;
-use buck2_events::span::SpanId;
+use buck2_build_signals::DurationPolicy;
 use dupe::Dupe;
 use gazebo::prelude::VecExt;
 use itertools::Itertools;
-use smallvec::SmallVec;
 
+use crate::backend::backend::is_self_dependency;
 use crate::backend::backend::BuildListenerBackend;
 use crate::BuildInfo;
 use crate::NodeData;
 use crate::NodeKey;
 
-#[derive(Clone, Dupe)]
+#[derive(Clone, Dupe, Allocative)]
 struct CriticalPathNode<TKey: Eq, TValue> {
     /// The aggregated duration of this critical path.
     pub duration: Duration,
     /// The value of this node. If None, this node just won't be included when displaying.
     pub value: TValue,
     pub prev: Option<TKey>,
+    /// The aggregated duration of the longest dependency other than `prev`, which is what this
+    /// node would wait on if `prev` took no time.
+    pub runner_up: Duration,
 }
 
+/// Walk back from the node with the longest aggregated duration to recover the critical path, in
+/// chronological order. The durations we return are each node's own duration, not aggregated
+/// ones.
 fn extract_critical_path<TKey: Hash + Eq, TValue>(
     predecessors: &HashMap<TKey, CriticalPathNode<TKey, TValue>>,
 ) -> anyhow::Result<Vec<(&TKey, &TValue, Duration)>>
@@ -66,19 +71,79 @@ where
         });
     }
 
-    // Take differences of adjacent elements to recover action time from cumulative sum.
     path.reverse();
+    cumulative_to_individual_durations(&mut path);
+
+    Ok(path)
+}
+
+/// Take differences of adjacent elements to recover each node's duration from the cumulative
+/// durations along `path`.
+///
+/// A node's aggregated duration is its predecessor's plus its own, so we expect durations to be
+/// non-decreasing along the path (with equal durations for zero-duration nodes). If they aren't,
+/// that's a bug, but we'd rather report a zero duration than panic on underflow.
+fn cumulative_to_individual_durations<A, B>(path: &mut [(A, B, Duration)]) {
+    // Iterate backwards so that `path[i - 1]` still holds a cumulative duration.
     for i in (1..path.len()).rev() {
         path[i].2 = path[i].2.saturating_sub(path[i - 1].2);
     }
+}
 
-    Ok(path)
+/// For each node on `critical_path`, approximate how much shorter the critical path would get if
+/// that node took no time. We only know the longest dependency (`prev`) and the runner-up of each
+/// node, so we recompute the path from that node on assuming nodes on it would otherwise wait on
+/// their runner-up, and that everything off the path keeps its aggregated duration. Both can only
+/// overestimate what the path would become, so this never overstates the savings, but it can
+/// understate them when the runner-up or nodes off the path themselves went through the node.
+/// `LongestPathGraphBackend` computes exact potentials, at the cost of keeping the whole graph.
+fn approximate_potentials(
+    predecessors: &HashMap<NodeKey, CriticalPathNode<NodeKey, NodeData>>,
+    critical_path: &[(&NodeKey, &NodeData, Duration)],
+    duration_policy: DurationPolicy,
+) -> Vec<Duration> {
+    let Some((tail, _, _)) = critical_path.last() else {
+        return Vec::new();
+    };
+    let total = predecessors[*tail].duration;
+
+    let on_path = critical_path
+        .iter()
+        .map(|(key, _, _)| *key)
+        .collect::<HashSet<_>>();
+    let longest_off_path = predecessors
+        .iter()
+        .filter(|(key, _)| !on_path.contains(key))
+        .map(|(_, node)| node.duration)
+        .max()
+        .unwrap_or(Duration::ZERO);
+
+    (0..critical_path.len())
+        .map(|i| {
+            let (key, data, _) = critical_path[i];
+            // What `key` would finish at if it took no time.
+            let mut duration = predecessors[key]
+                .duration
+                .saturating_sub(data.duration.duration_for(duration_policy));
+            for (key, data, _) in &critical_path[i + 1..] {
+                duration = duration
+                    .max(predecessors[*key].runner_up)
+                    .saturating_add(data.duration.duration_for(duration_policy));
+            }
+            total.saturating_sub(duration.max(longest_off_path))
+        })
+        .collect()
 }
 
+#[derive(Allocative)]
 pub(crate) struct DefaultBackend {
     predecessors: HashMap<NodeKey, CriticalPathNode<NodeKey, NodeData>>,
     num_nodes: u64,
     num_edges: u64,
+    duration_policy: DurationPolicy,
+    /// The node with the longest aggregated duration so far, and that duration.
+    tail: Option<(NodeKey, Duration)>,
+    approximate_potentials: bool,
 }
 
 impl DefaultBackend {
@@ -87,48 +152,93 @@ impl DefaultBackend {
             predecessors: HashMap::new(),
             num_nodes: 0,
             num_edges: 0,
+            duration_policy: DurationPolicy::default(),
+            tail: None,
+            approximate_potentials: false,
         }
     }
+
+    pub(crate) fn with_duration_policy(mut self, policy: DurationPolicy) -> Self {
+        self.duration_policy = policy;
+        self
+    }
+
+    /// Have `finish` approximate each critical path node's potential for improvement. See
+    /// `approximate_potentials`.
+    pub(crate) fn with_approximate_potentials(mut self) -> Self {
+        self.approximate_potentials = true;
+        self
+    }
+
+    /// Where the critical path ends so far, and how long it is. This is available while nodes
+    /// are still coming in, unlike what `finish` computes.
+    pub(crate) fn tail(&self) -> Option<(&NodeKey, Duration)> {
+        self.tail.as_ref().map(|(key, duration)| (key, *duration))
+    }
+
+    pub(crate) fn num_nodes(&self) -> u64 {
+        self.num_nodes
+    }
 }
 
 impl BuildListenerBackend for DefaultBackend {
     fn process_node(
         &mut self,
         key: NodeKey,
-        value: Option<Arc<RegisteredAction>>,
-        duration: NodeDuration,
+        value: NodeData,
         dep_keys: impl IntoIterator<Item = NodeKey>,
-        span_ids: SmallVec<[SpanId; 1]>,
     ) {
-        let longest_ancestor = dep_keys
+        let mut longest_ancestor: Option<(NodeKey, Duration)> = None;
+        let mut runner_up = Duration::ZERO;
+        for (node_key, node_duration) in dep_keys
             .into_iter()
+            .filter(|dep| !is_self_dependency(&key, dep))
             .unique()
             .filter_map(|node_key| {
                 self.num_edges += 1;
                 let node_data = self.predecessors.get(&node_key)?;
                 Some((node_key, node_data.duration))
             })
-            .max_by_key(|d| d.1);
+        {
+            // On ties, the last dependency wins.
+            match &longest_ancestor {
+                Some((_, longest)) if node_duration < *longest => {
+                    runner_up = runner_up.max(node_duration);
+                }
+                _ => {
+                    if let Some((_, previous)) = longest_ancestor.replace((node_key, node_duration))
+                    {
+                        runner_up = runner_up.max(previous);
+                    }
+                }
+            }
+        }
 
-        let value = NodeData {
-            action: value,
-            duration,
-            span_ids,
-        };
+        let duration = value.duration.duration_for(self.duration_policy);
 
         let node = match longest_ancestor {
             Some((key, ancestor_duration)) => CriticalPathNode {
                 prev: Some(key.dupe()),
                 value,
-                duration: ancestor_duration + duration.critical_path_duration(),
+                duration: ancestor_duration.saturating_add(duration),
+                runner_up,
             },
             None => CriticalPathNode {
                 prev: None,
                 value,
-                duration: duration.critical_path_duration(),
+                duration,
+                runner_up,
             },
         };
 
+        if self
+            .tail
+            .as_ref()
+            .map_or(true, |(_, longest)| node.duration > *longest)
+        {
+            self.tail = Some((key.dupe(), node.duration));
+        }
+
         self.num_nodes += 1;
         self.predecessors.insert(key, node);
     }
@@ -140,15 +250,45 @@ impl BuildListenerBackend for DefaultBackend {
     ) {
     }
 
-    fn finish(self) -> anyhow::Result<BuildInfo> {
-        let critical_path = extract_critical_path(&self.predecessors)
-            .context("Error extracting critical path")?
-            .into_map(|(key, data, _duration)| (key.dupe(), data.clone(), None));
+    fn finish(self, _deadline: Option<Instant>) -> anyhow::Result<BuildInfo> {
+        let critical_path =
+            extract_critical_path(&self.predecessors).context("Error extracting critical path")?;
+
+        let potentials = if self.approximate_potentials {
+            approximate_potentials(&self.predecessors, &critical_path, self.duration_policy)
+                .into_map(Some)
+        } else {
+            vec![None; critical_path.len()]
+        };
+
+        let critical_path = critical_path
+            .into_iter()
+            .zip(potentials)
+            .map(|((key, data, _duration), potential)| (key.dupe(), data.clone(), potential))
+            .collect();
 
         Ok(BuildInfo {
             critical_path,
             num_nodes: self.num_nodes,
             num_edges: self.num_edges,
+            potentials_timed_out: false,
+            exported_graph: None,
+            top_level_targets: Vec::new(),
+            analysis_critical_path: Vec::new(),
+            execution_critical_path: Vec::new(),
+            configuration_critical_path: Vec::new(),
+            retained_graph: None,
+            potential_improvement_bytes: Vec::new(),
+            analysis_dependents: Vec::new(),
+            hops_from_top_level: Vec::new(),
+            failure_critical_path: Vec::new(),
+            simulated_schedule: None,
+            slack_histogram: Vec::new(),
+            on_every_critical_path: Vec::new(),
+            always_critical: Vec::new(),
+            near_critical: Vec::new(),
+            critical_path_overhead: None,
+            last_blockers: Vec::new(),
         })
     }
 
@@ -159,6 +299,10 @@ impl BuildListenerBackend for DefaultBackend {
 
 #[cfg(test)]
 mod tests {
+    use buck2_build_signals::NodeDuration;
+    use buck2_common::package_listing::dice::PackageListingKey;
+    use buck2_core::package::PackageLabel;
+
     use super::*;
 
     type CriticalPathMap = HashMap<i32, CriticalPathNode<i32, Option<i32>>>;
@@ -175,6 +319,7 @@ mod tests {
                 duration,
                 value: Some(key),
                 prev,
+                runner_up: Duration::ZERO,
             },
         );
     }
@@ -217,6 +362,36 @@ mod tests {
         );
     }
 
+    #[test]
+    fn zero_duration_leaf() {
+        let mut path = vec![
+            (1, (), Duration::from_secs(5)),
+            (2, (), Duration::from_secs(5)),
+        ];
+        cumulative_to_individual_durations(&mut path);
+        assert_eq!(
+            path,
+            vec![(1, (), Duration::from_secs(5)), (2, (), Duration::ZERO)]
+        );
+    }
+
+    #[test]
+    fn misordered_cumulative_durations() {
+        let mut predecessors = HashMap::new();
+        // 2's aggregated duration is less than 1's, which should never happen.
+        cp_insert(&mut predecessors, 1, None, Duration::from_secs(10));
+        cp_insert(&mut predecessors, 2, Some(1), Duration::from_secs(3));
+        cp_insert(&mut predecessors, 3, Some(2), Duration::from_secs(12));
+        assert_eq!(
+            extract_critical_path(&predecessors).unwrap(),
+            vec![
+                (&1, &Some(1), Duration::from_secs(10)),
+                (&2, &Some(2), Duration::ZERO),
+                (&3, &Some(3), Duration::from_secs(9)),
+            ],
+        );
+    }
+
     #[test]
     fn cycle_path() {
         let mut predecessors = HashMap::new();
@@ -224,4 +399,115 @@ mod tests {
         cp_insert(&mut predecessors, 2, Some(1), Duration::from_secs(11));
         assert!(extract_critical_path(&predecessors).is_err());
     }
+
+    #[test]
+    fn test_saturating_durations() -> anyhow::Result<()> {
+        let key = |name: &str| {
+            NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", name)))
+        };
+        let data = NodeData::testing_new;
+
+        let huge = Duration::MAX - Duration::from_secs(1);
+        let mut backend = DefaultBackend::new();
+        backend.process_node(key("a"), data(huge), std::iter::empty());
+        backend.process_node(key("b"), data(huge), std::iter::once(key("a")));
+        backend.process_node(key("c"), data(Duration::from_secs(1)), std::iter::empty());
+        assert_eq!(backend.predecessors[&key("b")].duration, Duration::MAX);
+
+        let critical_path = backend
+            .finish(None)?
+            .critical_path
+            .into_iter()
+            .map(|(key, _, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(critical_path, vec![key("a"), key("b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duration_policy() -> anyhow::Result<()> {
+        let key = |name: &str| {
+            NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", name)))
+        };
+        let data = |user: u64, total: u64| NodeData {
+            duration: NodeDuration {
+                user: Duration::from_secs(user),
+                total: Duration::from_secs(total),
+            },
+            ..NodeData::testing_new(Duration::ZERO)
+        };
+
+        // `a` takes longer in total, but `b` has more user time.
+        let critical_path = |policy: DurationPolicy| -> anyhow::Result<Vec<NodeKey>> {
+            let mut backend = DefaultBackend::new().with_duration_policy(policy);
+            backend.process_node(key("a"), data(3, 5), std::iter::empty());
+            backend.process_node(key("b"), data(4, 4), std::iter::empty());
+            Ok(backend
+                .finish(None)?
+                .critical_path
+                .into_iter()
+                .map(|(key, _, _)| key)
+                .collect())
+        };
+
+        assert_eq!(critical_path(DurationPolicy::Total)?, vec![key("a")]);
+        assert_eq!(critical_path(DurationPolicy::Max)?, vec![key("a")]);
+        assert_eq!(critical_path(DurationPolicy::User)?, vec![key("b")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_approximate_potentials() -> anyhow::Result<()> {
+        let key = |name: &str| {
+            NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", name)))
+        };
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `c` waits on `a` (3s) and `b` (2s), and `d` (1s) is on its own, so the critical path is
+        // `a`, `c` (4s). Without `a`, `c` would wait on `b` instead, and without `c`, the path
+        // would be just `a`.
+        let potentials = |backend: DefaultBackend| -> anyhow::Result<Vec<Option<Duration>>> {
+            let mut backend = backend;
+            backend.process_node(key("a"), data(3), std::iter::empty());
+            backend.process_node(key("b"), data(2), std::iter::empty());
+            backend.process_node(key("c"), data(1), [key("a"), key("b")]);
+            backend.process_node(key("d"), data(1), std::iter::empty());
+            Ok(backend
+                .finish(None)?
+                .critical_path
+                .into_iter()
+                .map(|(_, _, potential)| potential)
+                .collect())
+        };
+
+        assert_eq!(
+            potentials(DefaultBackend::new().with_approximate_potentials())?,
+            vec![Some(Duration::from_secs(1)), Some(Duration::from_secs(1))]
+        );
+        // We don't compute potentials unless asked to.
+        assert_eq!(potentials(DefaultBackend::new())?, vec![None, None]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_dependency() -> anyhow::Result<()> {
+        let key =
+            NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", "pkg")));
+
+        let mut backend = DefaultBackend::new();
+        backend.process_node(
+            key.dupe(),
+            NodeData::testing_new(Duration::ZERO),
+            std::iter::once(key.dupe()),
+        );
+
+        let info = backend.finish(None)?;
+        assert_eq!(info.num_edges, 0);
+        assert_eq!(info.critical_path.len(), 1);
+
+        Ok(())
+    }
 }
@@ -7,35 +7,83 @@
  * of this source tree.
  */
 
-use std::sync::Arc;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::time::Duration;
+use std::time::Instant;
 
+use allocative::Allocative;
 use anyhow::Context as _;
-use buck2_build_api::actions::RegisteredAction;
 use buck2_build_signals::CriticalPathBackendName;
+use buck2_build_signals::DurationPolicy;
 use buck2_build_signals::NodeDuration;
+use buck2_core::pattern::pattern_type::TargetPatternExtra;
+use buck2_core::pattern::ParsedPattern;
 use buck2_core::soft_error;
-use buck2_critical_path::compute_critical_path_potentials;
+use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
+use buck2_critical_path::compute_critical_path;
+use buck2_critical_path::compute_critical_path_potentials_with_deadline;
+use buck2_critical_path::Graph;
 use buck2_critical_path::GraphBuilder;
 use buck2_critical_path::OptionalVertexId;
 use buck2_critical_path::PushError;
-use buck2_events::span::SpanId;
+use buck2_critical_path::VertexData;
+use buck2_critical_path::VertexId;
+use buck2_critical_path::VertexKeys;
 use dupe::Dupe;
-use smallvec::SmallVec;
 
+use crate::backend::backend::is_self_dependency;
 use crate::backend::backend::BuildListenerBackend;
+use crate::export::ExportedGraph;
+use crate::export::GraphExportOptions;
 use crate::BuildInfo;
 use crate::NodeData;
 use crate::NodeKey;
+use crate::NodePhase;
+use crate::SimulatedSchedule;
 
 /// An implementation of critical path that uses a longest-paths graph in order to produce
 /// potential savings in addition to the critical path.
+#[derive(Allocative)]
 pub(crate) struct LongestPathGraphBackend {
     builder: anyhow::Result<GraphBuilder<NodeKey, NodeData>>,
     top_level_analysis: Vec<VisibilityEdge>,
+    export_graph: Option<GraphExportOptions>,
+    target_filter: Option<Vec<ParsedPattern<TargetPatternExtra>>>,
+    phase_critical_paths: bool,
+    configuration_critical_path: bool,
+    retain_graph: bool,
+    duration_policy: DurationPolicy,
+    weight: NodeWeight,
+    contract_zero_weight: bool,
+    count_analysis_dependents: bool,
+    count_hops_from_top_level: bool,
+    /// The first action that failed, if any.
+    failed_action: Option<NodeKey>,
+    simulated_cores: Option<usize>,
+    compute_slack_histogram: bool,
+    compute_always_critical: bool,
+    near_critical_count: Option<usize>,
+    /// Weight to add to nodes of each kind, in microseconds. See `with_cost_floors`.
+    cost_floors: HashMap<String, u64>,
+}
+
+/// What we weigh nodes by when looking for the longest path.
+#[derive(Copy, Clone, PartialEq, Eq, Allocative)]
+enum NodeWeight {
+    /// Their duration, according to the backend's `DurationPolicy`.
+    Duration,
+    /// How many bytes they moved to and from RE.
+    TransferredBytes,
+    /// How many bytes of outputs they produced. Only actions produce outputs, so everything else
+    /// weighs nothing.
+    OutputBytes,
 }
 
 /// Represents nodes that block us "seeing" other parts of the graph until they finish evaluating.
+#[derive(Allocative)]
 struct VisibilityEdge {
     node: NodeKey,
     makes_visible: Vec<NodeKey>,
@@ -46,7 +94,221 @@ impl LongestPathGraphBackend {
         Self {
             builder: Ok(GraphBuilder::new()),
             top_level_analysis: Vec::new(),
+            export_graph: None,
+            target_filter: None,
+            phase_critical_paths: false,
+            configuration_critical_path: false,
+            retain_graph: false,
+            duration_policy: DurationPolicy::default(),
+            weight: NodeWeight::Duration,
+            contract_zero_weight: false,
+            count_analysis_dependents: false,
+            count_hops_from_top_level: false,
+            failed_action: None,
+            simulated_cores: None,
+            compute_slack_histogram: false,
+            compute_always_critical: false,
+            near_critical_count: None,
+            cost_floors: HashMap::new(),
+        }
+    }
+
+    /// Have `finish` also return the whole graph we computed the critical path over.
+    pub(crate) fn with_graph_export(mut self, options: GraphExportOptions) -> Self {
+        self.export_graph = Some(options);
+        self
+    }
+
+    /// Only compute the critical path over what top level targets matching `patterns` required.
+    /// This makes the (relatively expensive) computation in `finish` affordable on large builds
+    /// when we only care about a few targets, though we still need to hold the whole graph until
+    /// then since we don't know which nodes matter until the build finishes.
+    pub(crate) fn with_target_filter(
+        mut self,
+        patterns: Vec<ParsedPattern<TargetPatternExtra>>,
+    ) -> Self {
+        self.target_filter = Some(patterns);
+        self
+    }
+
+    /// Have `finish` also compute the critical paths through only analysis nodes and through
+    /// only execution nodes.
+    pub(crate) fn with_phase_critical_paths(mut self) -> Self {
+        self.phase_critical_paths = true;
+        self
+    }
+
+    /// Have `finish` also compute the critical path through only loading packages and configuring
+    /// targets.
+    pub(crate) fn with_configuration_critical_path(mut self) -> Self {
+        self.configuration_critical_path = true;
+        self
+    }
+
+    /// Have `finish` hand back the graph it computed the critical path over, so that we can ask
+    /// what the critical path would have been had some node been free. This keeps the whole
+    /// graph alive for as long as the `BuildInfo` is.
+    pub(crate) fn with_retained_graph(mut self) -> Self {
+        self.retain_graph = true;
+        self
+    }
+
+    pub(crate) fn with_duration_policy(mut self, policy: DurationPolicy) -> Self {
+        self.duration_policy = policy;
+        self
+    }
+
+    /// Find the chain of nodes that moved the most data to and from RE, instead of the one that
+    /// took the longest. Potentials are then expressed in bytes, and reported separately.
+    pub(crate) fn with_transferred_bytes_weight(mut self) -> Self {
+        self.weight = NodeWeight::TransferredBytes;
+        self
+    }
+
+    /// Find the chain of actions that produced the most output, instead of the one that took the
+    /// longest. Like with `with_transferred_bytes_weight`, potentials are then in bytes.
+    pub(crate) fn with_output_bytes_weight(mut self) -> Self {
+        self.weight = NodeWeight::OutputBytes;
+        self
+    }
+
+    /// Have `finish` remove nodes that weigh nothing (e.g. analyses without activation data,
+    /// ensure and deferred keys) before computing the critical path, connecting their dependents
+    /// directly to their dependencies. This leaves a smaller graph, and a critical path made only
+    /// of nodes that cost something.
+    pub(crate) fn with_zero_weight_contraction(mut self) -> Self {
+        self.contract_zero_weight = true;
+        self
+    }
+
+    /// Have `finish` count how many top level targets depend on each analysis on the critical
+    /// path, so that the cost of analyses shared by several targets can be split between them.
+    pub(crate) fn with_analysis_dependents(mut self) -> Self {
+        self.count_analysis_dependents = true;
+        self
+    }
+
+    /// Have `finish` report how many dependency hops away from the nearest top level target each
+    /// node on the critical path is.
+    pub(crate) fn with_hops_from_top_level(mut self) -> Self {
+        self.count_hops_from_top_level = true;
+        self
+    }
+
+    /// Have `finish` also simulate running the build on `cores` cores, which tells us how long it
+    /// would take on a given machine rather than with unlimited parallelism.
+    pub(crate) fn with_simulated_schedule(mut self, cores: usize) -> Self {
+        self.simulated_cores = Some(cores);
+        self
+    }
+
+    /// Have `finish` report how many nodes have how much slack before they would lengthen the
+    /// critical path.
+    pub(crate) fn with_slack_histogram(mut self) -> Self {
+        self.compute_slack_histogram = true;
+        self
+    }
+
+    /// Have `finish` find the nodes that are on every longest path, as opposed to only on the one
+    /// we happened to pick as the critical path. Those are the true bottlenecks: nothing else
+    /// overlaps them on a longest path, so making the build faster requires making them faster.
+    pub(crate) fn with_always_critical(mut self) -> Self {
+        self.compute_always_critical = true;
+        self
+    }
+
+    /// Have `finish` report up to `count` of the nodes that have the least slack without being
+    /// critical, which are what would hold the build back next if the critical path got faster.
+    pub(crate) fn with_near_critical(mut self, count: usize) -> Self {
+        self.near_critical_count = Some(count);
+        self
+    }
+
+    /// Add a fixed cost to every node of a kind (as `NodeKey::kind` names it) when looking for the
+    /// longest path, and have `finish` report how much of the critical path those costs make up.
+    /// This only applies when weighing nodes by duration.
+    pub(crate) fn with_cost_floors(mut self, floors: &HashMap<String, Duration>) -> Self {
+        self.cost_floors = floors
+            .iter()
+            .map(|(kind, floor)| (kind.clone(), duration_weight(*floor)))
+            .collect();
+        self
+    }
+}
+
+/// The graph we computed the critical path over, retained past `finish`.
+pub(crate) struct RetainedGraph {
+    graph: Graph,
+    keys: VertexKeys<NodeKey>,
+    data: VertexData<NodeData>,
+    durations: VertexData<u64>,
+    top_level_analysis: Vec<VisibilityEdge>,
+}
+
+impl RetainedGraph {
+    /// Find a node by the way we display its key.
+    pub(crate) fn find_key(&self, key: &str) -> Option<&NodeKey> {
+        self.graph
+            .iter_vertices()
+            .map(|i| &self.keys[i])
+            .find(|k| k.to_string() == key)
+    }
+
+    /// Recompute the critical path as if `key` had taken no time, to find what the bottleneck
+    /// would be if it went away. Returns `None` if `key` isn't in the graph.
+    pub(crate) fn critical_path_without(
+        &self,
+        key: &NodeKey,
+    ) -> anyhow::Result<Option<Vec<(NodeKey, NodeData)>>> {
+        let zeroed = match self.keys.get(key) {
+            Some(i) => i,
+            None => return Ok(None),
+        };
+
+        let mut durations = self.durations.clone();
+        durations[zeroed] = 0;
+
+        let (critical_path, _critical_path_cost) = compute_critical_path(&self.graph, &durations)
+            .context("Error computing critical path")?;
+
+        Ok(Some(
+            critical_path
+                .values()
+                .map(|i| (self.keys[*i].dupe(), self.data[*i].clone()))
+                .collect(),
+        ))
+    }
+
+    /// Recompute the critical path as if only the top level targets in `targets` had been
+    /// requested, i.e. through only what they required. Targets are matched by their label, with
+    /// or without configuration. Work that another target's analysis made visible first still
+    /// comes after that analysis, since that is how we discovered it.
+    pub(crate) fn critical_path_for_targets(
+        &self,
+        targets: &[String],
+    ) -> anyhow::Result<Vec<(NodeKey, NodeData)>> {
+        let focused = self
+            .top_level_analysis
+            .iter()
+            .filter(|visibility| match &visibility.node {
+                NodeKey::AnalysisKey(key) => targets.iter().any(|target| {
+                    *target == key.0.to_string() || *target == key.0.unconfigured().to_string()
+                }),
+                _ => false,
+            })
+            .collect::<Vec<_>>();
+        if focused.is_empty() {
+            return Ok(Vec::new());
         }
+
+        let required = required_by_top_level_targets(&self.graph, &self.keys, focused);
+        weighted_subgraph_critical_path(
+            &self.graph,
+            &self.keys,
+            &self.data,
+            |k| self.keys.get(k).map_or(false, |i| required[i]),
+            |i| self.durations[i],
+        )
     }
 }
 
@@ -54,25 +316,18 @@ impl BuildListenerBackend for LongestPathGraphBackend {
     fn process_node(
         &mut self,
         key: NodeKey,
-        action: Option<Arc<RegisteredAction>>,
-        duration: NodeDuration,
+        data: NodeData,
         dep_keys: impl IntoIterator<Item = NodeKey>,
-        span_ids: SmallVec<[SpanId; 1]>,
     ) {
         let builder = match self.builder.as_mut() {
             Ok(b) => b,
             Err(..) => return,
         };
 
-        let res = builder.push(
-            key,
-            dep_keys,
-            NodeData {
-                action,
-                duration,
-                span_ids,
-            },
-        );
+        let dep_keys = dep_keys
+            .into_iter()
+            .filter(|dep| !is_self_dependency(&key, dep));
+        let res = builder.push(key.dupe(), dep_keys, data);
 
         let res = res.or_else(|err| match err {
             e @ PushError::Overflow => Err(e.into()),
@@ -99,10 +354,27 @@ impl BuildListenerBackend for LongestPathGraphBackend {
         })
     }
 
-    fn finish(self) -> anyhow::Result<BuildInfo> {
+    fn process_failed_action(&mut self, key: NodeKey) {
+        if self.failed_action.is_none() {
+            self.failed_action = Some(key);
+        }
+    }
+
+    fn finish(mut self, deadline: Option<Instant>) -> anyhow::Result<BuildInfo> {
         let (graph, keys, mut data) = {
             let (graph, keys, data) = self.builder?.finish();
 
+            let (graph, keys, data) = match &self.target_filter {
+                Some(patterns) => {
+                    // We only honor visibility edges for the targets we care about.
+                    self.top_level_analysis
+                        .retain(|visibility| matches_any(&visibility.node, patterns));
+                    restrict_to_top_level_targets(graph, keys, data, &self.top_level_analysis)
+                        .context("Error restricting graph to the requested targets")?
+                }
+                None => (graph, keys, data),
+            };
+
             let mut first_analysis = graph.allocate_vertex_data(OptionalVertexId::none());
             let mut n = 0;
 
@@ -160,28 +432,262 @@ impl BuildListenerBackend for LongestPathGraphBackend {
                 .add_edges(&first_analysis, n)
                 .context("Error adding first_analysis edges to graph")?;
 
-            (graph, keys, data)
+            // We contract after adding the `first_analysis` edges so that the nodes we remove
+            // carry those over to their dependents too.
+            if self.contract_zero_weight {
+                contract_zero_weight_vertices(graph, &keys, data, |d| {
+                    node_weight(self.weight, self.duration_policy, d) == 0
+                })
+                .context("Error removing zero weight nodes from graph")?
+            } else {
+                (graph, keys, data)
+            }
         };
 
-        let durations = data.try_map_ref(|d| {
-            d.duration
-                .critical_path_duration()
-                .as_micros()
-                .try_into()
-                .context("Duration `as_micros()` exceeds u64")
-        })?;
+        let exported_graph = self
+            .export_graph
+            .map(|options| ExportedGraph::new(&graph, &keys, &data, options, self.duration_policy));
 
-        let (critical_path, critical_path_cost, replacement_durations) =
-            compute_critical_path_potentials(&graph, &durations)
+        let mut durations = data.map_ref(|d| node_weight(self.weight, self.duration_policy, d));
+        let cost_floor = |key: &NodeKey| match self.weight {
+            NodeWeight::Duration => self.cost_floors.get(key.kind()).copied().unwrap_or(0),
+            NodeWeight::TransferredBytes | NodeWeight::OutputBytes => 0,
+        };
+        if !self.cost_floors.is_empty() {
+            for i in graph.iter_vertices() {
+                durations[i] = durations[i].saturating_add(cost_floor(&keys[i]));
+            }
+        }
+
+        let potentials =
+            compute_critical_path_potentials_with_deadline(&graph, &durations, deadline)
                 .context("Error computing critical path potentials")?;
 
-        drop(durations);
+        // If we ran out of time computing potentials, fall back to just the critical path, which
+        // is much cheaper to obtain.
+        let (critical_path, potentials_timed_out) = match potentials {
+            Some((critical_path, critical_path_cost, replacement_durations)) => {
+                let critical_path = critical_path
+                    .iter()
+                    .map(|(cp_idx, vertex_idx)| {
+                        let potential = checked_potential(
+                            &keys[*vertex_idx],
+                            critical_path_cost.runtime,
+                            replacement_durations[cp_idx].runtime,
+                            durations[*vertex_idx],
+                        );
+                        (*vertex_idx, Some(potential))
+                    })
+                    .collect::<Vec<_>>();
+                (critical_path, false)
+            }
+            None => {
+                let (critical_path, _critical_path_cost) =
+                    compute_critical_path(&graph, &durations)
+                        .context("Error computing critical path")?;
+                let critical_path = critical_path
+                    .values()
+                    .map(|vertex_idx| (*vertex_idx, None))
+                    .collect::<Vec<_>>();
+                (critical_path, true)
+            }
+        };
 
-        let critical_path = critical_path
+        let slack = if self.compute_slack_histogram || self.near_critical_count.is_some() {
+            Some(vertex_slack(&graph, &durations).context("Error computing slack")?)
+        } else {
+            None
+        };
+
+        let slack_histogram = match &slack {
+            Some((cost, slack)) if self.compute_slack_histogram => {
+                slack_histogram(&graph, slack, *cost)
+            }
+            _ => Vec::new(),
+        };
+
+        let near_critical = match (&slack, self.near_critical_count) {
+            (Some((_, slack)), Some(count)) => near_critical(&graph, &durations, slack, count)
+                .into_iter()
+                .map(|i| {
+                    // Like potentials, slack is in whatever unit we weighed nodes by.
+                    let slack = match self.weight {
+                        NodeWeight::Duration => Some(Duration::from_micros(slack[i])),
+                        NodeWeight::TransferredBytes | NodeWeight::OutputBytes => None,
+                    };
+                    (keys[i].dupe(), data[i].clone(), slack)
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let always_critical_vertices = if self.compute_always_critical {
+            Some(
+                always_critical(&graph, &durations)
+                    .context("Error computing always critical nodes")?,
+            )
+        } else {
+            None
+        };
+
+        let retained_durations = if self.retain_graph {
+            Some(durations)
+        } else {
+            drop(durations);
+            None
+        };
+
+        let (analysis_critical_path, execution_critical_path) = if self.phase_critical_paths {
+            (
+                subgraph_critical_path(
+                    &graph,
+                    &keys,
+                    &data,
+                    |k| k.phase() == Some(NodePhase::Analysis),
+                    self.duration_policy,
+                )
+                .context("Error computing analysis critical path")?,
+                subgraph_critical_path(
+                    &graph,
+                    &keys,
+                    &data,
+                    |k| k.phase() == Some(NodePhase::Execution),
+                    self.duration_policy,
+                )
+                .context("Error computing execution critical path")?,
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        let configuration_critical_path = if self.configuration_critical_path {
+            subgraph_critical_path(
+                &graph,
+                &keys,
+                &data,
+                NodeKey::is_configuration,
+                self.duration_policy,
+            )
+            .context("Error computing configuration critical path")?
+        } else {
+            Vec::new()
+        };
+
+        let failure_critical_path = match self.failed_action.as_ref().and_then(|k| keys.get(k)) {
+            Some(failed) => {
+                let ancestors = ancestors(&graph, failed);
+                subgraph_critical_path(
+                    &graph,
+                    &keys,
+                    &data,
+                    |k| keys.get(k).map_or(false, |i| ancestors[i]),
+                    self.duration_policy,
+                )
+                .context("Error computing critical path to failure")?
+            }
+            None => Vec::new(),
+        };
+
+        let simulated_schedule = match self.simulated_cores {
+            Some(cores) => {
+                let durations = data
+                    .map_ref(|d| duration_weight(d.duration.duration_for(self.duration_policy)));
+                let (makespan, bottleneck) = simulate_schedule(&graph, &durations, cores)
+                    .context("Error simulating schedule")?;
+                Some(SimulatedSchedule {
+                    core_count: cores,
+                    makespan: Duration::from_micros(makespan),
+                    bottleneck: bottleneck
+                        .into_iter()
+                        .map(|i| (keys[i].dupe(), data[i].clone()))
+                        .collect(),
+                })
+            }
+            None => None,
+        };
+
+        let critical_path_vertices = critical_path
             .iter()
-            .map(|(cp_idx, vertex_idx)| {
-                let vertex_idx = *vertex_idx;
+            .map(|(vertex_idx, _)| *vertex_idx)
+            .collect::<Vec<_>>();
+
+        let top_level_targets = top_level_targets_reaching(
+            &graph,
+            &keys,
+            &self.top_level_analysis,
+            &critical_path_vertices,
+        );
+
+        let analysis_dependents = if self.count_analysis_dependents {
+            analysis_dependents(
+                &graph,
+                &keys,
+                &self.top_level_analysis,
+                &critical_path_vertices,
+            )
+        } else {
+            Vec::new()
+        };
+
+        let hops_from_top_level = if self.count_hops_from_top_level {
+            hops_from_top_level(
+                &graph,
+                &keys,
+                &self.top_level_analysis,
+                &critical_path_vertices,
+            )
+        } else {
+            Vec::new()
+        };
+
+        let (on_every_critical_path, always_critical) = match &always_critical_vertices {
+            Some(always_critical) => (
+                critical_path_vertices
+                    .iter()
+                    .map(|i| always_critical[*i])
+                    .collect(),
+                graph
+                    .iter_vertices()
+                    .filter(|i| always_critical[*i])
+                    .map(|i| (keys[i].dupe(), data[i].clone()))
+                    .collect(),
+            ),
+            None => (Vec::new(), Vec::new()),
+        };
+
+        let last_blockers = last_blockers(&graph, &data, &critical_path_vertices);
+
+        let critical_path_overhead = if self.cost_floors.is_empty() {
+            None
+        } else {
+            Some(Duration::from_micros(
+                critical_path
+                    .iter()
+                    .map(|(i, _)| cost_floor(&keys[*i]))
+                    .fold(0, u64::saturating_add),
+            ))
+        };
+
+        // Potentials are in whatever unit we weighed nodes by.
+        let potential_improvement_bytes = match self.weight {
+            NodeWeight::Duration => Vec::new(),
+            NodeWeight::TransferredBytes | NodeWeight::OutputBytes => {
+                critical_path.iter().map(|(_, p)| *p).collect()
+            }
+        };
+
+        let critical_path = critical_path
+            .into_iter()
+            .map(|(vertex_idx, potential)| {
                 let key = keys[vertex_idx].dupe();
+                let potential = match self.weight {
+                    NodeWeight::Duration => potential.map(Duration::from_micros),
+                    NodeWeight::TransferredBytes | NodeWeight::OutputBytes => None,
+                };
+
+                if retained_durations.is_some() {
+                    return (key, data[vertex_idx].clone(), potential);
+                }
 
                 // OK to replace `data` with empty things here because we know that we will not access
                 // the same index twice.
@@ -191,19 +697,54 @@ impl BuildListenerBackend for LongestPathGraphBackend {
                         action: None,
                         duration: NodeDuration::zero(),
                         span_ids: Default::default(),
+                        omitted_span_count: 0,
+                        finish_offset: None,
+                        transferred_bytes: 0,
+                        output_bytes: 0,
+                        cache_miss_reason: None,
+                        re_queue_duration: None,
+                        reused: false,
+                        materialized_bytes: None,
                     },
                 );
 
-                let potential = critical_path_cost.runtime - replacement_durations[cp_idx].runtime;
-
-                (key, data, Some(Duration::from_micros(potential)))
+                (key, data, potential)
             })
             .collect();
 
+        let num_nodes = graph.vertices_count() as _;
+        let num_edges = graph.edges_count() as _;
+
+        let retained_graph = retained_durations.map(|durations| RetainedGraph {
+            graph,
+            keys,
+            data,
+            durations,
+            top_level_analysis: self.top_level_analysis,
+        });
+
         Ok(BuildInfo {
             critical_path,
-            num_nodes: graph.vertices_count() as _,
-            num_edges: graph.edges_count() as _,
+            num_nodes,
+            num_edges,
+            potentials_timed_out,
+            exported_graph,
+            top_level_targets,
+            analysis_critical_path,
+            execution_critical_path,
+            configuration_critical_path,
+            retained_graph,
+            potential_improvement_bytes,
+            analysis_dependents,
+            hops_from_top_level,
+            failure_critical_path,
+            simulated_schedule,
+            slack_histogram,
+            on_every_critical_path,
+            always_critical,
+            near_critical,
+            critical_path_overhead,
+            last_blockers,
         })
     }
 
@@ -211,3 +752,1542 @@ impl BuildListenerBackend for LongestPathGraphBackend {
         CriticalPathBackendName::LongestPathGraph
     }
 }
+
+fn matches_any(analysis: &NodeKey, patterns: &[ParsedPattern<TargetPatternExtra>]) -> bool {
+    match analysis {
+        NodeKey::AnalysisKey(key) => patterns
+            .iter()
+            .any(|pattern| pattern.matches(key.0.unconfigured())),
+        _ => false,
+    }
+}
+
+/// Rebuild the graph with only the vertices required by `top_level_analysis`: those top level
+/// targets' analyses, anything their outputs (transitively) depend on, and the materializations
+/// of those outputs. Vertices are pushed in their original order, so dependencies still come
+/// before their dependents.
+fn restrict_to_top_level_targets(
+    graph: Graph,
+    keys: VertexKeys<NodeKey>,
+    data: VertexData<NodeData>,
+    top_level_analysis: &[VisibilityEdge],
+) -> anyhow::Result<(Graph, VertexKeys<NodeKey>, VertexData<NodeData>)> {
+    let required = required_by_top_level_targets(&graph, &keys, top_level_analysis);
+
+    let mut builder = GraphBuilder::new();
+    for (i, data) in graph.iter_vertices().zip(data.into_inner()) {
+        if !required[i] {
+            continue;
+        }
+
+        builder.push(
+            keys[i].dupe(),
+            graph.iter_edges(i).map(|dep| keys[dep].dupe()),
+            data,
+        )?;
+    }
+
+    Ok(builder.finish())
+}
+
+/// The vertices required by the top level targets in `top_level_analysis`: their analyses,
+/// anything their outputs (transitively) depend on, and the materializations of those outputs.
+fn required_by_top_level_targets<'a>(
+    graph: &Graph,
+    keys: &VertexKeys<NodeKey>,
+    top_level_analysis: impl IntoIterator<Item = &'a VisibilityEdge>,
+) -> VertexData<bool> {
+    let mut required = graph.allocate_vertex_data(false);
+
+    let mut queue = top_level_analysis
+        .into_iter()
+        .flat_map(|visibility| {
+            std::iter::once(&visibility.node).chain(visibility.makes_visible.iter())
+        })
+        .filter_map(|key| keys.get(key))
+        .collect::<Vec<_>>();
+
+    while let Some(i) = queue.pop() {
+        if required[i] {
+            continue;
+        }
+        required[i] = true;
+        queue.extend(graph.iter_edges(i));
+    }
+
+    for i in graph.iter_vertices() {
+        if let NodeKey::Materialization(..) = keys[i] {
+            if graph.iter_edges(i).any(|dep| required[dep]) {
+                required[i] = true;
+            }
+        }
+    }
+
+    required
+}
+
+/// Rebuild the graph without the vertices for which `weighs_nothing` holds, making their
+/// dependents depend on their dependencies instead, so that whatever was reachable from a vertex
+/// we keep still is. Such vertices can't make any path longer, so this doesn't change how long
+/// the critical path is.
+fn contract_zero_weight_vertices(
+    graph: Graph,
+    keys: &VertexKeys<NodeKey>,
+    data: VertexData<NodeData>,
+    weighs_nothing: impl Fn(&NodeData) -> bool,
+) -> anyhow::Result<(Graph, VertexKeys<NodeKey>, VertexData<NodeData>)> {
+    // The `first_analysis` edges can point to vertices that were pushed later, so vertex order
+    // isn't necessarily topological anymore. `topo_sort` puts dependents first.
+    let topo_order = graph.topo_sort()?;
+    let contracted = data.map_ref(&weighs_nothing);
+
+    // For each vertex we contract, the vertices we keep that its dependents depend on through it.
+    let mut kept_deps = graph.allocate_vertex_data(Vec::new());
+    let mut data = data.into_inner().into_iter().map(Some).collect::<Vec<_>>();
+
+    let mut builder = GraphBuilder::new();
+    for i in topo_order.into_iter().rev() {
+        let mut deps = Vec::new();
+        for dep in graph.iter_edges(i) {
+            if contracted[dep] {
+                deps.extend_from_slice(&kept_deps[dep]);
+            } else {
+                deps.push(dep);
+            }
+        }
+        deps.sort_unstable();
+        deps.dedup();
+
+        if contracted[i] {
+            kept_deps[i] = deps;
+            continue;
+        }
+
+        let vertex_data = data[i.as_usize()].take().context("Vertex visited twice")?;
+        builder.push(
+            keys[i].dupe(),
+            deps.into_iter().map(|dep| keys[dep].dupe()),
+            vertex_data,
+        )?;
+    }
+
+    Ok(builder.finish())
+}
+
+/// Compute the critical path through the subgraph of nodes whose key satisfies `include` (e.g.
+/// the nodes in one phase of the build). Edges to nodes outside the subgraph are dropped, so
+/// this is the longest chain of work we did exclusively in it.
+fn subgraph_critical_path(
+    graph: &Graph,
+    keys: &VertexKeys<NodeKey>,
+    data: &VertexData<NodeData>,
+    include: impl Fn(&NodeKey) -> bool,
+    duration_policy: DurationPolicy,
+) -> anyhow::Result<Vec<(NodeKey, NodeData)>> {
+    weighted_subgraph_critical_path(graph, keys, data, include, |i| {
+        duration_weight(data[i].duration.duration_for(duration_policy))
+    })
+}
+
+/// Like `subgraph_critical_path`, but with each vertex weighing `weight` of it.
+fn weighted_subgraph_critical_path(
+    graph: &Graph,
+    keys: &VertexKeys<NodeKey>,
+    data: &VertexData<NodeData>,
+    include: impl Fn(&NodeKey) -> bool,
+    weight: impl Fn(VertexId) -> u64,
+) -> anyhow::Result<Vec<(NodeKey, NodeData)>> {
+    let in_subgraph = |i: VertexId| include(&keys[i]);
+
+    // The `first_analysis` edges can point to vertices that were pushed later, so vertex order
+    // isn't necessarily topological, and the builder would drop those edges. `topo_sort` puts
+    // dependents first.
+    let mut builder = GraphBuilder::new();
+    for i in graph.topo_sort()?.into_iter().rev() {
+        if !in_subgraph(i) {
+            continue;
+        }
+
+        builder.push(
+            keys[i].dupe(),
+            graph
+                .iter_edges(i)
+                .filter(|dep| in_subgraph(*dep))
+                .map(|dep| keys[dep].dupe()),
+            i,
+        )?;
+    }
+
+    let (subgraph, _subgraph_keys, original) = builder.finish();
+
+    let durations = original.map_ref(|i| weight(*i));
+
+    let (critical_path, _critical_path_cost) = compute_critical_path(&subgraph, &durations)?;
+
+    Ok(critical_path
+        .values()
+        .map(|vertex_idx| {
+            let i = original[*vertex_idx];
+            (keys[i].dupe(), data[i].clone())
+        })
+        .collect())
+}
+
+/// For each vertex in `path`, find the top level targets that required it. A top level target
+/// requires its own analysis, as well as anything producing artifacts that its artifacts
+/// (transitively) depend on. This traverses the same edges as the `first_analysis` labeling, but
+/// doesn't stop at nodes that were already reached by another target.
+fn top_level_targets_reaching(
+    graph: &Graph,
+    keys: &VertexKeys<NodeKey>,
+    top_level_analysis: &[VisibilityEdge],
+    path: &[VertexId],
+) -> Vec<Vec<ConfiguredTargetLabel>> {
+    let mut position = graph.allocate_vertex_data(None);
+    for (i, vertex_idx) in path.iter().enumerate() {
+        position[*vertex_idx] = Some(i);
+    }
+
+    let mut res = vec![Vec::new(); path.len()];
+    // Which top level target last visited each vertex, so that we don't need to reallocate this
+    // for each target.
+    let mut visited = graph.allocate_vertex_data(usize::MAX);
+
+    for (generation, visibility) in top_level_analysis.iter().enumerate() {
+        let label = match &visibility.node {
+            NodeKey::AnalysisKey(key) => &key.0,
+            _ => continue,
+        };
+
+        let reached = |i: VertexId, res: &mut Vec<Vec<ConfiguredTargetLabel>>| {
+            if let Some(p) = position[i] {
+                let targets = &mut res[p];
+                if !targets.contains(label) {
+                    targets.push(label.dupe());
+                }
+            }
+        };
+
+        if let Some(analysis) = keys.get(&visibility.node) {
+            reached(analysis, &mut res);
+        }
+
+        let mut queue = visibility
+            .makes_visible
+            .iter()
+            .filter_map(|artifact| keys.get(artifact))
+            .collect::<Vec<_>>();
+
+        while let Some(i) = queue.pop() {
+            if visited[i] == generation {
+                continue;
+            }
+
+            match keys[i] {
+                NodeKey::BuildKey(..)
+                | NodeKey::EnsureTransitiveSetProjectionKey(..)
+                | NodeKey::EnsureProjectedArtifactKey(..) => {}
+                _ => {
+                    continue;
+                }
+            };
+
+            visited[i] = generation;
+            reached(i, &mut res);
+            queue.extend(graph.iter_edges(i));
+        }
+    }
+
+    res
+}
+
+/// Find `vertex` and everything it (transitively) depends on.
+fn ancestors(graph: &Graph, vertex: VertexId) -> VertexData<bool> {
+    let mut ancestors = graph.allocate_vertex_data(false);
+    let mut queue = vec![vertex];
+    while let Some(i) = queue.pop() {
+        if ancestors[i] {
+            continue;
+        }
+        ancestors[i] = true;
+        queue.extend(graph.iter_edges(i));
+    }
+    ancestors
+}
+
+/// Upper bounds on slack, as fractions of the critical path's cost, of the buckets we count nodes
+/// into in `slack_histogram`.
+const SLACK_HISTOGRAM_BOUNDS: [f32; 6] = [0.01, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// For each vertex, how much its duration could grow before the longest path through `graph` would
+/// get longer. This is the longest path's cost minus that of the longest path through the vertex.
+/// Returns the longest path's cost along with the slack.
+fn vertex_slack(
+    graph: &Graph,
+    durations: &VertexData<u64>,
+) -> anyhow::Result<(u64, VertexData<u64>)> {
+    let (cost, through, _after) = longest_paths_through(graph, durations)?;
+    Ok((cost, through.map_ref(|t| cost.saturating_sub(*t))))
+}
+
+/// For each vertex, the cost of the longest path through it, which is the longest chain of work up
+/// to and including it plus the longest chain after it (which is also returned). Returns the
+/// longest path's cost along with those.
+fn longest_paths_through(
+    graph: &Graph,
+    durations: &VertexData<u64>,
+) -> anyhow::Result<(u64, VertexData<u64>, VertexData<u64>)> {
+    let dependents = graph.reversed();
+    // `topo_sort` puts dependents first.
+    let order = graph.topo_sort()?;
+
+    let mut after = graph.allocate_vertex_data(0u64);
+    for i in order.iter().copied() {
+        after[i] = dependents
+            .iter_edges(i)
+            .map(|d| durations[d].saturating_add(after[d]))
+            .max()
+            .unwrap_or(0);
+    }
+
+    let mut through = graph.allocate_vertex_data(0u64);
+    let mut cost = 0;
+    for i in order.iter().rev().copied() {
+        let before = graph
+            .iter_edges(i)
+            .map(|d| through[d].saturating_sub(after[d]))
+            .max()
+            .unwrap_or(0);
+        through[i] = before.saturating_add(durations[i]).saturating_add(after[i]);
+        cost = cost.max(through[i]);
+    }
+
+    Ok((cost, through, after))
+}
+
+/// Which vertices are on every longest path through `graph`. Every vertex with no slack is on some
+/// longest path, where it runs from its earliest start to its earliest finish, and the vertices
+/// on a longest path run one after the other. So a vertex is on all of them exactly when no other
+/// vertex without slack runs at the same time. Vertices that take no time are never reported:
+/// they can't be what holds the build back.
+fn always_critical(graph: &Graph, durations: &VertexData<u64>) -> anyhow::Result<VertexData<bool>> {
+    let (cost, through, after) = longest_paths_through(graph, durations)?;
+
+    // The (start, finish) of every vertex with no slack, by start.
+    let mut intervals = graph
+        .iter_vertices()
+        .filter(|i| durations[*i] > 0 && through[*i] == cost)
+        .map(|i| {
+            let finish = cost.saturating_sub(after[i]);
+            (finish.saturating_sub(durations[i]), finish, i)
+        })
+        .collect::<Vec<_>>();
+    intervals.sort_by_key(|(start, finish, _)| (*start, *finish));
+
+    let mut always_critical = graph.allocate_vertex_data(false);
+    let mut finished_before = 0;
+    for (idx, (start, finish, i)) in intervals.iter().enumerate() {
+        let overlaps_previous = finished_before > *start;
+        let overlaps_next = intervals
+            .get(idx + 1)
+            .map_or(false, |(next_start, _, _)| next_start < finish);
+        always_critical[*i] = !overlaps_previous && !overlaps_next;
+        finished_before = finished_before.max(*finish);
+    }
+
+    Ok(always_critical)
+}
+
+/// Up to `count` of the vertices with the least slack that have some, by increasing slack (and
+/// otherwise in the order we got them). Like in `always_critical`, vertices that take no time are
+/// never reported.
+fn near_critical(
+    graph: &Graph,
+    durations: &VertexData<u64>,
+    slack: &VertexData<u64>,
+    count: usize,
+) -> Vec<VertexId> {
+    let mut vertices = graph
+        .iter_vertices()
+        .filter(|i| durations[*i] > 0 && slack[*i] > 0)
+        .collect::<Vec<_>>();
+    vertices.sort_by_key(|i| slack[*i]);
+    vertices.truncate(count);
+    vertices
+}
+
+/// Count vertices into buckets by their slack relative to the critical path's `cost`. See
+/// `SLACK_HISTOGRAM_BOUNDS`.
+fn slack_histogram(graph: &Graph, slack: &VertexData<u64>, cost: u64) -> Vec<(f32, u64)> {
+    let mut counts = [0u64; SLACK_HISTOGRAM_BOUNDS.len()];
+    for i in graph.iter_vertices() {
+        let fraction = if cost == 0 {
+            0.0
+        } else {
+            (slack[i] as f64 / cost as f64) as f32
+        };
+        let bucket = SLACK_HISTOGRAM_BOUNDS
+            .iter()
+            .position(|bound| fraction < *bound)
+            .unwrap_or(SLACK_HISTOGRAM_BOUNDS.len() - 1);
+        counts[bucket] += 1;
+    }
+    SLACK_HISTOGRAM_BOUNDS.iter().copied().zip(counts).collect()
+}
+
+/// List-schedule `graph` on `cores` cores: whenever a core is free, start the ready vertex with the
+/// longest chain of work after it (its own duration included). Returns when the last vertex
+/// finishes, and the chain of vertices that determined that, in chronological order: each vertex
+/// in it started when the one before it finished, either because it depended on it or because it
+/// was waiting for a core and that one freed it.
+fn simulate_schedule(
+    graph: &Graph,
+    durations: &VertexData<u64>,
+    cores: usize,
+) -> anyhow::Result<(u64, Vec<VertexId>)> {
+    let cores = cores.max(1);
+    let dependents = graph.reversed();
+
+    // `topo_sort` puts dependents first, so we see them before what they depend on.
+    let mut priority = graph.allocate_vertex_data(0u64);
+    for i in graph.topo_sort()? {
+        let after = dependents
+            .iter_edges(i)
+            .map(|d| priority[d])
+            .max()
+            .unwrap_or(0);
+        priority[i] = durations[i].saturating_add(after);
+    }
+
+    let mut pending_deps = graph.allocate_vertex_data(0usize);
+    let mut ready_at = graph.allocate_vertex_data(0u64);
+    let mut blocked_by = graph.allocate_vertex_data(OptionalVertexId::none());
+    let mut finish = graph.allocate_vertex_data(0u64);
+
+    let mut ready = BinaryHeap::new();
+    for i in graph.iter_vertices() {
+        pending_deps[i] = graph.iter_edges(i).count();
+        if pending_deps[i] == 0 {
+            ready.push((priority[i], Reverse(i)));
+        }
+    }
+
+    let mut running = BinaryHeap::new();
+    let mut now = 0;
+    // The last vertex to finish, which freed the core that whatever we start next runs on.
+    let mut last_freed = OptionalVertexId::none();
+    let mut last = None;
+
+    loop {
+        while running.len() < cores {
+            let Some((_, Reverse(i))) = ready.pop() else {
+                break;
+            };
+            if now > ready_at[i] {
+                blocked_by[i] = last_freed;
+            }
+            finish[i] = now.saturating_add(durations[i]);
+            running.push(Reverse((finish[i], i)));
+        }
+
+        let Some(Reverse((t, _))) = running.peek().copied() else {
+            break;
+        };
+        now = t;
+
+        // Let everything that finishes now make its dependents ready before we pick what to run
+        // next.
+        while let Some(Reverse((t, i))) = running.peek().copied() {
+            if t != now {
+                break;
+            }
+            running.pop();
+            last_freed = i.into();
+            // We pop vertices in the order they finish.
+            last = Some(i);
+
+            for d in dependents.iter_edges(i) {
+                if t >= ready_at[d] {
+                    ready_at[d] = t;
+                    blocked_by[d] = i.into();
+                }
+                pending_deps[d] -= 1;
+                if pending_deps[d] == 0 {
+                    ready.push((priority[d], Reverse(d)));
+                }
+            }
+        }
+    }
+
+    let mut bottleneck = Vec::new();
+    let mut next = last;
+    while let Some(i) = next {
+        bottleneck.push(i);
+        next = blocked_by[i].into_option();
+    }
+    bottleneck.reverse();
+
+    Ok((last.map_or(0, |i| finish[i]), bottleneck))
+}
+
+/// For each analysis in `path`, count the top level targets that (transitively) depend on it, i.e.
+/// whose own analysis or requested artifacts reach it, including via `first_analysis` edges.
+/// Other vertices get `None`. Unlike `top_level_targets_reaching`, this follows all edges, since
+/// analyses are reached through other analyses.
+fn analysis_dependents(
+    graph: &Graph,
+    keys: &VertexKeys<NodeKey>,
+    top_level_analysis: &[VisibilityEdge],
+    path: &[VertexId],
+) -> Vec<Option<u64>> {
+    // The top level targets that each vertex is a starting point for.
+    let mut roots = graph.allocate_vertex_data(Vec::new());
+    for (target, visibility) in top_level_analysis.iter().enumerate() {
+        for key in std::iter::once(&visibility.node).chain(visibility.makes_visible.iter()) {
+            if let Some(i) = keys.get(key) {
+                roots[i].push(target);
+            }
+        }
+    }
+
+    let dependents = graph.reversed();
+    // Which path vertex last visited each vertex, so that we don't need to reallocate this for
+    // each of them.
+    let mut visited = graph.allocate_vertex_data(usize::MAX);
+
+    path.iter()
+        .enumerate()
+        .map(|(generation, vertex_idx)| {
+            if !matches!(keys[*vertex_idx], NodeKey::AnalysisKey(..)) {
+                return None;
+            }
+
+            let mut reached = vec![false; top_level_analysis.len()];
+            let mut queue = vec![*vertex_idx];
+            while let Some(i) = queue.pop() {
+                if visited[i] == generation {
+                    continue;
+                }
+                visited[i] = generation;
+
+                for target in &roots[i] {
+                    reached[*target] = true;
+                }
+                queue.extend(dependents.iter_edges(i));
+            }
+
+            Some(reached.iter().filter(|r| **r).count() as u64)
+        })
+        .collect()
+}
+
+/// For each vertex in `path`, find the fewest dependency hops from a top level analysis to it, by
+/// breadth-first search from all of them at once. As in the `first_analysis` labeling, the
+/// artifacts an analysis made visible are one hop away from it. Vertices that no top level target
+/// reaches get `None`.
+fn hops_from_top_level(
+    graph: &Graph,
+    keys: &VertexKeys<NodeKey>,
+    top_level_analysis: &[VisibilityEdge],
+    path: &[VertexId],
+) -> Vec<Option<u32>> {
+    let mut hops = graph.allocate_vertex_data(None);
+    let mut queue = VecDeque::new();
+
+    // Queue up all the analyses before any artifacts, so that we visit vertices in order of hops.
+    for visibility in top_level_analysis {
+        if let Some(i) = keys.get(&visibility.node) {
+            queue.push_back((i, 0));
+        }
+    }
+    for visibility in top_level_analysis {
+        queue.extend(
+            visibility
+                .makes_visible
+                .iter()
+                .filter_map(|artifact| keys.get(artifact))
+                .map(|i| (i, 1)),
+        );
+    }
+
+    while let Some((i, h)) = queue.pop_front() {
+        if hops[i].is_some() {
+            continue;
+        }
+        hops[i] = Some(h);
+        queue.extend(graph.iter_edges(i).map(|dep| (dep, h + 1)));
+    }
+
+    path.iter().map(|i| hops[*i]).collect()
+}
+
+/// For each vertex on `path` but the last, whether it finished last among the dependencies of the
+/// vertex after it, which therefore could not start before it did. Such a vertex is on the path
+/// because of when it finished, which may be because it started late rather than because it was
+/// slow. Vertices get `None` if we don't know when they or the other dependencies finished.
+fn last_blockers(
+    graph: &Graph,
+    data: &VertexData<NodeData>,
+    path: &[VertexId],
+) -> Vec<Option<bool>> {
+    path.iter()
+        .enumerate()
+        .map(|(idx, i)| {
+            let next = *path.get(idx + 1)?;
+            let finish = data[*i].finish_offset?;
+            let mut last = true;
+            for dep in graph.iter_edges(next) {
+                if data[dep].finish_offset? > finish {
+                    last = false;
+                }
+            }
+            Some(last)
+        })
+        .collect()
+}
+
+/// How much a node weighs when looking for the longest path.
+fn node_weight(weight: NodeWeight, duration_policy: DurationPolicy, data: &NodeData) -> u64 {
+    match weight {
+        NodeWeight::Duration => duration_weight(data.duration.duration_for(duration_policy)),
+        NodeWeight::TransferredBytes => data.transferred_bytes,
+        NodeWeight::OutputBytes => data.output_bytes,
+    }
+}
+
+/// How much a node that took `duration` weighs when looking for the longest path, i.e. its
+/// duration in microseconds. Durations too long to represent (which indicates a bug in whatever
+/// measured them) are reported and saturate, rather than failing the whole computation.
+fn duration_weight(duration: Duration) -> u64 {
+    match duration.as_micros().try_into() {
+        Ok(micros) => micros,
+        Err(_) => {
+            let _ignored = soft_error!(
+                "critical_path_duration_overflow",
+                anyhow::anyhow!("Duration `{:?}` exceeds u64 microseconds", duration),
+                quiet: true
+            );
+            u64::MAX
+        }
+    }
+}
+
+/// The potential improvement of a node on the critical path, i.e. how much shorter the critical
+/// path would be if the node was free, given the cost of the critical path with and without it.
+/// Making a node free can't save more than the node costs, let alone more than the whole path, so
+/// if that happens there is a bug in the potentials math, which we report.
+fn checked_potential(key: &NodeKey, path_cost: u64, replacement_cost: u64, node_cost: u64) -> u64 {
+    let potential = match path_cost.checked_sub(replacement_cost) {
+        Some(potential) => potential,
+        None => {
+            let _ignored = soft_error!(
+                "critical_path_potential_exceeds_path",
+                anyhow::anyhow!(
+                    "Critical path without `{}` costs {}, which exceeds the critical path total ({})",
+                    key,
+                    replacement_cost,
+                    path_cost
+                ),
+                quiet: true
+            );
+            return 0;
+        }
+    };
+
+    if potential > node_cost {
+        let _ignored = soft_error!(
+            "critical_path_potential_exceeds_node",
+            anyhow::anyhow!(
+                "Potential improvement for `{}` ({}) exceeds its own cost ({})",
+                key,
+                potential,
+                node_cost
+            ),
+            quiet: true
+        );
+    }
+
+    potential
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_analysis::analysis::calculation::AnalysisKey;
+    use buck2_artifact::actions::key::ActionKey;
+    use buck2_artifact::deferred::id::DeferredId;
+    use buck2_artifact::deferred::key::DeferredKey;
+    use buck2_build_api::actions::calculation::BuildKey;
+    use buck2_common::package_listing::dice::PackageListingKey;
+    use buck2_configured::nodes::calculation::ConfiguredTargetNodeKey;
+    use buck2_core::base_deferred_key::BaseDeferredKey;
+    use buck2_core::configuration::data::ConfigurationData;
+    use buck2_core::package::PackageLabel;
+    use buck2_interpreter_for_build::interpreter::calculation::InterpreterResultsKey;
+
+    use super::*;
+
+    fn key(i: usize) -> NodeKey {
+        NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new(
+            "cell",
+            &format!("pkg{}", i),
+        )))
+    }
+
+    /// A chain of `len` nodes, each taking one millisecond.
+    fn chain(len: usize) -> LongestPathGraphBackend {
+        let mut backend = LongestPathGraphBackend::new();
+
+        for i in 0..len {
+            let deps = if i > 0 { vec![key(i - 1)] } else { vec![] };
+            backend.process_node(
+                key(i),
+                NodeData::testing_new(Duration::from_millis(1)),
+                deps,
+            );
+        }
+
+        backend
+    }
+
+    #[test]
+    fn test_potentials_deadline_fallback() -> anyhow::Result<()> {
+        let info = chain(10_000).finish(Some(Instant::now()))?;
+
+        assert!(info.potentials_timed_out);
+        assert_eq!(info.critical_path.len(), 10_000);
+        assert!(info
+            .critical_path
+            .iter()
+            .all(|(_key, _data, potential)| potential.is_none()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_potentials_no_deadline() -> anyhow::Result<()> {
+        let info = chain(100).finish(None)?;
+
+        assert!(!info.potentials_timed_out);
+        assert_eq!(info.critical_path.len(), 100);
+        assert!(info
+            .critical_path
+            .iter()
+            .all(|(_key, _data, potential)| potential.is_some()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_memory_usage_grows_with_graph() {
+        let small = allocative::size_of_unique(&chain(10));
+        let large = allocative::size_of_unique(&chain(1000));
+
+        // Each node holds at least its key and data.
+        assert!(large > small);
+        assert!(
+            large - small
+                >= 990 * (std::mem::size_of::<NodeKey>() + std::mem::size_of::<NodeData>())
+        );
+    }
+
+    struct TwoTargets {
+        a: ConfiguredTargetLabel,
+        b: ConfiguredTargetLabel,
+        analysis_a: NodeKey,
+        analysis_b: NodeKey,
+        shared: NodeKey,
+        a_out: NodeKey,
+        b_out: NodeKey,
+    }
+
+    /// Two top level targets, `a` and `b`, which both depend on `shared`. `a` is slower.
+    fn two_targets(mut backend: LongestPathGraphBackend) -> (LongestPathGraphBackend, TwoTargets) {
+        let target = |name: &str| {
+            ConfiguredTargetLabel::testing_parse(name, ConfigurationData::testing_new())
+        };
+        let action = |owner: &ConfiguredTargetLabel, id: u32| {
+            NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+                BaseDeferredKey::TargetLabel(owner.dupe()),
+                DeferredId::testing_new(id),
+            ))))
+        };
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        let a = target("cell//pkg:a");
+        let b = target("cell//pkg:b");
+        let t = TwoTargets {
+            analysis_a: NodeKey::AnalysisKey(AnalysisKey(a.dupe())),
+            analysis_b: NodeKey::AnalysisKey(AnalysisKey(b.dupe())),
+            shared: action(&a, 0),
+            a_out: action(&a, 1),
+            b_out: action(&b, 2),
+            a,
+            b,
+        };
+
+        backend.process_node(t.analysis_a.dupe(), data(1), std::iter::empty());
+        backend.process_node(t.analysis_b.dupe(), data(1), std::iter::empty());
+        backend.process_node(t.shared.dupe(), data(1), std::iter::empty());
+        backend.process_node(t.a_out.dupe(), data(2), std::iter::once(t.shared.dupe()));
+        backend.process_node(t.b_out.dupe(), data(1), std::iter::once(t.shared.dupe()));
+        backend.process_top_level_target(t.analysis_a.dupe(), std::iter::once(t.a_out.dupe()));
+        backend.process_top_level_target(t.analysis_b.dupe(), std::iter::once(t.b_out.dupe()));
+
+        (backend, t)
+    }
+
+    #[test]
+    fn test_top_level_targets() -> anyhow::Result<()> {
+        let (backend, t) = two_targets(LongestPathGraphBackend::new());
+        let info = backend.finish(None)?;
+
+        let path = info
+            .critical_path
+            .iter()
+            .map(|(key, _, _)| key.dupe())
+            .zip(info.top_level_targets.iter().cloned())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            path,
+            vec![
+                (t.analysis_a, vec![t.a.dupe()]),
+                (t.shared, vec![t.a.dupe(), t.b.dupe()]),
+                (t.a_out, vec![t.a.dupe()]),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_target_filter() -> anyhow::Result<()> {
+        let (backend, t) = two_targets(
+            LongestPathGraphBackend::new()
+                .with_target_filter(vec![ParsedPattern::testing_parse("cell//pkg:b")]),
+        );
+        let info = backend.finish(None)?;
+
+        assert_eq!(info.num_nodes, 3);
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![t.analysis_b, t.shared, t.b_out]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_phase_critical_paths() -> anyhow::Result<()> {
+        let (backend, _t) = two_targets(LongestPathGraphBackend::new());
+        let info = backend.finish(None)?;
+        assert!(info.analysis_critical_path.is_empty());
+        assert!(info.execution_critical_path.is_empty());
+
+        let (backend, t) = two_targets(LongestPathGraphBackend::new().with_phase_critical_paths());
+        let info = backend.finish(None)?;
+
+        // Both analyses take as long, and neither depends on the other.
+        assert_eq!(info.analysis_critical_path.len(), 1);
+        assert_eq!(
+            info.analysis_critical_path[0].0.phase(),
+            Some(NodePhase::Analysis)
+        );
+        // The edge from `a`'s analysis to its outputs isn't part of either phase.
+        assert_eq!(
+            info.execution_critical_path
+                .iter()
+                .map(|(key, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![t.shared, t.a_out]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_configuration_critical_path() -> anyhow::Result<()> {
+        let target = |name: &str| {
+            ConfiguredTargetLabel::testing_parse(name, ConfigurationData::testing_new())
+        };
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        let (a, b) = (target("cell//pkg:a"), target("cell//pkg:b"));
+        let load = NodeKey::InterpreterResultsKey(InterpreterResultsKey(
+            PackageLabel::testing_new("cell", "pkg"),
+        ));
+        let configure_a = NodeKey::ConfiguredTargetNodeKey(ConfiguredTargetNodeKey(a.dupe()));
+        let configure_b = NodeKey::ConfiguredTargetNodeKey(ConfiguredTargetNodeKey(b.dupe()));
+        let analysis_a = NodeKey::AnalysisKey(AnalysisKey(a.dupe()));
+
+        let mut backend = LongestPathGraphBackend::new().with_configuration_critical_path();
+        backend.process_node(load.dupe(), data(1), std::iter::empty());
+        backend.process_node(configure_a.dupe(), data(2), std::iter::once(load.dupe()));
+        backend.process_node(configure_b.dupe(), data(1), std::iter::once(load.dupe()));
+        // Analysis dominates the overall critical path, but isn't part of configuration.
+        backend.process_node(
+            analysis_a.dupe(),
+            data(10),
+            std::iter::once(configure_a.dupe()),
+        );
+        let info = backend.finish(None)?;
+
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![load.dupe(), configure_a.dupe(), analysis_a]
+        );
+        assert_eq!(
+            info.configuration_critical_path
+                .iter()
+                .map(|(key, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![load, configure_a]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_critical_path_without() -> anyhow::Result<()> {
+        let (backend, t) = two_targets(LongestPathGraphBackend::new());
+        let info = backend.finish(None)?;
+        assert!(info.critical_path_without(&t.a_out)?.is_none());
+
+        let (backend, t) = two_targets(LongestPathGraphBackend::new().with_retained_graph());
+        let info = backend.finish(None)?;
+        assert_eq!(info.find_key(&t.a_out.to_string()), Some(&t.a_out));
+
+        // Without `a_out`, `b_out` becomes the bottleneck.
+        let path = info
+            .critical_path_without(&t.a_out)?
+            .context("Graph was retained")?;
+        assert_eq!(
+            path.iter().map(|(key, _)| key.dupe()).collect::<Vec<_>>(),
+            vec![t.analysis_a, t.shared, t.b_out]
+        );
+
+        // The original critical path is unaffected.
+        assert_eq!(info.critical_path.len(), 3);
+        assert_eq!(info.critical_path[2].0, t.a_out);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_critical_path_for_targets() -> anyhow::Result<()> {
+        let (backend, t) = two_targets(LongestPathGraphBackend::new());
+        let info = backend.finish(None)?;
+        assert!(info
+            .critical_path_for_targets(&[t.b.to_string()])?
+            .is_none());
+
+        let (backend, t) = two_targets(LongestPathGraphBackend::new().with_retained_graph());
+        let info = backend.finish(None)?;
+        let focus = |targets: &[String]| {
+            anyhow::Ok(
+                info.critical_path_for_targets(targets)?
+                    .context("Graph was retained")?
+                    .into_iter()
+                    .map(|(key, _)| key)
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        // `shared` was made visible by `a`'s analysis, so it still comes after it.
+        assert_eq!(
+            focus(&[t.b.unconfigured().to_string()])?,
+            vec![t.analysis_a.dupe(), t.shared.dupe(), t.b_out.dupe()]
+        );
+        assert_eq!(
+            focus(&[t.a.to_string(), t.b.to_string()])?,
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .collect::<Vec<_>>()
+        );
+        assert!(focus(&["cell//pkg:c".to_owned()])?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_critical_path_for_targets_late_analysis() -> anyhow::Result<()> {
+        let a =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:a", ConfigurationData::testing_new());
+        let analysis = NodeKey::AnalysisKey(AnalysisKey(a.dupe()));
+        let out = NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+            BaseDeferredKey::TargetLabel(a.dupe()),
+            DeferredId::testing_new(0),
+        ))));
+
+        // We can receive the analysis after the action it made visible, so the edge from the
+        // action to the analysis points to a vertex that was pushed later.
+        let mut backend = LongestPathGraphBackend::new().with_retained_graph();
+        backend.process_node(
+            out.dupe(),
+            NodeData::testing_new(Duration::from_secs(1)),
+            std::iter::empty(),
+        );
+        backend.process_node(
+            analysis.dupe(),
+            NodeData::testing_new(Duration::from_secs(2)),
+            std::iter::empty(),
+        );
+        backend.process_top_level_target(analysis.dupe(), std::iter::once(out.dupe()));
+
+        let info = backend.finish(None)?;
+        assert_eq!(
+            info.critical_path_for_targets(&[a.to_string()])?
+                .context("Graph was retained")?
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec![analysis, out]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duration_policy() -> anyhow::Result<()> {
+        let data = |user: u64, total: u64| NodeData {
+            duration: NodeDuration {
+                user: Duration::from_secs(user),
+                total: Duration::from_secs(total),
+            },
+            ..NodeData::testing_new(Duration::ZERO)
+        };
+
+        // `1 -> 2` takes longer in total, but `3` has more user time.
+        let critical_path = |policy: DurationPolicy| -> anyhow::Result<Vec<NodeKey>> {
+            let mut backend = LongestPathGraphBackend::new().with_duration_policy(policy);
+            backend.process_node(key(1), data(1, 3), std::iter::empty());
+            backend.process_node(key(2), data(1, 3), std::iter::once(key(1)));
+            backend.process_node(key(3), data(4, 4), std::iter::empty());
+            Ok(backend
+                .finish(None)?
+                .critical_path
+                .into_iter()
+                .map(|(key, _, _)| key)
+                .collect())
+        };
+
+        assert_eq!(critical_path(DurationPolicy::Total)?, vec![key(1), key(2)]);
+        assert_eq!(critical_path(DurationPolicy::User)?, vec![key(3)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_checked_potential() {
+        assert_eq!(checked_potential(&key(0), 10, 4, 6), 6);
+        assert_eq!(checked_potential(&key(0), 10, 10, 6), 0);
+        // These are inconsistent, so they get reported, but we still report a potential.
+        assert_eq!(checked_potential(&key(0), 10, 2, 6), 8);
+        assert_eq!(checked_potential(&key(0), 10, 12, 6), 0);
+    }
+
+    #[test]
+    fn test_potentials_within_node_costs() -> anyhow::Result<()> {
+        let info = chain(100).finish(None)?;
+        for (_key, data, potential) in &info.critical_path {
+            assert_eq!(*potential, Some(data.duration.critical_path_duration()));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_saturating_durations() -> anyhow::Result<()> {
+        let data = NodeData::testing_new;
+
+        assert_eq!(duration_weight(Duration::from_secs(1)), 1_000_000);
+        assert_eq!(duration_weight(Duration::MAX), u64::MAX);
+
+        let huge = Duration::MAX - Duration::from_secs(1);
+        let mut backend = LongestPathGraphBackend::new();
+        backend.process_node(key(0), data(huge), std::iter::empty());
+        backend.process_node(key(1), data(huge), std::iter::once(key(0)));
+        backend.process_node(key(2), data(Duration::from_secs(1)), std::iter::empty());
+
+        let info = backend.finish(None)?;
+        assert_eq!(
+            info.critical_path
+                .into_iter()
+                .map(|(key, _, _)| key)
+                .collect::<Vec<_>>(),
+            vec![key(0), key(1)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_self_dependency() -> anyhow::Result<()> {
+        let mut backend = LongestPathGraphBackend::new();
+        backend.process_node(
+            key(0),
+            NodeData::testing_new(Duration::ZERO),
+            std::iter::once(key(0)),
+        );
+
+        let info = backend.finish(None)?;
+        assert_eq!(info.num_edges, 0);
+        assert_eq!(info.critical_path.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_analysis_dependents() -> anyhow::Result<()> {
+        let target = |name: &str| {
+            ConfiguredTargetLabel::testing_parse(name, ConfigurationData::testing_new())
+        };
+        let analysis =
+            |label: &ConfiguredTargetLabel| NodeKey::AnalysisKey(AnalysisKey(label.dupe()));
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `a` and `b` both depend on `lib`, whose analysis is slow.
+        let (lib, a, b) = (
+            target("cell//pkg:lib"),
+            target("cell//pkg:a"),
+            target("cell//pkg:b"),
+        );
+        let a_out = NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+            BaseDeferredKey::TargetLabel(a.dupe()),
+            DeferredId::testing_new(0),
+        ))));
+
+        let build = |mut backend: LongestPathGraphBackend| {
+            backend.process_node(analysis(&lib), data(3), std::iter::empty());
+            backend.process_node(analysis(&a), data(1), std::iter::once(analysis(&lib)));
+            backend.process_node(analysis(&b), data(1), std::iter::once(analysis(&lib)));
+            backend.process_node(a_out.dupe(), data(2), std::iter::empty());
+            backend.process_top_level_target(analysis(&a), std::iter::once(a_out.dupe()));
+            backend.process_top_level_target(analysis(&b), std::iter::empty());
+            backend.finish(None)
+        };
+
+        let info = build(LongestPathGraphBackend::new())?;
+        assert!(info.analysis_dependents.is_empty());
+
+        let info = build(LongestPathGraphBackend::new().with_analysis_dependents())?;
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .zip(info.analysis_dependents.iter().copied())
+                .collect::<Vec<_>>(),
+            vec![
+                (analysis(&lib), Some(2)),
+                (analysis(&a), Some(1)),
+                (a_out, None),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hops_from_top_level() -> anyhow::Result<()> {
+        let target = |name: &str| {
+            ConfiguredTargetLabel::testing_parse(name, ConfigurationData::testing_new())
+        };
+        let analysis =
+            |label: &ConfiguredTargetLabel| NodeKey::AnalysisKey(AnalysisKey(label.dupe()));
+        let action = |label: &ConfiguredTargetLabel, id: u32| {
+            NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+                BaseDeferredKey::TargetLabel(label.dupe()),
+                DeferredId::testing_new(id),
+            ))))
+        };
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `a` builds `a_out`, which depends on `lib_out`.
+        let (lib, a, orphan) = (
+            target("cell//pkg:lib"),
+            target("cell//pkg:a"),
+            target("cell//pkg:orphan"),
+        );
+        let (lib_out, a_out) = (action(&lib, 0), action(&a, 1));
+
+        let build = |orphan_secs: Option<u64>| {
+            let mut backend = LongestPathGraphBackend::new().with_hops_from_top_level();
+            backend.process_node(analysis(&a), data(1), std::iter::empty());
+            backend.process_node(lib_out.dupe(), data(5), std::iter::empty());
+            backend.process_node(a_out.dupe(), data(2), std::iter::once(lib_out.dupe()));
+            if let Some(secs) = orphan_secs {
+                // Nothing requested this, which shouldn't happen, but can.
+                backend.process_node(analysis(&orphan), data(secs), std::iter::empty());
+            }
+            backend.process_top_level_target(analysis(&a), std::iter::once(a_out.dupe()));
+            backend.finish(None)
+        };
+
+        let hops = |info: &BuildInfo| {
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .zip(info.hops_from_top_level.iter().copied())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            hops(&build(None)?),
+            vec![(lib_out.dupe(), Some(2)), (a_out.dupe(), Some(1))]
+        );
+        assert_eq!(hops(&build(Some(100))?), vec![(analysis(&orphan), None)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure_critical_path() -> anyhow::Result<()> {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `2` failed after `1`, while `3` (which doesn't lead to the failure) was slower.
+        let mut backend = LongestPathGraphBackend::new();
+        backend.process_node(key(0), data(1), std::iter::empty());
+        backend.process_node(key(1), data(2), std::iter::once(key(0)));
+        backend.process_node(key(2), data(1), std::iter::once(key(1)));
+        backend.process_node(key(3), data(10), std::iter::once(key(0)));
+        backend.process_failed_action(key(2));
+        // Only the first failure is reported.
+        backend.process_failed_action(key(3));
+
+        let info = backend.finish(None)?;
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![key(0), key(3)]
+        );
+        assert_eq!(
+            info.failure_critical_path
+                .iter()
+                .map(|(key, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![key(0), key(1), key(2)]
+        );
+
+        // Without a failure, there is nothing to report.
+        let info = chain(3).finish(None)?;
+        assert!(info.failure_critical_path.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_failure_critical_path_through_analysis() -> anyhow::Result<()> {
+        let a =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:a", ConfigurationData::testing_new());
+        let analysis = NodeKey::AnalysisKey(AnalysisKey(a.dupe()));
+        let out = NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+            BaseDeferredKey::TargetLabel(a.dupe()),
+            DeferredId::testing_new(0),
+        ))));
+
+        // The action that failed only started once `a`'s analysis made it visible, which we
+        // received after it. `0` is slower, but doesn't lead to the failure.
+        let mut backend = LongestPathGraphBackend::new();
+        backend.process_node(
+            key(0),
+            NodeData::testing_new(Duration::from_secs(10)),
+            std::iter::empty(),
+        );
+        backend.process_node(
+            out.dupe(),
+            NodeData::testing_new(Duration::from_secs(1)),
+            std::iter::empty(),
+        );
+        backend.process_node(
+            analysis.dupe(),
+            NodeData::testing_new(Duration::from_secs(2)),
+            std::iter::empty(),
+        );
+        backend.process_top_level_target(analysis.dupe(), std::iter::once(out.dupe()));
+        backend.process_failed_action(out.dupe());
+
+        let info = backend.finish(None)?;
+        assert_eq!(
+            info.failure_critical_path
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec![analysis, out]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_simulated_schedule() -> anyhow::Result<()> {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // Four independent nodes, and a quick one after the first of them.
+        let simulate = |cores| {
+            let mut backend = LongestPathGraphBackend::new().with_simulated_schedule(cores);
+            for i in 0..4 {
+                backend.process_node(key(i), data(2), std::iter::empty());
+            }
+            backend.process_node(key(4), data(1), std::iter::once(key(0)));
+            let schedule = backend
+                .finish(None)?
+                .simulated_schedule
+                .context("Missing simulated schedule")?;
+            assert_eq!(schedule.core_count, cores);
+            anyhow::Ok((
+                schedule.makespan,
+                schedule
+                    .bottleneck
+                    .iter()
+                    .map(|(key, _)| key.dupe())
+                    .collect::<Vec<_>>(),
+            ))
+        };
+
+        // With a core each, this is just the critical path.
+        assert_eq!(simulate(4)?, (Duration::from_secs(3), vec![key(0), key(4)]));
+        // `0` goes first since more work follows it, and then `4` waits for a core.
+        assert_eq!(simulate(2)?.0, Duration::from_secs(5));
+        // On one core, everything runs one after the other.
+        assert_eq!(
+            simulate(1)?,
+            (Duration::from_secs(9), (0..5).map(key).collect())
+        );
+
+        // We don't simulate anything unless asked to.
+        assert!(chain(3).finish(None)?.simulated_schedule.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_slack_histogram() -> anyhow::Result<()> {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `2` waits on `0` and `1`, which makes for a 5s critical path through `0`. `1` could take
+        // 2s longer without changing that, and `3`, which nothing waits on, 4s longer.
+        let mut backend = LongestPathGraphBackend::new().with_slack_histogram();
+        backend.process_node(key(0), data(3), std::iter::empty());
+        backend.process_node(key(1), data(1), std::iter::empty());
+        backend.process_node(key(2), data(2), [key(0), key(1)].into_iter());
+        backend.process_node(key(3), data(1), std::iter::empty());
+
+        assert_eq!(
+            backend.finish(None)?.slack_histogram,
+            vec![
+                (0.01, 2),
+                (0.05, 0),
+                (0.1, 0),
+                (0.25, 0),
+                (0.5, 1),
+                (1.0, 1),
+            ]
+        );
+
+        // We don't compute slack unless asked to.
+        assert!(chain(3).finish(None)?.slack_histogram.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_always_critical() -> anyhow::Result<()> {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `3` waits on `1` and `2`, which both wait on `0`, and `1` and `2` take as long as each
+        // other, so there are two longest paths. Only `0` and `3` are on both. `4` has slack, and
+        // `5` takes no time.
+        let mut backend = LongestPathGraphBackend::new().with_always_critical();
+        backend.process_node(key(0), data(1), std::iter::empty());
+        backend.process_node(key(1), data(2), std::iter::once(key(0)));
+        backend.process_node(key(2), data(2), std::iter::once(key(0)));
+        backend.process_node(key(3), data(1), [key(1), key(2)].into_iter());
+        backend.process_node(key(4), data(1), std::iter::empty());
+        backend.process_node(key(5), data(0), std::iter::empty());
+
+        let info = backend.finish(None)?;
+        assert_eq!(
+            info.always_critical
+                .iter()
+                .map(|(key, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![key(0), key(3)]
+        );
+        assert_eq!(info.on_every_critical_path, vec![true, false, true]);
+
+        // We don't look for them unless asked to.
+        let info = chain(3).finish(None)?;
+        assert!(info.always_critical.is_empty());
+        assert!(info.on_every_critical_path.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_blockers() -> anyhow::Result<()> {
+        let data = |secs: u64, finish_offset: Option<u64>| NodeData {
+            finish_offset: finish_offset.map(Duration::from_secs),
+            ..NodeData::testing_new(Duration::from_secs(secs))
+        };
+
+        // `2` waits on `0` (3s) and `1` (1s), so `0` is on the critical path. Whether it is what
+        // `2` waited on depends on whether `1`, despite being fast, finished after it.
+        let last_blockers = |finish_of_1: Option<u64>| -> anyhow::Result<Vec<Option<bool>>> {
+            let mut backend = LongestPathGraphBackend::new();
+            backend.process_node(key(0), data(3, Some(3)), std::iter::empty());
+            backend.process_node(key(1), data(1, finish_of_1), std::iter::empty());
+            backend.process_node(key(2), data(1, Some(5)), [key(0), key(1)].into_iter());
+            let info = backend.finish(None)?;
+            assert_eq!(
+                info.critical_path
+                    .iter()
+                    .map(|(key, _, _)| key.dupe())
+                    .collect::<Vec<_>>(),
+                vec![key(0), key(2)]
+            );
+            Ok(info.last_blockers)
+        };
+
+        assert_eq!(last_blockers(Some(2))?, vec![Some(true), None]);
+        assert_eq!(last_blockers(Some(4))?, vec![Some(false), None]);
+        assert_eq!(last_blockers(None)?, vec![None, None]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_near_critical() -> anyhow::Result<()> {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // As in `test_slack_histogram`, the critical path goes through `0` and `2`, `1` has 2s of
+        // slack and `3` has 4s. `4` takes no time, so it doesn't hold anything back.
+        let build = |count| {
+            let mut backend = LongestPathGraphBackend::new().with_near_critical(count);
+            backend.process_node(key(0), data(3), std::iter::empty());
+            backend.process_node(key(1), data(1), std::iter::empty());
+            backend.process_node(key(2), data(2), [key(0), key(1)].into_iter());
+            backend.process_node(key(3), data(1), std::iter::empty());
+            backend.process_node(key(4), data(0), std::iter::empty());
+            backend.finish(None)
+        };
+        let near_critical = |info: BuildInfo| {
+            info.near_critical
+                .into_iter()
+                .map(|(key, _, slack)| (key, slack))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            near_critical(build(5)?),
+            vec![
+                (key(1), Some(Duration::from_secs(2))),
+                (key(3), Some(Duration::from_secs(4))),
+            ]
+        );
+        assert_eq!(
+            near_critical(build(1)?),
+            vec![(key(1), Some(Duration::from_secs(2)))]
+        );
+
+        // We don't look for them unless asked to.
+        assert!(chain(3).finish(None)?.near_critical.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cost_floors() -> anyhow::Result<()> {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        // `0` takes longer than the chain of `1`, `2` and `3`, but not once every node costs an
+        // extra second.
+        let build = |mut backend: LongestPathGraphBackend| {
+            backend.process_node(key(0), data(4), std::iter::empty());
+            backend.process_node(key(1), data(1), std::iter::empty());
+            backend.process_node(key(2), data(1), std::iter::once(key(1)));
+            backend.process_node(key(3), data(1), std::iter::once(key(2)));
+            backend.finish(None)
+        };
+        let critical_path = |info: &BuildInfo| {
+            info.critical_path
+                .iter()
+                .map(|(key, data, _)| (key.dupe(), data.duration.user))
+                .collect::<Vec<_>>()
+        };
+
+        let info = build(LongestPathGraphBackend::new())?;
+        assert_eq!(critical_path(&info), vec![(key(0), Duration::from_secs(4))]);
+        assert_eq!(info.critical_path_overhead, None);
+
+        let floors = HashMap::from_iter([
+            ("PackageListingKey".to_owned(), Duration::from_secs(1)),
+            ("BuildKey".to_owned(), Duration::from_secs(10)),
+        ]);
+        let info = build(LongestPathGraphBackend::new().with_cost_floors(&floors))?;
+        // Entries still report what we measured.
+        assert_eq!(
+            critical_path(&info),
+            vec![
+                (key(1), Duration::from_secs(1)),
+                (key(2), Duration::from_secs(1)),
+                (key(3), Duration::from_secs(1)),
+            ]
+        );
+        assert_eq!(info.critical_path_overhead, Some(Duration::from_secs(3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_weight_contraction() -> anyhow::Result<()> {
+        // A chain alternating between nodes that took time and nodes that didn't.
+        let build = |mut backend: LongestPathGraphBackend| {
+            for (i, millis) in [1, 0, 2, 0, 0, 3].into_iter().enumerate() {
+                let deps = if i > 0 { vec![key(i - 1)] } else { vec![] };
+                backend.process_node(
+                    key(i),
+                    NodeData::testing_new(Duration::from_millis(millis)),
+                    deps,
+                );
+            }
+            backend.finish(None)
+        };
+
+        let info = build(LongestPathGraphBackend::new())?;
+        assert_eq!(info.num_nodes, 6);
+        assert_eq!(info.critical_path.len(), 6);
+
+        let info = build(LongestPathGraphBackend::new().with_zero_weight_contraction())?;
+        assert_eq!(info.num_nodes, 3);
+        // The nodes we kept are still connected to one another.
+        assert_eq!(info.num_edges, 2);
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(key, _, _)| key.dupe())
+                .collect::<Vec<_>>(),
+            vec![key(0), key(2), key(5)]
+        );
+        assert_eq!(
+            info.critical_path
+                .iter()
+                .map(|(_, _, potential)| *potential)
+                .collect::<Vec<_>>(),
+            vec![
+                Some(Duration::from_millis(1)),
+                Some(Duration::from_millis(2)),
+                Some(Duration::from_millis(3))
+            ]
+        );
+
+        Ok(())
+    }
+}
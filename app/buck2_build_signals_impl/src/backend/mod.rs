@@ -10,5 +10,7 @@
 #![allow(clippy::module_inception)]
 
 pub mod backend;
+pub mod composite;
 pub mod default;
 pub mod longest_path_graph;
+pub mod weighted;
@@ -0,0 +1,189 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::marker::PhantomData;
+use std::time::Instant;
+
+use allocative::Allocative;
+use buck2_build_signals::CriticalPathBackendName;
+
+use crate::backend::backend::BuildListenerBackend;
+use crate::backend::longest_path_graph::LongestPathGraphBackend;
+use crate::BuildInfo;
+use crate::NodeData;
+use crate::NodeKey;
+
+/// Something other than time to weigh nodes by, and the name of the backend that does.
+pub(crate) trait Weight: Allocative {
+    fn name() -> CriticalPathBackendName;
+
+    /// Have `backend` weigh nodes this way.
+    fn apply(backend: LongestPathGraphBackend) -> LongestPathGraphBackend;
+}
+
+/// The longest-path-graph backend with a different weight than time, reported under the name of
+/// that weight so the two can't be confused.
+#[derive(Allocative)]
+pub(crate) struct WeightedBackend<W: Weight> {
+    inner: LongestPathGraphBackend,
+    weight: PhantomData<W>,
+}
+
+impl<W: Weight> WeightedBackend<W> {
+    pub(crate) fn new(inner: LongestPathGraphBackend) -> Self {
+        Self {
+            inner: W::apply(inner),
+            weight: PhantomData,
+        }
+    }
+}
+
+impl<W: Weight> BuildListenerBackend for WeightedBackend<W> {
+    fn process_node(
+        &mut self,
+        key: NodeKey,
+        data: NodeData,
+        dep_keys: impl IntoIterator<Item = NodeKey>,
+    ) {
+        self.inner.process_node(key, data, dep_keys)
+    }
+
+    fn process_top_level_target(
+        &mut self,
+        analysis: NodeKey,
+        artifacts: impl IntoIterator<Item = NodeKey>,
+    ) {
+        self.inner.process_top_level_target(analysis, artifacts)
+    }
+
+    fn process_failed_action(&mut self, key: NodeKey) {
+        self.inner.process_failed_action(key)
+    }
+
+    fn finish(self, deadline: Option<Instant>) -> anyhow::Result<BuildInfo> {
+        self.inner.finish(deadline)
+    }
+
+    fn name() -> CriticalPathBackendName {
+        W::name()
+    }
+}
+
+/// Finds the chain of nodes that moved the most data to and from RE serially, rather than the one
+/// that took the longest. On RE-heavy builds, this is often the real bottleneck.
+#[derive(Allocative)]
+pub(crate) struct TransferredBytes;
+
+impl Weight for TransferredBytes {
+    fn name() -> CriticalPathBackendName {
+        CriticalPathBackendName::TransferredBytes
+    }
+
+    fn apply(backend: LongestPathGraphBackend) -> LongestPathGraphBackend {
+        backend.with_transferred_bytes_weight()
+    }
+}
+
+/// Finds the chain of actions that produced the most output, rather than the one that took the
+/// longest. This is what to look at when output size is the concern (e.g. links producing huge
+/// binaries). Nodes other than actions produce no output and weigh nothing.
+#[derive(Allocative)]
+pub(crate) struct OutputBytes;
+
+impl Weight for OutputBytes {
+    fn name() -> CriticalPathBackendName {
+        CriticalPathBackendName::OutputBytes
+    }
+
+    fn apply(backend: LongestPathGraphBackend) -> LongestPathGraphBackend {
+        backend.with_output_bytes_weight()
+    }
+}
+
+pub(crate) type TransferredBytesBackend = WeightedBackend<TransferredBytes>;
+
+pub(crate) type OutputBytesBackend = WeightedBackend<OutputBytes>;
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::export::tests::key;
+
+    fn data(secs: u64, transferred_bytes: u64, output_bytes: u64) -> NodeData {
+        NodeData {
+            transferred_bytes,
+            output_bytes,
+            ..NodeData::testing_new(Duration::from_secs(secs))
+        }
+    }
+
+    fn keys(info: &BuildInfo) -> Vec<NodeKey> {
+        info.critical_path
+            .iter()
+            .map(|(k, _, _)| k.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_weighs_by_transferred_bytes() -> anyhow::Result<()> {
+        let process = |backend: &mut dyn FnMut(NodeKey, NodeData, Vec<NodeKey>)| {
+            // a -> b is slow but moves little data, c -> d is fast but moves a lot.
+            backend(key("a"), data(10, 1, 0), vec![]);
+            backend(key("b"), data(10, 1, 0), vec![key("a")]);
+            backend(key("c"), data(1, 100, 0), vec![]);
+            backend(key("d"), data(1, 200, 0), vec![key("c")]);
+        };
+
+        let mut by_duration = LongestPathGraphBackend::new();
+        process(&mut |k, d, deps| by_duration.process_node(k, d, deps));
+        let by_duration = by_duration.finish(None)?;
+
+        let mut by_bytes = TransferredBytesBackend::new(LongestPathGraphBackend::new());
+        process(&mut |k, d, deps| by_bytes.process_node(k, d, deps));
+        let by_bytes = by_bytes.finish(None)?;
+
+        assert_eq!(keys(&by_duration), vec![key("a"), key("b")]);
+        assert!(by_duration.potential_improvement_bytes.is_empty());
+
+        assert_eq!(keys(&by_bytes), vec![key("c"), key("d")]);
+        // Potentials are in bytes, so they aren't reported as durations.
+        assert!(by_bytes.critical_path.iter().all(|(_, _, p)| p.is_none()));
+        assert_eq!(by_bytes.potential_improvement_bytes.len(), 2);
+        assert!(by_bytes
+            .potential_improvement_bytes
+            .iter()
+            .all(|p| p.is_some()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_weighs_by_output_bytes() -> anyhow::Result<()> {
+        // a -> b is slow but produces nothing (e.g. loads and analyses), c -> d -> e is fast and
+        // produces a lot, though d (e.g. an analysis in between actions) produces nothing.
+        let mut backend = OutputBytesBackend::new(LongestPathGraphBackend::new());
+        backend.process_node(key("a"), data(10, 0, 0), vec![]);
+        backend.process_node(key("b"), data(10, 0, 0), vec![key("a")]);
+        backend.process_node(key("c"), data(1, 0, 100), vec![]);
+        backend.process_node(key("d"), data(1, 0, 0), vec![key("c")]);
+        backend.process_node(key("e"), data(1, 0, 200), vec![key("d")]);
+        let info = backend.finish(None)?;
+
+        assert_eq!(keys(&info), vec![key("c"), key("d"), key("e")]);
+        assert!(info.critical_path.iter().all(|(_, _, p)| p.is_none()));
+        assert_eq!(
+            info.potential_improvement_bytes,
+            vec![Some(100), Some(0), Some(200)]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::time::Instant;
+
+/// Where the receiver gets the time from, for the timestamps and durations it measures itself
+/// (as opposed to those it receives). This is the system clock, except in tests that need to
+/// control how much time passes.
+pub(crate) trait Clock: Send + Sync + 'static {
+    fn now(&self) -> Instant;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub(crate) struct FakeClock {
+    now: std::sync::Mutex<Instant>,
+}
+
+#[cfg(test)]
+impl FakeClock {
+    pub(crate) fn new() -> Self {
+        Self {
+            now: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    pub(crate) fn advance(&self, duration: std::time::Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,416 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use buck2_build_signals::CollapsedDurationPolicy;
+use buck2_build_signals::DuplicateEvaluationPolicy;
+use buck2_build_signals::DurationPolicy;
+use buck2_build_signals::NodeDuration;
+use buck2_execute::execute::action_digest::ActionDigest;
+use dupe::Dupe;
+
+use crate::NodeData;
+use crate::NodeKey;
+
+/// A node waiting to be handed to the backend.
+pub(crate) struct PendingNode {
+    pub(crate) key: NodeKey,
+    pub(crate) data: NodeData,
+    pub(crate) dep_keys: Vec<NodeKey>,
+    /// The digest of the command this node's action ran, if any.
+    pub(crate) action_digest: Option<ActionDigest>,
+}
+
+/// Buffers nodes so that if the same key gets evaluated more than once in a build, the backend
+/// only sees one of those evaluations, picked according to a `DuplicateEvaluationPolicy`.
+pub(crate) struct NodeCoalescer {
+    policy: DuplicateEvaluationPolicy,
+    /// How we compare durations for `DuplicateEvaluationPolicy::MaxDurationWins`.
+    duration_policy: DurationPolicy,
+    /// Maps each key to its position in `nodes`.
+    index: HashMap<NodeKey, usize>,
+    /// Nodes in the order we want to hand them to the backend. Nodes that were replaced by a later
+    /// evaluation leave a `None` behind.
+    nodes: Vec<Option<PendingNode>>,
+}
+
+impl NodeCoalescer {
+    pub(crate) fn new(policy: DuplicateEvaluationPolicy, duration_policy: DurationPolicy) -> Self {
+        Self {
+            policy,
+            duration_policy,
+            index: HashMap::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, node: PendingNode) {
+        match self.index.entry(node.key.dupe()) {
+            Entry::Vacant(e) => {
+                e.insert(self.nodes.len());
+                self.nodes.push(Some(node));
+            }
+            Entry::Occupied(mut e) => {
+                let existing = self.nodes[*e.get()]
+                    .as_ref()
+                    .expect("Indexed nodes are present");
+
+                let replace = match self.policy {
+                    DuplicateEvaluationPolicy::FirstWins => false,
+                    DuplicateEvaluationPolicy::LastWins => true,
+                    DuplicateEvaluationPolicy::MaxDurationWins => {
+                        node.data.duration.duration_for(self.duration_policy)
+                            > existing.data.duration.duration_for(self.duration_policy)
+                    }
+                };
+
+                if replace {
+                    // The replacement goes at the end: its dependencies might have been
+                    // evaluated after the node it replaces, and backends only consider edges to
+                    // nodes they have already seen.
+                    self.nodes[*e.get()] = None;
+                    e.insert(self.nodes.len());
+                    self.nodes.push(Some(node));
+                }
+            }
+        }
+    }
+
+    /// Merge nodes whose actions ran identical commands into the first such node, and point edges
+    /// to any of them at that node instead. Durations are combined according to `policy`. Returns
+    /// how many nodes were merged into others.
+    pub(crate) fn collapse_identical_actions(&mut self, policy: CollapsedDurationPolicy) -> usize {
+        // Maps each digest to the position of the node we merge identical ones into.
+        let mut by_digest: HashMap<ActionDigest, usize> = HashMap::new();
+        // Maps the keys of nodes we merged to the key of the node we merged them into.
+        let mut aliases: HashMap<NodeKey, NodeKey> = HashMap::new();
+
+        for i in 0..self.nodes.len() {
+            let digest = match self.nodes[i]
+                .as_ref()
+                .and_then(|n| n.action_digest.as_ref())
+            {
+                Some(digest) => digest,
+                None => continue,
+            };
+
+            let into = match by_digest.entry(digest.clone()) {
+                Entry::Vacant(e) => {
+                    e.insert(i);
+                    continue;
+                }
+                Entry::Occupied(e) => *e.get(),
+            };
+
+            let node = self.nodes[i].take().expect("Checked above");
+            let merged = self.nodes[into].as_mut().expect("Merged nodes are present");
+            aliases.insert(node.key, merged.key.dupe());
+            merged.data.duration = combine_durations(
+                merged.data.duration,
+                node.data.duration,
+                policy,
+                self.duration_policy,
+            );
+            merged.data.transferred_bytes = merged
+                .data
+                .transferred_bytes
+                .saturating_add(node.data.transferred_bytes);
+            merged.data.output_bytes = merged
+                .data
+                .output_bytes
+                .saturating_add(node.data.output_bytes);
+            // The merged node only waited for one of the identical actions to get through the queue.
+            merged.data.re_queue_duration = merged
+                .data
+                .re_queue_duration
+                .max(node.data.re_queue_duration);
+            merged.data.reused &= node.data.reused;
+            merged.data.span_ids.extend(node.data.span_ids);
+            merged.data.omitted_span_count += node.data.omitted_span_count;
+            merged.data.finish_offset = merged.data.finish_offset.max(node.data.finish_offset);
+            merged.dep_keys.extend(node.dep_keys);
+        }
+
+        if aliases.is_empty() {
+            return 0;
+        }
+
+        for node in self.nodes.iter_mut().flatten() {
+            let key = &node.key;
+            let mut seen = HashSet::new();
+            node.dep_keys = std::mem::take(&mut node.dep_keys)
+                .into_iter()
+                .map(|dep| aliases.get(&dep).map_or(dep, |k| k.dupe()))
+                // Nodes we merged might have depended on one another.
+                .filter(|dep| dep != key && seen.insert(dep.dupe()))
+                .collect();
+        }
+
+        // Merged nodes now have dependencies (and dependents) that came after (and before) them,
+        // but backends only consider edges to nodes they have already seen, so reorder.
+        self.nodes = topological_order(
+            self.nodes.drain(..).flatten().collect(),
+            |n| Some(&n.key),
+            |n, i| n.dep_keys.get(i),
+        )
+        .into_iter()
+        .map(Some)
+        .collect();
+        self.index = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.as_ref().expect("We just set this").key.dupe(), i))
+            .collect();
+
+        aliases.len()
+    }
+
+    /// Return the nodes to pass to the backend, in order.
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = PendingNode> + '_ {
+        self.index.clear();
+        self.nodes.drain(..).flatten()
+    }
+}
+
+fn combine_durations(
+    a: NodeDuration,
+    b: NodeDuration,
+    policy: CollapsedDurationPolicy,
+    duration_policy: DurationPolicy,
+) -> NodeDuration {
+    match policy {
+        CollapsedDurationPolicy::Max => {
+            if b.duration_for(duration_policy) > a.duration_for(duration_policy) {
+                b
+            } else {
+                a
+            }
+        }
+        CollapsedDurationPolicy::Sum => NodeDuration {
+            user: a.user.saturating_add(b.user),
+            total: a.total.saturating_add(b.total),
+        },
+    }
+}
+
+/// Order `nodes` so that every node comes after its dependencies, keeping them in their original
+/// order otherwise. Edges that would form a cycle are left pointing forward. `key` identifies a
+/// node (if it has dependents), and `dep` returns the key of its dependency at an index, if it has
+/// that many; dependencies that aren't in `nodes` are ignored.
+pub(crate) fn topological_order<T, K: Hash + Eq + ?Sized>(
+    nodes: Vec<T>,
+    key: impl Fn(&T) -> Option<&K>,
+    dep: impl Fn(&T, usize) -> Option<&K>,
+) -> Vec<T> {
+    let index: HashMap<&K, usize> = nodes
+        .iter()
+        .enumerate()
+        .filter_map(|(i, n)| Some((key(n)?, i)))
+        .collect();
+
+    let mut order = Vec::with_capacity(nodes.len());
+    // Whether we started visiting each node.
+    let mut visited = vec![false; nodes.len()];
+
+    for root in 0..nodes.len() {
+        if visited[root] {
+            continue;
+        }
+        visited[root] = true;
+
+        // We do this iteratively since the graph can be deep. Each entry is a node and how many
+        // of its dependencies we went through already.
+        let mut stack = vec![(root, 0)];
+        while let Some((i, next_dep)) = stack.last_mut() {
+            let i = *i;
+            match dep(&nodes[i], *next_dep) {
+                Some(dep) => {
+                    *next_dep += 1;
+                    if let Some(&dep) = index.get(dep) {
+                        if !visited[dep] {
+                            visited[dep] = true;
+                            stack.push((dep, 0));
+                        }
+                    }
+                }
+                None => {
+                    stack.pop();
+                    order.push(i);
+                }
+            }
+        }
+    }
+
+    let mut nodes: Vec<Option<T>> = nodes.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|i| nodes[i].take().expect("Each node is ordered once"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::export::tests::key;
+
+    fn node(name: &str, secs: u64, deps: &[&str]) -> PendingNode {
+        PendingNode {
+            key: key(name),
+            data: NodeData::testing_new(Duration::from_secs(secs)),
+            dep_keys: deps.iter().map(|d| key(d)).collect(),
+            action_digest: None,
+        }
+    }
+
+    fn action(name: &str, secs: u64, deps: &[&str], digest: u8) -> PendingNode {
+        PendingNode {
+            action_digest: Some(ActionDigest::new_sha1([digest; 20], 1)),
+            ..node(name, secs, deps)
+        }
+    }
+
+    fn collapse(policy: CollapsedDurationPolicy) -> (usize, Vec<(String, u64, Vec<NodeKey>)>) {
+        let mut coalescer = NodeCoalescer::new(
+            DuplicateEvaluationPolicy::default(),
+            DurationPolicy::default(),
+        );
+        coalescer.push(action("a", 2, &[], 1));
+        coalescer.push(node("x", 1, &[]));
+        // Identical to `a`, but it depends on something that came after `a`.
+        coalescer.push(action("b", 3, &["x"], 1));
+        coalescer.push(node("c", 1, &["a", "b"]));
+        coalescer.push(action("d", 1, &["c"], 2));
+        let collapsed = coalescer.collapse_identical_actions(policy);
+        let nodes = coalescer
+            .drain()
+            .map(|n| {
+                (
+                    n.key.to_string(),
+                    n.data.duration.total.as_secs(),
+                    n.dep_keys,
+                )
+            })
+            .collect();
+        (collapsed, nodes)
+    }
+
+    fn coalesce(policy: DuplicateEvaluationPolicy) -> Vec<(String, u64, usize)> {
+        let mut coalescer = NodeCoalescer::new(policy, DurationPolicy::default());
+        coalescer.push(node("a", 2, &[]));
+        coalescer.push(node("b", 1, &[]));
+        coalescer.push(node("a", 1, &["b"]));
+        coalescer.push(node("c", 1, &[]));
+        coalescer.push(node("a", 3, &[]));
+        coalescer
+            .drain()
+            .map(|n| {
+                (
+                    n.key.to_string(),
+                    n.data.duration.total.as_secs(),
+                    n.dep_keys.len(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_first_wins() {
+        assert_eq!(
+            coalesce(DuplicateEvaluationPolicy::FirstWins),
+            vec![
+                (key("a").to_string(), 2, 0),
+                (key("b").to_string(), 1, 0),
+                (key("c").to_string(), 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_last_wins() {
+        assert_eq!(
+            coalesce(DuplicateEvaluationPolicy::LastWins),
+            vec![
+                (key("b").to_string(), 1, 0),
+                (key("c").to_string(), 1, 0),
+                (key("a").to_string(), 3, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_max_duration_wins() {
+        assert_eq!(
+            coalesce(DuplicateEvaluationPolicy::MaxDurationWins),
+            vec![
+                (key("b").to_string(), 1, 0),
+                (key("c").to_string(), 1, 0),
+                (key("a").to_string(), 3, 0),
+            ]
+        );
+
+        let mut coalescer = NodeCoalescer::new(
+            DuplicateEvaluationPolicy::MaxDurationWins,
+            DurationPolicy::default(),
+        );
+        coalescer.push(node("a", 1, &[]));
+        coalescer.push(node("b", 1, &[]));
+        coalescer.push(node("a", 2, &["b"]));
+        let nodes = coalescer.drain().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].key, key("a"));
+        assert_eq!(nodes[1].dep_keys, vec![key("b")]);
+    }
+
+    #[test]
+    fn test_collapse_identical_actions() {
+        let (collapsed, nodes) = collapse(CollapsedDurationPolicy::Max);
+        assert_eq!(collapsed, 1);
+        assert_eq!(
+            nodes,
+            vec![
+                // `a` now depends on `x`, so it comes after it.
+                (key("x").to_string(), 1, vec![]),
+                (key("a").to_string(), 3, vec![key("x")]),
+                // Edges to `b` now point to `a`.
+                (key("c").to_string(), 1, vec![key("a")]),
+                (key("d").to_string(), 1, vec![key("c")]),
+            ]
+        );
+
+        let (collapsed, nodes) = collapse(CollapsedDurationPolicy::Sum);
+        assert_eq!(collapsed, 1);
+        assert_eq!(nodes[1], (key("a").to_string(), 5, vec![key("x")]));
+    }
+
+    #[test]
+    fn test_collapse_nothing_identical() {
+        let mut coalescer = NodeCoalescer::new(
+            DuplicateEvaluationPolicy::default(),
+            DurationPolicy::default(),
+        );
+        coalescer.push(action("a", 1, &[], 1));
+        coalescer.push(node("b", 1, &["a"]));
+        coalescer.push(action("c", 1, &["b"], 2));
+        assert_eq!(
+            coalescer.collapse_identical_actions(CollapsedDurationPolicy::Max),
+            0
+        );
+        assert_eq!(
+            coalescer.drain().map(|n| n.key).collect::<Vec<_>>(),
+            vec![key("a"), key("b"), key("c")]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::export::ExportedGraph;
+
+/// How much slower a node must get before we report it as a regression. A slowdown must exceed
+/// both thresholds, so that tiny nodes jittering by a large fraction and large nodes jittering by
+/// a few milliseconds are both ignored.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RegressionThresholds {
+    pub absolute: Duration,
+    /// As a percentage of the node's duration in the baseline.
+    pub relative_percent: f32,
+}
+
+/// A difference between the critical path of a baseline build and that of a later build.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CriticalPathRegression {
+    /// The node is on both critical paths, but got slower by more than the thresholds.
+    Slower {
+        node: String,
+        baseline: Duration,
+        current: Duration,
+    },
+    /// The node is only on the later critical path.
+    Appeared { node: String, current: Duration },
+    /// The node is only on the baseline critical path.
+    Disappeared { node: String, baseline: Duration },
+    /// The node is on both critical paths and got slower by more than the thresholds, and its
+    /// action was a cache miss in the later build but not in the baseline. That explains the
+    /// slowdown, and is what to look into to fix it.
+    CacheMiss {
+        node: String,
+        baseline: Duration,
+        current: Duration,
+    },
+}
+
+/// Compare two critical paths, given as the stable ids of their nodes along with their durations.
+/// Nodes that appear or disappear are always reported, but nodes on both paths are only reported
+/// if they got slower by more than `thresholds`. Results follow the order of `current`, followed
+/// by disappeared nodes in the order of `baseline`.
+pub fn compare_critical_paths(
+    baseline: &[(String, Duration)],
+    current: &[(String, Duration)],
+    thresholds: RegressionThresholds,
+) -> Vec<CriticalPathRegression> {
+    let baseline_durations = baseline
+        .iter()
+        .map(|(node, duration)| (node.as_str(), *duration))
+        .collect::<HashMap<_, _>>();
+    let current_durations = current
+        .iter()
+        .map(|(node, duration)| (node.as_str(), *duration))
+        .collect::<HashMap<_, _>>();
+
+    let mut res = Vec::new();
+
+    for (node, current) in current {
+        match baseline_durations.get(node.as_str()) {
+            Some(baseline) => {
+                if exceeds_thresholds(*baseline, *current, thresholds) {
+                    res.push(CriticalPathRegression::Slower {
+                        node: node.clone(),
+                        baseline: *baseline,
+                        current: *current,
+                    });
+                }
+            }
+            None => res.push(CriticalPathRegression::Appeared {
+                node: node.clone(),
+                current: *current,
+            }),
+        }
+    }
+
+    for (node, baseline) in baseline {
+        if !current_durations.contains_key(node.as_str()) {
+            res.push(CriticalPathRegression::Disappeared {
+                node: node.clone(),
+                baseline: *baseline,
+            });
+        }
+    }
+
+    res
+}
+
+/// Like `compare_critical_paths`, but nodes also say whether their action was a cache miss (i.e. ran
+/// a command that wasn't served from a cache). Nodes that got slower and went from not being a
+/// cache miss to being one are reported as `CacheMiss` rather than `Slower`.
+pub fn compare_critical_paths_by_cause(
+    baseline: &[(String, Duration, bool)],
+    current: &[(String, Duration, bool)],
+    thresholds: RegressionThresholds,
+) -> Vec<CriticalPathRegression> {
+    let baseline_misses = cache_misses(baseline);
+    let current_misses = cache_misses(current);
+
+    compare_critical_paths(&durations(baseline), &durations(current), thresholds)
+        .into_iter()
+        .map(|regression| match regression {
+            CriticalPathRegression::Slower {
+                node,
+                baseline,
+                current,
+            } if current_misses.contains(node.as_str())
+                && !baseline_misses.contains(node.as_str()) =>
+            {
+                CriticalPathRegression::CacheMiss {
+                    node,
+                    baseline,
+                    current,
+                }
+            }
+            regression => regression,
+        })
+        .collect()
+}
+
+fn durations(path: &[(String, Duration, bool)]) -> Vec<(String, Duration)> {
+    path.iter()
+        .map(|(node, duration, _)| (node.clone(), *duration))
+        .collect()
+}
+
+fn cache_misses(path: &[(String, Duration, bool)]) -> HashSet<&str> {
+    path.iter()
+        .filter(|(_, _, cache_miss)| *cache_miss)
+        .map(|(node, _, _)| node.as_str())
+        .collect()
+}
+
+/// How the structure of the build graph changed between a baseline build and a later build,
+/// regardless of how long nodes took. Nodes are identified by their stable ids, and edges are
+/// given as the dependent followed by the dependency. Additions follow the order of the later
+/// build and removals that of the baseline.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GraphStructureDiff {
+    pub added_nodes: Vec<String>,
+    pub removed_nodes: Vec<String>,
+    pub added_edges: Vec<(String, String)>,
+    pub removed_edges: Vec<(String, String)>,
+}
+
+impl GraphStructureDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added_nodes.is_empty()
+            && self.removed_nodes.is_empty()
+            && self.added_edges.is_empty()
+            && self.removed_edges.is_empty()
+    }
+}
+
+/// Compare the structure of two graphs exported with `CriticalPathOptions::graph_export_path`.
+/// If the exports were focused on a node, this only compares the parts of the graphs that were
+/// exported.
+pub fn compare_graph_structures(
+    baseline: &Path,
+    current: &Path,
+) -> anyhow::Result<GraphStructureDiff> {
+    Ok(diff_graph_structures(
+        &ExportedGraph::read(baseline)?,
+        &ExportedGraph::read(current)?,
+    ))
+}
+
+fn diff_graph_structures(baseline: &ExportedGraph, current: &ExportedGraph) -> GraphStructureDiff {
+    fn nodes(graph: &ExportedGraph) -> HashSet<&str> {
+        graph.nodes.iter().map(|n| n.stable_id.as_str()).collect()
+    }
+
+    let baseline_nodes = nodes(baseline);
+    let current_nodes = nodes(current);
+    let baseline_edges = baseline.stable_edges().collect::<HashSet<_>>();
+    let current_edges = current.stable_edges().collect::<HashSet<_>>();
+
+    let missing_nodes = |graph: &ExportedGraph, other: &HashSet<&str>| {
+        graph
+            .nodes
+            .iter()
+            .filter(|n| !other.contains(n.stable_id.as_str()))
+            .map(|n| n.stable_id.clone())
+            .collect()
+    };
+    let missing_edges = |graph: &ExportedGraph, other: &HashSet<(&str, &str)>| {
+        graph
+            .stable_edges()
+            .filter(|edge| !other.contains(edge))
+            .map(|(from, to)| (from.to_owned(), to.to_owned()))
+            .collect()
+    };
+
+    GraphStructureDiff {
+        added_nodes: missing_nodes(current, &baseline_nodes),
+        removed_nodes: missing_nodes(baseline, &current_nodes),
+        added_edges: missing_edges(current, &baseline_edges),
+        removed_edges: missing_edges(baseline, &current_edges),
+    }
+}
+
+fn exceeds_thresholds(
+    baseline: Duration,
+    current: Duration,
+    thresholds: RegressionThresholds,
+) -> bool {
+    let delta = match current.checked_sub(baseline) {
+        Some(delta) => delta,
+        None => return false,
+    };
+
+    if delta <= thresholds.absolute {
+        return false;
+    }
+
+    // Any slowdown of something that took no time is infinitely large.
+    if baseline.is_zero() {
+        return true;
+    }
+
+    delta.as_secs_f64() / baseline.as_secs_f64() * 100.0 > thresholds.relative_percent as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::tests::export_graph;
+    use crate::export::tests::key;
+
+    fn path(nodes: &[(&str, u64)]) -> Vec<(String, Duration)> {
+        nodes
+            .iter()
+            .map(|(node, ms)| ((*node).to_owned(), Duration::from_millis(*ms)))
+            .collect()
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let thresholds = RegressionThresholds {
+            absolute: Duration::from_millis(10),
+            relative_percent: 5.0,
+        };
+        let baseline = path(&[("a", 1000), ("b", 1000), ("c", 100)]);
+
+        // `a` is just under the relative threshold, `c` is under the absolute one despite being
+        // 9% slower, and `b` exceeds both.
+        assert_eq!(
+            compare_critical_paths(
+                &baseline,
+                &path(&[("a", 1049), ("b", 1051), ("c", 109)]),
+                thresholds
+            ),
+            vec![CriticalPathRegression::Slower {
+                node: "b".to_owned(),
+                baseline: Duration::from_millis(1000),
+                current: Duration::from_millis(1051),
+            }]
+        );
+
+        // Getting faster is never a regression.
+        assert_eq!(
+            compare_critical_paths(
+                &baseline,
+                &path(&[("a", 1), ("b", 1), ("c", 1)]),
+                thresholds
+            ),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_appeared_and_disappeared() {
+        // Even huge thresholds don't hide nodes that changed.
+        let thresholds = RegressionThresholds {
+            absolute: Duration::from_secs(3600),
+            relative_percent: 1000.0,
+        };
+
+        assert_eq!(
+            compare_critical_paths(
+                &path(&[("a", 100), ("b", 100)]),
+                &path(&[("a", 100), ("c", 1)]),
+                thresholds
+            ),
+            vec![
+                CriticalPathRegression::Appeared {
+                    node: "c".to_owned(),
+                    current: Duration::from_millis(1),
+                },
+                CriticalPathRegression::Disappeared {
+                    node: "b".to_owned(),
+                    baseline: Duration::from_millis(100),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cache_miss() {
+        let path = |nodes: &[(&str, u64, bool)]| {
+            nodes
+                .iter()
+                .map(|(node, ms, cache_miss)| {
+                    ((*node).to_owned(), Duration::from_millis(*ms), *cache_miss)
+                })
+                .collect::<Vec<_>>()
+        };
+        let thresholds = RegressionThresholds {
+            absolute: Duration::from_millis(10),
+            relative_percent: 5.0,
+        };
+
+        // `a` was a cache hit and now missed, while `b` missed both times and just got slower.
+        // `c` missed too, but that didn't make it slower.
+        assert_eq!(
+            compare_critical_paths_by_cause(
+                &path(&[("a", 10, false), ("b", 1000, true), ("c", 100, false)]),
+                &path(&[("a", 1000, true), ("b", 2000, true), ("c", 100, true)]),
+                thresholds
+            ),
+            vec![
+                CriticalPathRegression::CacheMiss {
+                    node: "a".to_owned(),
+                    baseline: Duration::from_millis(10),
+                    current: Duration::from_millis(1000),
+                },
+                CriticalPathRegression::Slower {
+                    node: "b".to_owned(),
+                    baseline: Duration::from_millis(1000),
+                    current: Duration::from_millis(2000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graph_structures() -> anyhow::Result<()> {
+        let id = |name: &str| key(name).stable_id();
+
+        let dir = tempfile::tempdir()?;
+        let (baseline, current) = (dir.path().join("baseline"), dir.path().join("current"));
+
+        // `c` now depends on `a` too.
+        export_graph(&[("a", &[]), ("b", &["a"]), ("c", &["b"])]).write(&baseline)?;
+        export_graph(&[("a", &[]), ("b", &["a"]), ("c", &["b", "a"])]).write(&current)?;
+        assert_eq!(
+            compare_graph_structures(&baseline, &current)?,
+            GraphStructureDiff {
+                added_edges: vec![(id("c"), id("a"))],
+                ..Default::default()
+            }
+        );
+
+        let diff = diff_graph_structures(
+            &export_graph(&[("a", &[]), ("b", &["a"])]),
+            &export_graph(&[("a", &[]), ("c", &["a"])]),
+        );
+        assert_eq!(diff.added_nodes, vec![id("c")]);
+        assert_eq!(diff.removed_nodes, vec![id("b")]);
+        assert_eq!(diff.added_edges, vec![(id("c"), id("a"))]);
+        assert_eq!(diff.removed_edges, vec![(id("b"), id("a"))]);
+        assert!(!diff.is_empty());
+        assert!(
+            diff_graph_structures(&export_graph(&[("a", &[])]), &export_graph(&[("a", &[])]))
+                .is_empty()
+        );
+
+        Ok(())
+    }
+}
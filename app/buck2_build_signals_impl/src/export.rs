@@ -0,0 +1,569 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::fs::File;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use buck2_build_signals::DurationPolicy;
+use buck2_critical_path::Graph;
+use buck2_critical_path::VertexData;
+use buck2_critical_path::VertexId;
+use buck2_critical_path::VertexKeys;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::NodeData;
+use crate::NodeKey;
+
+#[derive(Clone, Allocative)]
+pub(crate) struct GraphExportOptions {
+    /// Whether to compute the slack on each edge. This requires nodes to have a `finish_offset`.
+    pub(crate) edge_timing: bool,
+    /// If set, only export the part of the graph around this node.
+    pub(crate) focus: Option<GraphExportFocus>,
+}
+
+#[derive(Clone, Allocative)]
+pub(crate) struct GraphExportFocus {
+    /// The node, as its key is displayed or as its stable id.
+    pub(crate) node: String,
+    /// We export nodes that are at most this many edges away from the focus node, following
+    /// edges either to dependencies or to dependents (but not a mix of both).
+    pub(crate) radius: usize,
+}
+
+/// A serializable snapshot of the whole graph we computed a critical path over (as opposed to just
+/// the critical path itself).
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct ExportedGraph {
+    pub(crate) nodes: Vec<ExportedNode>,
+    /// Edges refer to nodes by their index in `nodes`.
+    pub(crate) edges: Vec<ExportedEdge>,
+    /// Anything the reader should know about how we exported the graph, e.g. that we didn't find
+    /// the node we were asked to focus on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) note: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct ExportedNode {
+    pub(crate) key: String,
+    /// Identifies this node across builds, so that exports of different builds can be compared.
+    pub(crate) stable_id: String,
+    pub(crate) duration_us: u64,
+    /// When this node started, relative to when we started receiving build signals.
+    pub(crate) start_offset_us: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) struct ExportedEdge {
+    /// The dependent.
+    pub(crate) from: usize,
+    /// The dependency.
+    pub(crate) to: usize,
+    /// How long the dependent waited between its dependency finishing and itself starting. This
+    /// can be negative since our timestamps are only approximate. This is only present if edge
+    /// timing was requested and we have timing data for both nodes.
+    pub(crate) slack_us: Option<i64>,
+}
+
+impl ExportedGraph {
+    pub(crate) fn new(
+        graph: &Graph,
+        keys: &VertexKeys<NodeKey>,
+        data: &VertexData<NodeData>,
+        options: GraphExportOptions,
+        duration_policy: DurationPolicy,
+    ) -> Self {
+        let (selected, note) = match &options.focus {
+            None => (None, None),
+            Some(focus) => match keys
+                .iter()
+                .find(|(_, k)| k.to_string() == focus.node || k.stable_id() == focus.node)
+            {
+                Some((idx, _)) => (Some(neighborhood(graph, idx, focus.radius)), None),
+                None => (
+                    Some(graph.allocate_vertex_data(false)),
+                    Some(format!(
+                        "Focus node `{}` was not found in the graph",
+                        focus.node
+                    )),
+                ),
+            },
+        };
+        let is_selected = |idx: VertexId| selected.as_ref().map_or(true, |s| s[idx]);
+
+        // The position of each node we export in `nodes`.
+        let mut positions = graph.allocate_vertex_data(None);
+        let mut nodes = Vec::new();
+        for idx in graph.iter_vertices().filter(|idx| is_selected(*idx)) {
+            positions[idx] = Some(nodes.len());
+            nodes.push(ExportedNode {
+                key: keys[idx].to_string(),
+                stable_id: keys[idx].stable_id(),
+                duration_us: data[idx].duration.duration_for(duration_policy).as_micros() as u64,
+                start_offset_us: data[idx].start_offset().map(|s| s.as_micros() as u64),
+            });
+        }
+
+        let edges = graph
+            .iter_all_edges()
+            .filter_map(|(from, to)| Some((from, positions[from]?, to, positions[to]?)))
+            .map(|(from, from_position, to, to_position)| {
+                let slack_us = if options.edge_timing {
+                    match (data[from].start_offset(), data[to].finish_offset) {
+                        (Some(start), Some(finish)) => {
+                            Some(start.as_micros() as i64 - finish.as_micros() as i64)
+                        }
+                        _ => None,
+                    }
+                } else {
+                    None
+                };
+
+                ExportedEdge {
+                    from: from_position,
+                    to: to_position,
+                    slack_us,
+                }
+            })
+            .collect();
+
+        Self { nodes, edges, note }
+    }
+
+    pub(crate) fn read(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Error opening `{}`", path.display()))?;
+        serde_json::from_reader(BufReader::new(file))
+            .with_context(|| format!("Error reading graph from `{}`", path.display()))
+    }
+
+    /// The edges, as the stable ids of the dependent and the dependency.
+    pub(crate) fn stable_edges(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.edges.iter().filter_map(|edge| {
+            Some((
+                self.nodes.get(edge.from)?.stable_id.as_str(),
+                self.nodes.get(edge.to)?.stable_id.as_str(),
+            ))
+        })
+    }
+
+    pub(crate) fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("Error creating `{}`", path.display()))?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(&mut writer, self)
+            .with_context(|| format!("Error writing graph to `{}`", path.display()))?;
+        writer
+            .flush()
+            .with_context(|| format!("Error flushing `{}`", path.display()))?;
+        Ok(())
+    }
+}
+
+/// The vertices that are at most `radius` edges away from `focus`, following edges either to
+/// dependencies or to dependents.
+fn neighborhood(graph: &Graph, focus: VertexId, radius: usize) -> VertexData<bool> {
+    let mut selected = graph.allocate_vertex_data(false);
+    selected[focus] = true;
+
+    let reversed = graph.reversed();
+    for graph in [graph, &reversed] {
+        let mut visited = graph.allocate_vertex_data(false);
+        visited[focus] = true;
+        let mut frontier = vec![focus];
+        for _ in 0..radius {
+            let mut next = Vec::new();
+            for idx in frontier {
+                for dep in graph.iter_edges(idx) {
+                    if !visited[dep] {
+                        visited[dep] = true;
+                        selected[dep] = true;
+                        next.push(dep);
+                    }
+                }
+            }
+            frontier = next;
+        }
+    }
+
+    selected
+}
+
+/// Write the critical path in the "folded stacks" format used by flamegraph tooling. Each node's
+/// stack is the critical path leading to it, and its weight is its duration in microseconds.
+pub(crate) fn write_folded_stacks<'a>(
+    path: &Path,
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> anyhow::Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Error creating `{}`", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    write_folded_stacks_to(&mut writer, critical_path, duration_policy)
+        .with_context(|| format!("Error writing folded stacks to `{}`", path.display()))?;
+    writer
+        .flush()
+        .with_context(|| format!("Error flushing `{}`", path.display()))?;
+    Ok(())
+}
+
+/// Lay out the critical path's nodes end to end, returning when each node starts and finishes
+/// relative to the start of the path. This is how we assign timestamps to nodes when emitting
+/// the critical path as a trace.
+pub(crate) fn critical_path_timeline<'a>(
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> impl Iterator<Item = (&'a NodeKey, Duration, Duration)> {
+    let mut elapsed = Duration::ZERO;
+    critical_path.into_iter().map(move |(key, data)| {
+        let start = elapsed;
+        elapsed = elapsed.saturating_add(data.duration.duration_for(duration_policy));
+        (key, start, elapsed)
+    })
+}
+
+fn write_folded_stacks_to<'a>(
+    mut writer: impl Write,
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> anyhow::Result<()> {
+    let mut stack = String::new();
+
+    for (key, data) in critical_path {
+        if !stack.is_empty() {
+            stack.push(';');
+        }
+        // Semicolons separate frames, so they can't appear in a frame.
+        stack.push_str(&key.to_string().replace(';', ","));
+
+        writeln!(
+            writer,
+            "{} {}",
+            stack,
+            data.duration.duration_for(duration_policy).as_micros()
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Write the critical path as a Mermaid flowchart: a chain of its nodes, labeled with their
+/// durations, in a box labeled with the total.
+pub(crate) fn write_mermaid<'a>(
+    path: &Path,
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> anyhow::Result<()> {
+    let file =
+        File::create(path).with_context(|| format!("Error creating `{}`", path.display()))?;
+    let mut writer = BufWriter::new(file);
+    write_mermaid_to(&mut writer, critical_path, duration_policy)
+        .with_context(|| format!("Error writing Mermaid diagram to `{}`", path.display()))?;
+    writer
+        .flush()
+        .with_context(|| format!("Error flushing `{}`", path.display()))?;
+    Ok(())
+}
+
+fn write_mermaid_to<'a>(
+    mut writer: impl Write,
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> anyhow::Result<()> {
+    let mut nodes = Vec::new();
+    let mut total = Duration::ZERO;
+    for (key, data) in critical_path {
+        let duration = data.duration.duration_for(duration_policy);
+        total = total.saturating_add(duration);
+        nodes.push(format!(
+            "{}<br/>{:.3}s",
+            escape_mermaid(&key.to_string()),
+            duration.as_secs_f64()
+        ));
+    }
+
+    writeln!(writer, "graph LR")?;
+    writeln!(
+        writer,
+        "  subgraph critical_path [\"Critical path: {:.3}s\"]",
+        total.as_secs_f64()
+    )?;
+    for (i, label) in nodes.iter().enumerate() {
+        writeln!(writer, "    n{}[\"{}\"]", i, label)?;
+    }
+    for i in 1..nodes.len() {
+        writeln!(writer, "    n{} --> n{}", i - 1, i)?;
+    }
+    writeln!(writer, "  end")?;
+
+    Ok(())
+}
+
+/// Escape text for a quoted Mermaid label, in which quotes would end the label and angle brackets
+/// would be taken for HTML. Mermaid understands `#...;` entity codes, so we escape `#` too.
+fn escape_mermaid(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' => escaped.push_str("#35;"),
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use buck2_common::package_listing::dice::PackageListingKey;
+    use buck2_core::package::PackageLabel;
+    use buck2_critical_path::GraphBuilder;
+
+    use super::*;
+
+    pub(crate) fn key(name: &str) -> NodeKey {
+        NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", name)))
+    }
+
+    fn data(total_secs: u64, finish_offset_secs: u64) -> NodeData {
+        NodeData {
+            finish_offset: Some(Duration::from_secs(finish_offset_secs)),
+            ..NodeData::testing_new(Duration::from_secs(total_secs))
+        }
+    }
+
+    /// Export a graph of the `key`s of `nodes`, each depending on the nodes listed with it. Nodes
+    /// must be listed after their dependencies.
+    pub(crate) fn export_graph(nodes: &[(&str, &[&str])]) -> ExportedGraph {
+        let mut builder = GraphBuilder::new();
+        for (name, deps) in nodes {
+            builder
+                .push(key(name), deps.iter().map(|dep| key(dep)), data(1, 1))
+                .unwrap();
+        }
+        let (graph, keys, data) = builder.finish();
+
+        ExportedGraph::new(
+            &graph,
+            &keys,
+            &data,
+            GraphExportOptions {
+                edge_timing: false,
+                focus: None,
+            },
+            DurationPolicy::default(),
+        )
+    }
+
+    #[test]
+    fn test_edge_timing() {
+        let mut builder = GraphBuilder::new();
+        builder
+            .push(key("a"), std::iter::empty(), data(2, 10))
+            .unwrap();
+        // Starts at 12s, 2s after `a` finished.
+        builder
+            .push(key("b"), std::iter::once(key("a")), data(3, 15))
+            .unwrap();
+        let (graph, keys, data) = builder.finish();
+
+        let exported = ExportedGraph::new(
+            &graph,
+            &keys,
+            &data,
+            GraphExportOptions {
+                edge_timing: true,
+                focus: None,
+            },
+            DurationPolicy::default(),
+        );
+        assert_eq!(exported.nodes.len(), 2);
+        assert_eq!(exported.nodes[0].stable_id, key("a").stable_id());
+        assert_eq!(exported.nodes[0].start_offset_us, Some(8_000_000));
+        assert_eq!(exported.nodes[1].start_offset_us, Some(12_000_000));
+        assert_eq!(
+            exported.edges,
+            vec![ExportedEdge {
+                from: 1,
+                to: 0,
+                slack_us: Some(2_000_000),
+            }]
+        );
+
+        let exported = ExportedGraph::new(
+            &graph,
+            &keys,
+            &data,
+            GraphExportOptions {
+                edge_timing: false,
+                focus: None,
+            },
+            DurationPolicy::default(),
+        );
+        assert_eq!(exported.edges[0].slack_us, None);
+    }
+
+    #[test]
+    fn test_focus() {
+        // `b` depends on `a`, `c` on `b`, `d` on `c` and `e` on `b`.
+        let mut builder = GraphBuilder::new();
+        for (name, dep) in [
+            ("a", None),
+            ("b", Some("a")),
+            ("c", Some("b")),
+            ("d", Some("c")),
+        ] {
+            builder
+                .push(key(name), dep.map(key).into_iter(), data(1, 1))
+                .unwrap();
+        }
+        builder
+            .push(key("e"), std::iter::once(key("b")), data(1, 1))
+            .unwrap();
+        let (graph, keys, data) = builder.finish();
+
+        let export = |node: &NodeKey, radius| {
+            ExportedGraph::new(
+                &graph,
+                &keys,
+                &data,
+                GraphExportOptions {
+                    edge_timing: false,
+                    focus: Some(GraphExportFocus {
+                        node: node.to_string(),
+                        radius,
+                    }),
+                },
+                DurationPolicy::default(),
+            )
+        };
+        let node_keys = |exported: &ExportedGraph| {
+            exported
+                .nodes
+                .iter()
+                .map(|n| n.key.clone())
+                .collect::<Vec<_>>()
+        };
+
+        let exported = export(&key("c"), 1);
+        assert_eq!(
+            node_keys(&exported),
+            vec![
+                key("b").to_string(),
+                key("c").to_string(),
+                key("d").to_string()
+            ]
+        );
+        // Edges refer to positions among the nodes we exported.
+        assert_eq!(
+            exported
+                .edges
+                .iter()
+                .map(|e| (e.from, e.to))
+                .collect::<Vec<_>>(),
+            vec![(1, 0), (2, 1)]
+        );
+        assert_eq!(exported.note, None);
+
+        // We don't mix dependencies and dependents: `e` is a dependent of a dependency of `c`.
+        let exported = export(&key("c"), 2);
+        assert_eq!(
+            node_keys(&exported),
+            vec![
+                key("a").to_string(),
+                key("b").to_string(),
+                key("c").to_string(),
+                key("d").to_string(),
+            ]
+        );
+
+        let exported = export(&key("b"), 0);
+        assert_eq!(node_keys(&exported), vec![key("b").to_string()]);
+        assert!(exported.edges.is_empty());
+
+        let exported = export(&key("missing"), 1);
+        assert!(exported.nodes.is_empty());
+        assert!(exported.edges.is_empty());
+        assert!(exported.note.unwrap().contains("was not found"));
+    }
+
+    #[test]
+    fn test_folded_stacks() -> anyhow::Result<()> {
+        let a = key("a");
+        let b = key("b");
+        let c = key("c");
+        let path = [(&a, &data(1, 1)), (&b, &data(2, 3)), (&c, &data(3, 6))];
+
+        let mut out = Vec::new();
+        write_folded_stacks_to(
+            &mut out,
+            path.iter().map(|(k, d)| (*k, *d)),
+            DurationPolicy::default(),
+        )?;
+
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!(
+                "{a} 1000000\n{a};{b} 2000000\n{a};{b};{c} 3000000\n",
+                a = a,
+                b = b,
+                c = c
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mermaid() -> anyhow::Result<()> {
+        let a = key("a");
+        let b = key("b");
+        let path = [(&a, &data(1, 1)), (&b, &data(2, 3))];
+
+        let mut out = Vec::new();
+        write_mermaid_to(
+            &mut out,
+            path.iter().map(|(k, d)| (*k, *d)),
+            DurationPolicy::default(),
+        )?;
+
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!(
+                concat!(
+                    "graph LR\n",
+                    "  subgraph critical_path [\"Critical path: 3.000s\"]\n",
+                    "    n0[\"{a}<br/>1.000s\"]\n",
+                    "    n1[\"{b}<br/>2.000s\"]\n",
+                    "    n0 --> n1\n",
+                    "  end\n",
+                ),
+                a = a,
+                b = b
+            )
+        );
+
+        assert_eq!(
+            escape_mermaid("say \"#1\" <x>"),
+            "say #quot;#35;1#quot; #lt;x#gt;"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use buck2_build_signals::CriticalPathHistory;
+use buck2_build_signals::DurationAnomaly;
+use indexmap::IndexMap;
+
+/// The mean and variance of a node's durations, computed incrementally using Welford's online
+/// algorithm so that we don't have to keep every sample around.
+#[derive(Default, Debug, Clone, Copy)]
+struct RunningStats {
+    count: u64,
+    /// In seconds.
+    mean: f64,
+    /// The sum of squared differences from the mean, in seconds squared.
+    m2: f64,
+}
+
+impl RunningStats {
+    fn push(&mut self, sample: f64) {
+        self.count += 1;
+        let delta = sample - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (sample - self.mean);
+    }
+
+    fn stddev(&self) -> f64 {
+        if self.count < 2 {
+            return 0.0;
+        }
+        (self.m2 / (self.count - 1) as f64).sqrt()
+    }
+}
+
+/// How many builds' critical paths we keep by default to find what is chronically slow.
+const DEFAULT_RETAINED_BUILDS: usize = 100;
+
+/// How many sets of top level targets we remember the last critical path of by default.
+const DEFAULT_RETAINED_TARGET_SETS: usize = 1000;
+
+/// How many builds in a row a node can be off the critical path by default before we forget its
+/// durations.
+const DEFAULT_MAX_IDLE_BUILDS: u64 = 100;
+
+/// The durations of the nodes on the critical paths of the builds so far.
+#[derive(Default)]
+struct NodeStats {
+    /// How many builds reported their critical path so far.
+    builds: u64,
+    /// The durations of each node, and the last build it was on the critical path of.
+    nodes: HashMap<String, (RunningStats, u64)>,
+}
+
+/// Tracks the durations of critical path nodes across the builds that report to it, keyed by the
+/// nodes' stable ids (since keys aren't comparable across builds).
+pub struct NodeDurationHistory {
+    /// How many previous durations we need for a node before we flag anomalies for it.
+    min_samples: u64,
+    /// How many standard deviations above the mean a duration must be to be an anomaly.
+    threshold: f64,
+    /// How many builds in a row a node can be off the critical path before we forget it.
+    max_idle_builds: u64,
+    stats: Mutex<NodeStats>,
+    /// How many sets of top level targets we remember the last critical path of.
+    retained_target_sets: usize,
+    /// The nodes on the critical path of the last build of each set of top level targets, least
+    /// recently built first.
+    previous_paths: Mutex<IndexMap<String, HashSet<String>>>,
+    /// How many of the most recent builds we keep the critical paths of.
+    retained_builds: usize,
+    /// The critical paths of the most recent builds, oldest first.
+    recent_paths: Mutex<VecDeque<Vec<(String, Duration)>>>,
+}
+
+impl NodeDurationHistory {
+    pub fn new(min_samples: u64, threshold: f64) -> Self {
+        Self {
+            min_samples,
+            threshold,
+            max_idle_builds: DEFAULT_MAX_IDLE_BUILDS,
+            stats: Mutex::new(NodeStats::default()),
+            retained_target_sets: DEFAULT_RETAINED_TARGET_SETS,
+            previous_paths: Mutex::new(IndexMap::new()),
+            retained_builds: DEFAULT_RETAINED_BUILDS,
+            recent_paths: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Keep the critical paths of the last `builds` builds for `chronic_slowness`.
+    pub fn with_retained_builds(mut self, builds: usize) -> Self {
+        self.retained_builds = builds;
+        self
+    }
+
+    /// Forget the durations of nodes that weren't on the critical path of any of the last `builds`
+    /// builds, for `record`.
+    pub fn with_max_idle_builds(mut self, builds: u64) -> Self {
+        self.max_idle_builds = builds;
+        self
+    }
+
+    /// Remember the last critical path of only the `target_sets` sets of top level targets built
+    /// most recently, for `record_critical_path`.
+    pub fn with_retained_target_sets(mut self, target_sets: usize) -> Self {
+        self.retained_target_sets = target_sets;
+        self
+    }
+
+    /// Merge the critical paths of the builds we retained, ranking the nodes on them by how often
+    /// they were on the critical path times how long they took when they were. This is what is
+    /// consistently slow over a series of builds, rather than in any single one of them.
+    pub fn chronic_slowness(&self) -> Vec<ChronicNode> {
+        let recent_paths = self.recent_paths.lock().unwrap();
+
+        // For each node, the last build we saw it in, how many builds it was in, and its total
+        // duration across them.
+        let mut merged = HashMap::<&str, (usize, usize, Duration)>::new();
+        for (build, path) in recent_paths.iter().enumerate() {
+            for (node, duration) in path {
+                let (last_build, builds, total) =
+                    merged
+                        .entry(node.as_str())
+                        .or_insert((usize::MAX, 0, Duration::ZERO));
+                if *last_build != build {
+                    *last_build = build;
+                    *builds += 1;
+                }
+                *total = total.saturating_add(*duration);
+            }
+        }
+
+        let build_count = recent_paths.len();
+        let mut ranked = merged
+            .into_iter()
+            .map(|(node, (_, builds, total))| ChronicNode {
+                node: node.to_owned(),
+                builds,
+                mean_duration: total / builds as u32,
+                // The mean duration times the fraction of builds it was in.
+                score: total / build_count as u32,
+            })
+            .collect::<Vec<_>>();
+        ranked.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.node.cmp(&b.node)));
+        ranked
+    }
+}
+
+/// A node on the critical path of some of the builds a `NodeDurationHistory` retained.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChronicNode {
+    /// The node's stable id.
+    pub node: String,
+    /// How many builds it was on the critical path of.
+    pub builds: usize,
+    /// Its mean duration in those builds.
+    pub mean_duration: Duration,
+    /// Its mean duration, scaled by the fraction of builds it was on the critical path of.
+    pub score: Duration,
+}
+
+impl Default for NodeDurationHistory {
+    fn default() -> Self {
+        Self::new(5, 3.0)
+    }
+}
+
+impl CriticalPathHistory for NodeDurationHistory {
+    fn record(&self, node: &str, duration: Duration) -> Option<DurationAnomaly> {
+        let mut stats = self.stats.lock().unwrap();
+        let build = stats.builds;
+        let (stats, last_build) = stats.nodes.entry(node.to_owned()).or_default();
+        *last_build = build;
+
+        // Compare against previous builds before we add this one, otherwise an outlier would
+        // partly hide itself.
+        let previous = *stats;
+        stats.push(duration.as_secs_f64());
+
+        if previous.count < self.min_samples {
+            return None;
+        }
+
+        let stddev = previous.stddev();
+        if stddev <= 0.0 {
+            return None;
+        }
+
+        let sigma = (duration.as_secs_f64() - previous.mean) / stddev;
+        if sigma < self.threshold {
+            return None;
+        }
+
+        Some(DurationAnomaly {
+            duration,
+            mean: Duration::from_secs_f64(previous.mean),
+            stddev: Duration::from_secs_f64(stddev),
+            sigma,
+        })
+    }
+
+    fn record_critical_path(&self, targets: &str, nodes: &[String]) -> Option<f32> {
+        let nodes = nodes.iter().cloned().collect::<HashSet<_>>();
+        let mut previous_paths = self.previous_paths.lock().unwrap();
+        // Move these targets to the back, so that they are the last we forget.
+        let previous = previous_paths.shift_remove(targets);
+        previous_paths.insert(targets.to_owned(), nodes.clone());
+        while previous_paths.len() > self.retained_target_sets {
+            previous_paths.shift_remove_index(0);
+        }
+        Some(jaccard_similarity(&previous?, &nodes))
+    }
+
+    fn record_critical_path_durations(&self, path: &[(String, Duration)]) {
+        // This is the end of a build, so forget the nodes that haven't been on a critical path in a
+        // while.
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.builds += 1;
+            let builds = stats.builds;
+            stats
+                .nodes
+                .retain(|_, (_, last_build)| builds - 1 - *last_build <= self.max_idle_builds);
+        }
+
+        if self.retained_builds == 0 {
+            return;
+        }
+        let mut recent_paths = self.recent_paths.lock().unwrap();
+        while recent_paths.len() >= self.retained_builds {
+            recent_paths.pop_front();
+        }
+        recent_paths.push_back(path.to_vec());
+    }
+}
+
+/// The size of the intersection of `a` and `b` over the size of their union.
+fn jaccard_similarity(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        return 1.0;
+    }
+    a.intersection(b).count() as f32 / union as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_running_stats() {
+        let mut stats = RunningStats::default();
+        for sample in [2.0, 4.0, 4.0, 4.0, 5.0, 5.0, 7.0, 9.0] {
+            stats.push(sample);
+        }
+        assert_eq!(stats.count, 8);
+        assert!((stats.mean - 5.0).abs() < 1e-9);
+        // The sample variance is 32 / 7.
+        assert!((stats.stddev() - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_anomaly() {
+        let history = NodeDurationHistory::new(3, 3.0);
+        let secs = Duration::from_secs;
+
+        // Not enough history yet.
+        for s in [3, 4, 5] {
+            assert_eq!(history.record("a", secs(s)), None);
+        }
+        // Within the usual range.
+        assert_eq!(history.record("a", secs(5)), None);
+
+        let anomaly = history.record("a", secs(12)).unwrap();
+        assert_eq!(anomaly.duration, secs(12));
+        assert_eq!(anomaly.mean, Duration::from_millis(4250));
+        assert!(anomaly.sigma >= 3.0);
+
+        // Other nodes have their own history.
+        assert_eq!(history.record("b", secs(100)), None);
+    }
+
+    #[test]
+    fn test_no_anomaly_without_variance() {
+        let history = NodeDurationHistory::new(2, 3.0);
+        for _ in 0..5 {
+            assert_eq!(history.record("a", Duration::from_secs(4)), None);
+        }
+        // We can't tell how unusual this is when durations never varied.
+        assert_eq!(history.record("a", Duration::from_secs(40)), None);
+    }
+
+    #[test]
+    fn test_forget_idle_nodes() {
+        let history = NodeDurationHistory::default().with_max_idle_builds(1);
+        let build = |nodes: &[&str]| {
+            for node in nodes {
+                history.record(node, Duration::from_secs(1));
+            }
+            history.record_critical_path_durations(&[]);
+        };
+        let tracked = || {
+            let mut nodes = history
+                .stats
+                .lock()
+                .unwrap()
+                .nodes
+                .keys()
+                .cloned()
+                .collect::<Vec<_>>();
+            nodes.sort();
+            nodes
+        };
+
+        build(&["a", "b"]);
+        build(&["a"]);
+        // `b` missed one build, which it is allowed to.
+        assert_eq!(tracked(), vec!["a", "b"]);
+        build(&["a"]);
+        assert_eq!(tracked(), vec!["a"]);
+        assert_eq!(history.stats.lock().unwrap().nodes["a"].0.count, 3);
+    }
+
+    #[test]
+    fn test_chronic_slowness() {
+        let history = NodeDurationHistory::default().with_retained_builds(3);
+        let secs = Duration::from_secs;
+        let path = |nodes: &[(&str, u64)]| {
+            nodes
+                .iter()
+                .map(|(n, s)| ((*n).to_owned(), secs(*s)))
+                .collect::<Vec<_>>()
+        };
+
+        // This first build is forgotten by the time we ask.
+        history.record_critical_path_durations(&path(&[("gone", 100)]));
+        history.record_critical_path_durations(&path(&[("a", 2), ("slow", 9)]));
+        history.record_critical_path_durations(&path(&[("a", 4)]));
+        history.record_critical_path_durations(&path(&[("a", 3), ("b", 3)]));
+
+        let ranked = history.chronic_slowness();
+        assert_eq!(
+            ranked
+                .iter()
+                .map(|n| (n.node.as_str(), n.builds, n.score))
+                .collect::<Vec<_>>(),
+            vec![
+                // On every path, for 3s on average.
+                ("a", 3, secs(3)),
+                // Much slower, but only on one path in three.
+                ("slow", 1, secs(3)),
+                ("b", 1, secs(1)),
+            ]
+        );
+        assert_eq!(ranked[0].mean_duration, secs(3));
+    }
+
+    #[test]
+    fn test_critical_path_stability() {
+        let history = NodeDurationHistory::default();
+        let path = |nodes: &[&str]| nodes.iter().map(|n| (*n).to_owned()).collect::<Vec<_>>();
+
+        // Nothing to compare the first build against.
+        assert_eq!(history.record_critical_path("t", &path(&["a", "b"])), None);
+        assert_eq!(
+            history.record_critical_path("t", &path(&["b", "a"])),
+            Some(1.0)
+        );
+        // {b} out of {a, b, c}.
+        assert_eq!(
+            history.record_critical_path("t", &path(&["b", "c"])),
+            Some(1.0 / 3.0)
+        );
+        assert_eq!(history.record_critical_path("t", &path(&["d"])), Some(0.0));
+
+        // Other targets have their own previous build.
+        assert_eq!(history.record_critical_path("u", &path(&["d"])), None);
+    }
+
+    #[test]
+    fn test_critical_path_stability_forgets_least_recent_targets() {
+        let history = NodeDurationHistory::default().with_retained_target_sets(2);
+        let path = vec!["a".to_owned()];
+
+        assert_eq!(history.record_critical_path("t", &path), None);
+        assert_eq!(history.record_critical_path("u", &path), None);
+        // Building `t` again makes `u` the least recently built.
+        assert_eq!(history.record_critical_path("t", &path), Some(1.0));
+        assert_eq!(history.record_critical_path("v", &path), None);
+
+        assert_eq!(history.record_critical_path("t", &path), Some(1.0));
+        assert_eq!(history.record_critical_path("u", &path), None);
+    }
+}
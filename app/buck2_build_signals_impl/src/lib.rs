@@ -10,17 +10,24 @@
 #![feature(error_generic_member_access)]
 
 use std::any::Any;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::fmt;
 use std::hash::Hash;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
+use allocative::Allocative;
 use anyhow::Context as _;
 use async_trait::async_trait;
 use buck2_analysis::analysis::calculation::AnalysisKey;
 use buck2_analysis::analysis::calculation::AnalysisKeyActivationData;
+use buck2_artifact::actions::key::ActionKey;
 use buck2_artifact::artifact::build_artifact::BuildArtifact;
 use buck2_build_api::actions::calculation::BuildKey;
 use buck2_build_api::actions::calculation::BuildKeyActivationData;
@@ -31,24 +38,44 @@ use buck2_build_api::artifact_groups::ArtifactGroup;
 use buck2_build_api::artifact_groups::ResolvedArtifactGroup;
 use buck2_build_api::build_signals::BuildSignals;
 use buck2_build_api::build_signals::BuildSignalsInstaller;
+use buck2_build_api::build_signals::ExternalNode;
+use buck2_build_api::build_signals::ExternalNodeDep;
+use buck2_build_api::build_signals::ExternalNodeKey;
 use buck2_build_api::build_signals::CREATE_BUILD_SIGNALS;
+use buck2_build_api::build_signals::CREATE_CRITICAL_PATH_HISTORY;
 use buck2_build_api::deferred::calculation::DeferredCompute;
 use buck2_build_api::deferred::calculation::DeferredResolve;
 use buck2_build_signals::BuildSignalsContext;
+use buck2_build_signals::CacheMissReason;
 use buck2_build_signals::CriticalPathBackendName;
+use buck2_build_signals::CriticalPathOptions;
+use buck2_build_signals::CriticalPathRedaction;
 use buck2_build_signals::DeferredBuildSignals;
+use buck2_build_signals::DurationPolicy;
 use buck2_build_signals::FinishBuildSignals;
+use buck2_build_signals::MaterializationGrouping;
 use buck2_build_signals::NodeDuration;
 use buck2_common::package_listing::dice::PackageListingKey;
 use buck2_common::package_listing::dice::PackageListingKeyActivationData;
 use buck2_configured::nodes::calculation::ConfiguredTargetNodeKey;
+use buck2_configured::nodes::calculation::ConfiguredTargetNodeKeyActivationData;
+use buck2_core::base_deferred_key::BaseDeferredKey;
+use buck2_core::build_file_path::BuildFilePath;
+use buck2_core::cells::name::CellName;
+use buck2_core::configuration::data::ConfigurationData;
+use buck2_core::execution_types::executor_config::Executor;
+use buck2_core::fs::paths::forward_rel_path::ForwardRelativePathBuf;
 use buck2_core::package::PackageLabel;
+use buck2_core::soft_error;
 use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
 use buck2_data::ToProtoMessage;
+use buck2_events::dispatch::get_dispatcher;
 use buck2_events::dispatch::instant_event;
+use buck2_events::dispatch::with_dispatcher;
 use buck2_events::dispatch::with_dispatcher_async;
 use buck2_events::dispatch::EventDispatcher;
 use buck2_events::span::SpanId;
+use buck2_execute::execute::action_digest::ActionDigest;
 use buck2_interpreter_for_build::interpreter::calculation::IntepreterResultsKeyActivationData;
 use buck2_interpreter_for_build::interpreter::calculation::InterpreterResultsKey;
 use buck2_node::nodes::eval_result::EvaluationResult;
@@ -57,7 +84,11 @@ use dice::ActivationData;
 use dice::ActivationTracker;
 use dupe::Dupe;
 use dupe::OptionDupedExt;
+use indexmap::map::Entry;
+use indexmap::IndexMap;
 use itertools::Itertools;
+use serde::Deserialize;
+use serde::Serialize;
 use smallvec::SmallVec;
 use static_assertions::assert_eq_size;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -67,13 +98,55 @@ use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::StreamExt;
 
 use crate::backend::backend::BuildListenerBackend;
+use crate::backend::composite::CompositeBackend;
 use crate::backend::default::DefaultBackend;
 use crate::backend::longest_path_graph::LongestPathGraphBackend;
+use crate::backend::longest_path_graph::RetainedGraph;
+use crate::backend::weighted::OutputBytesBackend;
+use crate::backend::weighted::TransferredBytesBackend;
+use crate::clock::Clock;
+use crate::clock::SystemClock;
+use crate::coalesce::NodeCoalescer;
+use crate::coalesce::PendingNode;
+use crate::export::write_folded_stacks;
+use crate::export::write_mermaid;
+use crate::export::ExportedGraph;
+use crate::export::GraphExportFocus;
+use crate::export::GraphExportOptions;
+use crate::perfetto::write_perfetto_trace;
+use crate::redact::Redactor;
+use crate::replay::RecordedSignal;
+use crate::replay::SignalRecorder;
+use crate::stream::EvaluationStream;
+use crate::stream::StreamedEvaluation;
 
 mod backend;
+mod clock;
+mod coalesce;
+mod compare;
+mod export;
+mod history;
+mod merge;
+mod perfetto;
+mod redact;
+mod replay;
+mod stream;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use compare::compare_critical_paths;
+pub use compare::compare_critical_paths_by_cause;
+pub use compare::compare_graph_structures;
+pub use compare::CriticalPathRegression;
+pub use compare::GraphStructureDiff;
+pub use compare::RegressionThresholds;
+pub use history::ChronicNode;
+pub use history::NodeDurationHistory;
+pub use merge::merge_recordings;
+pub use replay::replay;
 
 /// A node in our critical path graph.
-#[derive(Hash, Eq, PartialEq, Clone, Dupe, Debug, From)]
+#[derive(Hash, Eq, PartialEq, Clone, Dupe, Debug, From, Allocative)]
 enum NodeKey {
     // Those are DICE keys.
     BuildKey(BuildKey),
@@ -86,8 +159,13 @@ enum NodeKey {
     InterpreterResultsKey(InterpreterResultsKey),
     PackageListingKey(PackageListingKey),
 
-    // This one is not a DICE key.
+    // These are not DICE keys.
     Materialization(BuildArtifact),
+    /// Work that an integration reported to us.
+    External {
+        id: Arc<str>,
+        display: Arc<str>,
+    },
 }
 
 // Explain the sizeof this struct (and avoid regressing it since we store it in the longest path
@@ -148,10 +226,142 @@ impl fmt::Display for NodeKey {
             Self::InterpreterResultsKey(k) => write!(f, "InterpreterResultsKey({})", k),
             Self::PackageListingKey(k) => write!(f, "PackageListingKey({})", k),
             Self::Materialization(k) => write!(f, "Materialization({})", k),
+            Self::External { display, .. } => write!(f, "External({})", display),
         }
     }
 }
 
+impl NodeKey {
+    /// The configured target this node was evaluated on behalf of, if any.
+    fn owner_target(&self) -> Option<&ConfiguredTargetLabel> {
+        match self {
+            Self::BuildKey(k) => match k.0.owner() {
+                BaseDeferredKey::TargetLabel(label) => Some(label),
+                BaseDeferredKey::AnonTarget(..) | BaseDeferredKey::BxlLabel(..) => None,
+            },
+            Self::AnalysisKey(k) => Some(&k.0),
+            _ => None,
+        }
+    }
+
+    /// What this node was evaluated on behalf of, if anything.
+    fn owner(&self) -> Option<BaseDeferredKey> {
+        match self {
+            Self::BuildKey(k) => Some(k.0.owner().dupe()),
+            Self::AnalysisKey(k) => Some(BaseDeferredKey::TargetLabel(k.0.dupe())),
+            Self::Materialization(k) => Some(k.key().owner().dupe()),
+            _ => None,
+        }
+    }
+
+    /// The cell this node belongs to, if we can tell: the cell of the package it loads or of the
+    /// target it is for.
+    fn cell(&self) -> Option<CellName> {
+        match self {
+            Self::BuildKey(..) | Self::AnalysisKey(..) | Self::Materialization(..) => {
+                match self.owner()? {
+                    BaseDeferredKey::TargetLabel(label) => Some(label.pkg().cell_name()),
+                    BaseDeferredKey::AnonTarget(key) | BaseDeferredKey::BxlLabel(key) => {
+                        Some(key.configured_label()?.pkg().cell_name())
+                    }
+                }
+            }
+            Self::ConfiguredTargetNodeKey(k) => Some(k.0.pkg().cell_name()),
+            Self::InterpreterResultsKey(k) => Some(k.0.cell_name()),
+            Self::PackageListingKey(k) => Some(k.0.cell_name()),
+            _ => None,
+        }
+    }
+
+    /// The kind of node this is, as it is displayed.
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::BuildKey(..) => "BuildKey",
+            Self::AnalysisKey(..) => "AnalysisKey",
+            Self::EnsureProjectedArtifactKey(..) => "EnsureProjectedArtifactKey",
+            Self::EnsureTransitiveSetProjectionKey(..) => "EnsureTransitiveSetProjectionKey",
+            Self::DeferredCompute(..) => "DeferredCompute",
+            Self::DeferredResolve(..) => "DeferredResolve",
+            Self::ConfiguredTargetNodeKey(..) => "ConfiguredTargetNodeKey",
+            Self::InterpreterResultsKey(..) => "InterpreterResultsKey",
+            Self::PackageListingKey(..) => "PackageListingKey",
+            Self::Materialization(..) => "Materialization",
+            Self::External { .. } => "External",
+        }
+    }
+
+    /// The configured target this node is for, if any. For actions, that is their owner.
+    fn configured_label(&self) -> Option<&ConfiguredTargetLabel> {
+        match self {
+            Self::BuildKey(k) => k.0.owner().unpack_target_label(),
+            Self::AnalysisKey(k) => Some(&k.0),
+            Self::ConfiguredTargetNodeKey(k) => Some(&k.0),
+            _ => None,
+        }
+    }
+
+    /// Whether this node is part of loading packages or configuring targets.
+    fn is_configuration(&self) -> bool {
+        matches!(
+            self,
+            Self::ConfiguredTargetNodeKey(..) | Self::InterpreterResultsKey(..)
+        )
+    }
+
+    /// Which phase of the build this node belongs to. Deferreds can happen in either, so they
+    /// don't belong to one, and we don't know what external nodes do.
+    fn phase(&self) -> Option<NodePhase> {
+        match self {
+            Self::AnalysisKey(..)
+            | Self::ConfiguredTargetNodeKey(..)
+            | Self::InterpreterResultsKey(..)
+            | Self::PackageListingKey(..) => Some(NodePhase::Analysis),
+            Self::BuildKey(..)
+            | Self::EnsureProjectedArtifactKey(..)
+            | Self::EnsureTransitiveSetProjectionKey(..)
+            | Self::Materialization(..) => Some(NodePhase::Execution),
+            Self::DeferredCompute(..) | Self::DeferredResolve(..) | Self::External { .. } => None,
+        }
+    }
+
+    /// The canonical id of this node across processes: the same logical node gets the same id in
+    /// every build, so this is what anything comparing or aggregating nodes across builds should
+    /// key on.
+    ///
+    /// This is the `Display` of the node, except for materializations and external nodes. All the
+    /// other keys display as labels, packages, paths and the indices of deferreds within their
+    /// owner's analysis, which are deterministic (anon targets display a hash of their attributes,
+    /// which uses a hasher with fixed keys). Artifacts however are equal if their paths are, but
+    /// also display the action that produced them, which differs for e.g. outputs of dynamic
+    /// actions. External nodes come with an id from the integration that reported them.
+    fn stable_id(&self) -> String {
+        match self {
+            Self::Materialization(k) => {
+                let path = k.get_path();
+                match path.action_key() {
+                    Some(action_key) => format!(
+                        "Materialization(({})/{} [{}])",
+                        path.owner(),
+                        path.path(),
+                        action_key
+                    ),
+                    None => format!("Materialization(({})/{})", path.owner(), path.path()),
+                }
+            }
+            Self::External { id, .. } => format!("External({})", id),
+            _ => self.to_string(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Dupe, Debug, PartialEq, Eq)]
+enum NodePhase {
+    /// Loading, configuring and analyzing targets.
+    Analysis,
+    /// Building and materializing artifacts.
+    Execution,
+}
+
 struct TopLevelTargetSignal {
     pub label: ConfiguredTargetLabel,
     pub artifacts: Vec<ArtifactGroup>,
@@ -159,8 +369,26 @@ struct TopLevelTargetSignal {
 
 struct FinalMaterializationSignal {
     pub artifact: BuildArtifact,
+    pub requester: Option<ConfiguredTargetLabel>,
     pub duration: NodeDuration,
     pub span_id: Option<SpanId>,
+    pub bytes: Option<u64>,
+}
+
+struct FinalMaterializationRequestedSignal {
+    pub artifact: BuildArtifact,
+    pub requester: ConfiguredTargetLabel,
+}
+
+struct ActionFailedSignal {
+    pub key: NodeKey,
+}
+
+/// A top level target from a recording we are replaying, whose keys we couldn't reconstruct.
+struct ReplayedTopLevelTargetSignal {
+    pub label: String,
+    pub analysis: NodeKey,
+    pub artifacts: Vec<NodeKey>,
 }
 
 /* These signals are distinct from the main Buck event bus because some
@@ -173,6 +401,9 @@ enum BuildSignal {
     Evaluation(Evaluation),
     TopLevelTarget(TopLevelTargetSignal),
     FinalMaterialization(FinalMaterializationSignal),
+    FinalMaterializationRequested(FinalMaterializationRequestedSignal),
+    ActionFailed(ActionFailedSignal),
+    ReplayedTopLevelTarget(ReplayedTopLevelTargetSignal),
     BuildFinished,
 }
 
@@ -186,6 +417,18 @@ pub struct Evaluation {
     dep_keys: Vec<NodeKey>,
     /// Spans that correspond to this key. We use this when producing a chrome trace.
     spans: SmallVec<[SpanId; 1]>,
+    /// How many bytes we moved to and from RE for this key (only present for NodeKey::BuildKey).
+    transferred_bytes: u64,
+    /// How many bytes of outputs this key's action produced (only present for NodeKey::BuildKey).
+    output_bytes: u64,
+    /// Why this key's action wasn't served from a cache (only present for NodeKey::BuildKey).
+    cache_miss_reason: Option<CacheMissReason>,
+    /// How long this key's action spent queued in RE (only present for NodeKey::BuildKey, if it
+    /// ran remotely).
+    re_queue_duration: Option<Duration>,
+    /// Whether DICE reused this key's value from a previous build rather than computing it in
+    /// this one.
+    reused: bool,
 
     // NOTE: The fields below aren't usually going to be both set, but it doesn't really hurt (for
     // now) to have them not tied to the right variant.
@@ -193,13 +436,72 @@ pub struct Evaluation {
     /// NodeKey::BuildKey).
     action: Option<Arc<RegisteredAction>>,
 
+    /// The digest of the command the action ran (this will only be present for
+    /// NodeKey::BuildKey, and only if the action ran a command successfully).
+    action_digest: Option<ActionDigest>,
+
     /// The Load result that corresponds to this Evaluation (this will only be pesent for
     /// InterpreterResultsKey).
     load_result: Option<Arc<EvaluationResult>>,
 }
 
+impl Evaluation {
+    /// Treat a node reported by an integration as if we had evaluated it.
+    fn external(node: ExternalNode) -> Self {
+        Self {
+            key: node.key.into(),
+            duration: node.duration,
+            dep_keys: node
+                .deps
+                .into_iter()
+                .map(|dep| match dep {
+                    ExternalNodeDep::Action(key) => NodeKey::BuildKey(BuildKey(key)),
+                    ExternalNodeDep::External(key) => key.into(),
+                })
+                .collect(),
+            spans: node.span_id.into_iter().collect(),
+            transferred_bytes: 0,
+            output_bytes: 0,
+            cache_miss_reason: None,
+            re_queue_duration: None,
+            reused: false,
+            action: None,
+            action_digest: None,
+            load_result: None,
+        }
+    }
+}
+
+impl From<ExternalNodeKey> for NodeKey {
+    fn from(key: ExternalNodeKey) -> Self {
+        Self::External {
+            id: key.id,
+            display: key.display,
+        }
+    }
+}
+
 pub struct BuildSignalSender {
     sender: UnboundedSender<BuildSignal>,
+    /// Whether we already told the receiver the build finished.
+    finished: AtomicBool,
+}
+
+impl BuildSignalSender {
+    fn new(sender: UnboundedSender<BuildSignal>) -> Self {
+        Self {
+            sender,
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Tell the receiver the build finished. This only sends a signal the first time it is
+    /// called, no matter who calls it, since the receiver stops at the first one.
+    fn build_finished(&self) {
+        if !self.finished.swap(true, Ordering::Relaxed) {
+            let _ignored = self.sender.send(BuildSignal::BuildFinished);
+        }
+    }
 }
 
 impl BuildSignals for BuildSignalSender {
@@ -212,18 +514,49 @@ impl BuildSignals for BuildSignalSender {
     fn final_materialization(
         &self,
         artifact: BuildArtifact,
+        requester: Option<ConfiguredTargetLabel>,
         duration: NodeDuration,
         span_id: Option<SpanId>,
+        bytes: Option<u64>,
     ) {
         let _ignored = self.sender.send(
             FinalMaterializationSignal {
                 artifact,
+                requester,
                 duration,
                 span_id,
+                bytes,
+            }
+            .into(),
+        );
+    }
+
+    fn final_materialization_requested(
+        &self,
+        artifact: BuildArtifact,
+        requester: ConfiguredTargetLabel,
+    ) {
+        let _ignored = self.sender.send(
+            FinalMaterializationRequestedSignal {
+                artifact,
+                requester,
+            }
+            .into(),
+        );
+    }
+
+    fn action_failed(&self, key: ActionKey) {
+        let _ignored = self.sender.send(
+            ActionFailedSignal {
+                key: NodeKey::BuildKey(BuildKey(key)),
             }
             .into(),
         );
     }
+
+    fn external_node(&self, node: ExternalNode) {
+        let _ignored = self.sender.send(Evaluation::external(node).into());
+    }
 }
 
 impl ActivationTracker for BuildSignalSender {
@@ -244,9 +577,15 @@ impl ActivationTracker for BuildSignalSender {
         let mut signal = Evaluation {
             key,
             action: None,
+            action_digest: None,
             duration: NodeDuration::zero(),
             dep_keys: deps.into_iter().filter_map(NodeKey::from_any).collect(),
             spans: Default::default(),
+            transferred_bytes: 0,
+            output_bytes: 0,
+            cache_miss_reason: None,
+            re_queue_duration: None,
+            reused: matches!(activation_data, ActivationData::Reused),
             load_result: None,
         };
 
@@ -267,11 +606,22 @@ impl ActivationTracker for BuildSignalSender {
                 action,
                 duration,
                 spans,
+                bytes_downloaded,
+                bytes_uploaded,
+                output_bytes,
+                re_queue_duration,
+                action_digest,
+                cache_miss_reason,
             }) = downcast_and_take(&mut activation_data)
             {
                 signal.action = Some(action);
                 signal.duration = duration;
                 signal.spans = spans;
+                signal.transferred_bytes = bytes_downloaded + bytes_uploaded;
+                signal.output_bytes = output_bytes;
+                signal.action_digest = action_digest;
+                signal.cache_miss_reason = cache_miss_reason;
+                signal.re_queue_duration = re_queue_duration;
             } else if let Some(AnalysisKeyActivationData { duration, spans }) =
                 downcast_and_take(&mut activation_data)
             {
@@ -301,6 +651,25 @@ impl ActivationTracker for BuildSignalSender {
                     total: duration,
                 };
                 signal.spans = spans;
+            } else if let Some(ConfiguredTargetNodeKeyActivationData { duration, spans }) =
+                downcast_and_take(&mut activation_data)
+            {
+                signal.duration = NodeDuration {
+                    user: duration,
+                    total: duration,
+                };
+                signal.spans = spans;
+            }
+
+            // If we didn't take the activation data, this key started reporting data we don't
+            // know about, and we'd silently report it as taking no time. Soft errors are rate
+            // limited, so this won't flood us if it happens for every key of some type.
+            if activation_data.is_some() {
+                let _ignored = soft_error!(
+                    "unhandled_activation_data",
+                    anyhow::anyhow!("Unhandled activation data for {}", signal.key),
+                    quiet: true
+                );
             }
         }
 
@@ -320,12 +689,87 @@ impl DeferredBuildSignals for DeferredBuildSignalsImpl {
         backend: CriticalPathBackendName,
         ctx: BuildSignalsContext,
     ) -> Box<dyn FinishBuildSignals> {
+        let longest_path_graph = || {
+            let mut backend =
+                LongestPathGraphBackend::new().with_duration_policy(ctx.options.duration_policy);
+            if ctx.options.graph_export_path.is_some() {
+                backend =
+                    backend.with_graph_export(GraphExportOptions {
+                        edge_timing: ctx.options.graph_export_edge_timing,
+                        focus: ctx.options.graph_export_focus.as_ref().map(|node| {
+                            GraphExportFocus {
+                                node: node.clone(),
+                                radius: ctx.options.graph_export_radius,
+                            }
+                        }),
+                    });
+            }
+            if let Some(patterns) = &ctx.options.longest_path_graph_targets {
+                backend = backend.with_target_filter(patterns.clone());
+            }
+            if ctx.options.phase_critical_paths {
+                backend = backend.with_phase_critical_paths();
+            }
+            if ctx.options.configuration_critical_path {
+                backend = backend.with_configuration_critical_path();
+            }
+            if ctx.options.what_if_zero_cost.is_some() || ctx.options.focus_targets.is_some() {
+                backend = backend.with_retained_graph();
+            }
+            if ctx.options.contract_zero_duration_nodes {
+                backend = backend.with_zero_weight_contraction();
+            }
+            if ctx.options.attribute_shared_analysis {
+                backend = backend.with_analysis_dependents();
+            }
+            if ctx.options.hops_from_top_level {
+                backend = backend.with_hops_from_top_level();
+            }
+            if let Some(cores) = ctx.options.simulated_core_count {
+                backend = backend.with_simulated_schedule(cores);
+            }
+            if ctx.options.slack_histogram {
+                backend = backend.with_slack_histogram();
+            }
+            if ctx.options.always_critical {
+                backend = backend.with_always_critical();
+            }
+            if let Some(count) = ctx.options.near_critical_count {
+                backend = backend.with_near_critical(count);
+            }
+            if !ctx.options.cost_floors.is_empty() {
+                backend = backend.with_cost_floors(&ctx.options.cost_floors);
+            }
+            backend
+        };
+        let default = || {
+            let mut backend =
+                DefaultBackend::new().with_duration_policy(ctx.options.duration_policy);
+            if ctx.options.default_backend_potentials {
+                backend = backend.with_approximate_potentials();
+            }
+            backend
+        };
+
         let handle = match backend {
             CriticalPathBackendName::LongestPathGraph => {
-                start_backend(events, self.receiver, LongestPathGraphBackend::new(), ctx)
+                let backend = longest_path_graph();
+                start_backend(events, self.receiver, backend, ctx)
             }
             CriticalPathBackendName::Default => {
-                start_backend(events, self.receiver, DefaultBackend::new(), ctx)
+                start_backend(events, self.receiver, default(), ctx)
+            }
+            CriticalPathBackendName::Composite => {
+                let backend = CompositeBackend::new(default(), longest_path_graph());
+                start_backend(events, self.receiver, backend, ctx)
+            }
+            CriticalPathBackendName::TransferredBytes => {
+                let backend = TransferredBytesBackend::new(longest_path_graph());
+                start_backend(events, self.receiver, backend, ctx)
+            }
+            CriticalPathBackendName::OutputBytes => {
+                let backend = OutputBytesBackend::new(longest_path_graph());
+                start_backend(events, self.receiver, backend, ctx)
             }
         };
 
@@ -344,7 +788,7 @@ pub struct FinishBuildSignalsImpl {
 #[async_trait]
 impl FinishBuildSignals for FinishBuildSignalsImpl {
     async fn finish(self: Box<Self>) -> anyhow::Result<()> {
-        let _ignored = self.sender.sender.send(BuildSignal::BuildFinished);
+        self.sender.build_finished();
 
         self.handle
             .await
@@ -358,7 +802,7 @@ fn start_backend(
     backend: impl BuildListenerBackend + Send + 'static,
     ctx: BuildSignalsContext,
 ) -> JoinHandle<anyhow::Result<()>> {
-    let listener = BuildSignalReceiver::new(receiver, backend);
+    let listener = BuildSignalReceiver::new(receiver, backend, &ctx.options);
     tokio::spawn(with_dispatcher_async(events.dupe(), async move {
         listener.run_and_log(ctx).await
     }))
@@ -370,44 +814,482 @@ struct BuildSignalReceiver<T> {
     // shows up, we'll give it a dependency on said first PackageLabel that had an edge to it, which
     // is how we discovered its existence.
     first_edge_to_load: HashMap<PackageLabel, PackageLabel>,
+    // Whether we maintain and add those edges at all.
+    enrich_load_graph: bool,
     backend: T,
+    // Where we get the time from.
+    clock: Arc<dyn Clock>,
+    // When we started receiving signals. Node timestamps are relative to this.
+    start: Instant,
+    // Nodes we received but haven't passed to the backend yet.
+    coalescer: NodeCoalescer,
+    // The build file we evaluated for each package we loaded.
+    build_file_paths: HashMap<PackageLabel, Arc<BuildFilePath>>,
+    // How many span ids we keep for each node.
+    max_span_ids_per_node: usize,
+    // The top level targets that requested each artifact we materialized at the end of the build.
+    // We don't keep this in `NodeData` because other targets might request the artifact after we
+    // receive its materialization.
+    materialization_requesters: HashMap<BuildArtifact, Vec<ConfiguredTargetLabel>>,
+    // Which of each node's durations we use for the critical path.
+    duration_policy: DurationPolicy,
+    // How many distinct packages a loaded package may depend on before we report it.
+    max_load_fan_out: usize,
+    // Where we stream evaluations to as we receive them, if anywhere.
+    stream: Option<EvaluationStream>,
+    // The top level targets that were requested, which identify builds of the same targets across
+    // builds.
+    requested_targets: BTreeSet<String>,
+    // What we know about which configurations are for which platform.
+    platforms: PlatformClassifier,
+    // How we combine materializations into nodes, and the groups we are building up if we do.
+    materialization_grouping: MaterializationGrouping,
+    // Kept in insertion order so that flushing them emits nodes deterministically.
+    materialization_groups: IndexMap<MaterializationGroupKey, MaterializationGroup>,
+    // Once groups are flushed, the group each representative artifact stands for, and how many
+    // artifacts are in it.
+    grouped_materializations: HashMap<BuildArtifact, (MaterializationGroupKey, u64)>,
+    // If we log where the critical path stands while the build runs, what we track to do so.
+    partial_critical_path: Option<PartialCriticalPathTracker>,
+    // Where we record the signals we process, if anywhere.
+    recorder: Option<SignalRecorder>,
+    // Whether we log each signal we process.
+    log_signals: bool,
+    // When we log signals, we take whatever is queued off the channel so that we can tell how far
+    // behind we are. These are the signals we took but haven't processed yet.
+    backlog: VecDeque<BuildSignal>,
+}
+
+/// Follows the critical path as nodes come in, so that we can log where it stands before the
+/// build finishes. This doesn't apply the duplicate evaluation policy or any of the grouping we do
+/// at the end, so it is only an approximation of what the backend eventually reports.
+struct PartialCriticalPathTracker {
+    backend: DefaultBackend,
+    interval: Duration,
+    last_logged: Instant,
 }
 
 impl<T> BuildSignalReceiver<T>
 where
-    T: BuildListenerBackend,
+    T: BuildListenerBackend + Send + 'static,
 {
-    fn new(receiver: UnboundedReceiver<BuildSignal>, backend: T) -> Self {
+    fn new(
+        receiver: UnboundedReceiver<BuildSignal>,
+        backend: T,
+        options: &CriticalPathOptions,
+    ) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        let now = clock.now();
         Self {
             receiver: UnboundedReceiverStream::new(receiver),
             backend,
             first_edge_to_load: HashMap::new(),
+            enrich_load_graph: options.enrich_load_graph,
+            clock,
+            start: now,
+            coalescer: NodeCoalescer::new(
+                options.duplicate_evaluation_policy,
+                options.duration_policy,
+            ),
+            build_file_paths: HashMap::new(),
+            max_span_ids_per_node: options.max_span_ids_per_node,
+            materialization_requesters: HashMap::new(),
+            duration_policy: options.duration_policy,
+            max_load_fan_out: options.max_load_fan_out,
+            stream: None,
+            requested_targets: BTreeSet::new(),
+            platforms: PlatformClassifier::new(&options.host_platforms),
+            materialization_grouping: options.materialization_grouping,
+            materialization_groups: IndexMap::new(),
+            grouped_materializations: HashMap::new(),
+            partial_critical_path: options.partial_critical_path_interval.map(|interval| {
+                PartialCriticalPathTracker {
+                    backend: DefaultBackend::new().with_duration_policy(options.duration_policy),
+                    interval,
+                    last_logged: now,
+                }
+            }),
+            recorder: None,
+            log_signals: options.log_signals,
+            backlog: VecDeque::new(),
+        }
+    }
+
+    /// Measure time with `clock` instead of the system clock, starting now.
+    #[cfg(test)]
+    fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.start = clock.now();
+        if let Some(partial) = &mut self.partial_critical_path {
+            partial.last_logged = self.start;
+        }
+        self.clock = clock;
+        self
+    }
+
+    /// How long it's been since we started receiving signals.
+    fn elapsed(&self) -> Duration {
+        self.clock.now().saturating_duration_since(self.start)
+    }
+
+    /// The next signal to process, if the channel isn't closed.
+    async fn next_signal(&mut self) -> Option<BuildSignal> {
+        if !self.log_signals {
+            return self.receiver.next().await;
+        }
+
+        if self.backlog.is_empty() {
+            let signal = self.receiver.next().await?;
+            self.backlog.push_back(signal);
+        }
+        while let Ok(signal) = self.receiver.as_mut().try_recv() {
+            self.backlog.push_back(signal);
+        }
+        self.backlog.pop_front()
+    }
+
+    /// Log a signal we are processing, if we were asked to. `queue_depth` is how many signals were
+    /// waiting behind it.
+    fn log_signal(
+        &self,
+        kind: &str,
+        key: &dyn fmt::Display,
+        duration: Option<Duration>,
+        deps: usize,
+    ) {
+        if self.log_signals {
+            tracing::debug!(
+                kind,
+                key = %key,
+                duration = ?duration,
+                deps,
+                queue_depth = self.backlog.len(),
+                "Build signal"
+            );
         }
     }
 
-    pub async fn run_and_log(mut self, ctx: BuildSignalsContext) -> anyhow::Result<()> {
-        while let Some(event) = self.receiver.next().await {
+    pub async fn run_and_log(mut self, mut ctx: BuildSignalsContext) -> anyhow::Result<()> {
+        self.stream = ctx.evaluation_stream.take().map(EvaluationStream::new);
+        if let Some(path) = &ctx.options.signal_recording_path {
+            match SignalRecorder::create(path) {
+                Ok(recorder) => self.recorder = Some(recorder),
+                Err(e) => report_recording_error(Err(e)),
+            }
+        }
+
+        // How long the build took, which we only know if we were told it finished. If we weren't,
+        // the channel closed because every sender was dropped, and we still report what we got.
+        let mut wall_clock = None;
+
+        while let Some(event) = self.next_signal().await {
             match event {
-                BuildSignal::Evaluation(eval) => self.process_evaluation(eval),
+                BuildSignal::Evaluation(eval) => {
+                    if let Some(streamed) = self.process_evaluation(eval) {
+                        self.send_to_stream(streamed).await;
+                    }
+                }
                 BuildSignal::TopLevelTarget(top_level) => {
+                    self.log_signal(
+                        "TopLevelTarget",
+                        &top_level.label,
+                        None,
+                        top_level.artifacts.len(),
+                    );
                     self.process_top_level_target(top_level)?
                 }
                 BuildSignal::FinalMaterialization(final_materialization) => {
+                    self.log_signal(
+                        "FinalMaterialization",
+                        &final_materialization.artifact,
+                        Some(
+                            final_materialization
+                                .duration
+                                .duration_for(self.duration_policy),
+                        ),
+                        0,
+                    );
                     self.process_final_materialization(final_materialization)?
                 }
-                BuildSignal::BuildFinished => break,
+                BuildSignal::FinalMaterializationRequested(requested) => {
+                    self.log_signal(
+                        "FinalMaterializationRequested",
+                        &requested.artifact,
+                        None,
+                        0,
+                    );
+                    self.add_materialization_requester(requested.artifact, requested.requester)
+                }
+                BuildSignal::ActionFailed(failed) => {
+                    self.log_signal("ActionFailed", &failed.key, None, 0);
+                    self.record(|| RecordedSignal::action_failed(&failed.key));
+                    self.backend.process_failed_action(failed.key)
+                }
+                BuildSignal::ReplayedTopLevelTarget(top_level) => {
+                    self.log_signal(
+                        "ReplayedTopLevelTarget",
+                        &top_level.label,
+                        None,
+                        top_level.artifacts.len(),
+                    );
+                    self.process_top_level_keys(
+                        top_level.label,
+                        top_level.analysis,
+                        top_level.artifacts,
+                    )
+                }
+                BuildSignal::BuildFinished => {
+                    self.log_signal("BuildFinished", &"", None, 0);
+                    wall_clock = Some(self.elapsed());
+                    break;
+                }
             }
+
+            self.log_partial_critical_path()?;
+        }
+
+        if let Some(stream) = self.stream.take() {
+            report_stream_error(stream.finish().await);
+        }
+
+        self.flush_materialization_groups();
+
+        if let Some(recorder) = self.recorder.take() {
+            report_recording_error(recorder.finish());
+        }
+
+        let collapsed_actions = ctx
+            .options
+            .collapse_identical_actions
+            .map(|policy| self.coalescer.collapse_identical_actions(policy) as u64);
+
+        // Everything the backend sees, so that we can tell how much work happened off the
+        // critical path once we know what is on it.
+        let mut node_count = 0u64;
+        let mut total_user_duration = Duration::ZERO;
+        for node in self.coalescer.drain() {
+            node_count += 1;
+            total_user_duration = total_user_duration.saturating_add(node.data.duration.user);
+            self.platforms.observe(&node.key);
+            self.backend
+                .process_node(node.key, node.data, node.dep_keys.into_iter());
         }
 
-        let now = Instant::now();
+        let backend_memory_bytes = if ctx.options.measure_backend_memory {
+            Some(allocative::size_of_unique(&self.backend) as u64)
+        } else {
+            None
+        };
+
+        let clock = self.clock.dupe();
+        let compute_start = clock.now();
+        let deadline = ctx
+            .options
+            .potentials_timeout
+            .map(|timeout| compute_start + timeout);
+
+        let info = {
+            // Computing the critical path can take a while on large graphs, so don't tie up an
+            // async worker while we do it.
+            let backend = self.backend;
+            let dispatcher = get_dispatcher();
+            tokio::task::spawn_blocking(move || {
+                with_dispatcher(dispatcher, || backend.finish(deadline))
+            })
+            .await
+            .context("Error joining critical path computation")??
+        };
+
+        let what_if_critical_path = match &ctx.options.what_if_zero_cost {
+            Some(key) => match info.find_key(key) {
+                Some(key) => info.critical_path_without(key)?.unwrap_or_default(),
+                None => Vec::new(),
+            },
+            None => Vec::new(),
+        };
+
+        let focused_critical_path = match &ctx.options.focus_targets {
+            Some(targets) => info.critical_path_for_targets(targets)?.unwrap_or_default(),
+            None => Vec::new(),
+        };
 
         let BuildInfo {
             critical_path,
             num_nodes,
             num_edges,
-        } = self.backend.finish()?;
+            potentials_timed_out,
+            exported_graph,
+            top_level_targets,
+            analysis_critical_path,
+            execution_critical_path,
+            configuration_critical_path,
+            retained_graph: _,
+            potential_improvement_bytes,
+            analysis_dependents,
+            hops_from_top_level,
+            failure_critical_path,
+            simulated_schedule,
+            slack_histogram,
+            on_every_critical_path,
+            always_critical,
+            near_critical,
+            critical_path_overhead,
+            last_blockers,
+        } = info;
+
+        let compute_elapsed = clock.now().saturating_duration_since(compute_start);
+
+        if let Some(degenerate) = degenerate_critical_path(
+            critical_path.len(),
+            num_nodes,
+            num_edges,
+            ctx.options.degenerate_critical_path_min_nodes,
+        ) {
+            let _ignored = soft_error!(
+                "critical_path_degenerate",
+                anyhow::anyhow!("{}", degenerate),
+                quiet: true
+            );
+        }
+
+        if let Some(observer) = &ctx.summary_observer {
+            observer
+                .critical_path_summary(critical_path_summary(&critical_path, self.duration_policy));
+        }
+
+        if let (Some(path), Some(graph)) = (&ctx.options.graph_export_path, &exported_graph) {
+            graph
+                .write(path)
+                .context("Error exporting critical path graph")?;
+        }
+
+        if let Some(path) = &ctx.options.folded_stacks_path {
+            write_folded_stacks(
+                path,
+                critical_path.iter().map(|(key, data, _)| (key, data)),
+                self.duration_policy,
+            )
+            .context("Error writing critical path folded stacks")?;
+        }
+
+        if let Some(path) = &ctx.options.mermaid_path {
+            write_mermaid(
+                path,
+                critical_path.iter().map(|(key, data, _)| (key, data)),
+                self.duration_policy,
+            )
+            .context("Error writing critical path Mermaid diagram")?;
+        }
+
+        if let Some(path) = &ctx.options.perfetto_trace_path {
+            write_perfetto_trace(
+                path,
+                critical_path.iter().map(|(key, data, _)| (key, data)),
+                self.duration_policy,
+            )
+            .context("Error writing critical path Perfetto trace")?;
+        }
+
+        let off_critical_path_node_count = node_count.saturating_sub(critical_path.len() as u64);
+        let off_critical_path_total_duration = critical_path
+            .iter()
+            .fold(total_user_duration, |total, (_, data, _)| {
+                total.saturating_sub(data.duration.user)
+            });
+
+        // However many cores the build had, it could not have been faster than its critical path.
+        let min_build_time = critical_path
+            .iter()
+            .fold(Duration::ZERO, |total, (_, data, _)| {
+                total.saturating_add(data.duration.duration_for(self.duration_policy))
+            });
+        let gap_to_min_build_time = wall_clock.map(|w| w.saturating_sub(min_build_time));
+
+        let critical_path_user_duration = critical_path
+            .iter()
+            .fold(Duration::ZERO, |total, (_, data, _)| {
+                total.saturating_add(data.duration.user)
+            });
+
+        let total_re_queue_duration = critical_path
+            .iter()
+            .filter_map(|(_, data, _)| data.re_queue_duration)
+            .fold(Duration::ZERO, Duration::saturating_add);
+
+        let (cacheable_duration, non_cacheable_duration) = cacheability_durations(
+            critical_path.iter().map(|(key, data, _)| (key, data)),
+            self.duration_policy,
+        );
+
+        let platform_durations = platform_durations(
+            critical_path.iter().map(|(key, data, _)| (key, data)),
+            &self.platforms,
+            self.duration_policy,
+        )
+        .into_iter()
+        .map(|(platform, duration)| {
+            anyhow::Ok(buck2_data::CriticalPathPlatformDuration {
+                platform: platform.to_owned(),
+                duration: Some(duration.try_into()?),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let mut cell_durations = cell_durations(
+            critical_path.iter().map(|(key, data, _)| (key, data)),
+            self.duration_policy,
+        )
+        .into_iter()
+        .map(|(cell, duration)| {
+            anyhow::Ok(buck2_data::CriticalPathCellDuration {
+                cell,
+                duration: Some(duration.try_into()?),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+        let mut duration_anomalies = match &ctx.critical_path_history {
+            Some(history) => critical_path
+                .iter()
+                .filter_map(|(key, data, _)| {
+                    let node = key.stable_id();
+                    let anomaly =
+                        history.record(&node, data.duration.duration_for(self.duration_policy))?;
+                    Some((node, anomaly))
+                })
+                .map(|(node, anomaly)| {
+                    anyhow::Ok(buck2_data::CriticalPathDurationAnomaly {
+                        node,
+                        duration: Some(anomaly.duration.try_into()?),
+                        mean: Some(anomaly.mean.try_into()?),
+                        stddev: Some(anomaly.stddev.try_into()?),
+                        sigma: anomaly.sigma,
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+
+        if let Some(history) = &ctx.critical_path_history {
+            let path = critical_path
+                .iter()
+                .map(|(key, data, _)| {
+                    (
+                        key.stable_id(),
+                        data.duration.duration_for(self.duration_policy),
+                    )
+                })
+                .collect::<Vec<_>>();
+            history.record_critical_path_durations(&path);
+        }
 
-        let compute_elapsed = now.elapsed();
+        let critical_path_stability = ctx.critical_path_history.as_ref().and_then(|history| {
+            let targets = self.requested_targets.iter().join(" ");
+            let nodes = critical_path
+                .iter()
+                .map(|(key, _, _)| key.stable_id())
+                .collect::<Vec<_>>();
+            history.record_critical_path(&targets, &nodes)
+        });
 
         let meta_entry_data = NodeData {
             action: None,
@@ -416,84 +1298,229 @@ where
                 total: compute_elapsed,
             },
             span_ids: Default::default(),
+            omitted_span_count: 0,
+            finish_offset: None,
+            transferred_bytes: 0,
+            output_bytes: 0,
+            cache_miss_reason: None,
+            re_queue_duration: None,
+            reused: false,
+            materialized_bytes: None,
         };
 
         let meta_entry = (
             buck2_data::critical_path_entry2::ComputeCriticalPath {}.into(),
             &meta_entry_data,
             &Some(compute_elapsed),
+            None,
+            None,
+            None,
+            None,
+            None,
+            &[][..],
         );
 
-        let critical_path2 = critical_path
+        let describe = |key: &NodeKey, data: &NodeData| {
+            let mut entry = describe_node(
+                &self.build_file_paths,
+                &self.grouped_materializations,
+                key,
+                data,
+            )?;
+            if ctx.options.load_chains {
+                if let (
+                    NodeKey::InterpreterResultsKey(key),
+                    buck2_data::critical_path_entry2::Entry::Load(load),
+                ) = (key, &mut entry)
+                {
+                    load.loaded_via = load_chain(&self.first_edge_to_load, &key.0)
+                        .into_iter()
+                        .map(|pkg| pkg.to_string())
+                        .collect();
+                }
+            }
+            Some(entry)
+        };
+
+        let mut critical_path2 = critical_path
             .iter()
-            .filter_map(|(key, data, potential_improvement)| {
-                let entry: buck2_data::critical_path_entry2::Entry = match key {
-                    NodeKey::BuildKey(key) => {
-                        let owner = key.0.owner().to_proto().into();
-
-                        // If we have a NodeKey that's an ActionKey we'd expect to have an `action`
-                        // in our data (unless we didn't actually run it because of e.g. early
-                        // cutoff, in which case omitting it is what we want).
-                        let action = data.action.as_ref()?;
-
-                        buck2_data::critical_path_entry2::ActionExecution {
-                            owner: Some(owner),
-                            name: Some(buck2_data::ActionName {
-                                category: action.category().as_str().to_owned(),
-                                identifier: action.identifier().unwrap_or("").to_owned(),
-                            }),
-                        }
-                        .into()
-                    }
-                    NodeKey::AnalysisKey(key) => buck2_data::critical_path_entry2::Analysis {
-                        target: Some(key.0.as_proto().into()),
+            .enumerate()
+            .filter_map(|(idx, (key, data, potential_improvement))| {
+                let entry = describe(key, data)?;
+                let top_level_targets = match key {
+                    // Backends can't tell which targets required a materialization, since
+                    // nothing depends on it, but we know who requested it.
+                    NodeKey::Materialization(artifact) => self
+                        .materialization_requesters
+                        .get(artifact)
+                        .map_or(&[][..], |t| &t[..]),
+                    _ => top_level_targets.get(idx).map_or(&[][..], |t| &t[..]),
+                };
+                let potential_improvement_bytes =
+                    potential_improvement_bytes.get(idx).copied().flatten();
+                let analysis_dependents = analysis_dependents.get(idx).copied().flatten();
+                let hops_from_top_level = hops_from_top_level.get(idx).copied().flatten();
+                let on_every_critical_path = on_every_critical_path.get(idx).copied();
+                let was_last_blocker = last_blockers.get(idx).copied().flatten();
+                Some((
+                    entry,
+                    data,
+                    potential_improvement,
+                    potential_improvement_bytes,
+                    analysis_dependents,
+                    hops_from_top_level,
+                    on_every_critical_path,
+                    was_last_blocker,
+                    top_level_targets,
+                ))
+            })
+            .chain(std::iter::once(meta_entry))
+            .map(
+                |(
+                    entry,
+                    data,
+                    potential_improvement,
+                    potential_improvement_bytes,
+                    analysis_dependents,
+                    hops_from_top_level,
+                    on_every_critical_path,
+                    was_last_blocker,
+                    top_level_targets,
+                )| {
+                    let mut entry = critical_path_entry2(
+                        entry,
+                        data,
+                        *potential_improvement,
+                        top_level_targets,
+                        self.duration_policy,
+                    )?;
+                    entry.potential_improvement_bytes = potential_improvement_bytes;
+                    entry.hops_from_top_level = hops_from_top_level;
+                    entry.on_every_critical_path = on_every_critical_path;
+                    entry.was_last_blocker = was_last_blocker;
+                    if let Some(dependents) = analysis_dependents {
+                        entry.dependent_top_level_targets = Some(dependents);
+                        entry.attributed_duration = attributed_duration(
+                            data.duration.duration_for(self.duration_policy),
+                            dependents,
+                        )
+                        .map(|d| d.try_into())
+                        .transpose()?;
                     }
-                    .into(),
-                    NodeKey::Materialization(key) => {
-                        let owner = key.key().owner().to_proto().into();
+                    anyhow::Ok(entry)
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
 
-                        buck2_data::critical_path_entry2::Materialization {
-                            owner: Some(owner),
-                            path: key.get_path().path().to_string(),
+        // Other critical paths we report don't have potentials, and aren't attributed to top
+        // level targets.
+        let other_critical_path2 = |path: &[(NodeKey, NodeData)]| {
+            path.iter()
+                .filter_map(|(key, data)| {
+                    let entry = describe(key, data)?;
+                    Some(critical_path_entry2(
+                        entry,
+                        data,
+                        None,
+                        &[],
+                        self.duration_policy,
+                    ))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let mut analysis_critical_path = other_critical_path2(&analysis_critical_path)?;
+        let mut execution_critical_path = other_critical_path2(&execution_critical_path)?;
+        let mut what_if_critical_path = other_critical_path2(&what_if_critical_path)?;
+        let mut focused_critical_path = other_critical_path2(&focused_critical_path)?;
+        let mut failure_critical_path = other_critical_path2(&failure_critical_path)?;
+        let mut always_critical = other_critical_path2(&always_critical)?;
+        let mut near_critical = near_critical
+            .iter()
+            .filter_map(|(key, data, slack)| {
+                let entry = describe(key, data)?;
+                Some(
+                    critical_path_entry2(entry, data, None, &[], self.duration_policy).and_then(
+                        |mut entry| {
+                            entry.slack_duration = slack.map(|s| s.try_into()).transpose()?;
+                            anyhow::Ok(entry)
+                        },
+                    ),
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut simulated_schedule = simulated_schedule
+            .map(|schedule| {
+                anyhow::Ok(buck2_data::CriticalPathSimulatedSchedule {
+                    core_count: schedule.core_count as u64,
+                    makespan: Some(schedule.makespan.try_into()?),
+                    bottleneck: other_critical_path2(&schedule.bottleneck)?,
+                })
+            })
+            .transpose()?;
+        let mut configuration_critical_path = configuration_critical_path
+            .iter()
+            .map(|(key, data)| {
+                let entry = match key {
+                    // These are uncategorized on other critical paths, but they are what this one
+                    // is about.
+                    NodeKey::ConfiguredTargetNodeKey(key) => {
+                        buck2_data::critical_path_entry2::Configuration {
+                            target: Some(key.0.as_proto()),
                         }
                         .into()
                     }
-                    NodeKey::InterpreterResultsKey(key) => buck2_data::critical_path_entry2::Load {
-                        package: key.0.to_string(),
-                    }
-                    .into(),
-                    NodeKey::PackageListingKey(key) => buck2_data::critical_path_entry2::Listing {
-                        package: key.0.to_string(),
-                    }
-                    .into(),
-                    NodeKey::EnsureProjectedArtifactKey(..) => return None,
-                    NodeKey::EnsureTransitiveSetProjectionKey(..) => return None,
-                    NodeKey::DeferredCompute(..) => return None,
-                    NodeKey::DeferredResolve(..) => return None,
-                    NodeKey::ConfiguredTargetNodeKey(..) => return None,
+                    _ => match describe(key, data) {
+                        Some(entry) => entry,
+                        None => return Ok(None),
+                    },
                 };
-
-                Some((entry, data, potential_improvement))
-            })
-            .chain(std::iter::once(meta_entry))
-            .map(|(entry, data, potential_improvement)| {
-                anyhow::Ok(buck2_data::CriticalPathEntry2 {
-                    span_ids: data
-                        .span_ids
-                        .iter()
-                        .map(|span_id| (*span_id).into())
-                        .collect(),
-                    duration: Some(data.duration.critical_path_duration().try_into()?),
-                    user_duration: Some(data.duration.user.try_into()?),
-                    total_duration: Some(data.duration.total.try_into()?),
-                    potential_improvement_duration: potential_improvement
-                        .map(|p| p.try_into())
-                        .transpose()?,
-                    entry: Some(entry),
-                })
+                critical_path_entry2(entry, data, None, &[], self.duration_policy).map(Some)
             })
+            .filter_map(Result::transpose)
             .collect::<Result<Vec<_>, _>>()?;
 
+        let build_id = get_dispatcher().trace_id().to_string();
+        let redactor = match ctx.options.redaction {
+            CriticalPathRedaction::None => None,
+            CriticalPathRedaction::Hash => Some(Redactor::new(build_id.clone())),
+        };
+        if ctx.options.span_link_template.is_some() || redactor.is_some() {
+            for entry in critical_path2
+                .iter_mut()
+                .chain(analysis_critical_path.iter_mut())
+                .chain(execution_critical_path.iter_mut())
+                .chain(what_if_critical_path.iter_mut())
+                .chain(focused_critical_path.iter_mut())
+                .chain(configuration_critical_path.iter_mut())
+                .chain(failure_critical_path.iter_mut())
+                .chain(always_critical.iter_mut())
+                .chain(near_critical.iter_mut())
+                .chain(
+                    simulated_schedule
+                        .iter_mut()
+                        .flat_map(|schedule| schedule.bottleneck.iter_mut()),
+                )
+            {
+                if let Some(template) = &ctx.options.span_link_template {
+                    entry.span_link = entry
+                        .span_ids
+                        .first()
+                        .map(|span_id| span_link(template, &build_id, *span_id));
+                }
+                if let Some(redactor) = &redactor {
+                    redactor.redact_entry(entry);
+                }
+            }
+        }
+        if let Some(redactor) = &redactor {
+            for anomaly in &mut duration_anomalies {
+                redactor.redact_anomaly(anomaly);
+            }
+            for cell_duration in &mut cell_durations {
+                redactor.redact_cell_duration(cell_duration);
+            }
+        }
+
         instant_event(buck2_data::BuildGraphExecutionInfo {
             critical_path: Vec::new(),
             critical_path2,
@@ -502,51 +1529,182 @@ where
             isolation_dir: Some(ctx.isolation_prefix.into_inner().into()),
             num_nodes,
             num_edges,
-            uses_total_duration: true,
+            uses_total_duration: self.duration_policy.uses_total_duration(),
             backend_name: Some(T::name().to_string()),
+            potentials_timed_out,
+            analysis_critical_path,
+            execution_critical_path,
+            what_if_critical_path,
+            configuration_critical_path,
+            failure_critical_path,
+            backend_memory_bytes,
+            duration_anomalies,
+            collapsed_actions,
+            critical_path_stability,
+            off_critical_path_node_count,
+            off_critical_path_total_duration: Some(off_critical_path_total_duration.try_into()?),
+            cacheable_critical_path_duration: Some(cacheable_duration.try_into()?),
+            non_cacheable_critical_path_duration: Some(non_cacheable_duration.try_into()?),
+            min_build_time_infinite_parallelism: Some(min_build_time.try_into()?),
+            gap_to_min_build_time: gap_to_min_build_time.map(|d| d.try_into()).transpose()?,
+            simulated_schedule,
+            total_work: Some(total_user_duration.try_into()?),
+            critical_path_user_duration: Some(critical_path_user_duration.try_into()?),
+            ideal_speedup: ideal_speedup(total_user_duration, critical_path_user_duration),
+            slack_histogram: slack_histogram
+                .into_iter()
+                .map(
+                    |(max_fraction, node_count)| buck2_data::CriticalPathSlackBucket {
+                        max_fraction,
+                        node_count,
+                    },
+                )
+                .collect(),
+            platform_durations,
+            total_re_queue_duration: Some(total_re_queue_duration.try_into()?),
+            incomplete: wall_clock.is_none(),
+            always_critical,
+            focused_critical_path,
+            near_critical,
+            critical_path_overhead: critical_path_overhead.map(|d| d.try_into()).transpose()?,
+            cell_durations,
         });
         Ok(())
     }
 
-    /// Receive an Evaluation. Do a little enrichment if it's a load, then pass through to the
-    /// underying backend.
-    fn process_evaluation(&mut self, mut evaluation: Evaluation) {
+    /// Receive an Evaluation. Do a little enrichment if it's a load, then queue it up for the
+    /// underying backend. If we are streaming evaluations, this returns what to stream.
+    fn process_evaluation(&mut self, mut evaluation: Evaluation) -> Option<StreamedEvaluation> {
         self.enrich_load(&mut evaluation);
-
-        self.backend.process_node(
-            evaluation.key,
-            evaluation.action,
-            evaluation.duration,
-            evaluation.dep_keys.into_iter(),
-            evaluation.spans,
+        // We log after enriching so that the edges we add show up.
+        self.log_signal(
+            evaluation.key.kind(),
+            &evaluation.key,
+            Some(evaluation.duration.duration_for(self.duration_policy)),
+            evaluation.dep_keys.len(),
         );
-    }
 
-    /// If the evaluation is a load (InterpreterResultsKey) and carries a load_result, then inject
-    /// some extra edges that indicate which packages have now become visibile as a result of this
-    /// load.
-    fn enrich_load(&mut self, evaluation: &mut Evaluation) {
-        let pkg = match &evaluation.key {
-            NodeKey::InterpreterResultsKey(InterpreterResultsKey(pkg)) => pkg,
-            _ => return,
+        let streamed = if self.stream.is_some() {
+            Some(StreamedEvaluation {
+                key: evaluation.key.stable_id(),
+                kind: evaluation.key.kind(),
+                duration_us: evaluation
+                    .duration
+                    .duration_for(self.duration_policy)
+                    .as_micros() as u64,
+                deps: evaluation.dep_keys.len(),
+            })
+        } else {
+            None
         };
 
-        if let Some(load_result) = &evaluation.load_result {
-            let deps_pkg = load_result
-                .targets()
-                .values()
-                .flat_map(|target| target.deps().map(|t| t.pkg()))
-                .unique()
-                .map(|pkg| pkg.dupe());
+        let (span_ids, omitted_span_count) =
+            cap_span_ids(evaluation.spans, self.max_span_ids_per_node);
+
+        self.push_node(PendingNode {
+            key: evaluation.key,
+            data: NodeData {
+                action: evaluation.action,
+                duration: evaluation.duration,
+                span_ids,
+                omitted_span_count,
+                finish_offset: Some(self.elapsed()),
+                transferred_bytes: evaluation.transferred_bytes,
+                output_bytes: evaluation.output_bytes,
+                cache_miss_reason: evaluation.cache_miss_reason,
+                re_queue_duration: evaluation.re_queue_duration,
+                reused: evaluation.reused,
+                materialized_bytes: None,
+            },
+            dep_keys: evaluation.dep_keys,
+            action_digest: evaluation.action_digest,
+        });
+
+        streamed
+    }
+
+    /// Queue up `node` for the backend, record it if we are recording signals, and follow it on
+    /// the partial critical path if we are tracking one.
+    fn push_node(&mut self, node: PendingNode) {
+        self.record(|| RecordedSignal::node(&node));
+        if let Some(partial) = &mut self.partial_critical_path {
+            partial.backend.process_node(
+                node.key.dupe(),
+                node.data.clone(),
+                node.dep_keys.iter().map(|dep| dep.dupe()),
+            );
+        }
+        self.coalescer.push(node);
+    }
+
+    /// Log where the critical path stands, if we are tracking it and it's been long enough since
+    /// we last did.
+    fn log_partial_critical_path(&mut self) -> anyhow::Result<()> {
+        let Some(partial) = &mut self.partial_critical_path else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        if now.saturating_duration_since(partial.last_logged) < partial.interval {
+            return Ok(());
+        }
+        let Some((tail, duration)) = partial.backend.tail() else {
+            return Ok(());
+        };
+
+        instant_event(buck2_data::PartialCriticalPath {
+            tail: tail.to_string(),
+            duration: Some(duration.try_into()?),
+            elapsed: Some(now.saturating_duration_since(self.start).try_into()?),
+            num_nodes: partial.backend.num_nodes(),
+        });
+        partial.last_logged = now;
 
-            for dep_pkg in deps_pkg {
-                if dep_pkg == *pkg {
-                    continue;
+        Ok(())
+    }
+
+    async fn send_to_stream(&mut self, streamed: StreamedEvaluation) {
+        if let Some(stream) = &self.stream {
+            if !stream.send(streamed).await {
+                // The writer gave up, so stop streaming, but carry on with the critical path.
+                if let Some(stream) = self.stream.take() {
+                    report_stream_error(stream.finish().await);
                 }
+            }
+        }
+    }
+
+    /// If the evaluation is a load (InterpreterResultsKey) and carries a load_result, then inject
+    /// some extra edges that indicate which packages have now become visibile as a result of this
+    /// load. We also record which build file was evaluated, for reporting.
+    fn enrich_load(&mut self, evaluation: &mut Evaluation) {
+        let pkg = match &evaluation.key {
+            NodeKey::InterpreterResultsKey(InterpreterResultsKey(pkg)) => pkg,
+            _ => return,
+        };
+
+        if let Some(load_result) = &evaluation.load_result {
+            self.build_file_paths
+                .insert(pkg.dupe(), load_result.buildfile_path().dupe());
+        }
+
+        if !self.enrich_load_graph {
+            return;
+        }
+
+        if let Some(load_result) = &evaluation.load_result {
+            let deps_pkg = load_result
+                .targets()
+                .values()
+                .flat_map(|target| target.deps().map(|t| t.pkg()))
+                .unique()
+                .map(|pkg| pkg.dupe());
 
-                self.first_edge_to_load
-                    .entry(dep_pkg)
-                    .or_insert_with(|| pkg.dupe());
+            if let Some(fan_out) = self.record_load_deps(pkg, deps_pkg) {
+                let _ignored = soft_error!(
+                    "critical_path_load_fan_out",
+                    anyhow::anyhow!("{}", fan_out),
+                    quiet: true
+                );
             }
         }
 
@@ -561,6 +1719,37 @@ where
         }
     }
 
+    /// Record that loading `pkg` is how we discovered each of `deps_pkg` (unless we already knew
+    /// about them). Returns the fan out if `pkg` depends on more packages than we expect.
+    fn record_load_deps(
+        &mut self,
+        pkg: &PackageLabel,
+        deps_pkg: impl IntoIterator<Item = PackageLabel>,
+    ) -> Option<LoadFanOut> {
+        let mut count = 0;
+
+        for dep_pkg in deps_pkg {
+            if dep_pkg == *pkg {
+                continue;
+            }
+
+            count += 1;
+            self.first_edge_to_load
+                .entry(dep_pkg)
+                .or_insert_with(|| pkg.dupe());
+        }
+
+        if count <= self.max_load_fan_out {
+            return None;
+        }
+
+        Some(LoadFanOut {
+            package: pkg.dupe(),
+            count,
+            max: self.max_load_fan_out,
+        })
+    }
+
     // TODO: We would need something similar with anon targets.
     fn process_top_level_target(
         &mut self,
@@ -583,30 +1772,503 @@ where
                     }
                 });
 
-        self.backend.process_top_level_target(
+        self.platforms
+            .top_level_configurations
+            .insert(top_level.label.cfg().dupe());
+        self.process_top_level_keys(
+            top_level.label.to_string(),
             NodeKey::AnalysisKey(AnalysisKey(top_level.label)),
-            artifact_keys,
+            artifact_keys.collect(),
         );
 
         Ok(())
     }
 
+    fn process_top_level_keys(
+        &mut self,
+        label: String,
+        analysis: NodeKey,
+        artifacts: Vec<NodeKey>,
+    ) {
+        self.record(|| RecordedSignal::top_level_target(label.clone(), &analysis, &artifacts));
+        self.requested_targets.insert(label);
+        self.backend
+            .process_top_level_target(analysis, artifacts.into_iter());
+    }
+
+    /// Record a signal, if we are recording them. If that fails, we report it and stop recording
+    /// rather than fail the build.
+    fn record(&mut self, signal: impl FnOnce() -> RecordedSignal) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.record(&signal()) {
+            self.recorder = None;
+            report_recording_error(Err(e));
+        }
+    }
+
     fn process_final_materialization(
         &mut self,
         materialization: FinalMaterializationSignal,
     ) -> Result<(), anyhow::Error> {
         let dep = NodeKey::BuildKey(BuildKey(materialization.artifact.key().dupe()));
 
-        self.backend.process_node(
-            NodeKey::Materialization(materialization.artifact),
-            None,
-            materialization.duration,
-            std::iter::once(dep),
-            materialization.span_id.into_iter().collect(),
-        );
+        if let Some(requester) = materialization.requester {
+            self.add_materialization_requester(materialization.artifact.dupe(), requester);
+        }
+
+        let finish_offset = self.elapsed();
+
+        if let Some(group_key) =
+            MaterializationGroupKey::new(self.materialization_grouping, &materialization.artifact)
+        {
+            match self.materialization_groups.entry(group_key) {
+                Entry::Occupied(mut e) => {
+                    let group = e.get_mut();
+                    let data = &mut group.node.data;
+                    data.duration.user = data
+                        .duration
+                        .user
+                        .saturating_add(materialization.duration.user);
+                    data.duration.total = data
+                        .duration
+                        .total
+                        .saturating_add(materialization.duration.total);
+                    data.span_ids.extend(materialization.span_id);
+                    data.finish_offset = Some(finish_offset);
+                    // We only know the size of the group if we know the size of all of it.
+                    data.materialized_bytes = data
+                        .materialized_bytes
+                        .zip(materialization.bytes)
+                        .map(|(a, b)| a.saturating_add(b));
+                    if !group.node.dep_keys.contains(&dep) {
+                        group.node.dep_keys.push(dep);
+                    }
+                    group.artifacts.push(materialization.artifact);
+                }
+                Entry::Vacant(e) => {
+                    e.insert(MaterializationGroup {
+                        node: PendingNode {
+                            key: NodeKey::Materialization(materialization.artifact.dupe()),
+                            data: NodeData {
+                                action: None,
+                                duration: materialization.duration,
+                                span_ids: materialization.span_id.into_iter().collect(),
+                                omitted_span_count: 0,
+                                finish_offset: Some(finish_offset),
+                                transferred_bytes: 0,
+                                output_bytes: 0,
+                                cache_miss_reason: None,
+                                re_queue_duration: None,
+                                reused: false,
+                                materialized_bytes: materialization.bytes,
+                            },
+                            dep_keys: vec![dep],
+                            action_digest: None,
+                        },
+                        artifacts: vec![materialization.artifact],
+                    });
+                }
+            }
+            return Ok(());
+        }
+
+        self.push_node(PendingNode {
+            key: NodeKey::Materialization(materialization.artifact),
+            data: NodeData {
+                action: None,
+                duration: materialization.duration,
+                span_ids: materialization.span_id.into_iter().collect(),
+                omitted_span_count: 0,
+                finish_offset: Some(finish_offset),
+                transferred_bytes: 0,
+                output_bytes: 0,
+                cache_miss_reason: None,
+                re_queue_duration: None,
+                reused: false,
+                materialized_bytes: materialization.bytes,
+            },
+            dep_keys: vec![dep],
+            action_digest: None,
+        });
 
         Ok(())
     }
+
+    /// Turn the materialization groups we built up into nodes. Each group is represented by its
+    /// first artifact, which also inherits the requesters of the rest of the group.
+    fn flush_materialization_groups(&mut self) {
+        for (group_key, group) in std::mem::take(&mut self.materialization_groups) {
+            let MaterializationGroup {
+                mut node,
+                artifacts,
+            } = group;
+            let mut artifacts = artifacts.into_iter();
+            let Some(representative) = artifacts.next() else {
+                continue;
+            };
+            let mut artifact_count = 1;
+            for artifact in artifacts {
+                artifact_count += 1;
+                if let Some(requesters) = self.materialization_requesters.remove(&artifact) {
+                    for requester in requesters {
+                        self.add_materialization_requester(representative.dupe(), requester);
+                    }
+                }
+            }
+
+            let (span_ids, omitted_span_count) = cap_span_ids(
+                std::mem::take(&mut node.data.span_ids),
+                self.max_span_ids_per_node,
+            );
+            node.data.span_ids = span_ids;
+            node.data.omitted_span_count = omitted_span_count;
+
+            self.grouped_materializations
+                .insert(representative, (group_key, artifact_count));
+            self.push_node(node);
+        }
+    }
+
+    fn add_materialization_requester(
+        &mut self,
+        artifact: BuildArtifact,
+        requester: ConfiguredTargetLabel,
+    ) {
+        let requesters = self.materialization_requesters.entry(artifact).or_default();
+        if !requesters.contains(&requester) {
+            requesters.push(requester);
+        }
+    }
+}
+
+/// What we group materializations by, when we group them.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum MaterializationGroupKey {
+    Directory(BaseDeferredKey, ForwardRelativePathBuf),
+    Action(ActionKey),
+}
+
+impl MaterializationGroupKey {
+    fn new(grouping: MaterializationGrouping, artifact: &BuildArtifact) -> Option<Self> {
+        match grouping {
+            MaterializationGrouping::PerArtifact => None,
+            MaterializationGrouping::ByDirectory => {
+                let path = artifact.get_path();
+                Some(Self::Directory(
+                    path.owner().dupe(),
+                    path.path()
+                        .parent()
+                        .map_or_else(ForwardRelativePathBuf::empty, |p| p.to_buf()),
+                ))
+            }
+            MaterializationGrouping::ByAction => Some(Self::Action(artifact.key().dupe())),
+        }
+    }
+}
+
+/// Materializations that we report as a single node.
+struct MaterializationGroup {
+    node: PendingNode,
+    artifacts: Vec<BuildArtifact>,
+}
+
+impl NodeKey {
+    /// Describe this node for the critical path we log. Every node gets an entry: those we have no
+    /// dedicated entry for are uncategorized, so that they don't silently go missing.
+    fn to_entry(
+        &self,
+        data: &NodeData,
+        build_file_paths: &HashMap<PackageLabel, Arc<BuildFilePath>>,
+    ) -> buck2_data::critical_path_entry2::Entry {
+        let uncategorized = |display: String| {
+            buck2_data::critical_path_entry2::Uncategorized {
+                kind: self.kind().to_owned(),
+                display,
+            }
+            .into()
+        };
+
+        match self {
+            Self::BuildKey(key) => buck2_data::critical_path_entry2::ActionExecution {
+                owner: Some(key.0.owner().to_proto().into()),
+                name: data.action_name(),
+                cache_miss_reason: data
+                    .cache_miss_reason
+                    .map(|reason| reason.to_proto() as i32),
+            }
+            .into(),
+            Self::AnalysisKey(key) => buck2_data::critical_path_entry2::Analysis {
+                target: Some(key.0.as_proto().into()),
+            }
+            .into(),
+            Self::Materialization(key) => buck2_data::critical_path_entry2::Materialization {
+                owner: Some(key.key().owner().to_proto().into()),
+                path: key.get_path().path().to_string(),
+                artifact_count: None,
+                bytes: data.materialized_bytes,
+            }
+            .into(),
+            Self::InterpreterResultsKey(key) => buck2_data::critical_path_entry2::Load {
+                package: key.0.to_string(),
+                // Synthetic packages don't have a build file.
+                build_file_path: build_file_paths
+                    .get(&key.0)
+                    .map(|path| path.path().to_string()),
+                loaded_via: Vec::new(),
+            }
+            .into(),
+            Self::PackageListingKey(key) => buck2_data::critical_path_entry2::Listing {
+                package: key.0.to_string(),
+            }
+            .into(),
+            Self::EnsureProjectedArtifactKey(key) => uncategorized(key.to_string()),
+            Self::EnsureTransitiveSetProjectionKey(key) => uncategorized(key.to_string()),
+            Self::DeferredCompute(key) => uncategorized(key.to_string()),
+            Self::DeferredResolve(key) => uncategorized(key.to_string()),
+            Self::ConfiguredTargetNodeKey(key) => uncategorized(key.to_string()),
+            Self::External { id, display } => buck2_data::critical_path_entry2::External {
+                id: id.to_string(),
+                display: display.to_string(),
+            }
+            .into(),
+        }
+    }
+}
+
+/// Describe a node for the critical path we log. This returns `None` only for actions we didn't
+/// run, which we deliberately omit.
+fn critical_path_entry(
+    build_file_paths: &HashMap<PackageLabel, Arc<BuildFilePath>>,
+    key: &NodeKey,
+    data: &NodeData,
+) -> Option<buck2_data::critical_path_entry2::Entry> {
+    // Actions we didn't run (e.g. because of early cutoff) didn't take any time, so we omit them.
+    if matches!(key, NodeKey::BuildKey(..)) && data.action.is_none() {
+        return None;
+    }
+
+    Some(key.to_entry(data, build_file_paths))
+}
+
+/// Like `critical_path_entry`, but describes the representative of a group of materializations as
+/// the group it stands for.
+fn describe_node(
+    build_file_paths: &HashMap<PackageLabel, Arc<BuildFilePath>>,
+    grouped_materializations: &HashMap<BuildArtifact, (MaterializationGroupKey, u64)>,
+    key: &NodeKey,
+    data: &NodeData,
+) -> Option<buck2_data::critical_path_entry2::Entry> {
+    let mut entry = critical_path_entry(build_file_paths, key, data)?;
+    if let (
+        NodeKey::Materialization(artifact),
+        buck2_data::critical_path_entry2::Entry::Materialization(materialization),
+    ) = (key, &mut entry)
+    {
+        if let Some((group_key, artifact_count)) = grouped_materializations.get(artifact) {
+            materialization.artifact_count = Some(*artifact_count);
+            if let MaterializationGroupKey::Directory(_, directory) = group_key {
+                materialization.path = directory.to_string();
+            }
+        }
+    }
+    Some(entry)
+}
+
+/// Walk `first_edge_to_load` from `pkg`: the package whose targets first depended on it, then
+/// the one whose targets first depended on that one, and so on.
+fn load_chain<'a>(
+    first_edge_to_load: &'a HashMap<PackageLabel, PackageLabel>,
+    pkg: &PackageLabel,
+) -> Vec<&'a PackageLabel> {
+    let mut chain = Vec::new();
+    let mut seen = HashSet::new();
+    let mut current = pkg;
+    while let Some(next) = first_edge_to_load.get(current) {
+        // Packages can depend on each other, which makes for a cycle here.
+        if next == pkg || !seen.insert(next) {
+            break;
+        }
+        chain.push(next);
+        current = next;
+    }
+    chain
+}
+
+fn critical_path_entry2(
+    entry: buck2_data::critical_path_entry2::Entry,
+    data: &NodeData,
+    potential_improvement: Option<Duration>,
+    top_level_targets: &[ConfiguredTargetLabel],
+    duration_policy: DurationPolicy,
+) -> anyhow::Result<buck2_data::CriticalPathEntry2> {
+    Ok(buck2_data::CriticalPathEntry2 {
+        span_ids: data
+            .span_ids
+            .iter()
+            .map(|span_id| (*span_id).into())
+            .collect(),
+        duration: Some(data.duration.duration_for(duration_policy).try_into()?),
+        user_duration: Some(data.duration.user.try_into()?),
+        total_duration: Some(data.duration.total.try_into()?),
+        potential_improvement_duration: potential_improvement.map(|p| p.try_into()).transpose()?,
+        top_level_targets: top_level_targets
+            .iter()
+            .map(|label| label.as_proto())
+            .collect(),
+        omitted_span_count: data.omitted_span_count.into(),
+        transferred_bytes: data.transferred_bytes,
+        output_bytes: data.output_bytes,
+        potential_improvement_bytes: None,
+        span_link: None,
+        dependent_top_level_targets: None,
+        attributed_duration: None,
+        efficiency: efficiency(&data.duration),
+        hops_from_top_level: None,
+        on_every_critical_path: None,
+        slack_duration: None,
+        was_last_blocker: None,
+        re_queue_duration: data.re_queue_duration.map(|d| d.try_into()).transpose()?,
+        freshly_computed: !data.reused,
+        entry: Some(entry),
+    })
+}
+
+/// How much of an analysis that took `duration` to attribute to each of the `dependents` top level
+/// targets that depend on it, if any do.
+fn attributed_duration(duration: Duration, dependents: u64) -> Option<Duration> {
+    match dependents {
+        0 => None,
+        n => Some(duration / u32::try_from(n).unwrap_or(u32::MAX)),
+    }
+}
+
+/// How much of a node's total duration was spent doing useful work, if it took any time at all.
+fn efficiency(duration: &NodeDuration) -> Option<f32> {
+    if duration.total.is_zero() {
+        return None;
+    }
+    let ratio = duration.user.as_secs_f64() / duration.total.as_secs_f64();
+    Some(ratio.clamp(0.0, 1.0) as f32)
+}
+
+/// How much faster than serially we could have done `total_work` with unlimited parallelism, given
+/// that `critical_path` of it had to happen serially.
+fn ideal_speedup(total_work: Duration, critical_path: Duration) -> Option<f32> {
+    if critical_path.is_zero() {
+        return None;
+    }
+    Some((total_work.as_secs_f64() / critical_path.as_secs_f64()) as f32)
+}
+
+/// Fill in the placeholders of a span link template.
+fn span_link(template: &str, build_id: &str, span_id: u64) -> String {
+    template
+        .replace("{build_id}", build_id)
+        .replace("{span_id}", &span_id.to_string())
+}
+
+fn report_stream_error(res: anyhow::Result<()>) {
+    if let Err(e) = res {
+        let _ignored = soft_error!("critical_path_evaluation_stream", e, quiet: true);
+    }
+}
+
+fn report_recording_error(res: anyhow::Result<()>) {
+    if let Err(e) = res {
+        let _ignored = soft_error!("critical_path_signal_recording", e, quiet: true);
+    }
+}
+
+/// A package whose targets depend on an unusually large number of other packages.
+#[derive(Debug, derive_more::Display)]
+#[display(
+    fmt = "Targets in package `{}` depend on {} distinct packages (more than {}), which makes loading it expensive to track",
+    package,
+    count,
+    max
+)]
+struct LoadFanOut {
+    package: PackageLabel,
+    count: usize,
+    max: usize,
+}
+
+/// A critical path that is a single node, in a build large enough that this is suspicious.
+#[derive(Debug, derive_more::Display)]
+#[display(
+    fmt = "The critical path is a single node in a build of {} nodes and {} edges, which usually means we missed the dependencies between nodes",
+    num_nodes,
+    num_edges
+)]
+struct DegenerateCriticalPath {
+    num_nodes: u64,
+    num_edges: u64,
+}
+
+fn degenerate_critical_path(
+    critical_path_len: usize,
+    num_nodes: u64,
+    num_edges: u64,
+    min_nodes: u64,
+) -> Option<DegenerateCriticalPath> {
+    if critical_path_len != 1 || num_nodes < min_nodes {
+        return None;
+    }
+    Some(DegenerateCriticalPath {
+        num_nodes,
+        num_edges,
+    })
+}
+
+/// How many nodes we list in `critical_path_summary`, to avoid flooding the terminal.
+const CRITICAL_PATH_SUMMARY_NODES: usize = 5;
+
+/// Summarize the critical path for humans: the nodes with the most potential for improvement
+/// (or the longest ones, if the backend didn't compute potentials), most first, one per line.
+fn critical_path_summary(
+    critical_path: &[(NodeKey, NodeData, Option<Duration>)],
+    duration_policy: DurationPolicy,
+) -> String {
+    let mut nodes = critical_path
+        .iter()
+        .map(|(key, data, potential)| {
+            let duration = data.duration.duration_for(duration_policy);
+            (key, duration, potential.unwrap_or(duration))
+        })
+        .collect::<Vec<_>>();
+    let total = nodes
+        .iter()
+        .fold(Duration::ZERO, |total, (_, duration, _)| {
+            total.saturating_add(*duration)
+        });
+    // Stable, so that ties stay in critical path order.
+    nodes.sort_by(|(_, _, a), (_, _, b)| b.cmp(a));
+
+    let mut summary = format!(
+        "Critical path: {:.1?} over {} nodes, most worth improving first:",
+        total,
+        nodes.len()
+    );
+    for (key, duration, _) in nodes.iter().take(CRITICAL_PATH_SUMMARY_NODES) {
+        summary.push_str(&format!("\n  {:.1?}  {}", duration, key));
+    }
+    summary
+}
+
+/// Keep at most `max` span ids, returning the ones we kept and how many we dropped.
+fn cap_span_ids(mut spans: SmallVec<[SpanId; 1]>, max: usize) -> (SmallVec<[SpanId; 1]>, u32) {
+    if spans.len() <= max {
+        return (spans, 0);
+    }
+
+    let omitted = u32::try_from(spans.len() - max).unwrap_or(u32::MAX);
+    spans.truncate(max);
+    // Don't hold on to the (potentially large) allocation, and go back to inline storage if we
+    // can.
+    spans.shrink_to_fit();
+    (spans, omitted)
 }
 
 pub struct BuildInfo {
@@ -614,21 +2276,458 @@ pub struct BuildInfo {
     critical_path: Vec<(NodeKey, NodeData, Option<Duration>)>,
     num_nodes: u64,
     num_edges: u64,
+    // Whether the backend gave up on computing potentials because it ran out of time.
+    potentials_timed_out: bool,
+    // The whole graph, if the backend was asked to export it.
+    exported_graph: Option<ExportedGraph>,
+    // For each node in the critical path, the top level targets that required it. This is empty
+    // if the backend doesn't track this.
+    top_level_targets: Vec<Vec<ConfiguredTargetLabel>>,
+    // The critical paths through only analysis nodes and through only execution nodes, if the
+    // backend was asked to compute them. These don't have potentials.
+    analysis_critical_path: Vec<(NodeKey, NodeData)>,
+    execution_critical_path: Vec<(NodeKey, NodeData)>,
+    // The critical path through only loading packages and configuring targets, if the backend
+    // was asked to compute it. This doesn't have potentials either.
+    configuration_critical_path: Vec<(NodeKey, NodeData)>,
+    // The graph we computed the critical path over, if the backend was asked to retain it.
+    retained_graph: Option<RetainedGraph>,
+    // For each node in the critical path, its potential for improvement in bytes. This is only
+    // present if the backend weighed nodes by how much data they transferred, in which case
+    // `critical_path` has no potentials.
+    potential_improvement_bytes: Vec<Option<u64>>,
+    // For each node in the critical path, how many top level targets depend on it if it is an
+    // analysis. This is empty if the backend wasn't asked to count them.
+    analysis_dependents: Vec<Option<u64>>,
+    // For each node in the critical path, the fewest dependency hops from a top level target's
+    // analysis to it, if any reaches it. This is empty if the backend wasn't asked to compute it.
+    hops_from_top_level: Vec<Option<u32>>,
+    // If an action failed, the critical path through what it (transitively) depended on, which
+    // ends at the failure. This is empty if the backend doesn't support it.
+    failure_critical_path: Vec<(NodeKey, NodeData)>,
+    // How the build would have gone on a fixed number of cores, if the backend was asked to
+    // simulate it.
+    simulated_schedule: Option<SimulatedSchedule>,
+    // How many nodes have how much slack, as (upper bound on slack as a fraction of the critical
+    // path's cost, node count) pairs in increasing order. This is empty if the backend wasn't
+    // asked to compute it.
+    slack_histogram: Vec<(f32, u64)>,
+    // For each node in the critical path, whether it is on every longest path through the graph.
+    // This is empty if the backend wasn't asked to compute it.
+    on_every_critical_path: Vec<bool>,
+    // All the nodes that are on every longest path through the graph, dependencies first. This is
+    // empty if the backend wasn't asked to compute it.
+    always_critical: Vec<(NodeKey, NodeData)>,
+    // Nodes with the least slack that aren't critical, by increasing slack, along with their
+    // slack (if the backend weighed nodes by duration). This is empty if the backend wasn't asked
+    // for them.
+    near_critical: Vec<(NodeKey, NodeData, Option<Duration>)>,
+    // How much the cost floors the backend added to nodes make up of the critical path, if it
+    // was given any.
+    critical_path_overhead: Option<Duration>,
+    // For each node in the critical path, whether it finished last among the dependencies of the
+    // next one, if we know when they finished. This is empty if the backend can't tell.
+    last_blockers: Vec<Option<bool>>,
+}
+
+/// The result of list-scheduling the build graph on `core_count` cores.
+struct SimulatedSchedule {
+    core_count: usize,
+    // When the last node would have finished.
+    makespan: Duration,
+    // The chain of nodes that determined the makespan: each one started as soon as the previous
+    // one finished, either because it depended on it or because it was waiting for its core.
+    bottleneck: Vec<(NodeKey, NodeData)>,
+}
+
+/// How to compare a target to critical path entries in `BuildInfo::find_target`.
+#[derive(Copy, Clone, Dupe, Debug, PartialEq, Eq)]
+pub enum TargetMatch {
+    /// The target and its configuration must match.
+    Exact,
+    /// Only the unconfigured target must match, so any configuration of it will do.
+    Unconfigured,
+}
+
+impl BuildInfo {
+    /// Find the first action or analysis on the critical path that was owned by `target`. This
+    /// returns its position in the critical path and its duration according to `duration_policy`
+    /// (which should be the one the critical path was computed with).
+    pub fn find_target(
+        &self,
+        target: &ConfiguredTargetLabel,
+        matching: TargetMatch,
+        duration_policy: DurationPolicy,
+    ) -> Option<(usize, Duration)> {
+        self.critical_path
+            .iter()
+            .position(|(key, _, _)| match key.owner_target() {
+                Some(owner) => match matching {
+                    TargetMatch::Exact => owner == target,
+                    TargetMatch::Unconfigured => owner.unconfigured() == target.unconfigured(),
+                },
+                None => false,
+            })
+            .map(|idx| {
+                let (_, data, _) = &self.critical_path[idx];
+                (idx, data.duration.duration_for(duration_policy))
+            })
+    }
+
+    /// The critical path, in chronological order.
+    pub fn critical_path_entries(&self) -> impl Iterator<Item = CriticalPathEntry<'_>> + '_ {
+        self.critical_path
+            .iter()
+            .map(|(key, data, potential_improvement)| CriticalPathEntry {
+                key,
+                data,
+                potential_improvement: *potential_improvement,
+            })
+    }
+
+    /// Find a node in the graph by the way we display its key. This requires the backend to
+    /// have retained its graph.
+    fn find_key(&self, key: &str) -> Option<&NodeKey> {
+        self.retained_graph.as_ref()?.find_key(key)
+    }
+
+    /// Recompute the critical path as if `key` had taken no time, to see what would become the
+    /// bottleneck if we fixed it. Returns `None` if the backend didn't retain its graph (only the
+    /// longest-path-graph backend can) or if `key` isn't in it.
+    fn critical_path_without(
+        &self,
+        key: &NodeKey,
+    ) -> anyhow::Result<Option<Vec<(NodeKey, NodeData)>>> {
+        match &self.retained_graph {
+            Some(graph) => graph.critical_path_without(key),
+            None => Ok(None),
+        }
+    }
+
+    /// Recompute the critical path as if only `targets` had been requested. Returns `None` if the
+    /// backend didn't retain its graph (only the longest-path-graph backend can).
+    fn critical_path_for_targets(
+        &self,
+        targets: &[String],
+    ) -> anyhow::Result<Option<Vec<(NodeKey, NodeData)>>> {
+        match &self.retained_graph {
+            Some(graph) => graph.critical_path_for_targets(targets).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// A node on the critical path of a `BuildInfo`. This is how code in this process reads the
+/// critical path, without depending on how we represent nodes internally.
+#[derive(Clone, Copy)]
+pub struct CriticalPathEntry<'a> {
+    key: &'a NodeKey,
+    data: &'a NodeData,
+    potential_improvement: Option<Duration>,
 }
 
-#[derive(Clone)]
+impl<'a> CriticalPathEntry<'a> {
+    /// The kind of node this is, e.g. `BuildKey` for actions.
+    pub fn kind(&self) -> &'static str {
+        self.key.kind()
+    }
+
+    /// Identifies this node across builds, e.g. to compare critical paths with
+    /// `compare_critical_paths`.
+    pub fn stable_id(&self) -> String {
+        self.key.stable_id()
+    }
+
+    /// What this node was evaluated on behalf of, if anything. This is set for actions, analyses
+    /// and materializations.
+    pub fn owner(&self) -> Option<BaseDeferredKey> {
+        self.key.owner()
+    }
+
+    /// The name of the action this node ran, if it ran one.
+    pub fn action_name(&self) -> Option<buck2_data::ActionName> {
+        self.data.action_name()
+    }
+
+    pub fn user_duration(&self) -> Duration {
+        self.data.duration.user
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.data.duration.total
+    }
+
+    /// How much faster the build would have been if this node had taken no time, if the backend
+    /// computed it.
+    pub fn potential_improvement(&self) -> Option<Duration> {
+        self.potential_improvement
+    }
+
+    /// The spans this node was evaluated in, which may not be all of them if there were too many.
+    pub fn span_ids(&self) -> &'a [SpanId] {
+        &self.data.span_ids
+    }
+
+    /// Why this node's action wasn't served from a cache, if it ran a command that wasn't. This
+    /// is what `compare_critical_paths_by_cause` wants to know.
+    pub fn cache_miss_reason(&self) -> Option<CacheMissReason> {
+        self.data.cache_miss_reason
+    }
+}
+
+#[derive(Clone, Allocative)]
 struct NodeData {
     action: Option<Arc<RegisteredAction>>,
     duration: NodeDuration,
     span_ids: SmallVec<[SpanId; 1]>,
+    /// How many span ids we dropped because this node had too many.
+    omitted_span_count: u32,
+    /// When we received this node, relative to when we started receiving signals. Since we
+    /// receive nodes once they finish evaluating, this approximates when they finished.
+    finish_offset: Option<Duration>,
+    /// How many bytes we moved to and from RE for this node.
+    transferred_bytes: u64,
+    /// How many bytes of outputs this node's action produced.
+    output_bytes: u64,
+    /// Why this node's action wasn't served from a cache, if it ran one that wasn't.
+    cache_miss_reason: Option<CacheMissReason>,
+    /// How long this node's action spent queued in RE, if it ran remotely.
+    re_queue_duration: Option<Duration>,
+    /// Whether DICE reused this node's value from a previous build (typically an earlier command
+    /// in the same daemon) rather than computing it in this one, in which case it cost (close to)
+    /// nothing.
+    reused: bool,
+    /// How many bytes we materialized for this node, if it's a materialization whose size we know.
+    materialized_bytes: Option<u64>,
+}
+
+assert_eq_size!(NodeData, [usize; 17]);
+
+impl NodeData {
+    /// The name of the action this node ran, if it ran one. We only know what the action was if
+    /// we ran it.
+    fn action_name(&self) -> Option<buck2_data::ActionName> {
+        self.action.as_ref().map(|action| buck2_data::ActionName {
+            category: action.category().as_str().to_owned(),
+            identifier: action.identifier().unwrap_or("").to_owned(),
+        })
+    }
+
+    /// When this node started, relative to when we started receiving signals.
+    fn start_offset(&self) -> Option<Duration> {
+        self.finish_offset
+            .map(|finish| finish.saturating_sub(self.duration.total))
+    }
+
+    /// A node that took `duration` (both user and total), and that we know nothing else about.
+    #[cfg(any(test, feature = "testing"))]
+    fn testing_new(duration: Duration) -> Self {
+        Self {
+            action: None,
+            duration: NodeDuration {
+                user: duration,
+                total: duration,
+            },
+            span_ids: Default::default(),
+            omitted_span_count: 0,
+            finish_offset: None,
+            transferred_bytes: 0,
+            output_bytes: 0,
+            cache_miss_reason: None,
+            re_queue_duration: None,
+            reused: false,
+            materialized_bytes: None,
+        }
+    }
+}
+
+/// Whether the work a node represents could have been served from a cache. This tells users
+/// whether they should improve their cache hit rate or speed up the work itself.
+#[derive(Copy, Clone, Dupe, Debug, PartialEq, Eq)]
+enum Cacheability {
+    Cacheable,
+    NonCacheable,
+}
+
+impl NodeKey {
+    /// Whether this node's work is cacheable. Only actions and materializations are classified.
+    fn cacheability(&self, data: &NodeData) -> Option<Cacheability> {
+        match self {
+            Self::BuildKey(..) => {
+                let action = data.action.as_ref()?;
+                // Only commands can be served from the action cache, and only if they can use
+                // remote execution. Other actions are simple enough that we always run them.
+                let cacheable = action.kind() == buck2_data::ActionKind::Run
+                    && matches!(
+                        action.execution_config().executor,
+                        Executor::RemoteEnabled { .. }
+                    );
+                Some(if cacheable {
+                    Cacheability::Cacheable
+                } else {
+                    Cacheability::NonCacheable
+                })
+            }
+            Self::Materialization(..) => Some(Cacheability::NonCacheable),
+            _ => None,
+        }
+    }
+}
+
+/// Sum the durations of cacheable and non-cacheable nodes on the critical path, in that order.
+fn cacheability_durations<'a>(
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> (Duration, Duration) {
+    let mut cacheable = Duration::ZERO;
+    let mut non_cacheable = Duration::ZERO;
+
+    for (key, data) in critical_path {
+        let duration = data.duration.duration_for(duration_policy);
+        match key.cacheability(data) {
+            Some(Cacheability::Cacheable) => cacheable = cacheable.saturating_add(duration),
+            Some(Cacheability::NonCacheable) => {
+                non_cacheable = non_cacheable.saturating_add(duration)
+            }
+            None => {}
+        }
+    }
+
+    (cacheable, non_cacheable)
+}
+
+/// Tells which coarse platform a configuration is for, from what we saw of the build.
+#[derive(Default)]
+struct PlatformClassifier {
+    // The labels of the platforms that are for the host, as configured.
+    host_platforms: HashSet<String>,
+    // The configurations top level targets were requested in.
+    top_level_configurations: HashSet<ConfigurationData>,
+    // The configurations toolchains were resolved to execute in.
+    exec_configurations: HashSet<ConfigurationData>,
+}
+
+impl PlatformClassifier {
+    fn new(host_platforms: &[String]) -> Self {
+        Self {
+            host_platforms: host_platforms.iter().cloned().collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Learn about the configurations involved in `key`.
+    fn observe(&mut self, key: &NodeKey) {
+        if let Some(exec_cfg) = key.configured_label().and_then(|label| label.exec_cfg()) {
+            if !self.exec_configurations.contains(exec_cfg) {
+                self.exec_configurations.insert(exec_cfg.dupe());
+            }
+        }
+    }
+
+    fn classify(&self, label: Option<&ConfiguredTargetLabel>) -> &'static str {
+        let Some(label) = label else {
+            return "unknown";
+        };
+        let cfg = label.cfg();
+
+        if cfg
+            .label()
+            .map_or(false, |l| self.host_platforms.contains(l))
+        {
+            "host"
+        } else if label.exec_cfg().is_some() || self.exec_configurations.contains(cfg) {
+            "exec"
+        } else if self.top_level_configurations.contains(cfg) {
+            "target"
+        } else {
+            "unknown"
+        }
+    }
+}
+
+/// Sum the durations of the actions on the critical path by the coarse platform they ran for, in
+/// the order each platform first appears.
+fn platform_durations<'a>(
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    platforms: &PlatformClassifier,
+    duration_policy: DurationPolicy,
+) -> Vec<(&'static str, Duration)> {
+    let mut res: Vec<(&'static str, Duration)> = Vec::new();
+
+    for (key, data) in critical_path {
+        let NodeKey::BuildKey(..) = key else {
+            continue;
+        };
+        let platform = platforms.classify(key.configured_label());
+        let duration = data.duration.duration_for(duration_policy);
+        match res.iter_mut().find(|(p, _)| *p == platform) {
+            Some((_, total)) => *total = total.saturating_add(duration),
+            None => res.push((platform, duration)),
+        }
+    }
+
+    res
+}
+
+/// Sum the durations of the nodes on the critical path by the cell they belong to, in the order
+/// each cell first appears. Nodes whose cell we can't tell count towards "unknown".
+fn cell_durations<'a>(
+    critical_path: impl IntoIterator<Item = (&'a NodeKey, &'a NodeData)>,
+    duration_policy: DurationPolicy,
+) -> Vec<(String, Duration)> {
+    let mut res: Vec<(String, Duration)> = Vec::new();
+
+    for (key, data) in critical_path {
+        let cell = key.cell().map_or("unknown", |cell| cell.as_str());
+        let duration = data.duration.duration_for(duration_policy);
+        match res.iter_mut().find(|(c, _)| c == cell) {
+            Some((_, total)) => *total = total.saturating_add(duration),
+            None => res.push((cell.to_owned(), duration)),
+        }
+    }
+
+    res
+}
+
+/// A projection of `NodeData` that can be serialized, to store critical path data outside of this
+/// process. The live `RegisteredAction` is intentionally not preserved: we only keep the parts of
+/// it we report. When adding fields to `NodeData`, consider adding them here too.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SerializableNodeData {
+    /// The owner of the action, if any.
+    pub owner: Option<String>,
+    pub category: Option<String>,
+    pub identifier: Option<String>,
+    pub kind: Option<buck2_data::ActionKind>,
+    pub user_duration_us: u64,
+    pub total_duration_us: u64,
+    pub span_ids: Vec<u64>,
+    /// Why the action wasn't served from a cache, if it ran a command that wasn't.
+    #[serde(default)]
+    pub cache_miss_reason: Option<buck2_data::CacheMissReason>,
 }
 
-assert_eq_size!(NodeData, [usize; 8]);
+impl From<&NodeData> for SerializableNodeData {
+    fn from(data: &NodeData) -> Self {
+        let action = data.action.as_deref();
+
+        Self {
+            owner: action.map(|a| a.owner().to_string()),
+            category: action.map(|a| a.category().as_str().to_owned()),
+            identifier: action.and_then(|a| a.identifier()).map(|i| i.to_owned()),
+            kind: action.map(|a| a.kind()),
+            user_duration_us: data.duration.user.as_micros() as u64,
+            total_duration_us: data.duration.total.as_micros() as u64,
+            span_ids: data.span_ids.iter().map(|s| (*s).into()).collect(),
+            cache_miss_reason: data.cache_miss_reason.map(|reason| reason.to_proto()),
+        }
+    }
+}
 
 fn create_build_signals() -> (BuildSignalsInstaller, Box<dyn DeferredBuildSignals>) {
     let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
 
-    let sender = Arc::new(BuildSignalSender { sender });
+    let sender = Arc::new(BuildSignalSender::new(sender));
     let installer = BuildSignalsInstaller {
         build_signals: sender.dupe() as _,
         activation_tracker: sender.dupe() as _,
@@ -640,5 +2739,796 @@ fn create_build_signals() -> (BuildSignalsInstaller, Box<dyn DeferredBuildSignal
 }
 
 pub fn init_late_bindings() {
-    CREATE_BUILD_SIGNALS.init(create_build_signals)
+    CREATE_BUILD_SIGNALS.init(create_build_signals);
+    CREATE_CRITICAL_PATH_HISTORY.init(|| Arc::new(NodeDurationHistory::default()));
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_artifact::artifact::artifact_type::testing::BuildArtifactTestingExt;
+    use buck2_artifact::deferred::id::DeferredId;
+    use buck2_artifact::deferred::key::DeferredKey;
+    use buck2_core::configuration::data::ConfigurationDataData;
+    use smallvec::smallvec;
+
+    use super::*;
+    use crate::clock::FakeClock;
+
+    #[test]
+    fn test_build_finished_once() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sender = Arc::new(BuildSignalSender::new(sender));
+
+        sender.build_finished();
+        sender.dupe().build_finished();
+        drop(sender);
+
+        let mut finished = 0;
+        while let Ok(signal) = receiver.try_recv() {
+            if let BuildSignal::BuildFinished = signal {
+                finished += 1;
+            }
+        }
+        assert_eq!(finished, 1);
+    }
+
+    fn analysis(label: &ConfiguredTargetLabel, secs: u64) -> (NodeKey, NodeData, Option<Duration>) {
+        let data = NodeData::testing_new(Duration::from_secs(secs));
+        (NodeKey::AnalysisKey(AnalysisKey(label.dupe())), data, None)
+    }
+
+    #[test]
+    fn test_find_target() {
+        let foo =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let bar =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:bar", ConfigurationData::testing_new());
+        let baz =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:baz", ConfigurationData::testing_new());
+        let unbound_bar =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:bar", ConfigurationData::unbound());
+
+        let mut info = BuildInfo {
+            critical_path: vec![analysis(&foo, 1), analysis(&bar, 2)],
+            num_nodes: 2,
+            num_edges: 1,
+            potentials_timed_out: false,
+            exported_graph: None,
+            top_level_targets: Vec::new(),
+            analysis_critical_path: Vec::new(),
+            execution_critical_path: Vec::new(),
+            configuration_critical_path: Vec::new(),
+            retained_graph: None,
+            potential_improvement_bytes: Vec::new(),
+            analysis_dependents: Vec::new(),
+            hops_from_top_level: Vec::new(),
+            failure_critical_path: Vec::new(),
+            simulated_schedule: None,
+            slack_histogram: Vec::new(),
+            on_every_critical_path: Vec::new(),
+            always_critical: Vec::new(),
+            near_critical: Vec::new(),
+            critical_path_overhead: None,
+            last_blockers: Vec::new(),
+        };
+
+        let policy = DurationPolicy::default();
+        assert_eq!(
+            info.find_target(&bar, TargetMatch::Exact, policy),
+            Some((1, Duration::from_secs(2)))
+        );
+        assert_eq!(info.find_target(&baz, TargetMatch::Exact, policy), None);
+        assert_eq!(
+            info.find_target(&unbound_bar, TargetMatch::Exact, policy),
+            None
+        );
+        assert_eq!(
+            info.find_target(&unbound_bar, TargetMatch::Unconfigured, policy),
+            Some((1, Duration::from_secs(2)))
+        );
+
+        info.critical_path[1].1.duration.user = Duration::from_secs(1);
+        assert_eq!(
+            info.find_target(&bar, TargetMatch::Exact, DurationPolicy::User),
+            Some((1, Duration::from_secs(1)))
+        );
+    }
+
+    #[test]
+    fn test_critical_path_entries() {
+        let foo =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let (key, data, _) = analysis(&foo, 2);
+
+        let info = BuildInfo {
+            critical_path: vec![(key, data, Some(Duration::from_secs(1)))],
+            num_nodes: 1,
+            num_edges: 0,
+            potentials_timed_out: false,
+            exported_graph: None,
+            top_level_targets: Vec::new(),
+            analysis_critical_path: Vec::new(),
+            execution_critical_path: Vec::new(),
+            configuration_critical_path: Vec::new(),
+            retained_graph: None,
+            potential_improvement_bytes: Vec::new(),
+            analysis_dependents: Vec::new(),
+            hops_from_top_level: Vec::new(),
+            failure_critical_path: Vec::new(),
+            simulated_schedule: None,
+            slack_histogram: Vec::new(),
+            on_every_critical_path: Vec::new(),
+            always_critical: Vec::new(),
+            near_critical: Vec::new(),
+            critical_path_overhead: None,
+            last_blockers: Vec::new(),
+        };
+
+        let entries = info.critical_path_entries().collect::<Vec<_>>();
+        assert_eq!(entries.len(), 1);
+        let entry = entries[0];
+        assert_eq!(entry.kind(), "AnalysisKey");
+        assert_eq!(entry.owner(), Some(BaseDeferredKey::TargetLabel(foo)));
+        assert_eq!(entry.action_name(), None);
+        assert_eq!(entry.user_duration(), Duration::from_secs(2));
+        assert_eq!(entry.total_duration(), Duration::from_secs(2));
+        assert_eq!(entry.potential_improvement(), Some(Duration::from_secs(1)));
+        assert!(entry.span_ids().is_empty());
+    }
+
+    #[test]
+    fn test_cache_miss_reason() {
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let key = NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+            BaseDeferredKey::TargetLabel(target),
+            DeferredId::testing_new(0),
+        ))));
+        let data = |cache_miss_reason| NodeData {
+            cache_miss_reason,
+            ..NodeData::testing_new(Duration::ZERO)
+        };
+        let reason = |data: &NodeData| match key.to_entry(data, &HashMap::new()) {
+            buck2_data::critical_path_entry2::Entry::ActionExecution(action) => {
+                action.cache_miss_reason
+            }
+            _ => panic!("Expected an action execution"),
+        };
+
+        assert_eq!(reason(&data(None)), None);
+        assert_eq!(
+            reason(&data(Some(CacheMissReason::NotInCache))),
+            Some(buck2_data::CacheMissReason::NotInCache as i32)
+        );
+    }
+
+    #[test]
+    fn test_serializable_node_data_round_trip() -> anyhow::Result<()> {
+        let data = NodeData {
+            duration: NodeDuration {
+                user: Duration::from_millis(1),
+                total: Duration::from_millis(3),
+            },
+            span_ids: smallvec![SpanId::from_u64(7)?],
+            ..NodeData::testing_new(Duration::ZERO)
+        };
+
+        let serializable = SerializableNodeData::from(&data);
+        assert_eq!(serializable.owner, None);
+        assert_eq!(serializable.user_duration_us, 1000);
+        assert_eq!(serializable.total_duration_us, 3000);
+        assert_eq!(serializable.span_ids, vec![7]);
+
+        let bytes = bincode::serialize(&serializable)?;
+        assert_eq!(
+            bincode::deserialize::<SerializableNodeData>(&bytes)?,
+            serializable
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_signals_backlog() {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions {
+                log_signals: true,
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..3 {
+            let _ignored = sender.send(BuildSignal::BuildFinished);
+        }
+        drop(sender);
+
+        // We take everything that is queued to tell how many signals are waiting, but still
+        // process them one at a time.
+        assert!(listener.next_signal().await.is_some());
+        assert_eq!(listener.backlog.len(), 2);
+        assert!(listener.next_signal().await.is_some());
+        assert!(listener.next_signal().await.is_some());
+        assert!(listener.next_signal().await.is_none());
+    }
+
+    #[test]
+    fn test_span_id_cap() -> anyhow::Result<()> {
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions {
+                max_span_ids_per_node: 3,
+                ..Default::default()
+            },
+        );
+
+        let evaluation = |name: &str, spans: SmallVec<[SpanId; 1]>| Evaluation {
+            spans,
+            ..crate::testing::evaluation(
+                NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new(
+                    "cell", name,
+                ))),
+                Duration::ZERO,
+            )
+        };
+
+        listener.process_evaluation(evaluation(
+            "many",
+            (1..=1000)
+                .map(SpanId::from_u64)
+                .collect::<anyhow::Result<_>>()?,
+        ));
+        listener.process_evaluation(evaluation("one", smallvec![SpanId::from_u64(7)?]));
+
+        let nodes = listener.coalescer.drain().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 2);
+
+        let many = &nodes[0].data;
+        assert_eq!(
+            many.span_ids
+                .iter()
+                .map(|s| u64::from(*s))
+                .collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        assert_eq!(many.omitted_span_count, 997);
+
+        let one = &nodes[1].data;
+        assert_eq!(one.span_ids.len(), 1);
+        assert!(!one.span_ids.spilled());
+        assert_eq!(one.omitted_span_count, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_graph_enrichment_disabled() {
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions {
+                enrich_load_graph: false,
+                ..Default::default()
+            },
+        );
+
+        let pkg = |name: &str| PackageLabel::testing_new("cell", name);
+        listener.record_load_deps(&pkg("a"), std::iter::once(pkg("b")));
+
+        // Even though `a` led us to `b`, loading `b` only depends on what DICE told us about.
+        let dep = NodeKey::PackageListingKey(PackageListingKey(pkg("b")));
+        let mut evaluation = Evaluation {
+            dep_keys: vec![dep.dupe()],
+            ..crate::testing::evaluation(
+                NodeKey::InterpreterResultsKey(InterpreterResultsKey(pkg("b"))),
+                Duration::ZERO,
+            )
+        };
+        listener.enrich_load(&mut evaluation);
+        assert_eq!(evaluation.dep_keys, vec![dep]);
+    }
+
+    #[test]
+    fn test_load_fan_out() {
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions {
+                max_load_fan_out: 100,
+                ..Default::default()
+            },
+        );
+
+        let pkg = |name: &str| PackageLabel::testing_new("cell", name);
+
+        // Depending on yourself doesn't count.
+        let fan_out = listener.record_load_deps(
+            &pkg("small"),
+            (0..100)
+                .map(|i| pkg(&format!("small_dep{}", i)))
+                .chain(std::iter::once(pkg("small"))),
+        );
+        assert!(fan_out.is_none());
+
+        let fan_out = listener
+            .record_load_deps(
+                &pkg("mega"),
+                (0..1000).map(|i| pkg(&format!("mega_dep{}", i))),
+            )
+            .unwrap();
+        assert_eq!(fan_out.package, pkg("mega"));
+        assert_eq!(fan_out.count, 1000);
+        assert!(fan_out.to_string().contains("1000 distinct packages"));
+
+        // We still track how we discovered every package, however many there are.
+        let mut evaluation = crate::testing::evaluation(
+            NodeKey::InterpreterResultsKey(InterpreterResultsKey(pkg("mega_dep999"))),
+            Duration::ZERO,
+        );
+        listener.enrich_load(&mut evaluation);
+        assert_eq!(
+            evaluation.dep_keys,
+            vec![NodeKey::InterpreterResultsKey(InterpreterResultsKey(pkg(
+                "mega"
+            )))]
+        );
+    }
+
+    #[test]
+    fn test_load_chain() {
+        let pkg = |name: &str| PackageLabel::testing_new("cell", name);
+        let first_edge_to_load = HashMap::from_iter([
+            (pkg("c"), pkg("b")),
+            (pkg("b"), pkg("a")),
+            (pkg("x"), pkg("y")),
+            (pkg("y"), pkg("x")),
+        ]);
+
+        assert_eq!(
+            load_chain(&first_edge_to_load, &pkg("c")),
+            vec![&pkg("b"), &pkg("a")]
+        );
+        assert_eq!(
+            load_chain(&first_edge_to_load, &pkg("a")),
+            Vec::<&PackageLabel>::new()
+        );
+        // We stop when we come back around.
+        assert_eq!(load_chain(&first_edge_to_load, &pkg("x")), vec![&pkg("y")]);
+    }
+
+    #[test]
+    fn test_degenerate_critical_path() {
+        // A single node is fine in a small build, or if the path has more than one.
+        assert!(degenerate_critical_path(1, 10, 0, 100).is_none());
+        assert!(degenerate_critical_path(2, 1000, 0, 100).is_none());
+
+        let degenerate = degenerate_critical_path(1, 1000, 3, 100).unwrap();
+        assert_eq!(degenerate.num_nodes, 1000);
+        assert_eq!(degenerate.num_edges, 3);
+        assert!(degenerate.to_string().contains("1000 nodes and 3 edges"));
+    }
+
+    #[test]
+    fn test_critical_path_summary() {
+        let node = |id: &str, secs: u64, potential: Option<u64>| {
+            (
+                NodeKey::External {
+                    id: Arc::from(id),
+                    display: Arc::from(id),
+                },
+                NodeData::testing_new(Duration::from_secs(secs)),
+                potential.map(Duration::from_secs),
+            )
+        };
+
+        let path = vec![
+            node("a", 1, Some(1)),
+            node("b", 8, Some(2)),
+            node("c", 3, Some(3)),
+            node("d", 1, Some(1)),
+            node("e", 4, Some(4)),
+            node("f", 2, Some(2)),
+            node("g", 5, Some(5)),
+        ];
+        assert_eq!(
+            critical_path_summary(&path, DurationPolicy::default()),
+            [
+                "Critical path: 24.0s over 7 nodes, most worth improving first:",
+                "  5.0s  External(g)",
+                "  4.0s  External(e)",
+                "  3.0s  External(c)",
+                "  8.0s  External(b)",
+                "  2.0s  External(f)",
+            ]
+            .join("\n")
+        );
+
+        // Without potentials, the longest nodes are the ones worth improving.
+        let path = vec![node("a", 1, None), node("b", 2, None)];
+        assert_eq!(
+            critical_path_summary(&path, DurationPolicy::default()),
+            [
+                "Critical path: 3.0s over 2 nodes, most worth improving first:",
+                "  2.0s  External(b)",
+                "  1.0s  External(a)",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_materialization_requesters() -> anyhow::Result<()> {
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions::default(),
+        );
+
+        let target = |name: &str| {
+            ConfiguredTargetLabel::testing_parse(name, ConfigurationData::testing_new())
+        };
+        let a = target("cell//pkg:a");
+        let b = target("cell//pkg:b");
+
+        let artifact = BuildArtifact::testing_new(
+            a.dupe(),
+            ForwardRelativePathBuf::unchecked_new("out".to_owned()),
+            DeferredId::testing_new(0),
+        );
+
+        listener.process_final_materialization(FinalMaterializationSignal {
+            artifact: artifact.dupe(),
+            requester: Some(a.dupe()),
+            duration: NodeDuration::zero(),
+            span_id: None,
+            bytes: None,
+        })?;
+        // A shared artifact, which was also requested by `b`, and `a` again.
+        listener.add_materialization_requester(artifact.dupe(), b.dupe());
+        listener.add_materialization_requester(artifact.dupe(), a.dupe());
+
+        assert_eq!(
+            listener.materialization_requesters.get(&artifact),
+            Some(&vec![a, b])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_clock() -> anyhow::Result<()> {
+        let clock = Arc::new(FakeClock::new());
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions::default(),
+        )
+        .with_clock(clock.dupe());
+
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:a", ConfigurationData::testing_new());
+        let artifact = BuildArtifact::testing_new(
+            target,
+            ForwardRelativePathBuf::unchecked_new("out".to_owned()),
+            DeferredId::testing_new(0),
+        );
+
+        clock.advance(Duration::from_secs(5));
+        listener.process_final_materialization(FinalMaterializationSignal {
+            artifact,
+            requester: None,
+            duration: NodeDuration::zero(),
+            span_id: None,
+            bytes: None,
+        })?;
+
+        // Nodes are timestamped by when we received them, relative to when we started.
+        let nodes = listener.coalescer.drain().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].data.finish_offset, Some(Duration::from_secs(5)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_materialization_grouping() -> anyhow::Result<()> {
+        let (_sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut listener = BuildSignalReceiver::new(
+            receiver,
+            DefaultBackend::new(),
+            &CriticalPathOptions {
+                materialization_grouping: MaterializationGrouping::ByDirectory,
+                ..Default::default()
+            },
+        );
+
+        let target = |name: &str| {
+            ConfiguredTargetLabel::testing_parse(name, ConfigurationData::testing_new())
+        };
+        let a = target("cell//pkg:a");
+        let b = target("cell//pkg:b");
+
+        let artifact = |path: &str, id| {
+            BuildArtifact::testing_new(
+                a.dupe(),
+                ForwardRelativePathBuf::unchecked_new(path.to_owned()),
+                DeferredId::testing_new(id),
+            )
+        };
+        let first = artifact("out/a.o", 0);
+        let second = artifact("out/b.o", 1);
+        let other = artifact("other/c.o", 2);
+
+        for (artifact, requester, secs) in [
+            (first.dupe(), a.dupe(), 1),
+            (second.dupe(), b.dupe(), 2),
+            (other.dupe(), a.dupe(), 2),
+        ] {
+            listener.process_final_materialization(FinalMaterializationSignal {
+                artifact,
+                requester: Some(requester),
+                duration: NodeDuration {
+                    user: Duration::from_secs(secs),
+                    total: Duration::from_secs(secs),
+                },
+                span_id: None,
+                bytes: Some(secs * 100),
+            })?;
+        }
+        listener.flush_materialization_groups();
+
+        let nodes = listener.coalescer.drain().collect::<Vec<_>>();
+        assert_eq!(nodes.len(), 2);
+        let grouped = nodes
+            .iter()
+            .find(|node| node.key == NodeKey::Materialization(first.dupe()))
+            .context("Missing grouped node")?;
+        assert_eq!(grouped.data.duration.user, Duration::from_secs(3));
+        assert_eq!(grouped.data.materialized_bytes, Some(300));
+        assert_eq!(grouped.dep_keys.len(), 2);
+
+        // The group's representative is requested by everything that requested the group.
+        assert_eq!(
+            listener.materialization_requesters.get(&first),
+            Some(&vec![a.dupe(), b])
+        );
+        assert_eq!(listener.materialization_requesters.get(&second), None);
+
+        let entry = describe_node(
+            &listener.build_file_paths,
+            &listener.grouped_materializations,
+            &grouped.key,
+            &grouped.data,
+        );
+        match entry {
+            Some(buck2_data::critical_path_entry2::Entry::Materialization(m)) => {
+                assert_eq!(m.path, "out");
+                assert_eq!(m.artifact_count, Some(2));
+                assert_eq!(m.bytes, Some(300));
+            }
+            _ => panic!("Expected a materialization entry"),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stable_id() {
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:foo", ConfigurationData::testing_new());
+        let artifact = |id| {
+            NodeKey::Materialization(BuildArtifact::testing_new(
+                target.dupe(),
+                ForwardRelativePathBuf::unchecked_new("out".to_owned()),
+                DeferredId::testing_new(id),
+            ))
+        };
+
+        // These are the same artifact, produced by different actions.
+        let (a, b) = (artifact(0), artifact(1));
+        assert_eq!(a, b);
+        assert_ne!(a.to_string(), b.to_string());
+        assert_eq!(a.stable_id(), b.stable_id());
+        assert!(a.stable_id().starts_with("Materialization("));
+        assert!(a.stable_id().ends_with("/out)"));
+
+        let analysis = NodeKey::AnalysisKey(AnalysisKey(target.dupe()));
+        assert_eq!(analysis.stable_id(), analysis.to_string());
+        assert_ne!(analysis.stable_id(), a.stable_id());
+    }
+
+    #[test]
+    fn test_attributed_duration() {
+        let secs = Duration::from_secs;
+        assert_eq!(attributed_duration(secs(6), 0), None);
+        assert_eq!(attributed_duration(secs(6), 1), Some(secs(6)));
+        assert_eq!(attributed_duration(secs(6), 3), Some(secs(2)));
+    }
+
+    #[test]
+    fn test_efficiency() {
+        let duration = |user, total| NodeDuration {
+            user: Duration::from_millis(user),
+            total: Duration::from_millis(total),
+        };
+        assert_eq!(efficiency(&duration(0, 0)), None);
+        assert_eq!(efficiency(&duration(250, 1000)), Some(0.25));
+        assert_eq!(efficiency(&duration(1000, 1000)), Some(1.0));
+        // User time can't meaningfully exceed the total, but we don't trust it not to.
+        assert_eq!(efficiency(&duration(2000, 1000)), Some(1.0));
+    }
+
+    #[test]
+    fn test_span_link() {
+        assert_eq!(
+            span_link("https://ui/{build_id}/spans/{span_id}", "abc", 42),
+            "https://ui/abc/spans/42"
+        );
+        assert_eq!(span_link("https://ui/", "abc", 42), "https://ui/");
+    }
+
+    #[test]
+    fn test_critical_path_entry() {
+        let data = NodeData::testing_new(Duration::ZERO);
+        let build_file_paths = HashMap::new();
+        let configured = NodeKey::ConfiguredTargetNodeKey(ConfiguredTargetNodeKey(
+            ConfiguredTargetLabel::testing_parse("cell//pkg:a", ConfigurationData::testing_new()),
+        ));
+        let listing =
+            NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", "pkg")));
+
+        // We don't have a dedicated entry for configured target nodes.
+        assert!(matches!(
+            critical_path_entry(&build_file_paths, &configured, &data),
+            Some(buck2_data::critical_path_entry2::Entry::Uncategorized(
+                buck2_data::critical_path_entry2::Uncategorized { kind, .. }
+            )) if kind == "ConfiguredTargetNodeKey"
+        ));
+        assert!(matches!(
+            critical_path_entry(&build_file_paths, &listing, &data),
+            Some(buck2_data::critical_path_entry2::Entry::Listing(..))
+        ));
+    }
+
+    #[test]
+    fn test_cacheability_durations() {
+        let target =
+            ConfiguredTargetLabel::testing_parse("cell//pkg:a", ConfigurationData::testing_new());
+        let artifact = BuildArtifact::testing_new(
+            target.dupe(),
+            ForwardRelativePathBuf::unchecked_new("out".to_owned()),
+            DeferredId::testing_new(0),
+        );
+
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+
+        let path = vec![
+            // Analysis isn't classified.
+            analysis(&target, 1),
+            // An action we didn't run (e.g. because of early cutoff) isn't either.
+            (
+                NodeKey::BuildKey(BuildKey(artifact.key().dupe())),
+                data(4),
+                None,
+            ),
+            (NodeKey::Materialization(artifact), data(2), None),
+        ];
+
+        assert_eq!(
+            cacheability_durations(
+                path.iter().map(|(key, data, _)| (key, data)),
+                DurationPolicy::default()
+            ),
+            (Duration::ZERO, Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn test_platform_durations() -> anyhow::Result<()> {
+        let target_cfg = ConfigurationData::testing_new();
+        let exec_cfg = ConfigurationData::from_platform(
+            "cfg//:exec".to_owned(),
+            ConfigurationDataData::empty(),
+        )?;
+        let host_cfg = ConfigurationData::from_platform(
+            "cfg//:host-linux".to_owned(),
+            ConfigurationDataData::empty(),
+        )?;
+        // Only configured host platforms count as such, whatever their name.
+        let ghost_cfg = ConfigurationData::from_platform(
+            "cfg//:ghost".to_owned(),
+            ConfigurationDataData::empty(),
+        )?;
+
+        let action = |label: &str, cfg: &ConfigurationData, secs: u64| {
+            let label = ConfiguredTargetLabel::testing_parse(label, cfg.dupe());
+            let artifact = BuildArtifact::testing_new(
+                label,
+                ForwardRelativePathBuf::unchecked_new("out".to_owned()),
+                DeferredId::testing_new(0),
+            );
+            let data = NodeData::testing_new(Duration::from_secs(secs));
+            (NodeKey::BuildKey(BuildKey(artifact.key().dupe())), data)
+        };
+
+        let mut platforms = PlatformClassifier::new(&["cfg//:host-linux".to_owned()]);
+        platforms.top_level_configurations.insert(target_cfg.dupe());
+        // A toolchain resolved to execute in `exec_cfg` tells us that it is an exec platform.
+        platforms.observe(&NodeKey::AnalysisKey(AnalysisKey(
+            ConfiguredTargetLabel::testing_parse("cell//toolchains:cxx", target_cfg.dupe())
+                .with_exec_cfg(exec_cfg.dupe()),
+        )));
+
+        let path = vec![
+            action("cell//tools:codegen", &exec_cfg, 4),
+            action("cell//pkg:lib", &target_cfg, 2),
+            action("cell//tools:legacy", &host_cfg, 1),
+            action("cell//pkg:bin", &target_cfg, 3),
+            action("cell//pkg:other", &ConfigurationData::unbound(), 5),
+            action("cell//pkg:spooky", &ghost_cfg, 6),
+        ];
+
+        assert_eq!(
+            platform_durations(
+                path.iter().map(|(key, data)| (key, data)),
+                &platforms,
+                DurationPolicy::default()
+            ),
+            vec![
+                ("exec", Duration::from_secs(4)),
+                ("target", Duration::from_secs(5)),
+                ("host", Duration::from_secs(1)),
+                ("unknown", Duration::from_secs(11)),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cell_durations() {
+        let data = |secs: u64| NodeData::testing_new(Duration::from_secs(secs));
+        let listing = |cell: &str| {
+            NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new(cell, "pkg")))
+        };
+        let analysis = |label: &str| {
+            NodeKey::AnalysisKey(AnalysisKey(ConfiguredTargetLabel::testing_parse(
+                label,
+                ConfigurationData::testing_new(),
+            )))
+        };
+
+        let path = vec![
+            (listing("third_party"), data(1)),
+            (analysis("root//pkg:lib"), data(2)),
+            (
+                NodeKey::External {
+                    id: Arc::from("remote"),
+                    display: Arc::from("remote"),
+                },
+                data(4),
+            ),
+            (analysis("third_party//zlib:zlib"), data(3)),
+        ];
+
+        assert_eq!(
+            cell_durations(
+                path.iter().map(|(key, data)| (key, data)),
+                DurationPolicy::default()
+            ),
+            vec![
+                ("third_party".to_owned(), Duration::from_secs(4)),
+                ("root".to_owned(), Duration::from_secs(2)),
+                ("unknown".to_owned(), Duration::from_secs(4)),
+            ]
+        );
+    }
 }
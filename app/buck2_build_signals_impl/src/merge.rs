@@ -0,0 +1,272 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Merge the signal recordings of a build that was sharded across several invocations (e.g. one
+//! per platform) into a single graph, and compute the critical path of the whole build from it.
+//!
+//! Recorded nodes are identified by their stable ids, so a node that several shards evaluated
+//! (e.g. a dependency they share) becomes a single node, through which paths cross from one
+//! shard to another. Of its evaluations, we keep the slowest (which is as long as any shard
+//! waited for it), along with the dependencies of all of them.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use buck2_build_signals::BuildSignalsContext;
+use buck2_build_signals::CriticalPathBackendName;
+
+use crate::coalesce::topological_order;
+use crate::replay::build_graph_info;
+use crate::replay::read_recording;
+use crate::replay::send_signals;
+use crate::replay::RecordedKey;
+use crate::replay::RecordedSignal;
+
+/// Merge recordings made with `CriticalPathOptions::signal_recording_path` by each shard of a
+/// build, run them against `backend`, and return what we would have logged had the build run as
+/// a single invocation.
+pub async fn merge_recordings(
+    paths: &[PathBuf],
+    backend: CriticalPathBackendName,
+    ctx: BuildSignalsContext,
+) -> anyhow::Result<buck2_data::BuildGraphExecutionInfo> {
+    let mut shards = Vec::with_capacity(paths.len());
+    for path in paths {
+        shards.push(read_recording(path)?);
+    }
+
+    let signals = merge_shards(shards)
+        .into_iter()
+        .map(RecordedSignal::into_signal)
+        .collect();
+
+    build_graph_info(&mut send_signals(backend, ctx, signals).await?)
+}
+
+/// Merge the signals of each shard. Nodes come first, in an order the backends accept, followed
+/// by top level targets and failed actions.
+fn merge_shards(shards: Vec<Vec<RecordedSignal>>) -> Vec<RecordedSignal> {
+    let mut nodes = Vec::new();
+    let mut node_indices = HashMap::new();
+    let mut top_level_targets = Vec::new();
+    let mut top_level_target_indices = HashMap::new();
+    let mut failed_actions = Vec::new();
+    let mut failed_action_ids = HashSet::new();
+
+    for signal in shards.into_iter().flatten() {
+        match &signal {
+            RecordedSignal::Node { key, .. } => match node_indices.entry(key.id.clone()) {
+                Entry::Vacant(e) => {
+                    e.insert(nodes.len());
+                    nodes.push(signal);
+                }
+                Entry::Occupied(e) => merge_node(&mut nodes[*e.get()], signal),
+            },
+            RecordedSignal::TopLevelTarget { label, .. } => {
+                match top_level_target_indices.entry(label.clone()) {
+                    Entry::Vacant(e) => {
+                        e.insert(top_level_targets.len());
+                        top_level_targets.push(signal);
+                    }
+                    Entry::Occupied(e) => {
+                        merge_top_level_target(&mut top_level_targets[*e.get()], signal)
+                    }
+                }
+            }
+            RecordedSignal::ActionFailed { key } => {
+                if failed_action_ids.insert(key.id.clone()) {
+                    failed_actions.push(signal);
+                }
+            }
+        }
+    }
+
+    // Each shard's recording is ordered so that nodes come after their dependencies already, but
+    // merging adds edges between their nodes.
+    let mut merged = topological_order(
+        nodes,
+        |node| match node {
+            RecordedSignal::Node { key, .. } => Some(key.id.as_str()),
+            _ => None,
+        },
+        |node, i| node_deps(node).get(i).map(|dep| dep.id.as_str()),
+    );
+    merged.extend(top_level_targets);
+    merged.extend(failed_actions);
+    merged
+}
+
+/// Merge another shard's evaluation of the node `merged` stands for into it.
+fn merge_node(merged: &mut RecordedSignal, mut other: RecordedSignal) {
+    if total_duration_us(&other) > total_duration_us(merged) {
+        std::mem::swap(merged, &mut other);
+    }
+
+    if let (
+        RecordedSignal::Node { deps, .. },
+        RecordedSignal::Node {
+            deps: other_deps, ..
+        },
+    ) = (merged, other)
+    {
+        for dep in other_deps {
+            if !deps.contains(&dep) {
+                deps.push(dep);
+            }
+        }
+    }
+}
+
+/// A target that several shards built is a top level target of all of them. We keep the first
+/// analysis we saw, which is the same node in every shard.
+fn merge_top_level_target(merged: &mut RecordedSignal, other: RecordedSignal) {
+    if let (
+        RecordedSignal::TopLevelTarget { artifacts, .. },
+        RecordedSignal::TopLevelTarget {
+            artifacts: other_artifacts,
+            ..
+        },
+    ) = (merged, other)
+    {
+        for artifact in other_artifacts {
+            if !artifacts.contains(&artifact) {
+                artifacts.push(artifact);
+            }
+        }
+    }
+}
+
+fn total_duration_us(signal: &RecordedSignal) -> u64 {
+    match signal {
+        RecordedSignal::Node { data, .. } => data.total_duration_us,
+        _ => 0,
+    }
+}
+
+fn node_deps(signal: &RecordedSignal) -> &[RecordedKey] {
+    match signal {
+        RecordedSignal::Node { deps, .. } => deps,
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use buck2_build_signals::CriticalPathOptions;
+    use buck2_common::package_listing::dice::PackageListingKey;
+    use buck2_core::fs::paths::file_name::FileNameBuf;
+    use buck2_core::package::PackageLabel;
+
+    use super::*;
+    use crate::testing;
+    use crate::testing::run_signals;
+    use crate::BuildSignal;
+    use crate::Evaluation;
+    use crate::NodeKey;
+
+    fn ctx(signal_recording_path: Option<PathBuf>) -> BuildSignalsContext {
+        BuildSignalsContext {
+            command_name: "test".to_owned(),
+            metadata: Default::default(),
+            isolation_prefix: FileNameBuf::unchecked_new("v2"),
+            options: CriticalPathOptions {
+                signal_recording_path,
+                ..Default::default()
+            },
+            critical_path_history: None,
+            evaluation_stream: None,
+            summary_observer: None,
+        }
+    }
+
+    fn listing(name: &str) -> NodeKey {
+        NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", name)))
+    }
+
+    fn evaluation(key: NodeKey, secs: u64, dep_keys: Vec<NodeKey>) -> BuildSignal {
+        BuildSignal::Evaluation(Evaluation {
+            dep_keys,
+            ..testing::evaluation(key, Duration::from_secs(secs))
+        })
+    }
+
+    async fn record(path: &Path, signals: Vec<BuildSignal>) -> anyhow::Result<()> {
+        run_signals(
+            CriticalPathBackendName::LongestPathGraph,
+            ctx(Some(path.to_owned())),
+            signals,
+        )
+        .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_merge_recordings() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let paths = vec![
+            dir.path().join("first.ndjson"),
+            dir.path().join("second.ndjson"),
+        ];
+
+        // Both shards evaluate `shared`, which only the first shard found to depend on `a`, and
+        // which the second shard took longer to evaluate.
+        record(
+            &paths[0],
+            vec![
+                evaluation(listing("a"), 1, vec![]),
+                evaluation(listing("shared"), 2, vec![listing("a")]),
+                evaluation(listing("x"), 1, vec![listing("shared")]),
+            ],
+        )
+        .await?;
+        record(
+            &paths[1],
+            vec![
+                evaluation(listing("shared"), 5, vec![]),
+                evaluation(listing("y"), 4, vec![listing("shared")]),
+            ],
+        )
+        .await?;
+        let merged = merge_recordings(&paths, CriticalPathBackendName::Default, ctx(None)).await?;
+
+        // The critical path crosses from the first shard into the second one.
+        let ids = merged
+            .critical_path2
+            .iter()
+            .filter_map(|entry| match &entry.entry {
+                Some(buck2_data::critical_path_entry2::Entry::External(external)) => {
+                    Some(external.id.clone())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                listing("a").stable_id(),
+                listing("shared").stable_id(),
+                listing("y").stable_id(),
+            ]
+        );
+        assert_eq!(
+            merged
+                .critical_path_user_duration
+                .as_ref()
+                .map(|d| d.seconds),
+            Some(10)
+        );
+
+        Ok(())
+    }
+}
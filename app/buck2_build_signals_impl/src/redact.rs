@@ -0,0 +1,187 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+use buck2_data::critical_path_entry2::action_execution;
+use buck2_data::critical_path_entry2::analysis;
+use buck2_data::critical_path_entry2::materialization;
+use buck2_data::critical_path_entry2::Entry;
+
+/// Replaces the names in critical path entries (targets, packages, paths, and so on) with hashes
+/// of them. The same name always hashes the same for a given salt, so the critical path can still
+/// be analyzed (e.g. to tell that two entries belong to the same target), but not read. Using a
+/// different salt for each build means names can't be correlated across builds either.
+pub(crate) struct Redactor {
+    salt: String,
+}
+
+impl Redactor {
+    pub(crate) fn new(salt: String) -> Self {
+        Self { salt }
+    }
+
+    fn hash(&self, name: &str) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.salt.hash(&mut hasher);
+        name.hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Empty strings carry no information, so we leave them alone.
+    fn redact(&self, name: &mut String) {
+        if !name.is_empty() {
+            *name = self.hash(name);
+        }
+    }
+
+    fn redact_target_label(&self, label: &mut buck2_data::TargetLabel) {
+        self.redact(&mut label.package);
+        self.redact(&mut label.name);
+    }
+
+    /// Configurations don't identify anything, so we keep them.
+    fn redact_configured_target_label(&self, label: &mut buck2_data::ConfiguredTargetLabel) {
+        if let Some(label) = &mut label.label {
+            self.redact_target_label(label);
+        }
+    }
+
+    fn redact_bxl_key(&self, key: &mut buck2_data::BxlFunctionKey) {
+        if let Some(label) = &mut key.label {
+            self.redact(&mut label.bxl_path);
+            self.redact(&mut label.name);
+        }
+    }
+
+    fn redact_anon_target(&self, target: &mut buck2_data::AnonTarget) {
+        if let Some(name) = &mut target.name {
+            self.redact_target_label(name);
+        }
+    }
+
+    /// Redact the names in `entry`. Its kind, durations and action categories are kept.
+    pub(crate) fn redact_entry(&self, entry: &mut buck2_data::CriticalPathEntry2) {
+        for target in &mut entry.top_level_targets {
+            self.redact_configured_target_label(target);
+        }
+
+        match &mut entry.entry {
+            Some(Entry::Analysis(analysis)) => match &mut analysis.target {
+                Some(analysis::Target::StandardTarget(target)) => {
+                    self.redact_configured_target_label(target)
+                }
+                None => {}
+            },
+            Some(Entry::ActionExecution(action)) => {
+                if let Some(name) = &mut action.name {
+                    self.redact(&mut name.identifier);
+                }
+                match &mut action.owner {
+                    Some(action_execution::Owner::TargetLabel(target)) => {
+                        self.redact_configured_target_label(target)
+                    }
+                    Some(action_execution::Owner::BxlKey(key)) => self.redact_bxl_key(key),
+                    Some(action_execution::Owner::AnonTarget(target)) => {
+                        self.redact_anon_target(target)
+                    }
+                    None => {}
+                }
+            }
+            Some(Entry::Materialization(materialization)) => {
+                self.redact(&mut materialization.path);
+                match &mut materialization.owner {
+                    Some(materialization::Owner::TargetLabel(target)) => {
+                        self.redact_configured_target_label(target)
+                    }
+                    Some(materialization::Owner::BxlKey(key)) => self.redact_bxl_key(key),
+                    Some(materialization::Owner::AnonTarget(target)) => {
+                        self.redact_anon_target(target)
+                    }
+                    None => {}
+                }
+            }
+            Some(Entry::Load(load)) => {
+                self.redact(&mut load.package);
+                if let Some(path) = &mut load.build_file_path {
+                    self.redact(path);
+                }
+                for package in &mut load.loaded_via {
+                    self.redact(package);
+                }
+            }
+            Some(Entry::Listing(listing)) => self.redact(&mut listing.package),
+            Some(Entry::Configuration(configuration)) => {
+                if let Some(target) = &mut configuration.target {
+                    self.redact_configured_target_label(target);
+                }
+            }
+            Some(Entry::External(external)) => {
+                self.redact(&mut external.id);
+                self.redact(&mut external.display);
+            }
+            Some(Entry::Uncategorized(uncategorized)) => self.redact(&mut uncategorized.display),
+            Some(Entry::ComputeCriticalPath(..)) | None => {}
+        }
+    }
+
+    /// Anomalies name nodes by their stable id, which we redact like any other name.
+    pub(crate) fn redact_anomaly(&self, anomaly: &mut buck2_data::CriticalPathDurationAnomaly) {
+        self.redact(&mut anomaly.node);
+    }
+
+    /// Cell names are part of the names of what's in them, so we redact them too.
+    pub(crate) fn redact_cell_duration(
+        &self,
+        cell_duration: &mut buck2_data::CriticalPathCellDuration,
+    ) {
+        self.redact(&mut cell_duration.cell);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(package: &str) -> buck2_data::CriticalPathEntry2 {
+        buck2_data::CriticalPathEntry2 {
+            entry: Some(Entry::Listing(buck2_data::critical_path_entry2::Listing {
+                package: package.to_owned(),
+            })),
+            ..Default::default()
+        }
+    }
+
+    fn package(entry: &buck2_data::CriticalPathEntry2) -> &str {
+        match &entry.entry {
+            Some(Entry::Listing(listing)) => &listing.package,
+            _ => panic!("Not a listing"),
+        }
+    }
+
+    #[test]
+    fn test_redaction_is_consistent() {
+        let redactor = Redactor::new("build".to_owned());
+        let mut entries = [listing("cell//a"), listing("cell//b"), listing("cell//a")];
+        for entry in &mut entries {
+            redactor.redact_entry(entry);
+        }
+
+        assert_ne!(package(&entries[0]), "cell//a");
+        assert_eq!(package(&entries[0]), package(&entries[2]));
+        assert_ne!(package(&entries[0]), package(&entries[1]));
+
+        // Another build hashes names differently.
+        let mut other = listing("cell//a");
+        Redactor::new("other build".to_owned()).redact_entry(&mut other);
+        assert_ne!(package(&other), package(&entries[0]));
+    }
+}
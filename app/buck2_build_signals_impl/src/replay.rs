@@ -0,0 +1,379 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Record the signals the receiver processes during a build to a file, as NDJSON, and replay
+//! them later against any backend. This makes working on backends reproducible without running
+//! builds.
+//!
+//! We record nodes as the receiver hands them to the backend, after it enriched and grouped
+//! them, rather than the signals it received. The keys of those nodes (and the
+//! `Arc<RegisteredAction>` of actions) can't be reconstructed outside of the build that produced
+//! them, so we only record their stable ids and how they display, and replay them as external
+//! nodes. This keeps the shape of the graph and the durations, but replayed entries report the
+//! recorded strings instead of actions' categories and identifiers.
+
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use buck2_build_signals::BuildSignalsContext;
+use buck2_build_signals::CriticalPathBackendName;
+use buck2_build_signals::NodeDuration;
+use buck2_events::create_source_sink_pair;
+use buck2_events::dispatch::EventDispatcher;
+use buck2_events::source::ChannelEventSource;
+use buck2_events::span::SpanId;
+use buck2_events::Event;
+use dupe::Dupe;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::coalesce::PendingNode;
+use crate::ActionFailedSignal;
+use crate::BuildSignal;
+use crate::BuildSignalSender;
+use crate::DeferredBuildSignalsImpl;
+use crate::Evaluation;
+use crate::NodeKey;
+use crate::ReplayedTopLevelTargetSignal;
+use crate::SerializableNodeData;
+
+/// A `NodeKey`, as we record it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct RecordedKey {
+    pub(crate) id: String,
+    display: String,
+}
+
+impl RecordedKey {
+    fn new(key: &NodeKey) -> Self {
+        Self {
+            id: key.stable_id(),
+            display: key.to_string(),
+        }
+    }
+
+    fn to_node_key(&self) -> NodeKey {
+        NodeKey::External {
+            id: Arc::from(self.id.as_str()),
+            display: Arc::from(self.display.as_str()),
+        }
+    }
+}
+
+/// One line of a recording.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub(crate) enum RecordedSignal {
+    Node {
+        key: RecordedKey,
+        deps: Vec<RecordedKey>,
+        data: SerializableNodeData,
+        transferred_bytes: u64,
+        #[serde(default)]
+        output_bytes: u64,
+        #[serde(default)]
+        re_queue_duration: Option<Duration>,
+        #[serde(default)]
+        reused: bool,
+    },
+    TopLevelTarget {
+        label: String,
+        analysis: RecordedKey,
+        artifacts: Vec<RecordedKey>,
+    },
+    ActionFailed {
+        key: RecordedKey,
+    },
+}
+
+impl RecordedSignal {
+    pub(crate) fn node(node: &PendingNode) -> Self {
+        Self::Node {
+            key: RecordedKey::new(&node.key),
+            deps: node.dep_keys.iter().map(RecordedKey::new).collect(),
+            data: SerializableNodeData::from(&node.data),
+            transferred_bytes: node.data.transferred_bytes,
+            output_bytes: node.data.output_bytes,
+            re_queue_duration: node.data.re_queue_duration,
+            reused: node.data.reused,
+        }
+    }
+
+    pub(crate) fn top_level_target(
+        label: String,
+        analysis: &NodeKey,
+        artifacts: &[NodeKey],
+    ) -> Self {
+        Self::TopLevelTarget {
+            label,
+            analysis: RecordedKey::new(analysis),
+            artifacts: artifacts.iter().map(RecordedKey::new).collect(),
+        }
+    }
+
+    pub(crate) fn action_failed(key: &NodeKey) -> Self {
+        Self::ActionFailed {
+            key: RecordedKey::new(key),
+        }
+    }
+
+    pub(crate) fn into_signal(self) -> BuildSignal {
+        match self {
+            Self::Node {
+                key,
+                deps,
+                data,
+                transferred_bytes,
+                output_bytes,
+                re_queue_duration,
+                reused,
+            } => Evaluation {
+                key: key.to_node_key(),
+                duration: NodeDuration {
+                    user: Duration::from_micros(data.user_duration_us),
+                    total: Duration::from_micros(data.total_duration_us),
+                },
+                dep_keys: deps.iter().map(RecordedKey::to_node_key).collect(),
+                spans: data
+                    .span_ids
+                    .into_iter()
+                    .filter_map(SpanId::from_u64_opt)
+                    .collect(),
+                transferred_bytes,
+                output_bytes,
+                cache_miss_reason: None,
+                re_queue_duration,
+                reused,
+                action: None,
+                action_digest: None,
+                load_result: None,
+            }
+            .into(),
+            Self::TopLevelTarget {
+                label,
+                analysis,
+                artifacts,
+            } => ReplayedTopLevelTargetSignal {
+                label,
+                analysis: analysis.to_node_key(),
+                artifacts: artifacts.iter().map(RecordedKey::to_node_key).collect(),
+            }
+            .into(),
+            Self::ActionFailed { key } => ActionFailedSignal {
+                key: key.to_node_key(),
+            }
+            .into(),
+        }
+    }
+}
+
+/// Writes the signals the receiver processes to a file.
+pub(crate) struct SignalRecorder {
+    writer: BufWriter<File>,
+}
+
+impl SignalRecorder {
+    pub(crate) fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Error creating signal recording `{}`", path.display()))?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    pub(crate) fn record(&mut self, signal: &RecordedSignal) -> anyhow::Result<()> {
+        serde_json::to_writer(&mut self.writer, signal).context("Error recording signal")?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> anyhow::Result<()> {
+        self.writer
+            .flush()
+            .context("Error flushing signal recording")
+    }
+}
+
+/// Replay a recording made with `CriticalPathOptions::signal_recording_path` against `backend`,
+/// and return what we would have logged at the end of the recorded build.
+pub async fn replay(
+    path: &Path,
+    backend: CriticalPathBackendName,
+    ctx: BuildSignalsContext,
+) -> anyhow::Result<buck2_data::BuildGraphExecutionInfo> {
+    let signals = read_recording(path)?
+        .into_iter()
+        .map(RecordedSignal::into_signal)
+        .collect();
+
+    build_graph_info(&mut send_signals(backend, ctx, signals).await?)
+}
+
+pub(crate) fn read_recording(path: &Path) -> anyhow::Result<Vec<RecordedSignal>> {
+    let file = File::open(path)
+        .with_context(|| format!("Error opening signal recording `{}`", path.display()))?;
+
+    let mut signals = Vec::new();
+    for (idx, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.context("Error reading signal recording")?;
+        let signal = serde_json::from_str::<RecordedSignal>(&line)
+            .with_context(|| format!("Invalid signal on line {}", idx + 1))?;
+        signals.push(signal);
+    }
+
+    Ok(signals)
+}
+
+/// Send `signals` straight to the receiver, and return everything we logged.
+pub(crate) async fn send_signals(
+    backend: CriticalPathBackendName,
+    ctx: BuildSignalsContext,
+    signals: Vec<BuildSignal>,
+) -> anyhow::Result<ChannelEventSource> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    for signal in signals {
+        let _ignored = sender.send(signal);
+    }
+
+    let deferred = Box::new(DeferredBuildSignalsImpl {
+        sender: Arc::new(BuildSignalSender::new(sender)),
+        receiver,
+    });
+    let (events, mut source) = dispatcher();
+
+    buck2_build_signals::scope(deferred, events, backend, ctx, true, || async { Ok(()) }).await?;
+
+    Ok(source)
+}
+
+/// A dispatcher whose events we can read back from the returned source. Replays aren't part of a
+/// command, so they log under the same null trace id as `EventDispatcher::null`.
+pub(crate) fn dispatcher() -> (EventDispatcher, ChannelEventSource) {
+    let (source, sink) = create_source_sink_pair();
+    (
+        EventDispatcher::new(EventDispatcher::null().trace_id().dupe(), sink),
+        source,
+    )
+}
+
+/// Find the `BuildGraphExecutionInfo` among the events we logged.
+pub(crate) fn build_graph_info(
+    source: &mut ChannelEventSource,
+) -> anyhow::Result<buck2_data::BuildGraphExecutionInfo> {
+    while let Some(event) = source.try_receive() {
+        let event = match event {
+            Event::Buck(event) => event,
+            _ => continue,
+        };
+
+        if let buck2_data::buck_event::Data::Instant(instant) = event.data() {
+            if let Some(buck2_data::instant_event::Data::BuildGraphInfo(info)) = &instant.data {
+                return Ok(info.clone());
+            }
+        }
+    }
+
+    Err(anyhow::anyhow!(
+        "The critical path was not logged (was there an error computing it?)"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_build_signals::CriticalPathOptions;
+    use buck2_common::package_listing::dice::PackageListingKey;
+    use buck2_core::fs::paths::file_name::FileNameBuf;
+    use buck2_core::package::PackageLabel;
+
+    use super::*;
+    use crate::testing;
+    use crate::testing::run_signals;
+
+    fn ctx(options: CriticalPathOptions) -> BuildSignalsContext {
+        BuildSignalsContext {
+            command_name: "test".to_owned(),
+            metadata: Default::default(),
+            isolation_prefix: FileNameBuf::unchecked_new("v2"),
+            options,
+            critical_path_history: None,
+            evaluation_stream: None,
+            summary_observer: None,
+        }
+    }
+
+    fn listing(name: &str) -> NodeKey {
+        NodeKey::PackageListingKey(PackageListingKey(PackageLabel::testing_new("cell", name)))
+    }
+
+    fn evaluation(key: NodeKey, secs: u64, dep_keys: Vec<NodeKey>) -> BuildSignal {
+        BuildSignal::Evaluation(Evaluation {
+            dep_keys,
+            ..testing::evaluation(key, Duration::from_secs(secs))
+        })
+    }
+
+    fn external_ids(info: &buck2_data::BuildGraphExecutionInfo) -> Vec<String> {
+        info.critical_path2
+            .iter()
+            .filter_map(|entry| match &entry.entry {
+                Some(buck2_data::critical_path_entry2::Entry::External(external)) => {
+                    Some(external.id.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("signals.ndjson");
+
+        let recorded = run_signals(
+            CriticalPathBackendName::LongestPathGraph,
+            ctx(CriticalPathOptions {
+                signal_recording_path: Some(path.clone()),
+                ..Default::default()
+            }),
+            vec![
+                evaluation(listing("a"), 1, vec![]),
+                evaluation(listing("b"), 2, vec![listing("a")]),
+                evaluation(listing("c"), 2, vec![]),
+            ],
+        )
+        .await?;
+
+        // Replaying against another backend finds the same path, through external nodes that
+        // stand in for the keys we recorded.
+        let replayed = replay(
+            &path,
+            CriticalPathBackendName::Default,
+            ctx(Default::default()),
+        )
+        .await?;
+
+        assert_eq!(
+            external_ids(&replayed),
+            vec![listing("a").stable_id(), listing("b").stable_id()]
+        );
+        assert_eq!(replayed.total_work, recorded.total_work);
+        assert_eq!(
+            replayed.critical_path_user_duration,
+            recorded.critical_path_user_duration
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Streams the evaluations we receive, as NDJSON, to consumers that want to follow the build as
+//! it happens rather than wait for the critical path at the end.
+
+use std::io::BufWriter;
+use std::io::Write;
+
+use anyhow::Context as _;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// How many lines we buffer before we wait for the writer to catch up.
+const CAPACITY: usize = 1024;
+
+/// One line of the stream.
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct StreamedEvaluation {
+    pub(crate) key: String,
+    pub(crate) kind: &'static str,
+    pub(crate) duration_us: u64,
+    pub(crate) deps: usize,
+}
+
+/// Writes evaluations to a sink on a blocking thread. Sending waits when the writer falls behind,
+/// which holds up the loop processing build signals (whose own queue then grows) rather than the
+/// build itself.
+pub(crate) struct EvaluationStream {
+    sender: mpsc::Sender<StreamedEvaluation>,
+    writer: JoinHandle<anyhow::Result<()>>,
+}
+
+impl EvaluationStream {
+    pub(crate) fn new(sink: Box<dyn Write + Send>) -> Self {
+        let (sender, mut receiver) = mpsc::channel::<StreamedEvaluation>(CAPACITY);
+
+        let writer = tokio::task::spawn_blocking(move || {
+            let mut sink = BufWriter::new(sink);
+            while let Some(evaluation) = receiver.blocking_recv() {
+                write_line(&mut sink, &evaluation)?;
+                // Write whatever else is queued before we flush, so that we don't make a syscall
+                // per line when the build is busy.
+                while let Ok(evaluation) = receiver.try_recv() {
+                    write_line(&mut sink, &evaluation)?;
+                }
+                sink.flush()?;
+            }
+            Ok(())
+        });
+
+        Self { sender, writer }
+    }
+
+    /// Queue `evaluation` to be written. Returns false if the writer stopped, in which case
+    /// `finish` returns why.
+    pub(crate) async fn send(&self, evaluation: StreamedEvaluation) -> bool {
+        self.sender.send(evaluation).await.is_ok()
+    }
+
+    /// Wait for everything we sent to be written.
+    pub(crate) async fn finish(self) -> anyhow::Result<()> {
+        drop(self.sender);
+        self.writer
+            .await
+            .context("Error joining evaluation stream writer")?
+            .context("Error streaming evaluations")
+    }
+}
+
+fn write_line(mut sink: impl Write, evaluation: &StreamedEvaluation) -> anyhow::Result<()> {
+    serde_json::to_writer(&mut sink, evaluation).context("Error writing evaluation")?;
+    sink.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// A sink whose contents tests can read back after handing a clone of it to a stream.
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuffer(pub(crate) Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let stream = EvaluationStream::new(Box::new(buffer.clone()));
+
+        for i in 0..3 {
+            let sent = stream
+                .send(StreamedEvaluation {
+                    key: format!("k{}", i),
+                    kind: "PackageListingKey",
+                    duration_us: i,
+                    deps: 1,
+                })
+                .await;
+            assert!(sent);
+        }
+        stream.finish().await?;
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        assert_eq!(
+            output,
+            concat!(
+                "{\"key\":\"k0\",\"kind\":\"PackageListingKey\",\"duration_us\":0,\"deps\":1}\n",
+                "{\"key\":\"k1\",\"kind\":\"PackageListingKey\",\"duration_us\":1,\"deps\":1}\n",
+                "{\"key\":\"k2\",\"kind\":\"PackageListingKey\",\"duration_us\":2,\"deps\":1}\n",
+            )
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,913 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under both the MIT license found in the
+ * LICENSE-MIT file in the root directory of this source tree and the Apache
+ * License, Version 2.0 found in the LICENSE-APACHE file in the root directory
+ * of this source tree.
+ */
+
+//! Drive the whole build signals pipeline (the receiver, the enrichment it does, and the
+//! backends) with synthetic signals instead of a real build, and inspect what it logged. This
+//! lets integrations against the build signals API be tested without a daemon. Code that consumes
+//! critical paths can also build them by hand with `BuildInfoBuilder`.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use allocative::Allocative;
+use anyhow::Context as _;
+use buck2_analysis::analysis::calculation::AnalysisKey;
+use buck2_artifact::actions::key::ActionKey;
+use buck2_artifact::deferred::id::DeferredId;
+use buck2_artifact::deferred::key::DeferredKey;
+use buck2_build_api::actions::calculation::BuildKey;
+use buck2_build_api::build_signals::BuildSignalsInstaller;
+use buck2_build_signals::BuildSignalsContext;
+use buck2_build_signals::CriticalPathBackendName;
+use buck2_build_signals::NodeDuration;
+use buck2_core::base_deferred_key::BaseDeferredKey;
+use buck2_core::configuration::data::ConfigurationData;
+use buck2_core::package::PackageLabel;
+use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
+use buck2_interpreter_for_build::interpreter::calculation::InterpreterResultsKey;
+
+use crate::backend::backend::BuildListenerBackend;
+use crate::create_build_signals;
+use crate::replay::build_graph_info;
+use crate::replay::dispatcher;
+use crate::start_backend;
+use crate::BuildInfo;
+use crate::BuildSignal;
+use crate::NodeData;
+use crate::NodeKey;
+
+/// Run `func` as if it were a build: it gets the same `BuildSignalsInstaller` a build would, and
+/// can use it to send signals. Once it returns, we compute the critical path as we would at the
+/// end of a build, and return what we logged. Unlike in a real build, failing to compute the
+/// critical path fails the dry run.
+pub async fn dry_run<F, Fut>(
+    backend: CriticalPathBackendName,
+    ctx: BuildSignalsContext,
+    func: F,
+) -> anyhow::Result<buck2_data::BuildGraphExecutionInfo>
+where
+    F: FnOnce(BuildSignalsInstaller) -> Fut + Send,
+    Fut: Future<Output = anyhow::Result<()>> + Send,
+{
+    let (installer, deferred) = create_build_signals();
+    let (events, mut source) = dispatcher();
+
+    buck2_build_signals::scope(deferred, events, backend, ctx, true, || func(installer)).await?;
+
+    build_graph_info(&mut source)
+}
+
+/// Like `dry_run`, but send `signals` straight to the receiver, which lets tests send signals
+/// that aren't easy to produce from outside of a build (e.g. evaluations of arbitrary keys).
+#[cfg(test)]
+pub(crate) async fn run_signals(
+    backend: CriticalPathBackendName,
+    ctx: BuildSignalsContext,
+    signals: Vec<BuildSignal>,
+) -> anyhow::Result<buck2_data::BuildGraphExecutionInfo> {
+    build_graph_info(&mut crate::replay::send_signals(backend, ctx, signals).await?)
+}
+
+/// An evaluation of `key` that took `duration`, with no dependencies, spans, or action. Tests set
+/// whatever else they need with struct update syntax.
+#[cfg(test)]
+pub(crate) fn evaluation(key: NodeKey, duration: Duration) -> crate::Evaluation {
+    crate::Evaluation {
+        key,
+        duration: NodeDuration {
+            user: duration,
+            total: duration,
+        },
+        dep_keys: Vec::new(),
+        spans: Default::default(),
+        transferred_bytes: 0,
+        output_bytes: 0,
+        cache_miss_reason: None,
+        re_queue_duration: None,
+        reused: false,
+        action: None,
+        action_digest: None,
+        load_result: None,
+    }
+}
+
+/// The kind of node an entry pushed to a `BuildInfoBuilder` is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SyntheticNodeKind {
+    /// An action, whose owner is a target label (e.g. `cell//pkg:target`).
+    Action,
+    /// The analysis of a target, whose owner is a target label.
+    Analysis,
+    /// The load of a package, whose owner is a package label (e.g. `cell//pkg`).
+    Load,
+    /// An external node, whose owner is its id.
+    External,
+}
+
+/// Builds a `BuildInfo` out of hand-written critical path entries, so that code consuming
+/// critical paths can be tested without running a build. Targets are in a testing configuration,
+/// and actions don't carry a name.
+#[derive(Default)]
+pub struct BuildInfoBuilder {
+    critical_path: Vec<(NodeKey, NodeData, Option<Duration>)>,
+    graph_size: Option<(u64, u64)>,
+}
+
+impl BuildInfoBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append an entry to the critical path.
+    pub fn entry(
+        mut self,
+        kind: SyntheticNodeKind,
+        owner: &str,
+        duration: NodeDuration,
+        potential_improvement: Option<Duration>,
+    ) -> Self {
+        let target =
+            || ConfiguredTargetLabel::testing_parse(owner, ConfigurationData::testing_new());
+        let key = match kind {
+            SyntheticNodeKind::Action => {
+                // Number the actions of each target, so that they are distinct, but keep the same
+                // ids across builders that push the same actions for a target.
+                let target = target();
+                let id = self
+                    .critical_path
+                    .iter()
+                    .filter(|(key, _, _)| {
+                        matches!(key, NodeKey::BuildKey(..)) && key.owner_target() == Some(&target)
+                    })
+                    .count();
+                NodeKey::BuildKey(BuildKey(ActionKey::unchecked_new(DeferredKey::Base(
+                    BaseDeferredKey::TargetLabel(target),
+                    DeferredId::testing_new(id as u32),
+                ))))
+            }
+            SyntheticNodeKind::Analysis => NodeKey::AnalysisKey(AnalysisKey(target())),
+            SyntheticNodeKind::Load => NodeKey::InterpreterResultsKey(InterpreterResultsKey(
+                PackageLabel::testing_parse(owner),
+            )),
+            SyntheticNodeKind::External => NodeKey::External {
+                id: Arc::from(owner),
+                display: Arc::from(owner),
+            },
+        };
+
+        self.critical_path.push((
+            key,
+            NodeData {
+                duration,
+                ..NodeData::testing_new(Duration::ZERO)
+            },
+            potential_improvement,
+        ));
+        self
+    }
+
+    /// The size of the graph the critical path was found in. By default, the graph is just the
+    /// critical path.
+    pub fn with_graph_size(mut self, num_nodes: u64, num_edges: u64) -> Self {
+        self.graph_size = Some((num_nodes, num_edges));
+        self
+    }
+
+    pub fn build(self) -> BuildInfo {
+        let len = self.critical_path.len() as u64;
+        let (num_nodes, num_edges) = self.graph_size.unwrap_or((len, len.saturating_sub(1)));
+
+        BuildInfo {
+            critical_path: self.critical_path,
+            num_nodes,
+            num_edges,
+            potentials_timed_out: false,
+            exported_graph: None,
+            top_level_targets: Vec::new(),
+            analysis_critical_path: Vec::new(),
+            execution_critical_path: Vec::new(),
+            configuration_critical_path: Vec::new(),
+            retained_graph: None,
+            potential_improvement_bytes: Vec::new(),
+            analysis_dependents: Vec::new(),
+            hops_from_top_level: Vec::new(),
+            failure_critical_path: Vec::new(),
+            simulated_schedule: None,
+            slack_histogram: Vec::new(),
+            on_every_critical_path: Vec::new(),
+            always_critical: Vec::new(),
+            near_critical: Vec::new(),
+            critical_path_overhead: None,
+            last_blockers: Vec::new(),
+        }
+    }
+}
+
+/// Log `info` the way we would at the end of a build whose backend found it, and return what we
+/// logged. We report it as coming from the longest-path-graph backend.
+pub async fn emit_build_info(
+    info: BuildInfo,
+    ctx: BuildSignalsContext,
+) -> anyhow::Result<buck2_data::BuildGraphExecutionInfo> {
+    let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let _ignored = sender.send(BuildSignal::BuildFinished);
+    let (events, mut source) = dispatcher();
+
+    start_backend(events, receiver, CannedBackend { info }, ctx)
+        .await
+        .context("Error joining critical path task")??;
+
+    build_graph_info(&mut source)
+}
+
+/// A backend that ignores the nodes it receives and produces a `BuildInfo` we built ahead of time.
+#[derive(Allocative)]
+struct CannedBackend {
+    #[allocative(skip)]
+    info: BuildInfo,
+}
+
+impl BuildListenerBackend for CannedBackend {
+    fn process_node(
+        &mut self,
+        _key: NodeKey,
+        _data: NodeData,
+        _dep_keys: impl IntoIterator<Item = NodeKey>,
+    ) {
+    }
+
+    fn process_top_level_target(
+        &mut self,
+        _analysis: NodeKey,
+        _artifacts: impl IntoIterator<Item = NodeKey>,
+    ) {
+    }
+
+    fn finish(self, _deadline: Option<Instant>) -> anyhow::Result<BuildInfo> {
+        Ok(self.info)
+    }
+
+    fn name() -> CriticalPathBackendName {
+        CriticalPathBackendName::LongestPathGraph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use buck2_build_api::build_signals::ExternalNode;
+    use buck2_build_api::build_signals::ExternalNodeDep;
+    use buck2_build_api::build_signals::ExternalNodeKey;
+    use buck2_build_signals::CriticalPathOptions;
+    use buck2_build_signals::CriticalPathRedaction;
+    use buck2_common::package_listing::dice::PackageListingKey;
+    use buck2_common::package_listing::dice::PackageListingKeyActivationData;
+    use buck2_core::fs::paths::file_name::FileNameBuf;
+    use buck2_events::Event;
+    use dice::ActivationData;
+    use dupe::Dupe;
+
+    use super::*;
+    use crate::backend::default::DefaultBackend;
+    use crate::compare_critical_paths;
+    use crate::replay::send_signals;
+    use crate::stream::tests::SharedBuffer;
+    use crate::CriticalPathRegression;
+    use crate::Evaluation;
+    use crate::RegressionThresholds;
+
+    fn ctx() -> BuildSignalsContext {
+        BuildSignalsContext {
+            command_name: "test".to_owned(),
+            metadata: Default::default(),
+            isolation_prefix: FileNameBuf::unchecked_new("v2"),
+            options: Default::default(),
+            critical_path_history: None,
+            evaluation_stream: None,
+            summary_observer: None,
+        }
+    }
+
+    fn listing(name: &str) -> PackageListingKey {
+        PackageListingKey(PackageLabel::testing_new("cell", name))
+    }
+
+    fn listing_packages(info: &buck2_data::BuildGraphExecutionInfo) -> Vec<String> {
+        info.critical_path2
+            .iter()
+            .filter_map(|entry| match &entry.entry {
+                Some(buck2_data::critical_path_entry2::Entry::Listing(listing)) => {
+                    Some(listing.package.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_run_signals() -> anyhow::Result<()> {
+        let evaluation = |name: &str, secs: u64, deps: Vec<PackageListingKey>| {
+            BuildSignal::Evaluation(Evaluation {
+                dep_keys: deps.into_iter().map(NodeKey::PackageListingKey).collect(),
+                ..super::evaluation(
+                    NodeKey::PackageListingKey(listing(name)),
+                    Duration::from_secs(secs),
+                )
+            })
+        };
+
+        for backend in [
+            CriticalPathBackendName::Default,
+            CriticalPathBackendName::LongestPathGraph,
+            CriticalPathBackendName::Composite,
+        ] {
+            let info = run_signals(
+                backend,
+                ctx(),
+                vec![
+                    evaluation("a", 1, vec![]),
+                    evaluation("b", 2, vec![listing("a")]),
+                    evaluation("c", 2, vec![]),
+                ],
+            )
+            .await?;
+
+            assert_eq!(info.backend_name, Some(backend.to_string()));
+            assert_eq!(
+                listing_packages(&info),
+                vec![listing("a").0.to_string(), listing("b").0.to_string()]
+            );
+            // Only `c` ran off the critical path.
+            assert_eq!(info.off_critical_path_node_count, 1);
+            assert_eq!(
+                info.off_critical_path_total_duration
+                    .as_ref()
+                    .map(|d| d.seconds),
+                Some(2)
+            );
+            // Even with infinite parallelism, `a` and `b` have to run one after the other.
+            assert_eq!(
+                info.min_build_time_infinite_parallelism
+                    .as_ref()
+                    .map(|d| d.seconds),
+                Some(3)
+            );
+            assert!(info.gap_to_min_build_time.is_some());
+            // Everything took 5s of work, 3s of which had to happen serially.
+            assert_eq!(info.total_work.as_ref().map(|d| d.seconds), Some(5));
+            assert_eq!(
+                info.critical_path_user_duration.as_ref().map(|d| d.seconds),
+                Some(3)
+            );
+            assert!(info
+                .ideal_speedup
+                .is_some_and(|speedup| (speedup - 5.0 / 3.0).abs() < 1e-6));
+            // The last entry is always the time it took to compute the critical path.
+            assert!(matches!(
+                info.critical_path2.last().and_then(|e| e.entry.as_ref()),
+                Some(buck2_data::critical_path_entry2::Entry::ComputeCriticalPath(..))
+            ));
+            assert!(!info.incomplete);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_senders_dropped_before_build_finished() -> anyhow::Result<()> {
+        let (sender, receiver) = tokio::sync::mpsc::unbounded_channel();
+        let _ignored = sender.send(BuildSignal::Evaluation(super::evaluation(
+            NodeKey::PackageListingKey(listing("a")),
+            Duration::from_secs(1),
+        )));
+        // Tear down without ever saying the build finished.
+        drop(sender);
+
+        let (events, mut source) = dispatcher();
+        start_backend(events, receiver, DefaultBackend::new(), ctx()).await??;
+
+        let info = build_graph_info(&mut source)?;
+        assert!(info.incomplete);
+        assert_eq!(listing_packages(&info), vec![listing("a").0.to_string()]);
+        // We don't know how long the build took.
+        assert_eq!(info.gap_to_min_build_time, None);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_re_queue_duration() -> anyhow::Result<()> {
+        let evaluation = |name: &str, queued_ms: Option<u64>, deps: Vec<PackageListingKey>| {
+            BuildSignal::Evaluation(Evaluation {
+                dep_keys: deps.into_iter().map(NodeKey::PackageListingKey).collect(),
+                re_queue_duration: queued_ms.map(Duration::from_millis),
+                ..super::evaluation(
+                    NodeKey::PackageListingKey(listing(name)),
+                    Duration::from_secs(1),
+                )
+            })
+        };
+
+        let info = run_signals(
+            CriticalPathBackendName::LongestPathGraph,
+            ctx(),
+            vec![
+                evaluation("a", Some(200), vec![]),
+                evaluation("b", None, vec![listing("a")]),
+                evaluation("c", Some(300), vec![listing("b")]),
+                evaluation("d", Some(5000), vec![]),
+            ],
+        )
+        .await?;
+
+        // `d` queued the longest, but it isn't on the critical path.
+        assert_eq!(
+            info.total_re_queue_duration.as_ref().map(|d| d.nanos),
+            Some(500_000_000)
+        );
+        assert_eq!(
+            info.critical_path2
+                .iter()
+                .filter(|entry| matches!(
+                    entry.entry,
+                    Some(buck2_data::critical_path_entry2::Entry::Listing(..))
+                ))
+                .map(|entry| entry.re_queue_duration.as_ref().map(|d| d.nanos))
+                .collect::<Vec<_>>(),
+            vec![Some(200_000_000), None, Some(300_000_000)]
+        );
+
+        Ok(())
+    }
+
+    fn secs(secs: u64) -> NodeDuration {
+        NodeDuration {
+            user: Duration::from_secs(secs),
+            total: Duration::from_secs(secs),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_build_info_builder() -> anyhow::Result<()> {
+        let info = BuildInfoBuilder::new()
+            .entry(SyntheticNodeKind::Load, "cell//pkg", secs(1), None)
+            .entry(SyntheticNodeKind::Analysis, "cell//pkg:bin", secs(2), None)
+            .entry(
+                SyntheticNodeKind::Action,
+                "cell//pkg:bin",
+                secs(3),
+                Some(Duration::from_secs(2)),
+            )
+            .entry(SyntheticNodeKind::External, "deploy", secs(4), None)
+            .with_graph_size(10, 12)
+            .build();
+
+        let logged = emit_build_info(info, ctx()).await?;
+
+        assert_eq!(
+            logged.backend_name,
+            Some(CriticalPathBackendName::LongestPathGraph.to_string())
+        );
+        assert_eq!(logged.num_nodes, 10);
+        assert_eq!(logged.num_edges, 12);
+        assert_eq!(
+            logged
+                .critical_path2
+                .iter()
+                .map(|entry| match &entry.entry {
+                    Some(buck2_data::critical_path_entry2::Entry::Load(..)) => "load",
+                    Some(buck2_data::critical_path_entry2::Entry::Analysis(..)) => "analysis",
+                    Some(buck2_data::critical_path_entry2::Entry::ActionExecution(..)) => "action",
+                    Some(buck2_data::critical_path_entry2::Entry::External(..)) => "external",
+                    Some(buck2_data::critical_path_entry2::Entry::ComputeCriticalPath(..)) => {
+                        "compute"
+                    }
+                    _ => "other",
+                })
+                .collect::<Vec<_>>(),
+            vec!["load", "analysis", "action", "external", "compute"]
+        );
+        assert_eq!(
+            logged.critical_path2[2]
+                .potential_improvement_duration
+                .as_ref()
+                .map(|d| d.seconds),
+            Some(2)
+        );
+        assert_eq!(
+            logged
+                .critical_path_user_duration
+                .as_ref()
+                .map(|d| d.seconds),
+            Some(10)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare_synthetic_critical_paths() {
+        let path = |info: &BuildInfo| {
+            info.critical_path_entries()
+                .map(|entry| (entry.stable_id(), entry.user_duration()))
+                .collect::<Vec<_>>()
+        };
+
+        let baseline = BuildInfoBuilder::new()
+            .entry(SyntheticNodeKind::Analysis, "cell//pkg:bin", secs(1), None)
+            .entry(SyntheticNodeKind::Action, "cell//pkg:bin", secs(2), None)
+            .entry(SyntheticNodeKind::Action, "cell//pkg:bin", secs(2), None)
+            .build();
+        let current = BuildInfoBuilder::new()
+            .entry(SyntheticNodeKind::Analysis, "cell//pkg:bin", secs(1), None)
+            .entry(SyntheticNodeKind::Action, "cell//pkg:bin", secs(2), None)
+            .entry(SyntheticNodeKind::Action, "cell//pkg:bin", secs(5), None)
+            .entry(SyntheticNodeKind::External, "deploy", secs(1), None)
+            .build();
+
+        let baseline = path(&baseline);
+        let current = path(&current);
+        assert_eq!(
+            compare_critical_paths(
+                &baseline,
+                &current,
+                RegressionThresholds {
+                    absolute: Duration::from_secs(1),
+                    relative_percent: 10.0,
+                },
+            ),
+            vec![
+                CriticalPathRegression::Slower {
+                    node: current[2].0.clone(),
+                    baseline: Duration::from_secs(2),
+                    current: Duration::from_secs(5),
+                },
+                CriticalPathRegression::Appeared {
+                    node: "External(deploy)".to_owned(),
+                    current: Duration::from_secs(1),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_strict_errors() -> anyhow::Result<()> {
+        let ctx = BuildSignalsContext {
+            options: buck2_build_signals::CriticalPathOptions {
+                mermaid_path: Some("/does/not/exist/critical_path.mmd".into()),
+                ..Default::default()
+            },
+            ..ctx()
+        };
+
+        let res = dry_run(CriticalPathBackendName::Default, ctx, |_| async { Ok(()) }).await;
+        assert!(format!("{:#}", res.unwrap_err()).contains("Error computing critical path"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_duration_anomalies() -> anyhow::Result<()> {
+        let history = Arc::new(crate::NodeDurationHistory::new(3, 3.0));
+
+        let mut anomalies = Vec::new();
+        let mut stabilities = Vec::new();
+        for secs in [4, 5, 4, 5, 20] {
+            let ctx = BuildSignalsContext {
+                critical_path_history: Some(history.dupe() as _),
+                ..ctx()
+            };
+            let info = run_signals(
+                CriticalPathBackendName::Default,
+                ctx,
+                vec![BuildSignal::Evaluation(super::evaluation(
+                    NodeKey::PackageListingKey(listing("a")),
+                    Duration::from_secs(secs),
+                ))],
+            )
+            .await?;
+            anomalies.push(info.duration_anomalies);
+            stabilities.push(info.critical_path_stability);
+        }
+
+        // The critical path is the same node in every build.
+        assert_eq!(
+            stabilities,
+            vec![None, Some(1.0), Some(1.0), Some(1.0), Some(1.0)]
+        );
+
+        assert!(anomalies[..4].iter().all(|a| a.is_empty()));
+        assert_eq!(anomalies[4].len(), 1);
+        let anomaly = &anomalies[4][0];
+        assert_eq!(
+            anomaly.node,
+            NodeKey::PackageListingKey(listing("a")).to_string()
+        );
+        assert_eq!(anomaly.duration.as_ref().map(|d| d.seconds), Some(20));
+        assert!(anomaly.sigma >= 3.0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_evaluation_stream() -> anyhow::Result<()> {
+        let buffer = SharedBuffer::default();
+        let ctx = BuildSignalsContext {
+            evaluation_stream: Some(Box::new(buffer.clone())),
+            ..ctx()
+        };
+
+        let evaluation = |name: &str, deps: Vec<PackageListingKey>| {
+            BuildSignal::Evaluation(Evaluation {
+                dep_keys: deps.into_iter().map(NodeKey::PackageListingKey).collect(),
+                ..super::evaluation(
+                    NodeKey::PackageListingKey(listing(name)),
+                    Duration::from_millis(1),
+                )
+            })
+        };
+
+        run_signals(
+            CriticalPathBackendName::Default,
+            ctx,
+            vec![evaluation("a", vec![]), evaluation("b", vec![listing("a")])],
+        )
+        .await?;
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone())?;
+        let lines = output
+            .lines()
+            .map(serde_json::from_str::<serde_json::Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(
+            lines,
+            vec![
+                serde_json::json!({
+                    "key": NodeKey::PackageListingKey(listing("a")).to_string(),
+                    "kind": "PackageListingKey",
+                    "duration_us": 1000,
+                    "deps": 0,
+                }),
+                serde_json::json!({
+                    "key": NodeKey::PackageListingKey(listing("b")).to_string(),
+                    "kind": "PackageListingKey",
+                    "duration_us": 1000,
+                    "deps": 1,
+                }),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_critical_paths() -> anyhow::Result<()> {
+        let evaluation = |name: &str, secs: u64, deps: Vec<PackageListingKey>| {
+            BuildSignal::Evaluation(Evaluation {
+                dep_keys: deps.into_iter().map(NodeKey::PackageListingKey).collect(),
+                ..super::evaluation(
+                    NodeKey::PackageListingKey(listing(name)),
+                    Duration::from_secs(secs),
+                )
+            })
+        };
+
+        let mut ctx = ctx();
+        ctx.options.partial_critical_path_interval = Some(Duration::ZERO);
+
+        let mut source = send_signals(
+            CriticalPathBackendName::Default,
+            ctx,
+            vec![
+                evaluation("a", 1, vec![]),
+                evaluation("b", 2, vec![listing("a")]),
+            ],
+        )
+        .await?;
+
+        let mut partials = Vec::new();
+        let mut info = None;
+        while let Some(event) = source.try_receive() {
+            let event = match event {
+                Event::Buck(event) => event,
+                _ => continue,
+            };
+            if let buck2_data::buck_event::Data::Instant(instant) = event.data() {
+                match &instant.data {
+                    Some(buck2_data::instant_event::Data::PartialCriticalPath(partial)) => {
+                        assert!(info.is_none(), "Partial critical path after the final one");
+                        partials.push(partial.clone());
+                    }
+                    Some(buck2_data::instant_event::Data::BuildGraphInfo(i)) => {
+                        info = Some(i.clone())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        assert_eq!(
+            partials
+                .iter()
+                .map(|p| (
+                    p.tail.clone(),
+                    p.duration.as_ref().map(|d| d.seconds),
+                    p.num_nodes
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    NodeKey::PackageListingKey(listing("a")).to_string(),
+                    Some(1),
+                    1
+                ),
+                (
+                    NodeKey::PackageListingKey(listing("b")).to_string(),
+                    Some(3),
+                    2
+                ),
+            ]
+        );
+        let info = info.context("The critical path was not logged")?;
+        assert_eq!(
+            listing_packages(&info),
+            vec![listing("a").0.to_string(), listing("b").0.to_string()]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_external_node() -> anyhow::Result<()> {
+        let action = ActionKey::unchecked_new(DeferredKey::Base(
+            BaseDeferredKey::TargetLabel(ConfiguredTargetLabel::testing_parse(
+                "cell//pkg:foo",
+                ConfigurationData::testing_new(),
+            )),
+            DeferredId::testing_new(0),
+        ));
+        let upload = ExternalNodeKey {
+            id: "upload-foo".into(),
+            display: "Upload foo".into(),
+        };
+
+        let info = run_signals(
+            CriticalPathBackendName::LongestPathGraph,
+            ctx(),
+            vec![
+                BuildSignal::Evaluation(super::evaluation(
+                    NodeKey::BuildKey(BuildKey(action.dupe())),
+                    Duration::from_secs(1),
+                )),
+                BuildSignal::Evaluation(Evaluation::external(ExternalNode {
+                    key: upload,
+                    duration: NodeDuration {
+                        user: Duration::from_secs(2),
+                        total: Duration::from_secs(2),
+                    },
+                    deps: vec![ExternalNodeDep::Action(action)],
+                    span_id: None,
+                })),
+            ],
+        )
+        .await?;
+
+        assert_eq!(
+            info.critical_path2.first().and_then(|e| e.entry.as_ref()),
+            Some(&buck2_data::critical_path_entry2::Entry::External(
+                buck2_data::critical_path_entry2::External {
+                    id: "upload-foo".to_owned(),
+                    display: "Upload foo".to_owned(),
+                }
+            ))
+        );
+        // The upload had to wait for the action, even though we don't report an action we have no
+        // details on.
+        assert_eq!(
+            info.min_build_time_infinite_parallelism
+                .as_ref()
+                .map(|d| d.seconds),
+            Some(3)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_dry_run() -> anyhow::Result<()> {
+        let info = dry_run(
+            CriticalPathBackendName::Default,
+            ctx(),
+            |installer| async move {
+                installer.activation_tracker.key_activated(
+                    &listing("a"),
+                    &mut std::iter::empty(),
+                    ActivationData::Evaluated(Some(Box::new(PackageListingKeyActivationData {
+                        duration: Duration::from_secs(1),
+                        spans: Default::default(),
+                    }))),
+                );
+                Ok(())
+            },
+        )
+        .await?;
+
+        assert_eq!(listing_packages(&info), vec![listing("a").0.to_string()]);
+        assert_eq!(
+            info.critical_path2[0].duration.as_ref().map(|d| d.seconds),
+            Some(1)
+        );
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn test_freshly_computed() -> anyhow::Result<()> {
+        let info = dry_run(
+            CriticalPathBackendName::Default,
+            ctx(),
+            |installer| async move {
+                // `a` was computed by a previous command, and `b` depends on it.
+                installer.activation_tracker.key_activated(
+                    &listing("a"),
+                    &mut std::iter::empty(),
+                    ActivationData::Reused,
+                );
+                installer.activation_tracker.key_activated(
+                    &listing("b"),
+                    &mut std::iter::once(&listing("a") as &dyn std::any::Any),
+                    ActivationData::Evaluated(Some(Box::new(PackageListingKeyActivationData {
+                        duration: Duration::from_secs(1),
+                        spans: Default::default(),
+                    }))),
+                );
+                Ok(())
+            },
+        )
+        .await?;
+
+        assert_eq!(
+            listing_packages(&info),
+            vec![listing("a").0.to_string(), listing("b").0.to_string()]
+        );
+        assert!(!info.critical_path2[0].freshly_computed);
+        assert!(info.critical_path2[1].freshly_computed);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_redaction() -> anyhow::Result<()> {
+        let evaluation = |name: &str, secs: u64, deps: Vec<PackageListingKey>| {
+            BuildSignal::Evaluation(Evaluation {
+                dep_keys: deps.into_iter().map(NodeKey::PackageListingKey).collect(),
+                ..super::evaluation(
+                    NodeKey::PackageListingKey(listing(name)),
+                    Duration::from_secs(secs),
+                )
+            })
+        };
+        let signals = || {
+            vec![
+                evaluation("a", 1, vec![]),
+                evaluation("b", 2, vec![listing("a")]),
+                evaluation("c", 3, vec![listing("b")]),
+            ]
+        };
+
+        let plain =
+            run_signals(CriticalPathBackendName::LongestPathGraph, ctx(), signals()).await?;
+        let redacted = run_signals(
+            CriticalPathBackendName::LongestPathGraph,
+            BuildSignalsContext {
+                options: CriticalPathOptions {
+                    redaction: CriticalPathRedaction::Hash,
+                    ..Default::default()
+                },
+                ..ctx()
+            },
+            signals(),
+        )
+        .await?;
+
+        // The same entries, which took as long, but whose names we can't read.
+        assert_eq!(plain.critical_path2.len(), redacted.critical_path2.len());
+        for (plain, redacted) in plain.critical_path2.iter().zip(&redacted.critical_path2) {
+            assert_eq!(plain.duration, redacted.duration);
+        }
+        let packages = listing_packages(&redacted);
+        assert_eq!(packages.len(), 3);
+        for (package, raw) in packages.iter().zip(listing_packages(&plain)) {
+            assert!(!package.contains("cell") && !package.contains(&raw));
+        }
+
+        Ok(())
+    }
+}
@@ -147,6 +147,24 @@ fn log_critical_path(critical_path: &buck2_data::BuildGraphExecutionInfo) -> any
                 kind = "listing";
                 name = listing.package.clone();
             }
+            Some(Entry::Uncategorized(uncategorized)) => {
+                kind = "uncategorized";
+                name = uncategorized.display.clone();
+                category = &uncategorized.kind;
+            }
+            Some(Entry::External(external)) => {
+                kind = "external";
+                name = external.display.clone();
+                identifier = &external.id;
+            }
+            Some(Entry::Configuration(configuration)) => {
+                kind = "configuration";
+
+                name = match &configuration.target {
+                    Some(t) => display::display_configured_target_label(t, target_display_options)?,
+                    None => continue,
+                };
+            }
             None => continue,
         }
 
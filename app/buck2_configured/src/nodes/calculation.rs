@@ -10,6 +10,8 @@
 //! Calculations relating to 'TargetNode's that runs on Dice
 
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
 use allocative::Allocative;
 use async_trait::async_trait;
@@ -36,6 +38,8 @@ use buck2_core::provider::label::ProvidersLabel;
 use buck2_core::target::configured_target_label::ConfiguredTargetLabel;
 use buck2_core::target::label::TargetLabel;
 use buck2_error::Context;
+use buck2_events::dispatch::async_record_root_spans;
+use buck2_events::span::SpanId;
 use buck2_execute::execute::dice_data::HasFallbackExecutorConfig;
 use buck2_node::attrs::configuration_context::AttrConfigurationContext;
 use buck2_node::attrs::configuration_context::AttrConfigurationContextImpl;
@@ -62,6 +66,7 @@ use dice::Key;
 use dupe::Dupe;
 use indexmap::IndexSet;
 use more_futures::cancellation::CancellationContext;
+use smallvec::SmallVec;
 use starlark_map::ordered_map::OrderedMap;
 use starlark_map::small_map::SmallMap;
 use starlark_map::small_set::SmallSet;
@@ -1038,6 +1043,11 @@ async fn compute_configured_target_node(
 #[derive(Clone, Dupe, Display, Debug, Eq, Hash, PartialEq, Allocative)]
 pub struct ConfiguredTargetNodeKey(pub ConfiguredTargetLabel);
 
+pub struct ConfiguredTargetNodeKeyActivationData {
+    pub duration: Duration,
+    pub spans: SmallVec<[SpanId; 1]>,
+}
+
 /// Similar to [`ConfiguredTargetNodeKey`], but used when the target
 /// is transitioned to different configuration because rule definition requires it.
 #[derive(Clone, Dupe, Display, Debug, Eq, Hash, PartialEq, Allocative)]
@@ -1070,7 +1080,16 @@ impl ConfiguredTargetNodeCalculationImpl for ConfiguredTargetNodeCalculationInst
                 ctx: &mut DiceComputations,
                 _cancellation: &CancellationContext,
             ) -> Self::Value {
-                let res = compute_configured_target_node(self, ctx).await;
+                let now = Instant::now();
+
+                let (res, spans) =
+                    async_record_root_spans(compute_configured_target_node(self, ctx)).await;
+
+                ctx.store_evaluation_data(ConfiguredTargetNodeKeyActivationData {
+                    duration: now.elapsed(),
+                    spans,
+                })?;
+
                 Ok(res.with_context(|| format!("Error looking up configured node {}", self.0))?)
             }
 
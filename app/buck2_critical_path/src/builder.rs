@@ -10,6 +10,7 @@
 use std::fmt::Display;
 use std::hash::Hash;
 
+use allocative::Allocative;
 use starlark_map::small_map::SmallMap;
 use starlark_map::Hashed;
 
@@ -28,6 +29,7 @@ pub enum PushError<K: Display> {
     Overflow,
 }
 
+#[derive(Allocative)]
 pub struct GraphBuilder<K: Hash + Eq, D> {
     keys: SmallMap<K, VertexId>,
     data: Vec<D>,
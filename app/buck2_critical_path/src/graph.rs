@@ -7,11 +7,15 @@
  * of this source tree.
  */
 
+use std::ops::Add;
+
+use allocative::Allocative;
+
 use crate::types::OptionalVertexId;
 use crate::types::VertexData;
 use crate::types::VertexId;
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Allocative)]
 pub struct GraphVertex {
     pub edges_idx: u32,
     pub edges_count: u32,
@@ -262,22 +266,25 @@ pub enum AddEdgesError {
     Overflow,
 }
 
-#[derive(
-    Default,
-    Clone,
-    Copy,
-    PartialEq,
-    Eq,
-    PartialOrd,
-    Ord,
-    derive_more::Add,
-    Debug
-)]
+#[derive(Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
 pub struct PathCost {
     pub runtime: u64,
     pub len: u32,
 }
 
+/// Costs saturate instead of overflowing: a path that costs more than we can represent is as long
+/// as any other such path, but still longer than any path we can represent.
+impl Add for PathCost {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            runtime: self.runtime.saturating_add(other.runtime),
+            len: self.len.saturating_add(other.len),
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -301,6 +308,21 @@ mod test {
         builder.finish()
     }
 
+    #[test]
+    fn test_path_cost_saturates() {
+        let cost = PathCost {
+            runtime: u64::MAX - 1,
+            len: 1,
+        };
+        assert_eq!(
+            cost + cost,
+            PathCost {
+                runtime: u64::MAX,
+                len: 2
+            }
+        );
+    }
+
     #[test]
     fn test_iter() {
         let (graph, _keys, data) = test_graph();
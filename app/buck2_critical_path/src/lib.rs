@@ -21,7 +21,10 @@ pub use builder::GraphBuilder;
 pub use builder::PushError;
 pub use graph::Graph;
 pub use graph::GraphVertex;
+pub use graph::PathCost;
+pub use potential::compute_critical_path;
 pub use potential::compute_critical_path_potentials;
+pub use potential::compute_critical_path_potentials_with_deadline;
 pub use types::CriticalPathIndex;
 pub use types::CriticalPathVertexData;
 pub use types::OptionalVertexId;
@@ -8,6 +8,7 @@
  */
 
 use std::collections::BinaryHeap;
+use std::time::Instant;
 
 use anyhow::Context as _;
 use crossbeam::thread;
@@ -17,6 +18,7 @@ use crate::graph::PathCost;
 use crate::types::CriticalPathIndex;
 use crate::types::CriticalPathVertexData;
 use crate::types::OptionalCriticalPathIndex;
+use crate::types::OptionalVertexId;
 use crate::types::VertexData;
 use crate::types::VertexId;
 
@@ -28,6 +30,26 @@ pub fn compute_critical_path_potentials(
     PathCost,
     CriticalPathVertexData<PathCost>,
 )> {
+    // Unwrap safety: without a deadline we always run to completion.
+    Ok(compute_critical_path_potentials_with_deadline(deps, weights, None)?.unwrap())
+}
+
+/// Like `compute_critical_path_potentials`, but gives up and returns `None` if `deadline` passes
+/// before we're done. The deadline is checked between phases of the computation, so this may
+/// overrun it by the duration of a single phase.
+pub fn compute_critical_path_potentials_with_deadline(
+    deps: &Graph,
+    weights: &VertexData<u64>,
+    deadline: Option<Instant>,
+) -> anyhow::Result<
+    Option<(
+        CriticalPathVertexData<VertexId>,
+        PathCost,
+        CriticalPathVertexData<PathCost>,
+    )>,
+> {
+    let deadline_exceeded = || deadline.map_or(false, |d| Instant::now() >= d);
+
     let mut rdeps = None;
     let mut topo_order = None;
 
@@ -45,6 +67,10 @@ pub fn compute_critical_path_potentials(
     let rdeps = rdeps.unwrap();
     let topo_order = topo_order.unwrap()?;
 
+    if deadline_exceeded() {
+        return Ok(None);
+    }
+
     let mut cost_to_sink = None;
     let mut cost_from_source = None;
     let mut predecessors = None;
@@ -67,40 +93,24 @@ pub fn compute_critical_path_potentials(
     let cost_from_source = cost_from_source.unwrap();
     let predecessors = predecessors.unwrap();
 
-    // Look up the critical path. Find the node with the highest cost from a source, then iterate
-    // over predecessors to reconstruct the critical path.
-    let critical_path_end = cost_from_source.iter().max_by_key(|(_idx, cost)| *cost);
-
-    let (critical_path_sink, critical_path_cost) = match critical_path_end {
-        Some(c) => c,
-        None => {
-            // The graph is empty.
-            return Ok((
-                CriticalPathVertexData::new(Vec::new()),
-                PathCost::default(),
-                CriticalPathVertexData::new(Vec::new()),
-            ));
-        }
-    };
+    let (critical_path, critical_path_cost) =
+        critical_path_from_longest_paths(&cost_from_source, &predecessors);
 
-    let critical_path_cost = *critical_path_cost;
-
-    // Now, traverse predecessors to actually get the list of ndoes on the critical path.
-    let critical_path = {
-        let critical_path_len = critical_path_cost.len as usize;
-        let mut critical_path = vec![VertexId::new(0); critical_path_len];
-        let mut idx: VertexId = critical_path_sink;
-        for i in 0..critical_path_len {
-            critical_path[critical_path_len - 1 - i] = idx;
-            if i != critical_path_len - 1 {
-                idx = predecessors[idx].into_option().unwrap();
-            }
-        }
-        CriticalPathVertexData::new(critical_path)
-    };
+    if critical_path.len() == 0 {
+        // The graph is empty.
+        return Ok(Some((
+            critical_path,
+            PathCost::default(),
+            CriticalPathVertexData::new(Vec::new()),
+        )));
+    }
 
     drop(predecessors); // We no longer need this.
 
+    if deadline_exceeded() {
+        return Ok(None);
+    }
+
     // For each node, we now identify:
 
     // - The last node on the critical path with a path to this node.
@@ -157,6 +167,10 @@ pub fn compute_critical_path_potentials(
     .ok()
     .context("Threads panicked")?;
 
+    if deadline_exceeded() {
+        return Ok(None);
+    }
+
     // Compute the cost of the longest path through each vertex. We do this here instead of inline
     // later to avoid jumping around 3 arrays later (whereas here we can do so linearly).
 
@@ -165,7 +179,7 @@ pub fn compute_critical_path_potentials(
     for idx in deps.iter_vertices() {
         let mut cost = cost_from_source[idx] + cost_to_sink[idx];
         // Don't double-count the vertex at `idx`.
-        cost.runtime -= weights[idx];
+        cost.runtime = cost.runtime.saturating_sub(weights[idx]);
         cost.len -= 1;
         vertices_cost[idx] = cost
     }
@@ -233,7 +247,7 @@ pub fn compute_critical_path_potentials(
     for (idx, vertex) in critical_path.iter() {
         // Drop this vertex's runtime.
         let mut cost = critical_path_cost;
-        cost.runtime -= weights[*vertex];
+        cost.runtime = cost.runtime.saturating_sub(weights[*vertex]);
         updated_critical_path_cost[idx] = cost;
     }
 
@@ -273,13 +287,63 @@ pub fn compute_critical_path_potentials(
         }
     }
 
-    Ok((
+    Ok(Some((
         critical_path,
         critical_path_cost,
         updated_critical_path_cost,
+    )))
+}
+
+/// Compute just the critical path, without potentials. This is much cheaper than
+/// `compute_critical_path_potentials` since it only needs a single longest-paths traversal.
+pub fn compute_critical_path(
+    deps: &Graph,
+    weights: &VertexData<u64>,
+) -> anyhow::Result<(CriticalPathVertexData<VertexId>, PathCost)> {
+    let topo_order = deps.topo_sort()?;
+    let (cost_from_source, predecessors) =
+        deps.find_longest_paths(topo_order.iter().rev().copied(), weights);
+    Ok(critical_path_from_longest_paths(
+        &cost_from_source,
+        &predecessors,
     ))
 }
 
+/// Look up the critical path. Find the node with the highest cost from a source, then iterate
+/// over predecessors to reconstruct the critical path. This returns an empty path if the graph is
+/// empty.
+fn critical_path_from_longest_paths(
+    cost_from_source: &VertexData<PathCost>,
+    predecessors: &VertexData<OptionalVertexId>,
+) -> (CriticalPathVertexData<VertexId>, PathCost) {
+    let critical_path_end = cost_from_source.iter().max_by_key(|(_idx, cost)| *cost);
+
+    let (critical_path_sink, critical_path_cost) = match critical_path_end {
+        Some(c) => c,
+        None => {
+            return (CriticalPathVertexData::new(Vec::new()), PathCost::default());
+        }
+    };
+
+    let critical_path_cost = *critical_path_cost;
+
+    // Now, traverse predecessors to actually get the list of ndoes on the critical path.
+    let critical_path_len = critical_path_cost.len as usize;
+    let mut critical_path = vec![VertexId::new(0); critical_path_len];
+    let mut idx: VertexId = critical_path_sink;
+    for i in 0..critical_path_len {
+        critical_path[critical_path_len - 1 - i] = idx;
+        if i != critical_path_len - 1 {
+            idx = predecessors[idx].into_option().unwrap();
+        }
+    }
+
+    (
+        CriticalPathVertexData::new(critical_path),
+        critical_path_cost,
+    )
+}
+
 #[cfg(test)]
 mod test {
     use std::time::Instant;
@@ -399,6 +463,31 @@ mod test {
         do_test(&test_dag(1_000_000))
     }
 
+    #[test]
+    fn test_deadline_exceeded() {
+        let dag = test_dag(100_000);
+        let res = compute_critical_path_potentials_with_deadline(
+            &dag.graph,
+            &dag.weights,
+            Some(Instant::now()),
+        )
+        .unwrap();
+        assert!(res.is_none());
+
+        let (critical_path, critical_path_cost) =
+            compute_critical_path(&dag.graph, &dag.weights).unwrap();
+        assert_eq!(critical_path_cost, naive_critical_path_cost(&dag, None));
+        assert_eq!(critical_path.len(), critical_path_cost.len as usize);
+    }
+
+    #[test]
+    fn test_no_deadline() {
+        let dag = test_dag(100);
+        let res =
+            compute_critical_path_potentials_with_deadline(&dag.graph, &dag.weights, None).unwrap();
+        assert!(res.is_some());
+    }
+
     /// Run on a larger number of random graphs.
     #[test]
     fn test_random_large() {
@@ -13,13 +13,34 @@ use std::marker::PhantomData;
 use std::ops::Index;
 use std::ops::IndexMut;
 
+use allocative::Allocative;
 use derive_more::Display;
 use starlark_map::small_map::SmallMap;
 
-#[derive(Copy, Clone, Default, PartialEq, Eq, Hash)]
+#[derive(
+    Copy,
+    Clone,
+    Default,
+    Ord,
+    PartialOrd,
+    PartialEq,
+    Eq,
+    Hash,
+    Allocative
+)]
 pub struct GraphVertexKind;
 
-#[derive(Copy, Clone, Default, Ord, PartialOrd, PartialEq, Eq, Debug)]
+#[derive(
+    Copy,
+    Clone,
+    Default,
+    Ord,
+    PartialOrd,
+    PartialEq,
+    Eq,
+    Debug,
+    Allocative
+)]
 pub struct CriticalPathIndexKind;
 
 pub trait VertexKind: Copy + Clone + Default {}
@@ -29,7 +50,18 @@ impl VertexKind for CriticalPathIndexKind {}
 
 /// The ID of a Vertex. This can be used to index into AbstractVertexData. Those IDs are given a
 /// kind so we don't confuse indices in a critical path with vertex indices in a graph.
-#[derive(Copy, Clone, Default, Ord, PartialOrd, PartialEq, Eq, Display, Hash)]
+#[derive(
+    Copy,
+    Clone,
+    Default,
+    Ord,
+    PartialOrd,
+    PartialEq,
+    Eq,
+    Display,
+    Hash,
+    Allocative
+)]
 #[display(fmt = "{}", "self.0")]
 pub struct AbstractVertexId<Kind: VertexKind>(u32, PhantomData<Kind>);
 
@@ -45,6 +77,12 @@ where
     pub(crate) fn into_inner(self) -> u32 {
         self.0
     }
+
+    /// The position of this vertex in its AbstractVertexData.
+    #[inline]
+    pub fn as_usize(self) -> usize {
+        self.0 as usize
+    }
 }
 
 impl<T, Kind> Index<AbstractVertexId<Kind>> for AbstractVertexData<T, Kind>
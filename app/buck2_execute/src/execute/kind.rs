@@ -82,6 +82,17 @@ impl CommandExecutionKind {
         }
     }
 
+    /// The digest of the action we ran (or looked up), if we computed one.
+    pub fn action_digest(&self) -> Option<&ActionDigest> {
+        match self {
+            Self::Local { digest, .. } | Self::LocalWorker { digest, .. } => Some(digest),
+            Self::Remote { details, .. }
+            | Self::ActionCache { details }
+            | Self::RemoteDepFileCache { details } => Some(&details.action_digest),
+            Self::LocalWorkerInit { .. } => None,
+        }
+    }
+
     pub fn to_proto(&self, omit_details: bool) -> buck2_data::CommandExecutionKind {
         use buck2_data::command_execution_kind::Command;
 
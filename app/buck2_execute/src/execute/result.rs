@@ -103,6 +103,10 @@ pub struct CommandExecutionMetadata {
 
     /// How long we spent hashing the action's inputs.
     pub hashing_duration: Duration,
+
+    /// How many bytes we uploaded to RE to run this command. This is only set for commands we
+    /// executed remotely.
+    pub re_bytes_uploaded: Option<u64>,
 }
 
 impl CommandExecutionMetadata {
@@ -115,6 +119,7 @@ impl CommandExecutionMetadata {
             input_materialization_duration: metadata.input_materialization_duration.try_into().ok(),
             execution_stats: metadata.execution_stats,
             hashing_duration: metadata.hashing_duration.try_into().ok(),
+            re_bytes_uploaded: metadata.re_bytes_uploaded,
         }
     }
 }
@@ -128,6 +133,7 @@ impl Default for CommandExecutionMetadata {
             execution_stats: None,
             input_materialization_duration: Duration::default(),
             hashing_duration: Duration::default(),
+            re_bytes_uploaded: None,
         }
     }
 }
@@ -343,6 +349,7 @@ mod tests {
             }),
             input_materialization_duration: Duration::from_secs(6),
             hashing_duration: Duration::from_secs(7),
+            re_bytes_uploaded: Some(8),
         };
         let std_streams = CommandStdStreams::Local {
             stdout: [65, 66, 67].to_vec(), // ABC
@@ -411,6 +418,7 @@ mod tests {
                 seconds: 7,
                 nanos: 0,
             }),
+            re_bytes_uploaded: Some(8),
         };
         let command_execution_details = buck2_data::CommandExecutionDetails {
             signed_exit_code: Some(456),
@@ -448,6 +448,7 @@ impl LocalExecutor {
                     execution_stats: None, // We fill this in later if available.
                     input_materialization_duration,
                     hashing_duration: Duration::ZERO, // We fill this in later if available.
+                    re_bytes_uploaded: None,
                 };
 
                 (timing, r)
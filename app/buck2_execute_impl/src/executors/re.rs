@@ -80,7 +80,7 @@ impl ReExecutor {
         blobs: &ActionBlobs,
         paths: &CommandExecutionPaths,
         digest_config: DigestConfig,
-    ) -> ControlFlow<CommandExecutionResult, CommandExecutionManager> {
+    ) -> ControlFlow<CommandExecutionResult, (CommandExecutionManager, u64)> {
         let re_client = &self.re_client;
 
         let upload_response = span_async(buck2_data::ReUploadStart {}, async move {
@@ -97,7 +97,7 @@ impl ReExecutor {
                 .await;
             match res {
                 Ok(stats) => (
-                    Ok(()),
+                    Ok(stats.bytes_uploaded),
                     buck2_data::ReUploadEnd {
                         digests_uploaded: Some(stats.digests_uploaded),
                         bytes_uploaded: Some(stats.bytes_uploaded),
@@ -108,12 +108,12 @@ impl ReExecutor {
         })
         .await;
 
-        match upload_response {
-            Ok(()) => {}
+        let bytes_uploaded = match upload_response {
+            Ok(bytes_uploaded) => bytes_uploaded,
             Err(e) => return ControlFlow::Break(manager.error("remote_upload_error", e)),
         };
 
-        ControlFlow::Continue(manager)
+        ControlFlow::Continue((manager, bytes_uploaded))
     }
 
     async fn re_execute(
@@ -257,7 +257,7 @@ impl PreparedCommandExecutor for ReExecutor {
         }
 
         // TODO(bobyf, torozco): remote execution probably needs to explicitly handle cancellations
-        let manager = self
+        let (manager, bytes_uploaded) = self
             .upload(
                 manager,
                 &action_and_blobs.blobs,
@@ -305,7 +305,9 @@ impl PreparedCommandExecutor for ReExecutor {
         .boxed()
         .await;
 
-        let DownloadResult::Result(res) = res;
+        let DownloadResult::Result(mut res) = res;
+
+        res.report.timing.re_bytes_uploaded = Some(bytes_uploaded);
 
         res
     }
@@ -13,6 +13,7 @@ use std::io::BufWriter;
 use std::marker::PhantomData;
 use std::ops::Deref;
 use std::sync::Arc;
+use std::time::Duration;
 
 use allocative::Allocative;
 use anyhow::Context;
@@ -27,6 +28,8 @@ use buck2_build_api::context::SetBuildContextData;
 use buck2_build_api::keep_going::HasKeepGoing;
 use buck2_build_api::spawner::BuckSpawner;
 use buck2_build_signals::CriticalPathBackendName;
+use buck2_build_signals::CriticalPathHistory;
+use buck2_build_signals::CriticalPathOptions;
 use buck2_build_signals::HasCriticalPathBackend;
 use buck2_cli_proto::client_context::HostArchOverride;
 use buck2_cli_proto::client_context::HostPlatformOverride;
@@ -424,6 +427,7 @@ impl<'a> ServerCommandContext<'a> {
                 .build_options
                 .as_ref()
                 .map_or(false, |opts| opts.materialize_failed_inputs),
+            critical_path_history: self.base_context.daemon.critical_path_history.dupe(),
         }
     }
 
@@ -531,6 +535,7 @@ struct DiceCommandDataProvider {
     paranoid: Option<ParanoidDownloader>,
     spawner: Arc<BuckSpawner>,
     materialize_failed_inputs: bool,
+    critical_path_history: Arc<dyn CriticalPathHistory>,
 }
 
 #[async_trait]
@@ -622,6 +627,13 @@ impl DiceDataProvider for DiceCommandDataProvider {
         let critical_path_backend = root_config
             .parse("buck2", "critical_path_backend2")?
             .unwrap_or(CriticalPathBackendName::Default);
+        let critical_path_options = critical_path_options(root_config, &cell_resolver)?;
+        let critical_path_strict = root_config
+            .parse::<bool>("buck2", "critical_path_strict")?
+            .unwrap_or(false);
+        let critical_path_history = root_config
+            .parse::<bool>("buck2", "critical_path_history")?
+            .unwrap_or(false);
 
         set_fallback_executor_config(&mut data.data, self.executor_config.dupe());
         data.set_re_client(self.re_connection.get_client());
@@ -654,6 +666,11 @@ impl DiceDataProvider for DiceCommandDataProvider {
         data.set_starlark_debugger_handle(self.starlark_debugger.clone().map(|v| Box::new(v) as _));
         data.set_keep_going(self.keep_going);
         data.set_critical_path_backend(critical_path_backend);
+        data.set_critical_path_options(critical_path_options);
+        data.set_critical_path_strict(critical_path_strict);
+        if critical_path_history {
+            data.set_critical_path_history(self.critical_path_history.dupe());
+        }
         data.spawner = self.spawner.dupe();
 
         let tags = vec![
@@ -671,6 +688,147 @@ impl DiceDataProvider for DiceCommandDataProvider {
     }
 }
 
+/// Reads the critical path options from the `buck2` section, falling back to the defaults for
+/// those that aren't set.
+fn critical_path_options(
+    root_config: &LegacyBuckConfig,
+    cell_resolver: &CellResolver,
+) -> anyhow::Result<CriticalPathOptions> {
+    let defaults = CriticalPathOptions::default();
+    Ok(CriticalPathOptions {
+        potentials_timeout: root_config
+            .parse("buck2", "critical_path_potentials_timeout_ms")?
+            .map(Duration::from_millis),
+        graph_export_path: root_config.parse("buck2", "critical_path_graph_export_path")?,
+        graph_export_edge_timing: root_config
+            .parse("buck2", "critical_path_graph_export_edge_timing")?
+            .unwrap_or(false),
+        duplicate_evaluation_policy: root_config
+            .parse("buck2", "critical_path_duplicate_evaluation_policy")?
+            .unwrap_or_default(),
+        folded_stacks_path: root_config.parse("buck2", "critical_path_folded_stacks_path")?,
+        longest_path_graph_targets: root_config
+            .parse_list::<String>("buck2", "critical_path_longest_path_graph_targets")?
+            .map(|patterns| {
+                patterns
+                    .iter()
+                    .map(|pattern| {
+                        ParsedPattern::parse_precise(
+                            pattern.trim(),
+                            cell_resolver.root_cell(),
+                            cell_resolver,
+                        )
+                    })
+                    .collect::<anyhow::Result<_>>()
+            })
+            .transpose()?,
+        phase_critical_paths: root_config
+            .parse("buck2", "critical_path_phase_paths")?
+            .unwrap_or(false),
+        max_span_ids_per_node: root_config
+            .parse("buck2", "critical_path_max_span_ids_per_node")?
+            .unwrap_or(defaults.max_span_ids_per_node),
+        what_if_zero_cost: root_config.parse("buck2", "critical_path_what_if_zero_cost")?,
+        perfetto_trace_path: root_config.parse("buck2", "critical_path_perfetto_trace_path")?,
+        duration_policy: root_config
+            .parse("buck2", "critical_path_duration_policy")?
+            .unwrap_or_default(),
+        configuration_critical_path: root_config
+            .parse("buck2", "critical_path_configuration_path")?
+            .unwrap_or(false),
+        measure_backend_memory: root_config
+            .parse("buck2", "critical_path_measure_backend_memory")?
+            .unwrap_or(false),
+        max_load_fan_out: root_config
+            .parse("buck2", "critical_path_max_load_fan_out")?
+            .unwrap_or(defaults.max_load_fan_out),
+        collapse_identical_actions: root_config
+            .parse("buck2", "critical_path_collapse_identical_actions")?,
+        graph_export_focus: root_config.parse("buck2", "critical_path_graph_export_focus")?,
+        graph_export_radius: root_config
+            .parse("buck2", "critical_path_graph_export_radius")?
+            .unwrap_or(defaults.graph_export_radius),
+        span_link_template: root_config.parse("buck2", "critical_path_span_link_template")?,
+        contract_zero_duration_nodes: root_config
+            .parse("buck2", "critical_path_contract_zero_duration_nodes")?
+            .unwrap_or(false),
+        attribute_shared_analysis: root_config
+            .parse("buck2", "critical_path_attribute_shared_analysis")?
+            .unwrap_or(false),
+        materialization_grouping: root_config
+            .parse("buck2", "critical_path_materialization_grouping")?
+            .unwrap_or_default(),
+        simulated_core_count: root_config.parse("buck2", "critical_path_simulated_core_count")?,
+        enrich_load_graph: root_config
+            .parse("buck2", "critical_path_enrich_load_graph")?
+            .unwrap_or(true),
+        partial_critical_path_interval: root_config
+            .parse("buck2", "critical_path_partial_interval_ms")?
+            .map(Duration::from_millis),
+        hops_from_top_level: root_config
+            .parse("buck2", "critical_path_hops_from_top_level")?
+            .unwrap_or(false),
+        mermaid_path: root_config.parse("buck2", "critical_path_mermaid_path")?,
+        slack_histogram: root_config
+            .parse("buck2", "critical_path_slack_histogram")?
+            .unwrap_or(false),
+        signal_recording_path: root_config.parse("buck2", "critical_path_signal_recording_path")?,
+        default_backend_potentials: root_config
+            .parse("buck2", "critical_path_default_backend_potentials")?
+            .unwrap_or(false),
+        degenerate_critical_path_min_nodes: root_config
+            .parse("buck2", "critical_path_degenerate_min_nodes")?
+            .unwrap_or(defaults.degenerate_critical_path_min_nodes),
+        load_chains: root_config
+            .parse("buck2", "critical_path_load_chains")?
+            .unwrap_or(false),
+        always_critical: root_config
+            .parse("buck2", "critical_path_always_critical")?
+            .unwrap_or(false),
+        redaction: root_config
+            .parse("buck2", "critical_path_redaction")?
+            .unwrap_or_default(),
+        log_signals: root_config
+            .parse("buck2", "critical_path_log_signals")?
+            .unwrap_or(false),
+        focus_targets: root_config
+            .parse_list::<String>("buck2", "critical_path_focus_targets")?
+            .map(|targets| targets.iter().map(|t| t.trim().to_owned()).collect()),
+        near_critical_count: root_config.parse("buck2", "critical_path_near_critical_count")?,
+        cost_floors: root_config
+            .parse_list::<String>("buck2", "critical_path_cost_floors")?
+            .unwrap_or_default()
+            .iter()
+            .map(|floor| parse_cost_floor(floor))
+            .collect::<anyhow::Result<_>>()?,
+        evaluation_stream_path: root_config
+            .parse("buck2", "critical_path_evaluation_stream_path")?,
+        console_summary: root_config
+            .parse("buck2", "critical_path_console_summary")?
+            .unwrap_or(false),
+        host_platforms: root_config
+            .parse_list::<String>("buck2", "critical_path_host_platforms")?
+            .unwrap_or_default()
+            .iter()
+            .map(|platform| platform.trim().to_owned())
+            .collect(),
+    })
+}
+
+/// Parses one `Kind=milliseconds` entry of `buck2.critical_path_cost_floors`.
+fn parse_cost_floor(floor: &str) -> anyhow::Result<(String, Duration)> {
+    let (kind, ms) = floor.split_once('=').with_context(|| {
+        format!(
+            "Invalid cost floor, expected `Kind=milliseconds`: `{}`",
+            floor
+        )
+    })?;
+    Ok((
+        kind.trim().to_owned(),
+        Duration::from_millis(ms.trim().parse()?),
+    ))
+}
+
 fn create_cycle_detector() -> Arc<dyn UserCycleDetector> {
     Arc::new(PairDiceCycleDetector(
         CycleDetectorAdapter::<LoadCycleDescriptor>::new(),
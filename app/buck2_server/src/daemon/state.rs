@@ -16,7 +16,9 @@ use std::time::Instant;
 
 use allocative::Allocative;
 use anyhow::Context;
+use buck2_build_api::build_signals::create_critical_path_history;
 use buck2_build_api::spawner::BuckSpawner;
+use buck2_build_signals::CriticalPathHistory;
 use buck2_cli_proto::unstable_dice_dump_request::DiceDumpFormat;
 use buck2_common::cas_digest::DigestAlgorithm;
 use buck2_common::cas_digest::DigestAlgorithmKind;
@@ -171,6 +173,11 @@ pub struct DaemonStateData {
 
     /// Spawner
     pub spawner: Arc<BuckSpawner>,
+
+    /// Durations of critical path nodes in previous builds, for commands that compare their
+    /// critical path against them.
+    #[allocative(skip)]
+    pub critical_path_history: Arc<dyn CriticalPathHistory>,
 }
 
 impl DaemonStateData {
@@ -504,6 +511,7 @@ impl DaemonState {
                 http_client,
                 paranoid,
                 spawner: Arc::new(BuckSpawner::new(daemon_state_data_rt)),
+                critical_path_history: create_critical_path_history(),
             }))
         })
         .await?
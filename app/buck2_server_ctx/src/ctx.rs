@@ -13,6 +13,7 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use buck2_build_signals::BuildSignalsContext;
+use buck2_build_signals::CriticalPathSummaryObserver;
 use buck2_build_signals::DeferredBuildSignals;
 use buck2_build_signals::HasCriticalPathBackend;
 use buck2_core::fs::paths::file_name::FileName;
@@ -104,6 +105,15 @@ pub trait ServerCommandDiceContext {
         R: Send;
 }
 
+/// Prints the summary of the critical path to the user's console.
+struct ConsoleCriticalPathSummary(EventDispatcher);
+
+impl CriticalPathSummaryObserver for ConsoleCriticalPathSummary {
+    fn critical_path_summary(&self, summary: String) {
+        self.0.console_message(summary);
+    }
+}
+
 #[async_trait]
 impl ServerCommandDiceContext for dyn ServerCommandContextTrait + '_ {
     /// Allows running a section of code that uses the shared DiceTransaction
@@ -150,6 +160,15 @@ impl ServerCommandDiceContext for dyn ServerCommandContextTrait + '_ {
 
                                 let request_metadata = self.request_metadata().await?;
                                 let config_metadata = self.config_metadata(&dice).await?;
+                                let critical_path_options =
+                                    dice.per_transaction_data().get_critical_path_options();
+                                let evaluation_stream =
+                                    critical_path_options.open_evaluation_stream()?;
+                                let summary_observer =
+                                    critical_path_options.console_summary.then(|| {
+                                        Arc::new(ConsoleCriticalPathSummary(events.dupe()))
+                                            as Arc<dyn CriticalPathSummaryObserver>
+                                    });
 
                                 events
                                     .span_async(
@@ -176,7 +195,15 @@ impl ServerCommandDiceContext for dyn ServerCommandContextTrait + '_ {
                                                     isolation_prefix: self
                                                         .isolation_prefix()
                                                         .to_owned(),
+                                                    options: critical_path_options,
+                                                    critical_path_history: dice
+                                                        .per_transaction_data()
+                                                        .get_critical_path_history(),
+                                                    evaluation_stream,
+                                                    summary_observer,
                                                 },
+                                                dice.per_transaction_data()
+                                                    .get_critical_path_strict(),
                                                 || exec(self, dice),
                                             )
                                             .await;